@@ -5,13 +5,18 @@ use anyhow::anyhow;
 use serde::{Deserialize, Serialize};
 
 use crate::loader::{Loader, MarkdownLoader, NotebookLoader};
+use crate::notebook::OutputFallback;
 use crate::parser::{Parser, ParserSettings};
 use crate::processors::exercises::ExercisesConfig;
+#[cfg(feature = "katex")]
 use crate::processors::katex::KaTeXConfig;
+use crate::processors::listings::ListingsConfig;
+#[cfg(not(feature = "katex"))]
+use crate::processors::mathml::MathMLConfig;
 use crate::processors::shortcodes::ShortcodesConfig;
 use crate::renderers::html::HtmlRenderer;
 use crate::renderers::notebook::NotebookRenderer;
-use crate::renderers::Renderer;
+use crate::renderers::{HtmlPolicy, Renderer};
 
 #[derive(Hash, Clone, Copy, Eq, PartialEq, Serialize, Deserialize, Debug)]
 #[serde(rename_all = "lowercase")]
@@ -26,13 +31,15 @@ pub enum OutputFormat {
     Notebook,
     Html,
     Info,
+    Pdf,
+    Epub,
 }
 
 impl InputFormat {
     pub fn loader(&self) -> Box<dyn Loader> {
         match self {
             InputFormat::Markdown => Box::new(MarkdownLoader),
-            InputFormat::Notebook => Box::new(NotebookLoader),
+            InputFormat::Notebook => Box::new(NotebookLoader::default()),
         }
     }
 
@@ -73,6 +80,8 @@ impl OutputFormat {
             OutputFormat::Notebook => false,
             OutputFormat::Html => false,
             OutputFormat::Info => true,
+            OutputFormat::Pdf => false,
+            OutputFormat::Epub => false,
         }
     }
 
@@ -80,6 +89,8 @@ impl OutputFormat {
         match ext {
             "ipynb" => Ok(OutputFormat::Notebook),
             "html" => Ok(OutputFormat::Html),
+            "pdf" => Ok(OutputFormat::Pdf),
+            "epub" => Ok(OutputFormat::Epub),
             _ => Err(anyhow!("Invalid extension for output")),
         }
     }
@@ -89,6 +100,8 @@ impl OutputFormat {
             "notebook" => Ok(OutputFormat::Notebook),
             "html" => Ok(OutputFormat::Html),
             "info" => Ok(OutputFormat::Info),
+            "pdf" => Ok(OutputFormat::Pdf),
+            "epub" => Ok(OutputFormat::Epub),
             _ => Err(anyhow!("Invalid format name for output")),
         }
     }
@@ -98,6 +111,8 @@ impl OutputFormat {
             OutputFormat::Notebook => "ipynb",
             OutputFormat::Html => "html",
             OutputFormat::Info => "yml",
+            OutputFormat::Pdf => "pdf",
+            OutputFormat::Epub => "epub",
         }
     }
 
@@ -106,6 +121,8 @@ impl OutputFormat {
             OutputFormat::Notebook => "md",
             OutputFormat::Html => "html",
             OutputFormat::Info => "yml",
+            OutputFormat::Pdf => "html",
+            OutputFormat::Epub => "html",
         }
     }
 
@@ -114,13 +131,17 @@ impl OutputFormat {
             OutputFormat::Notebook => "notebook",
             OutputFormat::Html => "html",
             OutputFormat::Info => "info",
+            OutputFormat::Pdf => "pdf",
+            OutputFormat::Epub => "epub",
         }
     }
 
     pub fn renderer(&self) -> Option<Box<dyn Renderer>> {
         match self {
             OutputFormat::Notebook => Some(Box::new(NotebookRenderer)),
-            OutputFormat::Html => Some(Box::new(HtmlRenderer)),
+            OutputFormat::Html | OutputFormat::Pdf | OutputFormat::Epub => {
+                Some(Box::new(HtmlRenderer))
+            }
             OutputFormat::Info => None,
         }
     }
@@ -141,11 +162,25 @@ impl Display for OutputFormat {
 #[allow(unused)]
 fn get_default_parser(_format: OutputFormat) -> Parser {
     Parser {
-        preprocessors: vec![Box::new(ShortcodesConfig), Box::new(KaTeXConfig)],
-        event_processors: vec![Box::new(ExercisesConfig)],
+        preprocessors: vec![
+            Box::new(ShortcodesConfig::default()),
+            #[cfg(feature = "katex")]
+            Box::new(KaTeXConfig::default()),
+            #[cfg(not(feature = "katex"))]
+            Box::new(MathMLConfig::default()),
+        ],
+        event_processors: vec![
+            Box::new(ExercisesConfig::default()),
+            Box::new(ListingsConfig),
+        ],
         settings: ParserSettings {
             solutions: false,
             notebook_outputs: false,
+            html_policy: HtmlPolicy::default(),
+            cell_prompts: false,
+            cell_languages: true,
+            numbering_scope: None,
+            output_fallback: OutputFallback::default(),
         },
     }
 }
@@ -4,23 +4,35 @@ use std::vec::IntoIter;
 
 use pulldown_cmark::CodeBlockKind::Fenced;
 use pulldown_cmark::Tag::CodeBlock;
-use pulldown_cmark::{CowStr, Event, OffsetIter, Options, Parser};
+use pulldown_cmark::{CowStr, Event, HeadingLevel, OffsetIter, Options, Parser};
 use serde::{Deserialize, Serialize};
-use thiserror::Error;
 
-use crate::ast::AEvent;
+use crate::ast::{AEvent, ATag};
 use crate::config::OutputFormat;
-use crate::notebook::{Cell, CellOutput, Notebook};
-use crate::processors::shortcodes::ShortCodeProcessError;
+use crate::notebook::{
+    magic_language, script_json, Cell, CellOutput, Notebook, OutputFallback, DEFAULT_LANGUAGE,
+};
 use crate::processors::MarkdownPreprocessor;
+use crate::renderers::notebook::heading_num;
+use crate::utils::HeadingIds;
 
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct DocumentMetadata {
     pub title: Option<String>,
+    /// Short description of the document, e.g. shown in the document list of its chapter index.
+    pub summary: Option<String>,
+    /// The kind of document. It determines the defaults of other options such as `code_split`.
+    #[serde(alias = "type")]
+    pub doc_type: Option<DocType>,
     pub code_split: Option<bool>,
     pub notebook_output: Option<bool>,
     pub code_solutions: Option<bool>,
+    /// Set to false to leave the document out of the section numbering (e.g. for a preface).
+    pub numbered: Option<bool>,
+    /// Set to false to keep quotes and dashes as written instead of converting them to
+    /// typographic ones (e.g. for ASCII art).
+    pub smart_punctuation: Option<bool>,
     #[serde(default)]
     pub layout: LayoutSettings,
 
@@ -28,6 +40,72 @@ pub struct DocumentMetadata {
     pub outputs: Vec<OutputFormat>,
 }
 
+impl DocumentMetadata {
+    /// Deserialize metadata from a (possibly merged) front matter value. An empty front matter
+    /// gives the default values.
+    pub fn from_front_matter(front_matter: serde_yaml::Value) -> Result<Self, serde_yaml::Error> {
+        match front_matter {
+            serde_yaml::Value::Null => serde_yaml::from_value(serde_yaml::Mapping::new().into()),
+            value => serde_yaml::from_value(value),
+        }
+    }
+
+    /// Whether the placeholder/solution syntax of code blocks should be parsed. An explicit
+    /// `code_split` option takes precedence over the default for the document type.
+    pub fn code_split_enabled(&self) -> bool {
+        self.code_split
+            .unwrap_or_else(|| self.doc_type.unwrap_or_default().code_split())
+    }
+}
+
+/// Document types. Each type maps to a set of defaults for the document options.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DocType {
+    /// Regular document. Code blocks are processed if the syntax is used.
+    #[default]
+    Document,
+    /// Plain text where code blocks are always shown as written.
+    Text,
+    /// Exercise with placeholder/solution code blocks.
+    Exercise,
+}
+
+impl DocType {
+    /// Default for the `code_split` option.
+    pub fn code_split(&self) -> bool {
+        match self {
+            DocType::Document => true,
+            DocType::Text => false,
+            DocType::Exercise => true,
+        }
+    }
+}
+
+/// Merge the `overlay` front matter over `base`. Mappings are merged recursively with the values
+/// in `overlay` taking precedence. All other values (scalars and sequences) in `overlay` replace
+/// the corresponding value in `base`. An empty overlay leaves `base` unchanged.
+pub fn merge_front_matter(
+    base: &serde_yaml::Value,
+    overlay: &serde_yaml::Value,
+) -> serde_yaml::Value {
+    match (base, overlay) {
+        (base, serde_yaml::Value::Null) => base.clone(),
+        (serde_yaml::Value::Mapping(base), serde_yaml::Value::Mapping(overlay)) => {
+            let mut merged = base.clone();
+            for (key, value) in overlay {
+                let value = match merged.get(key) {
+                    Some(existing) => merge_front_matter(existing, value),
+                    None => value.clone(),
+                };
+                merged.insert(key.clone(), value);
+            }
+            serde_yaml::Value::Mapping(merged)
+        }
+        (_, overlay) => overlay.clone(),
+    }
+}
+
 fn default_outputs() -> Vec<OutputFormat> {
     vec![
         OutputFormat::Notebook,
@@ -37,8 +115,12 @@ fn default_outputs() -> Vec<OutputFormat> {
 }
 
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct LayoutSettings {
     pub hide_sidebar: bool,
+    /// Leave the first level 1 heading out of the rendered content, for layouts that show the
+    /// document title above it.
+    pub hide_title_heading: bool,
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
@@ -55,38 +137,158 @@ pub type EventContent = Vec<(AEvent, DocPos)>;
 pub enum Element {
     Markdown {
         content: String,
+        /// Number of lines before the content in the source file (e.g. the front matter). It is
+        /// added to the lines of errors in the content so that they match the file.
+        line_offset: usize,
     },
     Code {
         cell_number: usize,
         content: String,
         output: Option<Vec<CellOutput>>,
+        /// Execution count of a notebook cell (shown in its prompt).
+        execution_count: Option<i64>,
+        /// Language of the cell from a cell magic (see [magic_language]) or the notebook kernel.
+        language: Option<String>,
     },
     Raw {
         content: String,
     },
+    /// The state of the interactive widgets of a notebook (see [Notebook::widget_state]).
+    WidgetState {
+        state: serde_json::Value,
+    },
     #[default]
     Default,
 }
 
-#[derive(Debug, Clone)]
+/// The position of an event in the source of a document. Positions are relative to the element
+/// (see [Element]) the event comes from: the content of a markdown document or a single notebook
+/// cell. Lines of markdown documents are counted from the start of the file (including the front
+/// matter).
+#[derive(Debug, Clone, Default)]
 pub struct DocPos {
+    /// Number of the notebook cell for events from code cells. None for markdown.
     cell_number: Option<usize>,
     #[allow(unused)]
     global_offset: usize,
+    /// Line where the event starts (starting at 1).
     line: usize,
-    #[allow(unused)]
+    /// Byte range of the event in the source of the element. Events that don't correspond to
+    /// source text (e.g. cell outputs) have an empty range.
     local_position: Range<usize>,
 }
 
 #[derive(Clone, Default, Debug, Serialize, Deserialize)]
 pub struct DocumentVariables {
     pub first_heading: Option<String>,
+    /// Number of placeholder/solution blocks found by the code split processor.
+    #[serde(default)]
+    pub exercises: usize,
+    /// The hints of the exercises found by the code split processor, in order.
+    #[serde(default)]
+    pub hints: Vec<String>,
+    /// Number of the part or chapter containing the document (e.g. `1.2`) if numbering is enabled.
+    #[serde(default)]
+    pub number: Option<String>,
+    /// The numbered headings of the document.
+    #[serde(default)]
+    pub sections: Vec<Section>,
+    /// Outline of the document (see [Document::headings]).
+    #[serde(default)]
+    pub headings: Vec<Heading>,
+    /// Problems found by the processors that don't prevent the document from being built.
+    #[serde(default)]
+    pub warnings: Vec<DocumentWarning>,
+}
+
+/// A position in the source of a document. Lines and columns start at 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourcePosition {
+    /// Notebook cell the line is in. None for markdown documents.
+    pub cell: Option<usize>,
+    pub line: usize,
+    pub column: Option<usize>,
+}
+
+impl Display for SourcePosition {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(cell) = self.cell {
+            write!(f, "cell {}, ", cell)?;
+        }
+        write!(f, "line {}", self.line)?;
+        if let Some(column) = self.column {
+            write!(f, ", column {}", column)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<&DocPos> for SourcePosition {
+    fn from(pos: &DocPos) -> Self {
+        SourcePosition {
+            cell: pos.cell_number,
+            line: pos.line,
+            column: None,
+        }
+    }
+}
+
+/// A problem in a document that doesn't prevent it from being built, e.g. an exercise with an
+/// empty placeholder.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DocumentWarning {
+    /// Where the problem is in the document, if known.
+    pub position: Option<SourcePosition>,
+    pub message: String,
 }
 
-#[derive(Error, Debug)]
-pub enum PreprocessError {
-    #[error(transparent)]
-    Shortcode(#[from] ShortCodeProcessError),
+/// A heading in the outline of a document.
+#[derive(Clone, Default, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Heading {
+    pub level: usize,
+    pub text: String,
+    /// Id of the heading in html output, which can be used as a link target.
+    pub slug: String,
+}
+
+/// The text of events without formatting (text and inline code).
+pub(crate) fn plain_text<'a>(events: impl Iterator<Item = &'a AEvent>) -> String {
+    events
+        .filter_map(|e| match e {
+            AEvent::Text(text) | AEvent::Code(text) => Some(text.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The HTML comment that ends the summary of a document.
+pub const SUMMARY_MARKER: &str = "<!--more-->";
+
+/// The text of the summary events as a single line. Headings and code blocks are left out.
+fn summary_text<'a>(events: impl Iterator<Item = &'a AEvent>) -> Option<String> {
+    let mut text = String::new();
+    let mut skip = false;
+    for event in events {
+        match event {
+            AEvent::Start(ATag::Heading(..) | ATag::CodeBlock(_)) => skip = true,
+            AEvent::End(ATag::Heading(..) | ATag::CodeBlock(_)) => skip = false,
+            AEvent::Text(t) | AEvent::Code(t) if !skip => text.push_str(t),
+            AEvent::SoftBreak | AEvent::HardBreak | AEvent::End(ATag::Paragraph) => text.push(' '),
+            _ => {}
+        }
+    }
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    (!text.is_empty()).then_some(text)
+}
+
+/// A numbered heading.
+#[derive(Clone, Default, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Section {
+    pub number: String,
+    pub title: String,
+    /// Id of the heading number, which can be used as a link target.
+    pub id: String,
+    pub level: usize,
 }
 
 impl Display for DocPos {
@@ -112,6 +314,18 @@ impl DocPos {
             local_position,
         }
     }
+
+    pub fn cell_number(&self) -> Option<usize> {
+        self.cell_number
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.local_position.clone()
+    }
 }
 
 impl Document<RawContent> {
@@ -119,17 +333,41 @@ impl Document<RawContent> {
         self,
         processor: &dyn MarkdownPreprocessor,
         ctx: &tera::Context,
-    ) -> Result<Document<RawContent>, anyhow::Error> {
+    ) -> Result<Document<RawContent>, crate::processors::Error> {
         let elements = self
             .content
             .iter()
             .map(|e| match e {
-                Element::Markdown { content } => Ok(Element::Markdown {
-                    content: processor.process(content, ctx)?,
+                Element::Markdown {
+                    content,
+                    line_offset,
+                } => Ok(Element::Markdown {
+                    content: processor
+                        .process(content, ctx)
+                        .map_err(|e| e.offset_lines(*line_offset))?,
+                    line_offset: *line_offset,
+                }),
+                Element::Code {
+                    cell_number,
+                    content,
+                    output: Some(output),
+                    execution_count,
+                    language,
+                } => Ok(Element::Code {
+                    cell_number: *cell_number,
+                    content: content.clone(),
+                    output: Some(
+                        output
+                            .iter()
+                            .map(|o| o.typeset_latex(|latex| processor.typeset_math(latex, true)))
+                            .collect::<Result<_, _>>()?,
+                    ),
+                    execution_count: *execution_count,
+                    language: language.clone(),
                 }),
                 _ => Ok(e.clone()),
             })
-            .collect::<Result<Vec<Element>, anyhow::Error>>()?;
+            .collect::<Result<Vec<Element>, crate::processors::Error>>()?;
         Ok(Document {
             content: elements,
             metadata: self.metadata,
@@ -137,6 +375,21 @@ impl Document<RawContent> {
         })
     }
 
+    /// The elements of the document: its markdown content or the cells of a notebook.
+    pub fn elements(&self) -> &[Element] {
+        &self.content
+    }
+
+    /// Transform each element of the document, e.g. to rewrite all code cells. The metadata and
+    /// variables are kept.
+    pub fn map_elements(self, f: impl Fn(&Element) -> Element) -> Document<RawContent> {
+        Document {
+            content: self.content.iter().map(f).collect(),
+            metadata: self.metadata,
+            variables: self.variables,
+        }
+    }
+
     pub(crate) fn new<C: IntoRawContent>(content: C, metadata: DocumentMetadata) -> Self {
         Document {
             metadata,
@@ -145,8 +398,15 @@ impl Document<RawContent> {
         }
     }
 
+    /// The events of the document with their positions. Notebook cells are converted to events as
+    /// configured, e.g. with or without their outputs. This is the input of the event processors
+    /// and renderers, so custom processors can be run over it.
+    pub fn events(&self, config: IteratorConfig) -> impl Iterator<Item = (Event<'_>, DocPos)> {
+        self.configure_iterator(config)
+    }
+
     pub fn to_events(&self, config: IteratorConfig) -> Document<EventContent> {
-        let content = self.configure_iterator(config).map(|(e, p)| (e.into(), p));
+        let content = self.events(config).map(|(e, p)| (e.into(), p));
         Document {
             metadata: self.metadata.clone(),
             variables: DocumentVariables::default(),
@@ -163,21 +423,126 @@ impl Document<EventContent> {
     pub fn to_events_with_pos(&self) -> impl Iterator<Item = (Event<'static>, DocPos)> {
         self.content.clone().into_iter().map(|(e, p)| (e.into(), p))
     }
+
+    /// The headings of the document in order, e.g. for a table of contents. The slugs are the ids
+    /// that the html renderer gives the headings.
+    pub fn headings(&self) -> Vec<Heading> {
+        let mut ids = HeadingIds::default();
+        let mut headings = Vec::new();
+        let mut start = None;
+        for (i, (event, _)) in self.content.iter().enumerate() {
+            match event {
+                AEvent::Start(ATag::Heading(..)) => start = Some(i + 1),
+                AEvent::End(ATag::Heading(level, id, _)) => {
+                    if let Some(start) = start.take() {
+                        let text = plain_text(self.content[start..i].iter().map(|(e, _)| e));
+                        headings.push(Heading {
+                            level: heading_num(*level),
+                            slug: ids.next(id.as_deref(), &text),
+                            text,
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+        headings
+    }
+
+    /// Remove the summary marker (see [SUMMARY_MARKER]) and return the summary of the document: the
+    /// text before the marker or, if there is none, the text of the first paragraph.
+    pub fn extract_summary(&mut self) -> Option<String> {
+        let is_marker =
+            |e: &AEvent| matches!(e, AEvent::Html(html) if html.trim() == SUMMARY_MARKER);
+        let summary = match self.content.iter().position(|(e, _)| is_marker(e)) {
+            Some(marker) => summary_text(self.content[..marker].iter().map(|(e, _)| e)),
+            None => {
+                let start = self
+                    .content
+                    .iter()
+                    .position(|(e, _)| matches!(e, AEvent::Start(ATag::Paragraph)));
+                start.and_then(|start| {
+                    summary_text(
+                        self.content[start..]
+                            .iter()
+                            .map(|(e, _)| e)
+                            .take_while(|e| !matches!(e, AEvent::End(ATag::Paragraph))),
+                    )
+                })
+            }
+        };
+        self.content.retain(|(e, _)| !is_marker(e));
+        summary
+    }
+
+    /// Remove the first level 1 heading. The remaining headings get the ids listed by
+    /// [Document::headings] so that they are the same as when the heading is kept.
+    pub fn remove_title_heading(&mut self) {
+        let start = self
+            .content
+            .iter()
+            .position(|(e, _)| matches!(e, AEvent::Start(ATag::Heading(HeadingLevel::H1, ..))));
+        let Some(start) = start else {
+            return;
+        };
+        let end = self.content[start..]
+            .iter()
+            .position(|(e, _)| matches!(e, AEvent::End(ATag::Heading(..))))
+            .map_or(self.content.len(), |end| start + end + 1);
+
+        let mut slugs = self.headings().into_iter().map(|h| h.slug);
+        let mut slug = None;
+        for (event, _) in self.content.iter_mut() {
+            match event {
+                AEvent::Start(ATag::Heading(_, id, _)) => {
+                    slug = slugs.next();
+                    if id.is_none() {
+                        *id = slug.clone();
+                    }
+                }
+                AEvent::End(ATag::Heading(_, id, _)) if id.is_none() => *id = slug.take(),
+                _ => {}
+            }
+        }
+        self.content.drain(start..end);
+    }
+
+    /// A document is empty if it contains no text other than whitespace. Text, inline code and
+    /// raw HTML count as text.
+    pub fn is_empty(&self) -> bool {
+        !self.content.iter().any(|(e, _)| match e {
+            AEvent::Text(text) | AEvent::Code(text) | AEvent::Html(text) => !text.trim().is_empty(),
+            _ => false,
+        })
+    }
 }
 
 pub trait IntoRawContent {
     fn into(self) -> RawContent;
 }
 
+impl IntoRawContent for RawContent {
+    fn into(self) -> RawContent {
+        self
+    }
+}
+
 impl IntoRawContent for String {
     fn into(self) -> RawContent {
-        vec![Element::Markdown { content: self }]
+        vec![Element::Markdown {
+            content: self,
+            line_offset: 0,
+        }]
     }
 }
 
 impl IntoRawContent for Notebook {
-    fn into(self) -> RawContent {
-        self.cells
+    fn into(mut self) -> RawContent {
+        self.remove_markdown_front_matter();
+        let widget_state = self.metadata.widget_state().cloned();
+        let kernel_language = self.metadata.language().map(str::to_string);
+        let mut content: RawContent = self
+            .cells
             .into_iter()
             .fold((1, Vec::new()), |(num, mut acc), cell| {
                 let next = match &cell {
@@ -192,24 +557,37 @@ impl IntoRawContent for Notebook {
             .map(|(i, cell)| match cell {
                 Cell::Markdown { common } => Element::Markdown {
                     content: common.source,
+                    line_offset: 0,
                 },
                 Cell::Code {
-                    common, outputs, ..
+                    common,
+                    outputs,
+                    execution_count,
                 } => Element::Code {
                     cell_number: i,
+                    language: magic_language(&common.source)
+                        .map(str::to_string)
+                        .or_else(|| kernel_language.clone()),
                     content: common.source,
                     output: Some(outputs),
+                    execution_count,
                 },
                 Cell::Raw { common } => Element::Raw {
                     content: common.source,
                 },
             })
-            .collect()
+            .collect();
+        if let Some(state) = widget_state {
+            content.push(Element::WidgetState { state });
+        }
+        content
     }
 }
 
 pub struct ElementIterator<'a, 'b> {
     global_offset: usize,
+    /// Lines before the source in the file (see [Element::Markdown]).
+    line_offset: usize,
     source: String,
     cell_iter: ElementIteratorCell<'a, 'b>,
 }
@@ -222,6 +600,10 @@ pub enum ElementIteratorCell<'a, 'b> {
         cell_number: usize,
         events: Box<IntoIter<(Event<'a>, Range<usize>)>>,
     },
+    /// Events generated for the document rather than parsed from an element.
+    Generated {
+        events: Box<IntoIter<(Event<'a>, Range<usize>)>>,
+    },
     Raw {},
 }
 
@@ -231,11 +613,11 @@ impl<'a, 'b> ElementIterator<'a, 'b> {
             ElementIteratorCell::Code { cell_number, .. } => Some(*cell_number),
             _ => None,
         };
-        let line = &self.source[elem.1.start..elem.1.end].lines().count();
+        let line = self.source[..elem.1.start].matches('\n').count() + 1 + self.line_offset;
 
         (
             elem.0,
-            DocPos::new(cell_num, self.global_offset, *line, elem.1),
+            DocPos::new(cell_num, self.global_offset, line, elem.1),
         )
     }
 }
@@ -249,35 +631,82 @@ impl<'a, 'b> Iterator for ElementIterator<'a, 'b> {
                 parser.next().map(|e| self.map_doc_pos(e))
             }
             ElementIteratorCell::Code { events, .. } => events.next().map(|e| self.map_doc_pos(e)),
+            ElementIteratorCell::Generated { events } => events.next().map(|e| self.map_doc_pos(e)),
             ElementIteratorCell::Raw { .. } => None,
         }
     }
 }
 
-#[derive(Default, Copy, Clone)]
+#[derive(Copy, Clone)]
 pub struct IteratorConfig {
     pub include_output: bool,
-    pub include_solutions: bool,
+    /// Wrap code cells in HTML with Jupyter-like `In [n]:` and `Out [n]:` prompts.
+    pub cell_prompts: bool,
+    /// Embed interactive widgets in HTML. Only used if the widget state is saved in the notebook.
+    pub widgets: bool,
+    /// Use the language of code cells (see [Element::Code]) for their code blocks instead of
+    /// Python, and show `%%html` cells without outputs as HTML.
+    pub cell_languages: bool,
+    /// What is shown for outputs that can't be rendered.
+    pub output_fallback: OutputFallback,
+    /// Convert quotes and dashes in markdown to typographic ones.
+    pub smart_punctuation: bool,
+}
+
+impl Default for IteratorConfig {
+    fn default() -> Self {
+        IteratorConfig {
+            include_output: false,
+            cell_prompts: false,
+            widgets: false,
+            cell_languages: true,
+            output_fallback: OutputFallback::default(),
+            smart_punctuation: true,
+        }
+    }
 }
 
 impl IteratorConfig {
+    /// The options of the markdown parser.
+    pub fn markdown_options(&self) -> Options {
+        let mut options = Options::all();
+        options.set(Options::ENABLE_SMART_PUNCTUATION, self.smart_punctuation);
+        options
+    }
+
     #[allow(unused)]
     pub fn include_output(self) -> Self {
         IteratorConfig {
             include_output: true,
-            include_solutions: self.include_solutions,
+            ..self
         }
     }
 
     #[allow(unused)]
-    pub fn include_solutions(self) -> Self {
+    pub fn cell_prompts(self) -> Self {
         IteratorConfig {
-            include_output: self.include_output,
-            include_solutions: true,
+            cell_prompts: true,
+            ..self
         }
     }
 }
 
+/// The script that displays the widgets embedded in HTML.
+const WIDGET_MANAGER_URL: &str =
+    "https://cdn.jsdelivr.net/npm/@jupyter-widgets/html-manager@^1.0.0/dist/embed.js";
+
+/// The prompt shown in the gutter of a code cell, e.g. `In [3]:`. Cells that have not been
+/// executed get an empty count (`In [ ]:`) and outputs without a result an empty prompt.
+fn cell_prompt(label: &str, execution_count: Option<i64>) -> String {
+    let count = execution_count.map_or(" ".to_string(), |c| c.to_string());
+    let text = if label.is_empty() {
+        String::new()
+    } else {
+        format!("{}&nbsp;[{}]:", label, count)
+    };
+    format!("<div class=\"prompt\">{}</div>", text)
+}
+
 pub trait ConfigureCollector {
     type Item;
     type IntoIter;
@@ -297,10 +726,16 @@ impl<'a> ConfigureCollector for &'a Element {
     type IntoIter = ElementIterator<'a, 'a>;
 
     fn configure_iterator(self, config: IteratorConfig) -> Self::IntoIter {
+        let line_offset = match self {
+            Element::Markdown { line_offset, .. } => *line_offset,
+            _ => 0,
+        };
         let (cell, content) = match self {
-            Element::Markdown { content } => (
+            Element::Markdown { content, .. } => (
                 ElementIteratorCell::Markdown {
-                    parser: Box::new(Parser::new_ext(content, Options::all()).into_offset_iter()),
+                    parser: Box::new(
+                        Parser::new_ext(content, config.markdown_options()).into_offset_iter(),
+                    ),
                 },
                 content.clone(),
             ),
@@ -309,20 +744,73 @@ impl<'a> ConfigureCollector for &'a Element {
                 cell_number,
                 content,
                 output: outputs,
+                execution_count,
+                language,
             } => {
-                let cblock = CodeBlock(Fenced(CowStr::Boxed("python".into())));
+                let language = language
+                    .as_deref()
+                    .filter(|_| config.cell_languages)
+                    .unwrap_or(DEFAULT_LANGUAGE);
+                let cblock = CodeBlock(Fenced(CowStr::Boxed(language.into())));
                 let mut events = vec![
                     (Event::Start(cblock.clone()), (0..0)),
                     (Event::Text(CowStr::Borrowed(content)), (0..content.len())),
                     (Event::End(cblock), (content.len()..content.len())),
                 ];
-                if config.include_output {
-                    if let Some(os) = outputs {
-                        for o in os {
-                            events.append(&mut o.to_events());
-                        }
+                let outputs = outputs
+                    .as_ref()
+                    .filter(|os| config.include_output && !os.is_empty());
+                let html =
+                    |html: String| (Event::Html(CowStr::Boxed(html.into_boxed_str())), (0..0));
+                // Jupyter displays the content of `%%html` cells, which is done here for cells
+                // that have not been executed.
+                let html_cell = (config.cell_languages
+                    && config.include_output
+                    && outputs.is_none()
+                    && magic_language(content) == Some("html"))
+                .then(|| {
+                    let body = content.trim_start().split_once('\n').map_or("", |(_, b)| b);
+                    html(format!("{}\n", body))
+                });
+
+                if config.cell_prompts {
+                    events.insert(
+                        0,
+                        html(format!(
+                            "<div class=\"cell code-cell\">\n<div class=\"cell-input\">{}<div class=\"cell-body\">\n",
+                            cell_prompt("In", *execution_count)
+                        )),
+                    );
+                    events.push(html("</div>\n</div>\n".to_string()));
+                }
+                if outputs.is_some() || html_cell.is_some() {
+                    if config.cell_prompts {
+                        let result = outputs.into_iter().flatten().any(|o| {
+                            matches!(
+                                o,
+                                CellOutput::Data {
+                                    execution_count: Some(_),
+                                    ..
+                                }
+                            )
+                        });
+                        let label = if result { "Out" } else { "" };
+                        events.push(html(format!(
+                            "<div class=\"cell-output\">{}<div class=\"cell-body\">\n",
+                            cell_prompt(label, *execution_count)
+                        )));
+                    }
+                    for o in outputs.into_iter().flatten() {
+                        events.append(&mut o.to_events_with(config));
+                    }
+                    events.extend(html_cell);
+                    if config.cell_prompts {
+                        events.push(html("</div>\n</div>\n".to_string()));
                     }
                 }
+                if config.cell_prompts {
+                    events.push(html("</div>\n".to_string()));
+                }
 
                 (
                     ElementIteratorCell::Code {
@@ -333,11 +821,32 @@ impl<'a> ConfigureCollector for &'a Element {
                 )
             }
             Element::Raw { content } => (ElementIteratorCell::Raw {}, content.clone()),
+            Element::WidgetState { state } if config.widgets => {
+                let html =
+                    |html: String| (Event::Html(CowStr::Boxed(html.into_boxed_str())), (0..0));
+                let events = vec![
+                    html(format!(
+                        "<script type=\"application/vnd.jupyter.widget-state+json\">{}</script>\n",
+                        script_json(state)
+                    )),
+                    html(format!(
+                        "<script src=\"{}\" crossorigin=\"anonymous\"></script>\n",
+                        WIDGET_MANAGER_URL
+                    )),
+                ];
+                (
+                    ElementIteratorCell::Generated {
+                        events: Box::new(events.into_iter()),
+                    },
+                    String::new(),
+                )
+            }
             _ => (ElementIteratorCell::Raw {}, "".to_string()),
         };
         ElementIterator {
             source: content,
             global_offset: 0,
+            line_offset,
             cell_iter: cell,
         }
     }
@@ -348,6 +857,14 @@ impl<'a> ConfigureCollector for &'a Document<RawContent> {
     type IntoIter = Box<dyn Iterator<Item = Self::Item> + 'a>;
 
     fn configure_iterator(self, config: IteratorConfig) -> Self::IntoIter {
+        let config = IteratorConfig {
+            widgets: config.widgets
+                && self
+                    .content
+                    .iter()
+                    .any(|e| matches!(e, Element::WidgetState { .. })),
+            ..config
+        };
         Box::new(
             self.content
                 .iter()
@@ -355,3 +872,231 @@ impl<'a> ConfigureCollector for &'a Document<RawContent> {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::ACodeBlockKind;
+    use crate::loader::{Loader, NotebookLoader};
+
+    #[test]
+    fn test_event_positions() {
+        let source = "# Title\n\nText\n\n```python\nx = 1\n```\n";
+        let doc = Document::<RawContent>::new(source.to_string(), DocumentMetadata::default());
+        let (_, pos) = doc
+            .events(IteratorConfig::default())
+            .find(|(e, _)| matches!(e, Event::Text(t) if t.as_ref() == "x = 1\n"))
+            .unwrap();
+
+        assert_eq!(pos.cell_number(), None);
+        assert_eq!(pos.line(), 6);
+        assert_eq!(&source[pos.range()], "x = 1\n");
+    }
+
+    #[test]
+    fn test_notebook_event_positions() {
+        let json = r#"{
+            "metadata": {},
+            "nbformat": 4,
+            "nbformat_minor": 5,
+            "cells": [
+                {"cell_type": "markdown", "metadata": {}, "source": ["Intro"]},
+                {"cell_type": "code", "metadata": {}, "execution_count": null, "outputs": [],
+                 "source": ["a = 1\n", "b = 2"]}
+            ]
+        }"#;
+        let doc = NotebookLoader::default().load(json).unwrap();
+        let positions: Vec<DocPos> = doc
+            .events(IteratorConfig::default())
+            .filter(|(e, _)| matches!(e, Event::Text(_)))
+            .map(|(_, pos)| pos)
+            .collect();
+
+        assert_eq!(positions[0].cell_number(), None);
+        assert!(positions[1].cell_number().is_some());
+        assert_eq!(positions[1].line(), 1);
+    }
+
+    #[test]
+    fn test_cell_languages() {
+        let doc = NotebookLoader::default()
+            .load(
+                r#"{"metadata": {"language_info": {"name": "julia"}}, "nbformat": 4, "nbformat_minor": 5,
+                "cells": [
+                    {"cell_type": "code", "metadata": {}, "execution_count": 1, "outputs": [],
+                     "source": ["%%bash\n", "ls -l"]},
+                    {"cell_type": "code", "metadata": {}, "execution_count": null, "outputs": [],
+                     "source": ["%%html\n", "<b>bold</b>"]},
+                    {"cell_type": "code", "metadata": {}, "execution_count": 2, "outputs": [],
+                     "source": ["%%time\n", "x = 1"]}
+                ]}"#,
+            )
+            .unwrap();
+        let events = |config: IteratorConfig| {
+            doc.to_events(config)
+                .content
+                .into_iter()
+                .filter_map(|(e, _)| match e {
+                    AEvent::Start(ATag::CodeBlock(ACodeBlockKind::Fenced(lang))) => Some(lang),
+                    AEvent::Html(html) => Some(html),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            events(IteratorConfig::default().include_output()),
+            vec!["bash", "html", "<b>bold</b>\n", "julia"]
+        );
+        // Without outputs, the html cell is only shown as code.
+        assert_eq!(
+            events(IteratorConfig::default()),
+            vec!["bash", "html", "julia"]
+        );
+        let python = IteratorConfig {
+            cell_languages: false,
+            ..Default::default()
+        };
+        assert_eq!(
+            events(python.include_output()),
+            vec!["python", "python", "python"]
+        );
+    }
+
+    #[test]
+    fn test_widget_state() {
+        let notebook = |metadata: &str| {
+            let json = format!(
+                r#"{{
+                "metadata": {},
+                "nbformat": 4,
+                "nbformat_minor": 5,
+                "cells": [
+                    {{"cell_type": "code", "metadata": {{}}, "execution_count": 1, "source": ["w"],
+                     "outputs": [{{"output_type": "display_data", "metadata": {{}},
+                                  "data": {{"application/vnd.jupyter.widget-view+json": {{"model_id": "abc"}}}}}}]}}
+                ]
+            }}"#,
+                metadata
+            );
+            NotebookLoader::default().load(&json).unwrap()
+        };
+        let html = |doc: &Document<RawContent>, config: IteratorConfig| {
+            doc.events(config)
+                .filter_map(|(e, _)| match e {
+                    Event::Html(html) => Some(html.to_string()),
+                    _ => None,
+                })
+                .collect::<String>()
+        };
+        let config = IteratorConfig {
+            widgets: true,
+            ..IteratorConfig::default().include_output()
+        };
+
+        let doc = notebook(
+            r#"{"widgets": {"application/vnd.jupyter.widget-state+json":
+                {"state": {"abc": {"model_name": "</script>"}}, "version_major": 2}}}"#,
+        );
+        let embedded = html(&doc, config);
+        assert!(embedded.contains("<script type=\"application/vnd.jupyter.widget-view+json\">"));
+        assert!(embedded.contains("<script type=\"application/vnd.jupyter.widget-state+json\">"));
+        assert!(embedded.contains(r#""model_name":"<\/script>""#));
+        assert!(embedded.contains(WIDGET_MANAGER_URL));
+        assert!(html(&doc, IteratorConfig::default().include_output()).is_empty());
+
+        // Widgets can't be displayed without their state.
+        let doc = notebook("{}");
+        assert!(html(&doc, config).is_empty());
+    }
+
+    #[test]
+    fn test_extract_summary() {
+        let events = |source: &str| {
+            Document::<RawContent>::new(source.to_string(), DocumentMetadata::default())
+                .to_events(IteratorConfig::default())
+        };
+
+        let mut doc =
+            events("# Title\n\nIntro *text*\nmore.\n\n```\ncode\n```\n\n<!--more-->\n\nBody.\n");
+        assert_eq!(doc.extract_summary().as_deref(), Some("Intro text more."));
+        assert!(!doc
+            .content
+            .iter()
+            .any(|(e, _)| matches!(e, AEvent::Html(html) if html.contains("more"))));
+
+        let mut doc = events("# Title\n\n- item\n\nFirst paragraph.\n\nSecond.\n");
+        // Items of tight lists are not paragraphs.
+        assert_eq!(doc.extract_summary().as_deref(), Some("First paragraph."));
+
+        let mut doc = events("Inline marker <!--more--> here.\n");
+        assert_eq!(doc.extract_summary().as_deref(), Some("Inline marker"));
+
+        let mut doc = events("# Only a title\n");
+        assert_eq!(doc.extract_summary(), None);
+    }
+
+    #[test]
+    fn test_map_elements() {
+        let notebook: Notebook = serde_json::from_str(
+            r#"{"metadata": {}, "nbformat": 4, "nbformat_minor": 5, "cells": [
+                {"cell_type": "markdown", "metadata": {}, "source": ["Text"]},
+                {"cell_type": "code", "metadata": {}, "execution_count": null, "outputs": [],
+                 "source": ["fn main() {}"]}
+            ]}"#,
+        )
+        .unwrap();
+        let doc = Document::new(notebook, DocumentMetadata::default());
+        assert_eq!(doc.elements().len(), 2);
+
+        let doc = doc.map_elements(|element| match element {
+            Element::Code { content, .. } => Element::Markdown {
+                content: format!("```rust\n{}\n```\n", content),
+                line_offset: 0,
+            },
+            other => other.clone(),
+        });
+        let events = doc.to_events(IteratorConfig::default()).content;
+        assert!(events.iter().any(|(e, _)| matches!(
+            e,
+            AEvent::Start(ATag::CodeBlock(ACodeBlockKind::Fenced(lang))) if lang == "rust"
+        )));
+        assert!(events
+            .iter()
+            .any(|(e, _)| matches!(e, AEvent::Text(text) if text == "Text")));
+    }
+
+    #[test]
+    fn test_remove_title_heading() {
+        let mut doc = Document::<RawContent>::new(
+            "Intro.\n\n# Intro\n\nText.\n\n## Intro\n".to_string(),
+            DocumentMetadata::default(),
+        )
+        .to_events(IteratorConfig::default());
+        let headings = doc.headings();
+        doc.remove_title_heading();
+
+        let ids: Vec<_> = doc
+            .content
+            .iter()
+            .filter_map(|(e, _)| match e {
+                AEvent::End(ATag::Heading(_, id, _)) => Some(id.clone()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(ids, vec![Some("intro-1".to_string())]);
+        assert_eq!(headings.len(), 2);
+        assert!(doc
+            .content
+            .iter()
+            .any(|(e, _)| matches!(e, AEvent::Text(text) if text == "Text.")));
+
+        // Nothing to remove without a level 1 heading.
+        let mut doc =
+            Document::<RawContent>::new("## Sub\n".to_string(), DocumentMetadata::default())
+                .to_events(IteratorConfig::default());
+        let len = doc.content.len();
+        doc.remove_title_heading();
+        assert_eq!(doc.content.len(), len);
+    }
+}
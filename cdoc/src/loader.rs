@@ -1,11 +1,13 @@
 use std::fmt::Debug;
 
 use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
 
 use anyhow::{anyhow, Context};
 use thiserror::Error;
+use tracing::debug;
 
-use crate::document::{Document, DocumentMetadata, RawContent};
+use crate::document::{merge_front_matter, Document, DocumentMetadata, Element, RawContent};
 use crate::notebook::Notebook;
 
 #[derive(Error, Debug)]
@@ -22,22 +24,63 @@ pub enum LoaderError {
 #[typetag::serde(tag = "type")]
 pub trait Loader: Debug {
     /// Perform any parsing/conversion necessary.
-    fn load(&self, input: &str) -> anyhow::Result<Document<RawContent>>;
+    fn load(&self, input: &str) -> anyhow::Result<Document<RawContent>> {
+        self.load_with_defaults(input, &Value::Null)
+    }
+
+    /// Like [Loader::load] but the document's front matter is merged over the given defaults
+    /// (see [merge_front_matter]) before it is parsed.
+    fn load_with_defaults(
+        &self,
+        input: &str,
+        defaults: &Value,
+    ) -> anyhow::Result<Document<RawContent>>;
+
+    /// Read the unparsed front matter of the input. Returns [Value::Null] if there is none.
+    fn front_matter(&self, input: &str) -> anyhow::Result<Value>;
 }
 
 /// Parses a Jupyter Notebook file (.ipynb).
-#[derive(Serialize, Deserialize, Debug)]
-pub struct NotebookLoader;
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct NotebookLoader {
+    /// Remove the outputs of code cells that have not been executed (see
+    /// [Notebook::clear_stale_outputs]).
+    #[serde(default)]
+    pub clear_stale_outputs: bool,
+}
 
 #[typetag::serde(name = "notebook_loader")]
 impl Loader for NotebookLoader {
-    fn load(&self, input: &str) -> anyhow::Result<Document<RawContent>> {
-        let nb: Notebook = serde_json::from_str(input)?;
-        let meta = nb
-            .get_front_matter()
-            .context("Failed to read front matter")?;
+    fn load_with_defaults(
+        &self,
+        input: &str,
+        defaults: &Value,
+    ) -> anyhow::Result<Document<RawContent>> {
+        debug!("parsing notebook");
+        let mut nb: Notebook = serde_json::from_str(input)?;
+        if self.clear_stale_outputs {
+            let cleared = nb.clear_stale_outputs();
+            debug!("cleared the outputs of {} unexecuted cells", cleared);
+        }
+        let meta = match nb.get_front_matter_value() {
+            Ok(None) if defaults.is_null() => Ok(DocumentMetadata::default()),
+            Ok(front_matter) => DocumentMetadata::from_front_matter(merge_front_matter(
+                defaults,
+                &front_matter.unwrap_or(Value::Null),
+            )),
+            Err(e) => Err(e),
+        }
+        .context("Failed to read front matter")?;
         Ok(Document::new(nb, meta))
     }
+
+    fn front_matter(&self, input: &str) -> anyhow::Result<Value> {
+        let nb: Notebook = serde_json::from_str(input)?;
+        Ok(nb
+            .get_front_matter_value()
+            .context("Failed to read front matter")?
+            .unwrap_or(Value::Null))
+    }
 }
 
 /// Loads a markdown document. It reads the yml frontmatter and creates the document from the remaining input.
@@ -46,10 +89,47 @@ pub struct MarkdownLoader;
 
 #[typetag::serde(name = "markdown_loader")]
 impl Loader for MarkdownLoader {
-    fn load(&self, input: &str) -> anyhow::Result<Document<RawContent>> {
+    fn load_with_defaults(
+        &self,
+        input: &str,
+        defaults: &Value,
+    ) -> anyhow::Result<Document<RawContent>> {
         // let yml: yaml_front_matter::Document<DocumentMetadata> =
         //     // YamlFrontMatter::parse(input).map_err(|_e| anyhow!("Could not parse front matter"))?;
         //     YamlFrontMatter::parse(input)?;
+        // An empty file is an empty document without front matter.
+        if input.trim().is_empty() {
+            let meta = DocumentMetadata::from_front_matter(defaults.clone())
+                .context("Could not parse frontmatter")?;
+            return Ok(Document::new(String::new(), meta));
+        }
+
+        debug!("parsing markdown front matter");
+        let (start, end) = MarkdownLoader::front_matter_range(input)?;
+
+        let front_matter: Value =
+            serde_yaml::from_str(&input[start..end]).context("Could not parse frontmatter")?;
+        let meta = DocumentMetadata::from_front_matter(merge_front_matter(defaults, &front_matter))
+            .context("Could not parse frontmatter")?;
+        let content = vec![Element::Markdown {
+            content: input[end + 3..].to_string(),
+            line_offset: input[..end + 3].matches('\n').count(),
+        }];
+        Ok(Document::new(content, meta))
+    }
+
+    fn front_matter(&self, input: &str) -> anyhow::Result<Value> {
+        if input.trim().is_empty() {
+            return Ok(Value::Null);
+        }
+        let (start, end) = MarkdownLoader::front_matter_range(input)?;
+        serde_yaml::from_str(&input[start..end]).context("Could not parse frontmatter")
+    }
+}
+
+impl MarkdownLoader {
+    /// Byte range of the front matter between the `---` delimiters.
+    fn front_matter_range(input: &str) -> anyhow::Result<(usize, usize)> {
         let start = input
             .find("---")
             .ok_or_else(|| anyhow!("Missing frontmatter specifier"))?;
@@ -58,16 +138,35 @@ impl Loader for MarkdownLoader {
             + input[start + 3..]
                 .find("---")
                 .ok_or_else(|| anyhow!("Missing frontmatter specifier"))?;
-
-        let meta: DocumentMetadata =
-            serde_yaml::from_str(&input[start + 3..end]).context("Could not parse frontmatter")?;
-        Ok(Document::new(input[end + 3..].to_string(), meta))
+        Ok((start + 3, end))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    // use super::*;
+    use super::*;
+    use crate::document::Element;
+
+    #[test]
+    fn test_load_with_defaults() {
+        let defaults: Value = serde_yaml::from_str(
+            "code_split: false\nnotebook_output: false\nlayout:\n  hide_sidebar: true",
+        )
+        .unwrap();
+        let input = "---\ntitle: Doc\nnotebook_output: true\n---\n# Doc";
+
+        let doc = MarkdownLoader.load_with_defaults(input, &defaults).unwrap();
+        assert_eq!(doc.metadata.title, Some("Doc".to_string()));
+        // Absent values are inherited and explicit values win
+        assert_eq!(doc.metadata.code_split, Some(false));
+        assert_eq!(doc.metadata.notebook_output, Some(true));
+        assert!(doc.metadata.layout.hide_sidebar);
+
+        let doc = MarkdownLoader.load(input).unwrap();
+        assert_eq!(doc.metadata.code_split, None);
+        assert!(!doc.metadata.layout.hide_sidebar);
+    }
+
     //
     // #[test]
     // fn test_deserialization() {
@@ -80,4 +179,69 @@ mod tests {
     //
     //     let p: LoaderConfig = serde_json::from_str(config).unwrap();
     // }
+
+    #[test]
+    fn test_clear_stale_outputs() {
+        let input = r#"{
+            "metadata": {},
+            "nbformat": 4,
+            "nbformat_minor": 5,
+            "cells": [
+                {"cell_type": "code", "metadata": {}, "source": ["print(1)"], "execution_count": null,
+                 "outputs": [{"output_type": "stream", "name": "stdout", "text": ["1"]}]},
+                {"cell_type": "code", "metadata": {}, "source": ["print(2)"], "execution_count": 1,
+                 "outputs": [{"output_type": "stream", "name": "stdout", "text": ["2"]}]}
+            ]
+        }"#;
+        let output_counts = |loader: NotebookLoader| {
+            let doc = loader.load(input).unwrap();
+            doc.content
+                .iter()
+                .filter_map(|elem| match elem {
+                    Element::Code { output, .. } => Some(output.as_ref().map_or(0, Vec::len)),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(output_counts(NotebookLoader::default()), vec![1, 1]);
+        let loader = NotebookLoader {
+            clear_stale_outputs: true,
+        };
+        assert_eq!(output_counts(loader), vec![0, 1]);
+    }
+
+    #[test]
+    fn test_notebook_front_matter() {
+        let notebook = |cells: &str| {
+            format!(
+                r#"{{"metadata": {{}}, "nbformat": 4, "nbformat_minor": 5, "cells": [{}]}}"#,
+                cells
+            )
+        };
+
+        let doc = NotebookLoader::default().load(&notebook("")).unwrap();
+        assert!(doc.metadata.title.is_none());
+        assert!(doc.content.is_empty());
+
+        let input = notebook(
+            r##"{"cell_type": "markdown", "metadata": {},
+                "source": ["---\n", "title: Notebook\n", "---\n", "# Intro"]}"##,
+        );
+        let doc = NotebookLoader::default().load(&input).unwrap();
+        assert_eq!(doc.metadata.title.as_deref(), Some("Notebook"));
+        assert!(
+            matches!(&doc.content[0], Element::Markdown { content, .. } if content == "# Intro")
+        );
+
+        // A horizontal rule later in the cell is not front matter.
+        let input = notebook(
+            r##"{"cell_type": "markdown", "metadata": {}, "source": ["# Intro\n", "---\n", "text"]}"##,
+        );
+        let doc = NotebookLoader::default().load(&input).unwrap();
+        assert!(doc.metadata.title.is_none());
+        assert!(
+            matches!(&doc.content[0], Element::Markdown { content, .. } if content.starts_with("# Intro"))
+        );
+    }
 }
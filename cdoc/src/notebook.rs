@@ -1,18 +1,19 @@
-use crate::document::DocumentMetadata;
+use crate::document::{DocumentMetadata, IteratorConfig};
 use crate::parsers::split::parse_code_string;
 use crate::parsers::split_types::Output;
+use crate::utils::strip_ansi;
 use base64;
+use pulldown_cmark::escape::escape_html;
 use pulldown_cmark::CodeBlockKind::Fenced;
 use pulldown_cmark::Tag::CodeBlock;
 use pulldown_cmark::{CowStr, Event, OffsetIter, Options, Parser, Tag};
-use serde::de::Error;
+use serde::de::{Error, MapAccess, Visitor};
+use serde::ser::{Error as _, SerializeMap};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde_json::Value;
-use serde_with::EnumMap;
 use std::collections::HashMap;
-use std::iter::FlatMap;
+use std::fmt;
 use std::ops::Range;
-use std::slice::Iter;
 use std::vec::IntoIter;
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
@@ -57,7 +58,6 @@ pub struct CellCommon {
     pub source: String,
 }
 
-#[serde_with::serde_as]
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "output_type")]
 pub enum CellOutput {
@@ -73,7 +73,10 @@ pub enum CellOutput {
     #[serde(rename = "display_data", alias = "execute_result")]
     Data {
         execution_count: Option<i64>,
-        #[serde_as(as = "EnumMap")]
+        #[serde(
+            deserialize_with = "deserialize_output_data",
+            serialize_with = "serialize_output_data"
+        )]
         data: Vec<OutputValue>,
         metadata: HashMap<String, Value>,
     },
@@ -97,6 +100,10 @@ pub enum OutputValue {
     ),
     #[serde(rename = "image/png")]
     Image(String),
+    #[serde(rename = "image/jpeg")]
+    Jpeg(String),
+    #[serde(rename = "image/gif")]
+    Gif(String),
     #[serde(rename = "image/svg+xml")]
     Svg(String),
     #[serde(rename = "application/json")]
@@ -111,6 +118,41 @@ pub enum OutputValue {
     ),
     #[serde(rename = "application/javascript")]
     Javascript(String),
+    /// View of an interactive widget (ipywidgets). The model it displays is part of the widget
+    /// state in the notebook metadata.
+    #[serde(rename = "application/vnd.jupyter.widget-view+json")]
+    Widget(Value),
+    #[serde(rename = "text/latex")]
+    Latex(
+        #[serde(
+            deserialize_with = "concatenate_deserialize",
+            serialize_with = "concatenate_serialize"
+        )]
+        String,
+    ),
+    #[serde(rename = "text/markdown")]
+    Markdown(
+        #[serde(
+            deserialize_with = "concatenate_deserialize",
+            serialize_with = "concatenate_serialize"
+        )]
+        String,
+    ),
+    /// A MIME type that can't be rendered (or data that can't be read), kept as is so that it is
+    /// written back unchanged.
+    #[serde(skip)]
+    Unsupported(String, Value),
+}
+
+/// What is shown for notebook outputs without any representation that can be rendered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFallback {
+    /// The `text/plain` representation if the output has one, otherwise a note.
+    #[default]
+    Plain,
+    /// A note with the MIME types of the output, e.g. `[output: application/x-foo]`.
+    Note,
 }
 
 type Dict = HashMap<String, Value>;
@@ -134,15 +176,173 @@ pub struct CellMeta {
     additional: Dict,
 }
 
+impl NotebookMeta {
+    /// The kernel the notebook was written for.
+    pub fn kernelspec(&self) -> Option<&HashMap<String, Value>> {
+        self.kernelspec.as_ref()
+    }
+
+    /// Any other notebook metadata (e.g. `language_info`).
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.optional.get(key)
+    }
+
+    /// The programming language of the notebook, from `language_info` or the kernelspec.
+    pub fn language(&self) -> Option<&str> {
+        self.get("language_info")
+            .and_then(|info| info.get("name"))
+            .or_else(|| self.kernelspec.as_ref()?.get("language"))
+            .and_then(Value::as_str)
+    }
+
+    /// The state of the interactive widgets, saved by Jupyter with "Save Widget State
+    /// Automatically". Widget outputs can only be displayed with it.
+    pub fn widget_state(&self) -> Option<&Value> {
+        self.get("widgets")?
+            .get("application/vnd.jupyter.widget-state+json")
+    }
+}
+
+impl CellMeta {
+    pub fn collapsed(&self) -> Option<bool> {
+        self.collapsed
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// The tags of the cell (empty if it has none).
+    pub fn tags(&self) -> &[String] {
+        self.tags.as_deref().unwrap_or_default()
+    }
+
+    /// Any other cell metadata.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.additional.get(key)
+    }
+}
+
+impl Cell {
+    /// The Jupyter cell type (`markdown`, `code` or `raw`).
+    pub fn cell_type(&self) -> &str {
+        match self {
+            Cell::Markdown { .. } => "markdown",
+            Cell::Code { .. } => "code",
+            Cell::Raw { .. } => "raw",
+        }
+    }
+
+    pub fn common(&self) -> &CellCommon {
+        match self {
+            Cell::Markdown { common } | Cell::Code { common, .. } | Cell::Raw { common } => common,
+        }
+    }
+
+    pub fn source(&self) -> &str {
+        &self.common().source
+    }
+
+    pub fn metadata(&self) -> &CellMeta {
+        &self.common().metadata
+    }
+
+    /// The outputs of a code cell (empty for other cells).
+    pub fn outputs(&self) -> &[CellOutput] {
+        match self {
+            Cell::Code { outputs, .. } => outputs,
+            _ => &[],
+        }
+    }
+
+    /// The execution count of a code cell (None for other cells and cells that were not run).
+    pub fn execution_count(&self) -> Option<i64> {
+        match self {
+            Cell::Code {
+                execution_count, ..
+            } => *execution_count,
+            _ => None,
+        }
+    }
+}
+
 impl Notebook {
+    /// The cells of the notebook in order. Together with the accessors of [Cell], they allow
+    /// custom renderers to work with the structure of the notebook instead of an event stream.
+    pub fn cells(&self) -> &[Cell] {
+        &self.cells
+    }
+
+    pub fn metadata(&self) -> &NotebookMeta {
+        &self.metadata
+    }
+
+    /// The notebook format version (major and minor).
+    pub fn nbformat(&self) -> (i64, i64) {
+        (self.nbformat, self.nbformat_minor)
+    }
+
+    /// Remove the outputs of code cells that have not been executed (`execution_count` is null).
+    /// Such outputs are left over from an earlier run and don't match the code. Returns the number
+    /// of cells that had outputs removed.
+    pub fn clear_stale_outputs(&mut self) -> usize {
+        let mut cleared = 0;
+        for cell in &mut self.cells {
+            if let Cell::Code {
+                execution_count: None,
+                outputs,
+                ..
+            } = cell
+            {
+                if !outputs.is_empty() {
+                    outputs.clear();
+                    cleared += 1;
+                }
+            }
+        }
+        cleared
+    }
+
     pub fn get_front_matter(&self) -> Result<DocumentMetadata, serde_yaml::Error> {
-        match &self.cells[0] {
-            Cell::Raw { common } => Ok(serde_yaml::from_str(&common.source)?),
-            _ => Ok(DocumentMetadata::default()),
+        match self.front_matter_source() {
+            Some(source) => Ok(serde_yaml::from_str(source)?),
+            None => Ok(DocumentMetadata::default()),
+        }
+    }
+
+    /// The unparsed front matter (see [Notebook::front_matter_source]).
+    pub fn get_front_matter_value(&self) -> Result<Option<serde_yaml::Value>, serde_yaml::Error> {
+        self.front_matter_source()
+            .map(serde_yaml::from_str)
+            .transpose()
+    }
+
+    /// The source of the front matter: the first cell if it is a raw cell, or the block between
+    /// `---` lines at the start of the first cell if it is a markdown cell.
+    fn front_matter_source(&self) -> Option<&str> {
+        match self.cells.first()? {
+            Cell::Raw { common } => Some(&common.source),
+            Cell::Markdown { common } => split_front_matter(&common.source).map(|(fm, _)| fm),
+            Cell::Code { .. } => None,
         }
     }
 
-    pub fn map_cell(&self, f: fn(&Cell) -> anyhow::Result<Cell>) -> anyhow::Result<Notebook> {
+    /// Remove the front matter block from the first cell if it is a markdown cell so that it is
+    /// not shown as content.
+    pub(crate) fn remove_markdown_front_matter(&mut self) {
+        if let Some(Cell::Markdown { common }) = self.cells.first_mut() {
+            if let Some((_, rest)) = split_front_matter(&common.source) {
+                common.source = rest.to_string();
+            }
+        }
+    }
+
+    /// The programming language of the code cells (see [NotebookMeta::language]).
+    pub fn language(&self) -> &str {
+        self.metadata.language().unwrap_or(DEFAULT_LANGUAGE)
+    }
+
+    pub fn map_cell(&self, f: impl Fn(&Cell) -> anyhow::Result<Cell>) -> anyhow::Result<Notebook> {
         let cells = self.cells.iter().map(f);
         Ok(Notebook {
             metadata: self.metadata.clone(),
@@ -153,13 +353,14 @@ impl Notebook {
     }
 
     pub fn placeholder_notebook(&self) -> anyhow::Result<Notebook> {
+        let language = self.language();
         self.map_cell(|c| match c {
             Cell::Code {
                 common,
                 execution_count,
                 ..
             } => {
-                let def = parse_code_string(&common.source)?;
+                let def = parse_code_string(&common.source, language)?;
                 let placeholder = def.write_string(false);
                 Ok(Cell::Code {
                     common: CellCommon {
@@ -176,83 +377,196 @@ impl Notebook {
 }
 
 impl CellOutput {
+    /// Replace the LaTeX representations of the output (e.g. from SymPy) by the HTML that
+    /// `typeset` returns for their math, given without delimiters. LaTeX that `typeset` returns
+    /// None for is kept.
+    pub(crate) fn typeset_latex<E>(
+        &self,
+        typeset: impl Fn(&str) -> Option<Result<String, E>>,
+    ) -> Result<CellOutput, E> {
+        match self {
+            CellOutput::Data {
+                execution_count,
+                data,
+                metadata,
+            } => Ok(CellOutput::Data {
+                execution_count: *execution_count,
+                data: data
+                    .iter()
+                    .map(|value| match value {
+                        OutputValue::Latex(v) => match typeset(strip_math_delimiters(v)) {
+                            Some(html) => Ok(OutputValue::Html(html?)),
+                            None => Ok(value.clone()),
+                        },
+                        value => Ok(value.clone()),
+                    })
+                    .collect::<Result<_, E>>()?,
+                metadata: metadata.clone(),
+            }),
+            output => Ok(output.clone()),
+        }
+    }
+
     pub fn to_events(&self) -> Vec<(Event, Range<usize>)> {
+        self.to_events_with(IteratorConfig::default())
+    }
+
+    /// Output events where widget views are embedded in HTML if `config.widgets` is set.
+    /// Otherwise widgets are replaced by the images of the same output or a placeholder. Outputs
+    /// that can't be rendered are replaced as set by `config.output_fallback`.
+    pub fn to_events_with(&self, config: IteratorConfig) -> Vec<(Event<'_>, Range<usize>)> {
         match self {
-            CellOutput::Stream { text, .. } => {
+            CellOutput::Stream { name, text } => {
+                // Errors and warnings are written to stderr.
+                let class = if name == "stderr" {
+                    "alert-danger"
+                } else {
+                    "alert-info"
+                };
+                let mut escaped = String::new();
+                escape_html(&mut escaped, text).expect("Invalid format");
                 vec![(
                     Event::Html(CowStr::Boxed(
                         format!(
-                            r#"
-                <div class="alert alert-info">
-                    <p>{}</p>
-                </div>
-                "#,
-                            text
+                            "\n<div class=\"alert {}\"><pre>{}</pre></div>\n",
+                            class, escaped
                         )
                         .into_boxed_str(),
                     )),
                     (0..0),
                 )]
             }
-            CellOutput::Data { data, .. } => data
-                .iter()
-                .flat_map(|value| match value {
-                    OutputValue::Plain(v) => {
-                        let block = Tag::CodeBlock(Fenced(CowStr::Boxed(
-                            "plaintext".to_string().into_boxed_str(),
-                        )));
-                        vec![
-                            (Event::Start(block.clone()), (0..0)),
-                            (Event::Text(CowStr::Borrowed(v)), (0..0)),
-                            (Event::End(block), (0..0)),
-                        ]
-                    }
-                    OutputValue::Image(v) => {
-                        vec![(
-                            Event::Html(CowStr::Boxed(
-                                format!("<img src=\"data:image/png;base64,{}\"></img>", v)
-                                    .into_boxed_str(),
-                            )),
-                            (0..0),
-                        )]
-                    }
-                    OutputValue::Svg(v) => {
-                        vec![(
-                            Event::Html(CowStr::Boxed(
-                                format!(
-                                    "<img><svg width=\"640px\" height=\"480px\">{}</svg></img>",
-                                    v
+            CellOutput::Data { data, .. } => {
+                let values: Vec<&OutputValue> = match data.iter().find_map(|v| match v {
+                    OutputValue::Widget(view) => Some(view),
+                    _ => None,
+                }) {
+                    Some(view) if config.widgets => return vec![widget_view_event(view)],
+                    Some(_) => {
+                        let images: Vec<&OutputValue> = data
+                            .iter()
+                            .filter(|v| {
+                                matches!(
+                                    v,
+                                    OutputValue::Image(_)
+                                        | OutputValue::Jpeg(_)
+                                        | OutputValue::Gif(_)
+                                        | OutputValue::Svg(_)
                                 )
-                                .into_boxed_str(),
-                            )),
-                            (0..0),
-                        )]
+                            })
+                            .collect();
+                        if images.is_empty() {
+                            return widget_placeholder();
+                        }
+                        images
                     }
-                    OutputValue::Json(v) => {
-                        vec![(
-                            Event::Text(CowStr::Boxed(format!("{:?}", v).into_boxed_str())),
-                            (0..0),
-                        )]
+                    None => {
+                        let unsupported: Vec<&str> = data
+                            .iter()
+                            .filter_map(|v| match v {
+                                OutputValue::Unsupported(mime, _) => Some(mime.as_str()),
+                                _ => None,
+                            })
+                            .collect();
+                        let renderable = data.iter().any(|v| {
+                            !matches!(v, OutputValue::Plain(_) | OutputValue::Unsupported(..))
+                        });
+                        let plain = data.iter().any(|v| matches!(v, OutputValue::Plain(_)));
+                        if unsupported.is_empty()
+                            || renderable
+                            || (plain && config.output_fallback == OutputFallback::Plain)
+                        {
+                            data.iter().collect()
+                        } else {
+                            return output_note(&unsupported);
+                        }
                     }
-                    OutputValue::Html(v) => {
-                        vec![(
-                            Event::Html(CowStr::Boxed(v.to_string().into_boxed_str())),
-                            (0..0),
-                        )]
-                    }
-                    OutputValue::Javascript(v) => {
-                        vec![(
-                            Event::Html(CowStr::Boxed(
-                                format!("<script>{}</script>", v).into_boxed_str(),
-                            )),
-                            (0..0),
-                        )]
-                    }
-                })
-                .collect(),
-            CellOutput::Error { .. } => {
+                };
+                values
+                    .into_iter()
+                    .flat_map(|value| match value {
+                        OutputValue::Plain(v) => {
+                            let block = Tag::CodeBlock(Fenced(CowStr::Boxed(
+                                "plaintext".to_string().into_boxed_str(),
+                            )));
+                            vec![
+                                (Event::Start(block.clone()), (0..0)),
+                                (Event::Text(CowStr::Borrowed(v)), (0..0)),
+                                (Event::End(block), (0..0)),
+                            ]
+                        }
+                        OutputValue::Image(v) => vec![image_event("image/png", v)],
+                        OutputValue::Jpeg(v) => vec![image_event("image/jpeg", v)],
+                        OutputValue::Gif(v) => vec![image_event("image/gif", v)],
+                        // Kept as display math for output formats without a math preprocessor,
+                        // which otherwise typesets it (see [CellOutput::typeset_latex]).
+                        OutputValue::Latex(v) => vec![
+                            (Event::Start(Tag::Paragraph), (0..0)),
+                            (
+                                Event::Text(CowStr::Boxed(
+                                    format!("$${}$$", strip_math_delimiters(v)).into_boxed_str(),
+                                )),
+                                (0..0),
+                            ),
+                            (Event::End(Tag::Paragraph), (0..0)),
+                        ],
+                        OutputValue::Svg(v) => {
+                            vec![(
+                                Event::Html(CowStr::Boxed(
+                                    format!(
+                                        "<img><svg width=\"640px\" height=\"480px\">{}</svg></img>",
+                                        v
+                                    )
+                                    .into_boxed_str(),
+                                )),
+                                (0..0),
+                            )]
+                        }
+                        OutputValue::Json(v) => {
+                            vec![(
+                                Event::Text(CowStr::Boxed(format!("{:?}", v).into_boxed_str())),
+                                (0..0),
+                            )]
+                        }
+                        OutputValue::Html(v) => {
+                            vec![(
+                                Event::Html(CowStr::Boxed(v.to_string().into_boxed_str())),
+                                (0..0),
+                            )]
+                        }
+                        OutputValue::Javascript(v) => {
+                            vec![(
+                                Event::Html(CowStr::Boxed(
+                                    format!("<script>{}</script>", v).into_boxed_str(),
+                                )),
+                                (0..0),
+                            )]
+                        }
+                        // Rich display output (e.g. `IPython.display.Markdown`) is rendered like the
+                        // markdown of the document.
+                        OutputValue::Markdown(v) => Parser::new_ext(v, config.markdown_options())
+                            .map(|e| (e, (0..0)))
+                            .collect(),
+                        OutputValue::Widget(_) | OutputValue::Unsupported(..) => vec![],
+                    })
+                    .collect()
+            }
+            CellOutput::Error {
+                ename,
+                evalue,
+                traceback,
+            } => {
+                let mut text = format!("{}: {}", ename, evalue);
+                for line in traceback {
+                    text.push('\n');
+                    text.push_str(line);
+                }
+                let mut escaped = String::new();
+                escape_html(&mut escaped, &strip_ansi(&text)).expect("Invalid format");
                 vec![(
-                    Event::Text(CowStr::Boxed("Error".to_string().into_boxed_str())),
+                    Event::Html(CowStr::Boxed(
+                        format!("\n<pre class=\"error\">{}</pre>\n", escaped).into_boxed_str(),
+                    )),
                     (0..0),
                 )]
             }
@@ -276,6 +590,79 @@ where
     serializer.collect_seq(value.split('\n'))
 }
 
+/// Split a markdown cell that starts with a front matter block between `---` lines into the front
+/// matter and the rest of the cell.
+fn split_front_matter(source: &str) -> Option<(&str, &str)> {
+    let body = source.trim_start().strip_prefix("---")?;
+    let body = body
+        .strip_prefix("\r\n")
+        .or_else(|| body.strip_prefix('\n'))?;
+    let mut offset = 0;
+    for line in body.split_inclusive('\n') {
+        if line.trim_end() == "---" {
+            return Some((&body[..offset], &body[offset + line.len()..]));
+        }
+        offset += line.len();
+    }
+    None
+}
+
+/// Read the MIME bundle of an output in order. Representations that can't be read are kept as
+/// [OutputValue::Unsupported].
+fn deserialize_output_data<'de, D>(input: D) -> Result<Vec<OutputValue>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct DataVisitor;
+
+    impl<'de> Visitor<'de> for DataVisitor {
+        type Value = Vec<OutputValue>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a map of MIME types to output data")
+        }
+
+        fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+        where
+            A: MapAccess<'de>,
+        {
+            let mut data = Vec::new();
+            while let Some((mime, value)) = map.next_entry::<String, Value>()? {
+                let entry = Value::Object([(mime.clone(), value.clone())].into_iter().collect());
+                data.push(
+                    OutputValue::deserialize(entry)
+                        .unwrap_or(OutputValue::Unsupported(mime, value)),
+                );
+            }
+            Ok(data)
+        }
+    }
+
+    input.deserialize_map(DataVisitor)
+}
+
+fn serialize_output_data<S>(data: &[OutputValue], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let mut map = serializer.serialize_map(Some(data.len()))?;
+    for value in data {
+        match value {
+            OutputValue::Unsupported(mime, value) => map.serialize_entry(mime, value)?,
+            value => {
+                if let Value::Object(entries) =
+                    serde_json::to_value(value).map_err(S::Error::custom)?
+                {
+                    for (mime, value) in entries {
+                        map.serialize_entry(&mime, &value)?;
+                    }
+                }
+            }
+        }
+    }
+    map.end()
+}
+
 #[allow(unused)]
 fn escape_string_deserialize(source: String) -> String {
     let escaped = source
@@ -291,6 +678,110 @@ fn escape_string_deserialize(source: String) -> String {
     escaped
 }
 
+/// Mount point of a widget. The view is displayed by the widget manager script using the widget
+/// state of the document.
+fn widget_view_event<'a>(view: &Value) -> (Event<'a>, Range<usize>) {
+    (
+        Event::Html(CowStr::Boxed(
+            format!(
+                "<script type=\"application/vnd.jupyter.widget-view+json\">{}</script>\n",
+                script_json(view)
+            )
+            .into_boxed_str(),
+        )),
+        (0..0),
+    )
+}
+
+fn output_note<'a>(mime_types: &[&str]) -> Vec<(Event<'a>, Range<usize>)> {
+    vec![
+        (Event::Start(Tag::Paragraph), (0..0)),
+        (
+            Event::Text(CowStr::Boxed(
+                format!("[output: {}]", mime_types.join(", ")).into_boxed_str(),
+            )),
+            (0..0),
+        ),
+        (Event::End(Tag::Paragraph), (0..0)),
+    ]
+}
+
+fn widget_placeholder<'a>() -> Vec<(Event<'a>, Range<usize>)> {
+    vec![
+        (Event::Start(Tag::Paragraph), (0..0)),
+        (Event::Start(Tag::Emphasis), (0..0)),
+        (Event::Text(CowStr::Borrowed("Interactive widget")), (0..0)),
+        (Event::End(Tag::Emphasis), (0..0)),
+        (Event::End(Tag::Paragraph), (0..0)),
+    ]
+}
+
+/// Language of code cells if the notebook doesn't name the language of its kernel.
+pub const DEFAULT_LANGUAGE: &str = "python";
+
+/// Cell magics that run the cell in another language and the code block language used for them.
+const LANGUAGE_MAGICS: [(&str, &str); 12] = [
+    ("bash", "bash"),
+    ("sh", "sh"),
+    ("html", "html"),
+    ("javascript", "javascript"),
+    ("js", "javascript"),
+    ("latex", "latex"),
+    ("markdown", "markdown"),
+    ("perl", "perl"),
+    ("python", "python"),
+    ("python3", "python"),
+    ("ruby", "ruby"),
+    ("svg", "xml"),
+];
+
+/// The language of a code cell that starts with a cell magic like `%%bash`. Other magics (e.g.
+/// `%%time`) run the cell in the language of the kernel and give None.
+pub fn magic_language(source: &str) -> Option<&'static str> {
+    let magic = source.trim_start().strip_prefix("%%")?;
+    let name = magic.split_whitespace().next()?;
+    LANGUAGE_MAGICS
+        .iter()
+        .find(|(magic, _)| *magic == name)
+        .map(|(_, language)| *language)
+}
+
+/// JSON that can be embedded in a script element.
+pub(crate) fn script_json(value: &Value) -> String {
+    value.to_string().replace("</", "<\\/")
+}
+
+/// Remove the math delimiters that usually surround LaTeX outputs (e.g. `$\displaystyle x$` from
+/// SymPy).
+fn strip_math_delimiters(latex: &str) -> &str {
+    let latex = latex.trim();
+    [("$$", "$$"), ("$", "$"), ("\\[", "\\]")]
+        .iter()
+        .find_map(|(open, close)| {
+            latex
+                .strip_prefix(open)?
+                .strip_suffix(close)
+                .filter(|inner| !inner.is_empty())
+        })
+        .map(str::trim)
+        .unwrap_or(latex)
+}
+
+/// An image with the base64 encoded data embedded as a data URI.
+fn image_event<'a>(mime: &str, data: &str) -> (Event<'a>, Range<usize>) {
+    (
+        Event::Html(CowStr::Boxed(
+            format!(
+                "<img src=\"data:{};base64,{}\"></img>",
+                mime,
+                data.trim_end()
+            )
+            .into_boxed_str(),
+        )),
+        (0..0),
+    )
+}
+
 #[allow(unused)]
 fn deserialize_png<'de, D>(input: D) -> Result<Vec<u8>, D::Error>
 where
@@ -338,6 +829,13 @@ impl<'a> IntoIterator for &'a Cell {
     type IntoIter = CellEventIterator<'a, 'a>;
 
     fn into_iter(self) -> Self::IntoIter {
+        self.events(DEFAULT_LANGUAGE)
+    }
+}
+
+impl Cell {
+    /// The events of the cell. Code is a code block in the given language.
+    pub fn events(&self, language: &str) -> CellEventIterator<'_, '_> {
         match self {
             Cell::Markdown { common } => CellEventIterator::Markdown {
                 parser: Box::new(
@@ -347,7 +845,7 @@ impl<'a> IntoIterator for &'a Cell {
             Cell::Code {
                 common, outputs, ..
             } => {
-                let cblock = CodeBlock(Fenced(CowStr::Boxed("python".into())));
+                let cblock = CodeBlock(Fenced(CowStr::Boxed(language.into())));
                 let mut events = vec![
                     (Event::Start(cblock.clone()), (0..0)),
                     (
@@ -387,26 +885,24 @@ impl<'a, 'b> Iterator for CellEventIterator<'a, 'b> {
     }
 }
 
-pub struct NotebookIterator<'a, 'b> {
-    iter: FlatMap<
-        Iter<'a, Cell>,
-        CellEventIterator<'a, 'b>,
-        fn(&'a Cell) -> CellEventIterator<'a, 'b>,
-    >,
+pub struct NotebookIterator<'a> {
+    iter: Box<dyn Iterator<Item = (Event<'a>, Range<usize>)> + 'a>,
 }
 
 impl<'a> IntoIterator for &'a Notebook {
     type Item = (Event<'a>, Range<usize>);
-    type IntoIter = NotebookIterator<'a, 'a>;
+    type IntoIter = NotebookIterator<'a>;
 
+    /// The events of the cells. Code cells are in the language of the notebook.
     fn into_iter(self) -> Self::IntoIter {
+        let language = self.language();
         NotebookIterator {
-            iter: self.cells.iter().flat_map(|c| c.into_iter()),
+            iter: Box::new(self.cells.iter().flat_map(move |c| c.events(language))),
         }
     }
 }
 
-impl<'a, 'b> Iterator for NotebookIterator<'a, 'b> {
+impl<'a> Iterator for NotebookIterator<'a> {
     type Item = (Event<'a>, Range<usize>);
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -416,8 +912,11 @@ impl<'a, 'b> Iterator for NotebookIterator<'a, 'b> {
 
 #[cfg(test)]
 mod tests {
-    use crate::notebook::Notebook;
-    use pulldown_cmark::html;
+    use crate::document::IteratorConfig;
+    use crate::notebook::{magic_language, Notebook, OutputFallback};
+    use pulldown_cmark::CodeBlockKind::Fenced;
+    use pulldown_cmark::Tag::CodeBlock;
+    use pulldown_cmark::{html, Event};
     use std::fs::File;
     use std::io::BufReader;
     use std::path::PathBuf;
@@ -445,4 +944,282 @@ mod tests {
 
         // println!("{}", html_output);
     }
+
+    #[test]
+    fn cell_accessors() {
+        let input = r##"{
+            "metadata": {"kernelspec": {"name": "python3"}, "language_info": {"name": "python"}},
+            "nbformat": 4,
+            "nbformat_minor": 5,
+            "cells": [
+                {"cell_type": "markdown", "metadata": {"tags": ["intro"]}, "source": ["# Title"]},
+                {"cell_type": "code", "metadata": {"custom": 1}, "source": ["print(1)"],
+                 "execution_count": 2,
+                 "outputs": [{"output_type": "stream", "name": "stdout", "text": ["1"]}]},
+                {"cell_type": "raw", "metadata": {}, "source": ["raw"]}
+            ]
+        }"##;
+        let nb: Notebook = serde_json::from_str(input).expect("Deserialization failed");
+
+        assert_eq!(nb.nbformat(), (4, 5));
+        assert!(nb.metadata().kernelspec().unwrap().contains_key("name"));
+        assert!(nb.metadata().get("language_info").is_some());
+
+        let types: Vec<&str> = nb.cells().iter().map(|c| c.cell_type()).collect();
+        assert_eq!(types, vec!["markdown", "code", "raw"]);
+
+        let cells = nb.cells();
+        assert_eq!(cells[0].source(), "# Title");
+        assert_eq!(cells[0].metadata().tags(), ["intro".to_string()]);
+        assert!(cells[0].outputs().is_empty());
+        assert_eq!(cells[1].execution_count(), Some(2));
+        assert_eq!(cells[1].outputs().len(), 1);
+        assert_eq!(
+            cells[1].metadata().get("custom"),
+            Some(&serde_json::Value::from(1))
+        );
+        assert!(cells[2].metadata().tags().is_empty());
+        assert_eq!(cells[2].execution_count(), None);
+    }
+
+    /// Notebook with a single code cell holding the given outputs array.
+    fn outputs_notebook(outputs: &str) -> Notebook {
+        let input = format!(
+            r#"{{
+                "metadata": {{}},
+                "nbformat": 4,
+                "nbformat_minor": 5,
+                "cells": [
+                    {{"cell_type": "code", "metadata": {{}}, "source": [], "execution_count": 1,
+                      "outputs": {outputs}}}
+                ]
+            }}"#
+        );
+        serde_json::from_str(&input).expect("Deserialization failed")
+    }
+
+    fn render_output(nb: &Notebook, i: usize, config: IteratorConfig) -> String {
+        let mut output = String::new();
+        html::push_html(
+            &mut output,
+            nb.cells()[0].outputs()[i]
+                .to_events_with(config)
+                .into_iter()
+                .map(|(e, _)| e),
+        );
+        output
+    }
+
+    #[test]
+    fn markdown_output() {
+        let nb = outputs_notebook(
+            r##"[{"output_type": "execute_result", "execution_count": 1, "metadata": {},
+                  "data": {"text/markdown": ["Results:\n", "\n", "- *one*\n", "- two"]}}]"##,
+        );
+
+        assert_eq!(
+            render_output(&nb, 0, IteratorConfig::default()),
+            "<p>Results:</p>\n<ul>\n<li><em>one</em></li>\n<li>two</li>\n</ul>\n"
+        );
+    }
+
+    #[test]
+    fn image_outputs() {
+        let nb = outputs_notebook(
+            r##"[{"output_type": "display_data", "metadata": {}, "data": {"image/jpeg": "/9j/4AAQ\n"}},
+                 {"output_type": "display_data", "metadata": {}, "data": {"image/gif": "R0lGODlh"}}]"##,
+        );
+
+        assert_eq!(
+            render_output(&nb, 0, IteratorConfig::default()),
+            "<img src=\"data:image/jpeg;base64,/9j/4AAQ\"></img>"
+        );
+
+        let json = serde_json::to_value(&nb).unwrap();
+        let outputs = &json["cells"][0]["outputs"];
+        assert_eq!(outputs[0]["data"]["image/jpeg"], "/9j/4AAQ\n");
+        assert_eq!(outputs[1]["data"]["image/gif"], "R0lGODlh");
+    }
+
+    #[test]
+    fn latex_output() {
+        let nb = outputs_notebook(
+            r##"[{"output_type": "execute_result", "execution_count": 1, "metadata": {},
+                  "data": {"text/latex": ["$\\displaystyle x^{2} + 1$"], "text/plain": ["x**2 + 1"]}}]"##,
+        );
+
+        assert!(render_output(&nb, 0, IteratorConfig::default())
+            .starts_with("<p>$$\\displaystyle x^{2} + 1$$</p>\n"));
+
+        let json = serde_json::to_value(&nb).unwrap();
+        assert_eq!(
+            json["cells"][0]["outputs"][0]["data"]["text/latex"],
+            serde_json::json!(["$\\displaystyle x^{2} + 1$"])
+        );
+    }
+
+    #[test]
+    fn stream_outputs() {
+        let nb = outputs_notebook(
+            r##"[{"output_type": "stream", "name": "stdout", "text": ["a  b\n", "<c>\n"]},
+                 {"output_type": "stream", "name": "stderr", "text": ["Warning: x\n"]}]"##,
+        );
+
+        assert_eq!(
+            render_output(&nb, 0, IteratorConfig::default()),
+            "\n<div class=\"alert alert-info\"><pre>a  b\n&lt;c&gt;\n</pre></div>\n"
+        );
+        assert_eq!(
+            render_output(&nb, 1, IteratorConfig::default()),
+            "\n<div class=\"alert alert-danger\"><pre>Warning: x\n</pre></div>\n"
+        );
+    }
+
+    #[test]
+    fn widget_outputs() {
+        let nb = outputs_notebook(
+            r##"[{"output_type": "display_data", "metadata": {},
+                  "data": {"application/vnd.jupyter.widget-view+json":
+                               {"model_id": "abc", "version_major": 2, "unknown": [1]},
+                           "text/plain": ["IntSlider(value=5)"]}},
+                 {"output_type": "display_data", "metadata": {},
+                  "data": {"application/vnd.jupyter.widget-view+json": {"model_id": "def"},
+                           "image/png": "iVBORw0K",
+                           "text/plain": ["Figure"]}}]"##,
+        );
+        let render = |i: usize, widgets: bool| {
+            render_output(
+                &nb,
+                i,
+                IteratorConfig {
+                    widgets,
+                    ..IteratorConfig::default()
+                },
+            )
+        };
+
+        assert_eq!(render(0, false), "<p><em>Interactive widget</em></p>\n");
+        assert_eq!(
+            render(1, false),
+            "<img src=\"data:image/png;base64,iVBORw0K\"></img>"
+        );
+        assert!(render(0, true).starts_with(
+            "<script type=\"application/vnd.jupyter.widget-view+json\">{\"model_id\":\"abc\""
+        ));
+        assert!(!render(1, true).contains("<img"));
+    }
+
+    #[test]
+    fn unsupported_outputs() {
+        let nb = outputs_notebook(
+            r##"[{"output_type": "display_data", "metadata": {}, "data": {"application/x-foo": {"a": 1}}},
+                 {"output_type": "display_data", "metadata": {},
+                  "data": {"text/plain": ["<Foo>"], "application/x-foo": "x"}},
+                 {"output_type": "display_data", "metadata": {},
+                  "data": {"image/png": ["iVBO", "Rw0K"], "text/html": ["<b>x</b>"]}}]"##,
+        );
+        let render = |i: usize, output_fallback: OutputFallback| {
+            render_output(
+                &nb,
+                i,
+                IteratorConfig {
+                    output_fallback,
+                    ..IteratorConfig::default()
+                },
+            )
+        };
+
+        assert_eq!(
+            render(0, OutputFallback::Plain),
+            "<p>[output: application/x-foo]</p>\n"
+        );
+        assert!(render(1, OutputFallback::Plain).contains("&lt;Foo&gt;"));
+        assert_eq!(
+            render(1, OutputFallback::Note),
+            "<p>[output: application/x-foo]</p>\n"
+        );
+        // Image data that can't be read doesn't hide the other representations.
+        assert_eq!(render(2, OutputFallback::Note), "<b>x</b>");
+
+        let json = serde_json::to_value(&nb).unwrap();
+        let outputs = &json["cells"][0]["outputs"];
+        assert_eq!(outputs[0]["data"]["application/x-foo"]["a"], 1);
+        assert_eq!(
+            outputs[1]["data"]["text/plain"],
+            serde_json::json!(["<Foo>"])
+        );
+        assert_eq!(
+            outputs[2]["data"]["image/png"],
+            serde_json::json!(["iVBO", "Rw0K"])
+        );
+    }
+
+    #[test]
+    fn error_output() {
+        let nb = outputs_notebook(
+            r##"[{"output_type": "error", "ename": "ValueError",
+                  "evalue": "invalid literal for int() with base 10: 'x'",
+                  "traceback": [
+                      "\u001b[0;31m---------------------------------------------------------------------------\u001b[0m",
+                      "\u001b[0;31mValueError\u001b[0m                                Traceback (most recent call last)",
+                      "Cell \u001b[0;32mIn[1], line 1\u001b[0m\n\u001b[0;32m----> 1\u001b[0m \u001b[38;5;28mint\u001b[39m(\u001b[38;5;124m'\u001b[39m\u001b[38;5;124mx\u001b[39m\u001b[38;5;124m'\u001b[39m)\n",
+                      "\u001b[0;31mValueError\u001b[0m: invalid literal for int() with base 10: 'x'"
+                  ]}]"##,
+        );
+
+        let output = render_output(&nb, 0, IteratorConfig::default());
+        assert!(output.starts_with(
+            "\n<pre class=\"error\">ValueError: invalid literal for int() with base 10: 'x'\n---"
+        ));
+        assert!(output.contains("----&gt; 1 int('x')"));
+        assert!(output.ends_with("ValueError: invalid literal for int() with base 10: 'x'</pre>\n"));
+        assert!(!output.contains('\u{1b}'));
+    }
+
+    #[test]
+    fn empty_notebook_front_matter() {
+        let nb: Notebook = serde_json::from_str(
+            r#"{"metadata": {}, "nbformat": 4, "nbformat_minor": 5, "cells": []}"#,
+        )
+        .expect("Deserialization failed");
+        assert!(nb.get_front_matter_value().unwrap().is_none());
+        assert!(nb.get_front_matter().unwrap().title.is_none());
+    }
+
+    #[test]
+    fn test_magic_language() {
+        assert_eq!(magic_language("%%bash\nls"), Some("bash"));
+        assert_eq!(
+            magic_language("\n%%html --isolated\n<b>x</b>"),
+            Some("html")
+        );
+        assert_eq!(magic_language("%%svg\n<svg/>"), Some("xml"));
+        assert_eq!(magic_language("%%time\nx = 1"), None);
+        assert_eq!(magic_language("%matplotlib inline"), None);
+        assert_eq!(magic_language("x = 1"), None);
+    }
+
+    #[test]
+    fn test_kernel_language() {
+        let input = r##"{
+            "metadata": {"language_info": {"name": "rust"}},
+            "nbformat": 4,
+            "nbformat_minor": 5,
+            "cells": [
+                {"cell_type": "code", "metadata": {}, "execution_count": null, "outputs": [],
+                 "source": ["//| << CODE\n", "// todo\n", "//| >> SOLUTION <<\n", "let x = 1;\n", "//| >> END_CODE\n"]}
+            ]
+        }"##;
+        let nb: Notebook = serde_json::from_str(input).expect("Deserialization failed");
+        assert_eq!(nb.language(), "rust");
+
+        // Code cells are code blocks in the language of the kernel.
+        assert!(nb.into_iter().any(|(e, _)| matches!(
+            e,
+            Event::Start(CodeBlock(Fenced(language))) if &*language == "rust"
+        )));
+        // Exercises use its comment symbol.
+        let placeholder = nb.placeholder_notebook().unwrap();
+        assert_eq!(placeholder.cells()[0].source().trim(), "todo");
+    }
 }
@@ -1,13 +1,18 @@
 use anyhow::Context;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use tracing::debug;
 
-use crate::document::{Document, EventContent, IteratorConfig, PreprocessError, RawContent};
+use crate::config::OutputFormat;
+use crate::document::{Document, Element, EventContent, IteratorConfig, RawContent};
+use crate::notebook::OutputFallback;
+use crate::processors::numbering::NumberingScope;
 use crate::processors::shortcodes::ShortCodeProcessError;
 use crate::processors::{
     EventPreprocessor, EventPreprocessorConfig, MarkdownPreprocessor, PreprocessorConfig,
     PreprocessorContext,
 };
+use crate::renderers::HtmlPolicy;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Parser {
@@ -25,9 +30,43 @@ pub struct ParserSettings {
     /// Include notebook outputs (from cells) in the loaded output.
     #[serde(default)]
     pub notebook_outputs: bool,
+    /// How raw HTML in the source is handled (allow, sanitize or strip).
+    #[serde(default)]
+    pub html_policy: HtmlPolicy,
+    /// Show notebook code cells with Jupyter-like `In [n]:` and `Out [n]:` prompts (for html).
+    #[serde(default)]
+    pub cell_prompts: bool,
+    /// Render notebook code cells in the language of their cell magic (e.g. `%%bash`) or kernel
+    /// instead of Python. Not used for notebook outputs, whose code cells are always Python.
+    #[serde(default = "default_cell_languages")]
+    pub cell_languages: bool,
+    /// Where listing numbers restart. The default depends on the output format (see
+    /// [NumberingScope::default_for]).
+    #[serde(default)]
+    pub numbering_scope: Option<NumberingScope>,
+    /// What is shown for notebook outputs that can't be rendered.
+    #[serde(default)]
+    pub output_fallback: OutputFallback,
+}
+
+impl ParserSettings {
+    /// The configured numbering scope or the default of the format.
+    pub fn numbering_scope(&self, format: OutputFormat) -> NumberingScope {
+        self.numbering_scope
+            .unwrap_or_else(|| NumberingScope::default_for(format))
+    }
+}
+
+fn default_cell_languages() -> bool {
+    true
 }
 
 impl Parser {
+    /// Whether math is rendered with KaTeX (see [PreprocessorConfig::uses_katex]).
+    pub fn uses_katex(&self) -> bool {
+        self.preprocessors.iter().any(|p| p.uses_katex())
+    }
+
     pub fn parse(
         &self,
         doc: &Document<RawContent>,
@@ -35,7 +74,9 @@ impl Parser {
         ctx: &PreprocessorContext,
     ) -> Result<Document<EventContent>, anyhow::Error> {
         let doc = self.run_preprocessors(doc, template_context, ctx)?;
-        self.run_event_processors(&doc, ctx)
+        let mut doc = self.run_event_processors(&doc, ctx)?;
+        doc.variables.warnings.extend(ctx.warnings.take());
+        Ok(doc)
     }
 
     pub fn run_preprocessors(
@@ -50,8 +91,22 @@ impl Parser {
             .map(|p| p.build(ctx))
             .collect::<anyhow::Result<Vec<Box<dyn MarkdownPreprocessor>>>>()?;
 
-        let content = built.iter().fold(Ok(doc.clone()), |c, preprocessor| {
+        // Raw HTML is handled before the preprocessors add their own.
+        let policy = self.settings.html_policy;
+        let doc = doc.clone().map_elements(|e| match e {
+            Element::Markdown {
+                content,
+                line_offset,
+            } => Element::Markdown {
+                content: policy.apply(content),
+                line_offset: *line_offset,
+            },
+            e => e.clone(),
+        });
+
+        let content = built.iter().fold(Ok(doc), |c, preprocessor| {
             c.and_then(|c| {
+                debug!("running preprocessor: {}", preprocessor);
                 c.preprocess(preprocessor.as_ref(), template_context)
                     .with_context(|| format!("Preprocessing error in {}", preprocessor))
             })
@@ -70,10 +125,12 @@ impl Parser {
                 .metadata
                 .notebook_output
                 .unwrap_or(self.settings.notebook_outputs),
-            include_solutions: doc
-                .metadata
-                .code_solutions
-                .unwrap_or(self.settings.solutions),
+            cell_prompts: self.settings.cell_prompts,
+            widgets: ctx.output_format == OutputFormat::Html,
+            cell_languages: self.settings.cell_languages
+                && ctx.output_format != OutputFormat::Notebook,
+            output_fallback: self.settings.output_fallback,
+            smart_punctuation: doc.metadata.smart_punctuation.unwrap_or(true),
         });
 
         let built = self
@@ -83,7 +140,10 @@ impl Parser {
             .collect::<anyhow::Result<Vec<Box<dyn EventPreprocessor>>>>()?;
 
         let events = built.iter().fold(Ok(v), |c, event_processor| {
-            c.and_then(|c| event_processor.process(c))
+            c.and_then(|c| {
+                debug!("running event processor: {}", event_processor);
+                event_processor.process(c)
+            })
         })?;
 
         Ok(events)
@@ -110,15 +170,13 @@ pub enum ParserError {
     #[error("Error parsing frontmatter: ")]
     FrontMatter(#[from] serde_yaml::Error),
 
-    #[error(transparent)]
-    Preprocess(#[from] PreprocessError),
-
     #[error(transparent)]
     ExtensionError(#[from] crate::processors::Error),
 
     #[error(transparent)]
     ShortCode(#[from] ShortCodeProcessError),
 
+    #[cfg(feature = "katex")]
     #[error(transparent)]
     KaTeX(#[from] katex::Error),
 
@@ -1,3 +1,4 @@
+use pest::iterators::Pair;
 use pest::Parser;
 use pest_derive::Parser;
 use std::collections::HashMap;
@@ -8,7 +9,43 @@ pub struct ShortCodeParser;
 
 pub struct ShortCode {
     pub(crate) name: String,
-    pub(crate) parameters: HashMap<String, String>,
+    pub(crate) parameters: HashMap<String, ParameterValue>,
+    /// Positional arguments in order.
+    pub(crate) arguments: Vec<ParameterValue>,
+}
+
+impl ShortCode {
+    /// All arguments by name. Positional arguments are named `arg0`, `arg1`, ... in order. A
+    /// named argument wins if it uses the same name.
+    pub(crate) fn into_parameters(self) -> HashMap<String, ParameterValue> {
+        let mut parameters: HashMap<String, ParameterValue> = self
+            .arguments
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| (format!("arg{}", i), v))
+            .collect();
+        parameters.extend(self.parameters);
+        parameters
+    }
+}
+
+/// The value of a `value` pair.
+fn parameter_value(value: Pair<Rule>) -> ParameterValue {
+    let value = value.into_inner().next().expect("Missing value inner");
+    match value.as_rule() {
+        Rule::string_val => ParameterValue::Literal(value.as_str().to_string()),
+        Rule::basic_val => ParameterValue::Expression(value.as_str().to_string()),
+        _ => unreachable!(),
+    }
+}
+
+/// A shortcode argument value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParameterValue {
+    /// Quoted string. It is always used literally.
+    Literal(String),
+    /// Unquoted value. It may refer to a variable in the template context (e.g. `project.url_prefix`).
+    Expression(String),
 }
 
 pub fn parse_shortcode(content: &str) -> Result<ShortCode, Box<pest::error::Error<Rule>>> {
@@ -19,6 +56,7 @@ pub fn parse_shortcode(content: &str) -> Result<ShortCode, Box<pest::error::Erro
     let name = iter.next().expect("Missing name").as_str().to_string();
 
     let mut parameters = HashMap::new();
+    let mut arguments = Vec::new();
 
     match iter.next() {
         None => {}
@@ -28,20 +66,12 @@ pub fn parse_shortcode(content: &str) -> Result<ShortCode, Box<pest::error::Erro
                     Rule::param => {
                         let mut inner = p.into_inner();
                         let key = inner.next().expect("Missing key").as_str().to_string();
-
-                        let value = inner
-                            .next()
-                            .expect("Missing value")
-                            .into_inner()
-                            .next()
-                            .expect("Missing value inner");
-
-                        let value = match value.as_rule() {
-                            Rule::string_val => value.as_str(),
-                            Rule::basic_val => value.as_str(),
-                            _ => unreachable!(),
-                        };
-                        parameters.insert(key, value.to_string());
+                        let value = parameter_value(inner.next().expect("Missing value"));
+                        parameters.insert(key, value);
+                    }
+                    Rule::arg => {
+                        let value = p.into_inner().next().expect("Missing value");
+                        arguments.push(parameter_value(value));
                     }
                     _ => unreachable!(),
                 }
@@ -49,5 +79,64 @@ pub fn parse_shortcode(content: &str) -> Result<ShortCode, Box<pest::error::Erro
         }
     }
 
-    Ok(ShortCode { name, parameters })
+    Ok(ShortCode {
+        name,
+        parameters,
+        arguments,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_named() {
+        let code = parse_shortcode(r#"figure(src="a.png", width=50)"#).unwrap();
+        assert_eq!(code.name, "figure");
+        assert!(code.arguments.is_empty());
+        assert_eq!(
+            code.parameters.get("src"),
+            Some(&ParameterValue::Literal("a.png".to_string()))
+        );
+        assert_eq!(
+            code.parameters.get("width"),
+            Some(&ParameterValue::Expression("50".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_positional() {
+        let code = parse_shortcode(r#"youtube("abc")"#).unwrap();
+        assert_eq!(
+            code.arguments,
+            vec![ParameterValue::Literal("abc".to_string())]
+        );
+        assert!(code.parameters.is_empty());
+
+        let code = parse_shortcode(r#"video("abc", page.title, start=10)"#).unwrap();
+        assert_eq!(
+            code.arguments,
+            vec![
+                ParameterValue::Literal("abc".to_string()),
+                ParameterValue::Expression("page.title".to_string())
+            ]
+        );
+        let parameters = code.into_parameters();
+        assert_eq!(parameters.len(), 3);
+        assert_eq!(
+            parameters.get("arg1"),
+            Some(&ParameterValue::Expression("page.title".to_string()))
+        );
+        assert_eq!(
+            parameters.get("start"),
+            Some(&ParameterValue::Expression("10".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_positional_after_named() {
+        assert!(parse_shortcode(r#"video(start=10, "abc")"#).is_err());
+        assert!(parse_shortcode(r#"video("abc", start=10, "def")"#).is_err());
+    }
 }
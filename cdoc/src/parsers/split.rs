@@ -1,5 +1,6 @@
+use pest::error::InputLocation;
 use pest::iterators::Pair;
-use pest::Parser;
+use pest::{Parser, Position, Span};
 use pest_derive::Parser;
 use std::collections::HashMap;
 
@@ -27,10 +28,29 @@ pub fn parse_src_block(pair: Pair<Rule>) -> Result<Content, Box<pest::error::Err
         Rule::markup_block => Content::Markup {
             markup: parse_markup_block(pair)?,
         },
+        Rule::hint_block => Content::Hint {
+            hint: parse_hint_block(pair),
+        },
         _ => unreachable!(),
     })
 }
 
+/// The text of a hint: its comment lines without the comment symbols and indentation.
+fn parse_hint_block(pair: Pair<Rule>) -> String {
+    pair.into_inner()
+        .map(|comment| {
+            comment
+                .into_inner()
+                .nth(1)
+                .map_or("", |text| text.as_str())
+                .to_string()
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+        .trim()
+        .to_string()
+}
+
 fn parse_source_comment(pair: Pair<Rule>) -> Result<String, Box<pest::error::Error<Rule>>> {
     Ok(match pair.as_rule() {
         Rule::source_comment => {
@@ -88,7 +108,9 @@ pub fn parse_inner_block(pair: Pair<Rule>) -> Result<Inner, Box<pest::error::Err
                 solution,
             })
         }
-        Rule::source_code_block | Rule::markup_block => Inner::SrcBlock(parse_src_block(pair)?),
+        Rule::source_code_block | Rule::markup_block | Rule::hint_block => {
+            Inner::SrcBlock(parse_src_block(pair)?)
+        }
         _ => unreachable!(),
     })
 }
@@ -149,7 +171,7 @@ pub fn parse_value(pair: Pair<Rule>) -> Result<Value, Box<pest::error::Error<Rul
                 },
             }
         }
-        Rule::source_code_block | Rule::markup_block => Value::SrcBlock {
+        Rule::source_code_block | Rule::markup_block | Rule::hint_block => Value::SrcBlock {
             content: parse_src_block(pair)?,
         },
         Rule::comment_def => Value::SrcBlock {
@@ -182,18 +204,48 @@ pub fn parse_value(pair: Pair<Rule>) -> Result<Value, Box<pest::error::Error<Rul
     })
 }
 
+/// The line comment symbol of a language, given as the info string of a code block (e.g.
+/// `rust title="main.rs"`). Unknown languages use `#` like Python.
+pub fn comment_symbol(language: &str) -> &'static str {
+    match language.split_whitespace().next().unwrap_or_default() {
+        "rust" | "c" | "cpp" | "c++" | "csharp" | "cs" | "java" | "kotlin" | "scala" | "swift"
+        | "go" | "dart" | "javascript" | "js" | "typescript" | "ts" => "//",
+        "sql" | "haskell" | "lua" => "--",
+        "latex" | "tex" | "matlab" | "octave" => "%",
+        _ => "#",
+    }
+}
+
+/// Parse the placeholder/solution syntax of code in the given language (see [comment_symbol]),
+/// e.g. `//| << CODE` for Rust.
 pub fn parse_code_string(
     content: &str,
+    language: &str,
 ) -> Result<CodeTaskDefinition, Box<pest::error::Error<Rule>>> {
-    let mut padded = content.to_string();
+    // The grammar reads the comment symbol from the first line.
+    let prefix = format!("{}\n", comment_symbol(language));
+    let mut padded = format!("{}{}", prefix, content);
     padded.push('\n');
-    let p = TaskParser::parse(Rule::doc, &padded)?;
+    let p = TaskParser::parse(Rule::doc, &padded).map_err(|e| {
+        let input = &padded[prefix.len()..];
+        let shift = |pos: usize| pos.saturating_sub(prefix.len());
+        Box::new(match e.location {
+            InputLocation::Pos(pos) => pest::error::Error::new_from_pos(
+                e.variant,
+                Position::new(input, shift(pos)).expect("Invalid error position"),
+            ),
+            InputLocation::Span((start, end)) => pest::error::Error::new_from_span(
+                e.variant,
+                Span::new(input, shift(start), shift(end)).expect("Invalid error span"),
+            ),
+        })
+    })?;
 
     let vals = p
         .into_iter()
         .map(parse_value)
         .collect::<anyhow::Result<Vec<Value>, Box<pest::error::Error<Rule>>>>()?;
-    Ok(CodeTaskDefinition { blocks: vals })
+    Ok(CodeTaskDefinition::new(vals))
 }
 
 pub fn human_errors(error: pest::error::Error<Rule>) -> Box<pest::error::Error<Rule>> {
@@ -205,6 +257,7 @@ pub fn human_errors(error: pest::error::Error<Rule>) -> Box<pest::error::Error<R
         Rule::source_comment => "code comment".to_owned(),
         Rule::source_comment_block => "code comment".to_owned(),
         Rule::markup_block => "markup lines".to_owned(),
+        Rule::hint_block => "hint block".to_owned(),
         _ => "Unknown".to_owned(),
     }))
 }
@@ -224,11 +277,12 @@ pub fn format_pest_err(error: pest::error::Error<Rule>) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use pest::error::LineColLocation;
 
     #[test]
     fn test_parse() {
         let str = include_str!("../../resources/test/sample.py");
-        let _doc = parse_code_string(str).unwrap();
+        let _doc = parse_code_string(str, "python").unwrap();
     }
 
     // #[test]
@@ -240,10 +294,60 @@ mod tests {
     //     let _output_placeholder = doc.write_string(false);
     // }
 
+    #[test]
+    fn test_languages() {
+        assert_eq!(comment_symbol("rust title=\"main.rs\""), "//");
+        assert_eq!(comment_symbol("python"), "#");
+        assert_eq!(comment_symbol(""), "#");
+
+        let doc = parse_code_string(
+            "fn f() {\n    //| << CODE\n    // todo!()\n    //| >> SOLUTION <<\n    1\n    //| >> END_CODE\n}",
+            "rust",
+        )
+        .unwrap();
+        assert_eq!(doc.solution_blocks(), 1);
+        let (placeholder, solution) = doc.split();
+        assert!(placeholder.contains("    todo!()"));
+        assert!(solution.contains("    1\n"));
+
+        // Python markers are plain code in Rust.
+        let doc = parse_code_string(
+            "#| << CODE\n# x\n#| >> SOLUTION <<\ny\n#| >> END_CODE\n",
+            "rust",
+        )
+        .unwrap();
+        assert_eq!(doc.solution_blocks(), 0);
+    }
+
+    #[test]
+    fn test_hints() {
+        let source = "def f(x):\n    #| << HINT\n    # Use a loop.\n    #   Or a comprehension.\n    #| >> END_HINT\n    #| << CODE\n    # ...\n    #| >> SOLUTION <<\n    return [2 * y for y in x]\n    #| >> END_CODE\n#| << HINT\n# Multiply <each> value.\n#| >> END_HINT\nf([1])\n";
+        let doc = parse_code_string(source, "python").unwrap();
+        assert_eq!(
+            doc.hints,
+            vec![
+                "Use a loop.\nOr a comprehension.".to_string(),
+                "Multiply <each> value.".to_string()
+            ]
+        );
+        let (placeholder, solution) = doc.split();
+        assert!(!placeholder.contains("loop") && !placeholder.contains("Multiply"));
+        assert!(!solution.contains("HINT"));
+        assert!(placeholder.contains("f([1])"));
+    }
+
+    #[test]
+    fn test_error_position() {
+        // Positions are relative to the code, not the comment symbol line added for the grammar.
+        let error = parse_code_string("#| << CODE\n", "python").unwrap_err();
+        assert_eq!(error.line_col, LineColLocation::Pos((2, 1)));
+        assert_eq!(error.location, InputLocation::Pos(11));
+    }
+
     #[test]
     fn test_serialize() {
         let str = include_str!("../../resources/test/sample.rs");
-        let doc = parse_code_string(str).unwrap();
+        let doc = parse_code_string(str, "rust").unwrap();
 
         let _res = serde_json::to_string(&doc).unwrap();
     }
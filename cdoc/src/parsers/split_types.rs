@@ -12,6 +12,10 @@ pub enum Content {
     Markup { markup: String },
     #[serde(rename = "code")]
     Code { code: String },
+    /// A hint for an exercise. It is left out of the code and listed in
+    /// [CodeTaskDefinition::hints].
+    #[serde(rename = "hint")]
+    Hint { hint: String },
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -53,9 +57,35 @@ pub enum Value {
 #[serde(rename = "document")]
 pub struct CodeTaskDefinition {
     pub blocks: Vec<Value>,
+    /// The hints of the code in order (see [Content::Hint]).
+    #[serde(default)]
+    pub hints: Vec<String>,
 }
 
 impl CodeTaskDefinition {
+    pub fn new(blocks: Vec<Value>) -> Self {
+        let hint = |content: &Content| match content {
+            Content::Hint { hint } => Some(hint.clone()),
+            _ => None,
+        };
+        let hints = blocks
+            .iter()
+            .flat_map(|value| match value {
+                Value::Block { block } => block
+                    .inner
+                    .iter()
+                    .filter_map(|inner| match inner {
+                        Inner::SrcBlock(content) => hint(content),
+                        Inner::SolutionBlock(_) => None,
+                    })
+                    .collect(),
+                Value::SrcBlock { content } => hint(content).into_iter().collect(),
+                Value::SolutionBlock(_) => vec![],
+            })
+            .collect();
+        CodeTaskDefinition { blocks, hints }
+    }
+
     #[allow(unused)]
     fn to_json(&self) -> String {
         serde_json::to_string(&self).expect("Could not construct JSON representation.")
@@ -83,6 +113,7 @@ impl Output for Content {
         match self {
             Content::Code { code: value } => value.to_string(),
             Content::Markup { markup: _value } => "".to_string(),
+            Content::Hint { .. } => "".to_string(),
         }
     }
 }
@@ -136,8 +167,63 @@ impl Output for CodeTaskDefinition {
     }
 }
 
+impl SolutionBlock {
+    /// Use `text` as the placeholder if the block doesn't define one. Each line of it is indented
+    /// like the first line of the solution.
+    fn fill_placeholder(&mut self, text: &str) {
+        if !self.placeholder.write_string(false).trim().is_empty() {
+            return;
+        }
+        let solution = self.solution.write_string(true);
+        let indent: String = solution
+            .lines()
+            .find(|line| !line.trim().is_empty())
+            .map(|line| line.chars().take_while(|c| c.is_whitespace()).collect())
+            .unwrap_or_default();
+        let code = text
+            .lines()
+            .map(|line| format!("{}{}\n", indent, line))
+            .collect();
+        self.placeholder.push(Content::Code { code });
+    }
+}
+
 impl CodeTaskDefinition {
     pub fn split(&self) -> (String, String) {
         (self.write_string(false), self.write_string(true))
     }
+
+    /// Use `text` as the placeholder of the placeholder/solution blocks (including nested ones)
+    /// that don't define one.
+    pub fn fill_placeholders(&mut self, text: &str) {
+        for value in self.blocks.iter_mut() {
+            match value {
+                Value::Block { block } => {
+                    for inner in block.inner.iter_mut() {
+                        if let Inner::SolutionBlock(solution) = inner {
+                            solution.fill_placeholder(text);
+                        }
+                    }
+                }
+                Value::SolutionBlock(solution) => solution.fill_placeholder(text),
+                Value::SrcBlock { .. } => {}
+            }
+        }
+    }
+
+    /// Number of placeholder/solution blocks, including those nested in other blocks.
+    pub fn solution_blocks(&self) -> usize {
+        self.blocks
+            .iter()
+            .map(|value| match value {
+                Value::Block { block } => block
+                    .inner
+                    .iter()
+                    .filter(|inner| matches!(inner, Inner::SolutionBlock(_)))
+                    .count(),
+                Value::SolutionBlock(_) => 1,
+                Value::SrcBlock { .. } => 0,
+            })
+            .sum()
+    }
 }
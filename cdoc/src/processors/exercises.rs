@@ -1,24 +1,106 @@
+use pulldown_cmark::escape::escape_html;
 use serde::{Deserialize, Serialize};
 use std::fmt::{Display, Formatter};
 
 use crate::ast::{ACodeBlockKind, AEvent, ATag};
-use crate::document::{DocPos, Document, EventContent};
+use crate::config::OutputFormat;
+use crate::document::{DocPos, Document, DocumentVariables, DocumentWarning, EventContent};
 use crate::parsers::split::{human_errors, parse_code_string};
 use crate::processors::Error::CodeParseError;
 use crate::processors::{Error, EventPreprocessor, EventPreprocessorConfig, PreprocessorContext};
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct ExercisesConfig;
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ExercisesConfig {
+    /// Placeholder used for blocks that don't define one, e.g. `# YOUR CODE HERE`.
+    #[serde(default)]
+    pub placeholder: Option<String>,
+}
 
 #[typetag::serde(name = "code_split")]
 impl EventPreprocessorConfig for ExercisesConfig {
-    fn build(&self, _ctx: &PreprocessorContext) -> anyhow::Result<Box<dyn EventPreprocessor>> {
-        Ok(Box::new(Exercises))
+    fn build(&self, ctx: &PreprocessorContext) -> anyhow::Result<Box<dyn EventPreprocessor>> {
+        Ok(Box::new(Exercises {
+            include_solutions: ctx.include_solutions,
+            placeholder: self.placeholder.clone(),
+            output_format: ctx.output_format,
+        }))
     }
 }
 
+/// Splits exercise code blocks into placeholders and solutions. The placeholders are shown
+/// unless solutions are included (for instructor builds), either for the output format or by
+/// the `code_solutions` option of the document.
 #[derive(Debug)]
-pub struct Exercises;
+pub struct Exercises {
+    pub include_solutions: bool,
+    /// Placeholder for blocks that don't define one (see [ExercisesConfig]).
+    pub placeholder: Option<String>,
+    pub output_format: OutputFormat,
+}
+
+impl Default for Exercises {
+    fn default() -> Self {
+        Exercises {
+            include_solutions: false,
+            placeholder: None,
+            output_format: OutputFormat::Html,
+        }
+    }
+}
+
+/// Fence option that allows a placeholder to be empty (e.g. ```` ```python allow_empty ````).
+pub const ALLOW_EMPTY: &str = "allow_empty";
+
+/// Remove the [ALLOW_EMPTY] option from a fence info string. Returns the remaining info and
+/// whether the option was present.
+fn strip_allow_empty(info: &str) -> (String, bool) {
+    let mut allow = false;
+    let remaining = info
+        .split_whitespace()
+        .filter(|word| {
+            let is_option = *word == ALLOW_EMPTY;
+            allow |= is_option;
+            !is_option
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    (remaining, allow)
+}
+
+/// The events of a hint of an exercise. HTML formats get a toggle that reveals the hint and
+/// notebooks a paragraph with the hint after a bold label. The label is numbered if the code
+/// block has more than one hint.
+fn hint_events(format: OutputFormat, hint: &str, number: usize, count: usize) -> Vec<AEvent> {
+    let label = if count > 1 {
+        format!("Hint {}", number)
+    } else {
+        "Hint".to_string()
+    };
+    match format {
+        OutputFormat::Html | OutputFormat::Pdf | OutputFormat::Epub => {
+            let mut text = String::new();
+            escape_html(&mut text, hint).expect("Invalid format");
+            vec![AEvent::Html(format!(
+                "<details class=\"hint\">\n<summary>{}</summary>\n<p>{}</p>\n</details>\n",
+                label, text
+            ))]
+        }
+        _ => vec![
+            AEvent::Start(ATag::Paragraph),
+            AEvent::Start(ATag::Strong),
+            AEvent::Text(format!("{}:", label)),
+            AEvent::End(ATag::Strong),
+            AEvent::Text(format!(" {}", hint)),
+            AEvent::End(ATag::Paragraph),
+        ],
+    }
+}
+
+/// A placeholder that is empty while the solution is not is most likely an authoring mistake
+/// (e.g. the whole block was marked as solution).
+fn empty_placeholder(placeholder: &str, solution: &str) -> bool {
+    placeholder.trim().is_empty() && !solution.trim().is_empty()
+}
 
 impl EventPreprocessor for Exercises {
     fn name(&self) -> String {
@@ -26,9 +108,21 @@ impl EventPreprocessor for Exercises {
     }
 
     fn process(&self, input: Document<EventContent>) -> Result<Document<EventContent>, Error> {
+        if !input.metadata.code_split_enabled() {
+            return Ok(input);
+        }
+        let include_solutions = input
+            .metadata
+            .code_solutions
+            .unwrap_or(self.include_solutions);
+
         let mut code_block = false;
         let mut source = "".to_string();
         let mut code_attr = String::new();
+        let mut allow_empty = false;
+        let mut exercises = 0;
+        let mut hints = Vec::new();
+        let mut warnings = input.variables.warnings.clone();
 
         let content = input
             .content
@@ -37,23 +131,51 @@ impl EventPreprocessor for Exercises {
                 AEvent::Start(tag) => {
                     if let ATag::CodeBlock(ACodeBlockKind::Fenced(attr)) = &tag {
                         code_block = true;
-                        code_attr = attr.to_string();
+                        (code_attr, allow_empty) = strip_allow_empty(attr);
+                        let tag = ATag::CodeBlock(ACodeBlockKind::Fenced(code_attr.clone()));
+                        vec![Ok((AEvent::Start(tag), pos))]
+                    } else {
+                        vec![Ok((AEvent::Start(tag.clone()), pos))]
                     }
-                    vec![Ok((AEvent::Start(tag.clone()), pos))]
                 }
                 AEvent::End(tag) => {
                     if let ATag::CodeBlock(ACodeBlockKind::Fenced(_)) = tag {
-                        // TODO: Here
-                        let res = parse_code_string(source.clone().as_ref());
+                        let res = parse_code_string(source.as_ref(), &code_attr);
                         code_block = false;
                         source = String::new();
                         match res {
-                            Ok(doc) => {
-                                let (placeholder, _solution) = doc.split();
-                                vec![
-                                    Ok((AEvent::Text(placeholder.trim().to_string()), pos.clone())),
-                                    Ok((AEvent::End(tag.clone()), pos)),
-                                ]
+                            Ok(mut doc) => {
+                                if let (Some(text), false) = (&self.placeholder, allow_empty) {
+                                    doc.fill_placeholders(text);
+                                }
+                                exercises += doc.solution_blocks();
+                                let (placeholder, solution) = doc.split();
+                                if !allow_empty && empty_placeholder(&placeholder, &solution) {
+                                    warnings.push(DocumentWarning {
+                                        position: Some((&pos).into()),
+                                        message: format!(
+                                            "Empty placeholder for a code block with a solution. Add '{}' to the code block options if this is intended.",
+                                            ALLOW_EMPTY
+                                        ),
+                                    });
+                                }
+                                let tag = ATag::CodeBlock(ACodeBlockKind::Fenced(code_attr.clone()));
+                                let code = if include_solutions { solution } else { placeholder };
+                                let mut events = vec![
+                                    Ok((AEvent::Text(code.trim().to_string()), pos.clone())),
+                                    Ok((AEvent::End(tag), pos.clone())),
+                                ];
+                                for (i, hint) in doc.hints.iter().enumerate() {
+                                    let hint = hint_events(
+                                        self.output_format,
+                                        hint,
+                                        i + 1,
+                                        doc.hints.len(),
+                                    );
+                                    events.extend(hint.into_iter().map(|e| Ok((e, pos.clone()))));
+                                }
+                                hints.extend(doc.hints);
+                                events
                             }
                             Err(e) => vec![Err(CodeParseError(human_errors(*e), pos))],
                         }
@@ -75,7 +197,12 @@ impl EventPreprocessor for Exercises {
 
         Ok(Document {
             metadata: input.metadata,
-            variables: input.variables,
+            variables: DocumentVariables {
+                exercises,
+                hints,
+                warnings,
+                ..input.variables
+            },
             content,
         })
     }
@@ -86,3 +213,226 @@ impl Display for Exercises {
         write!(f, "{}", self.name())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::{DocType, DocumentMetadata, IteratorConfig, SourcePosition};
+    use crate::loader::{Loader, MarkdownLoader};
+
+    const SOURCE: &str =
+        "#| << CODE\n# print(\"hello\")\n#| >> SOLUTION <<\nprint(\"solution\")\n#| >> END_CODE\n";
+
+    fn document(metadata: DocumentMetadata) -> Document<EventContent> {
+        let pos = DocPos::default();
+        let block = ATag::CodeBlock(ACodeBlockKind::Fenced("python".to_string()));
+        Document {
+            content: vec![
+                (AEvent::Start(block.clone()), pos.clone()),
+                (AEvent::Text(SOURCE.to_string()), pos.clone()),
+                (AEvent::End(block), pos),
+            ],
+            metadata,
+            variables: DocumentVariables::default(),
+        }
+    }
+
+    #[test]
+    fn test_exercise_count() {
+        let res = Exercises::default()
+            .process(document(Default::default()))
+            .unwrap();
+        assert_eq!(res.variables.exercises, 1);
+
+        let text = DocumentMetadata {
+            doc_type: Some(DocType::Text),
+            ..Default::default()
+        };
+        let res = Exercises::default().process(document(text)).unwrap();
+        assert_eq!(res.variables.exercises, 0);
+
+        let mut prose = document(Default::default());
+        prose.content = vec![(AEvent::Text("Just text".to_string()), DocPos::default())];
+        let res = Exercises::default().process(prose).unwrap();
+        assert_eq!(res.variables.exercises, 0);
+    }
+
+    fn code(doc: Document<EventContent>) -> String {
+        doc.content
+            .into_iter()
+            .filter_map(|(e, _)| match e {
+                AEvent::Text(txt) => Some(txt),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_split_by_doc_type() {
+        let exercise = DocumentMetadata {
+            doc_type: Some(DocType::Exercise),
+            ..Default::default()
+        };
+        let res = code(Exercises::default().process(document(exercise)).unwrap());
+        assert_eq!(res, "print(\"hello\")");
+
+        let text = DocumentMetadata {
+            doc_type: Some(DocType::Text),
+            ..Default::default()
+        };
+        let res = code(Exercises::default().process(document(text)).unwrap());
+        assert_eq!(res, SOURCE);
+    }
+
+    #[test]
+    fn test_empty_placeholder() {
+        assert!(empty_placeholder("\n", "print(1)\n"));
+        assert!(!empty_placeholder("# code here", "print(1)"));
+        assert!(!empty_placeholder("", ""));
+
+        assert_eq!(
+            strip_allow_empty("python allow_empty"),
+            ("python".to_string(), true)
+        );
+        assert_eq!(strip_allow_empty("python"), ("python".to_string(), false));
+
+        // Empty placeholders are reported as warnings of the document, at the line of the code
+        // block in the source file.
+        let doc = MarkdownLoader
+            .load(
+                "---\ntitle: Doc\n---\nSome text.\n\n```python\n#| << CODE\n#| >> SOLUTION <<\n\
+                 print(1)\n#| >> END_CODE\n```\n",
+            )
+            .unwrap()
+            .to_events(IteratorConfig::default());
+        let res = Exercises::default().process(doc).unwrap();
+        assert_eq!(res.variables.warnings.len(), 1);
+        assert_eq!(
+            res.variables.warnings[0].position,
+            Some(SourcePosition {
+                cell: None,
+                line: 6,
+                column: None
+            })
+        );
+    }
+
+    #[test]
+    fn test_configured_placeholder() {
+        let exercises = Exercises {
+            placeholder: Some("# YOUR CODE HERE".to_string()),
+            ..Default::default()
+        };
+        // Blocks with a placeholder keep it.
+        let res = code(exercises.process(document(Default::default())).unwrap());
+        assert_eq!(res, "print(\"hello\")");
+
+        let mut doc = document(Default::default());
+        doc.content[1].0 = AEvent::Text(
+            "def f():\n    #| << CODE\n    #| >> SOLUTION <<\n    return 1\n    #| >> END_CODE\n"
+                .to_string(),
+        );
+        let res = code(exercises.process(doc.clone()).unwrap());
+        assert_eq!(res, "def f():\n    # YOUR CODE HERE");
+
+        // Without the setting the placeholder stays empty.
+        let res = code(Exercises::default().process(doc).unwrap());
+        assert_eq!(res, "def f():");
+    }
+
+    #[test]
+    fn test_hints() {
+        let mut doc = document(Default::default());
+        doc.content[1].0 = AEvent::Text(format!(
+            "#| << HINT\n# Print <it>.\n#| >> END_HINT\n#| << HINT\n# Use print.\n#| >> END_HINT\n{}",
+            SOURCE
+        ));
+        let res = Exercises::default().process(doc.clone()).unwrap();
+        assert_eq!(res.variables.hints, vec!["Print <it>.", "Use print."]);
+
+        let html: Vec<String> = res
+            .content
+            .iter()
+            .filter_map(|(e, _)| match e {
+                AEvent::Html(html) => Some(html.clone()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(html.len(), 2);
+        assert!(html[0].contains("<summary>Hint 1</summary>\n<p>Print &lt;it&gt;.</p>"));
+        assert!(html[1].contains("<summary>Hint 2</summary>"));
+        assert_eq!(code(res), "print(\"hello\")");
+
+        // Notebooks get the hints as markdown.
+        let notebook = Exercises {
+            output_format: OutputFormat::Notebook,
+            ..Default::default()
+        };
+        let res = notebook.process(doc).unwrap();
+        assert!(!res
+            .content
+            .iter()
+            .any(|(e, _)| matches!(e, AEvent::Html(_))));
+        assert_eq!(
+            code(res),
+            "print(\"hello\")Hint 1: Print <it>.Hint 2: Use print."
+        );
+    }
+
+    #[test]
+    fn test_language() {
+        let mut doc = document(Default::default());
+        let block = ATag::CodeBlock(ACodeBlockKind::Fenced("rust".to_string()));
+        doc.content[0].0 = AEvent::Start(block.clone());
+        doc.content[1].0 = AEvent::Text(
+            "//| << CODE\n// todo!()\n//| >> SOLUTION <<\n1\n//| >> END_CODE\n".to_string(),
+        );
+        doc.content[2].0 = AEvent::End(block);
+        let res = Exercises::default().process(doc).unwrap();
+        assert_eq!(res.variables.exercises, 1);
+        assert_eq!(code(res), "todo!()");
+    }
+
+    #[test]
+    fn test_code_split_overrides_doc_type() {
+        let text = DocumentMetadata {
+            doc_type: Some(DocType::Text),
+            code_split: Some(true),
+            ..Default::default()
+        };
+        let res = code(Exercises::default().process(document(text)).unwrap());
+        assert_eq!(res, "print(\"hello\")");
+    }
+
+    #[test]
+    fn test_include_solutions() {
+        let exercises = Exercises {
+            include_solutions: true,
+            ..Default::default()
+        };
+        let res = exercises.process(document(Default::default())).unwrap();
+        assert_eq!(res.variables.exercises, 1);
+        assert_eq!(code(res), "print(\"solution\")");
+
+        let res = code(
+            Exercises::default()
+                .process(document(Default::default()))
+                .unwrap(),
+        );
+        assert_eq!(res, "print(\"hello\")");
+
+        // The document option wins over the setting.
+        let hidden = DocumentMetadata {
+            code_solutions: Some(false),
+            ..Default::default()
+        };
+        let res = code(exercises.process(document(hidden)).unwrap());
+        assert_eq!(res, "print(\"hello\")");
+        let shown = DocumentMetadata {
+            code_solutions: Some(true),
+            ..Default::default()
+        };
+        let res = code(Exercises::default().process(document(shown)).unwrap());
+        assert_eq!(res, "print(\"solution\")");
+    }
+}
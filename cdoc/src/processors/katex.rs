@@ -1,37 +1,157 @@
 use katex::Opts;
 use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
-use thiserror::Error;
 
-use crate::processors::{MarkdownPreprocessor, PreprocessorConfig, PreprocessorContext};
+use pulldown_cmark::escape::escape_html;
+
+use crate::document::DocumentWarning;
+use crate::processors::math::{default_display, default_inline, render_math};
+use crate::processors::{
+    Error, MarkdownPreprocessor, PreprocessorConfig, PreprocessorContext, Warnings,
+};
+use crate::utils::escape_punctuation;
+
+pub use crate::processors::math::Delimiter;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct KaTeXConfig;
+pub struct KaTeXConfig {
+    /// Delimiters of inline math. Defaults to `$...$`.
+    #[serde(default = "default_inline")]
+    pub inline: Vec<Delimiter>,
+    /// Delimiters of display math. Defaults to `$$...$$`.
+    #[serde(default = "default_display")]
+    pub display: Vec<Delimiter>,
+    /// LaTeX macros, e.g. `\R` for `\mathbb{R}`. They are added to the macros of the project
+    /// and take precedence over them.
+    #[serde(default)]
+    pub macros: HashMap<String, String>,
+    /// Fail on math that can't be rendered. Otherwise the math is shown as written (in a
+    /// `katex-error` element with the error as its title) and the document gets a warning.
+    #[serde(default = "default_strict")]
+    pub strict: bool,
+}
+
+fn default_strict() -> bool {
+    true
+}
+
+impl Default for KaTeXConfig {
+    fn default() -> Self {
+        KaTeXConfig {
+            inline: default_inline(),
+            display: default_display(),
+            macros: HashMap::new(),
+            strict: default_strict(),
+        }
+    }
+}
 
 #[typetag::serde(name = "katex")]
 impl PreprocessorConfig for KaTeXConfig {
-    fn build(&self, _ctx: &PreprocessorContext) -> anyhow::Result<Box<dyn MarkdownPreprocessor>> {
-        Ok(Box::new(KaTeX))
+    fn build(&self, ctx: &PreprocessorContext) -> anyhow::Result<Box<dyn MarkdownPreprocessor>> {
+        let mut macros = ctx.math_macros.clone();
+        macros.extend(self.macros.clone());
+        Ok(Box::new(KaTeX {
+            inline: self.inline.clone(),
+            display: self.display.clone(),
+            macros,
+            strict: self.strict,
+            warnings: ctx.warnings.clone(),
+            cache: RefCell::default(),
+        }))
     }
-}
 
-#[derive(Error, Debug)]
-pub enum KaTeXPreprocessorError {}
+    fn uses_katex(&self) -> bool {
+        true
+    }
+}
 
 #[derive(Debug)]
-pub struct KaTeX;
+pub struct KaTeX {
+    pub inline: Vec<Delimiter>,
+    pub display: Vec<Delimiter>,
+    pub macros: HashMap<String, String>,
+    pub strict: bool,
+    pub warnings: Warnings,
+    /// Rendered math by source and display mode. Documents tend to repeat the same expressions
+    /// (e.g. `$x$`), which are then only rendered once.
+    cache: RefCell<HashMap<(String, bool), String>>,
+}
 
-fn find_block(input: &str) -> Option<(usize, usize, usize)> {
-    let begin = input.find('$')?;
-    let end_delim = if &input[(begin + 1)..(begin + 2)] == "$" {
-        "$$"
-    } else {
-        "$"
-    };
+impl KaTeX {
+    /// Render math with `render`, unless the same source has been rendered in the same mode.
+    fn render_cached(
+        &self,
+        source: &str,
+        display: bool,
+        render: impl FnOnce(&str, bool) -> Result<String, Error>,
+    ) -> Result<String, Error> {
+        let key = (source.to_string(), display);
+        if let Some(html) = self.cache.borrow().get(&key) {
+            return Ok(html.clone());
+        }
+        let html = render(source, display)?;
+        self.cache.borrow_mut().insert(key, html.clone());
+        Ok(html)
+    }
 
-    let end = begin + end_delim.len() + input[begin + end_delim.len()..].find(end_delim)?;
+    fn opts(&self, display: bool) -> Opts {
+        Opts::builder()
+            .display_mode(display)
+            .macros(self.macros.clone())
+            .build()
+            .expect("KaTeX options have defaults")
+    }
 
-    Some((begin, end, end_delim.len()))
+    /// Render math with the given options. In lenient mode, math that can't be rendered is shown
+    /// as written and a warning is added to the document.
+    fn typeset(
+        &self,
+        source: &str,
+        display: bool,
+        written: &str,
+        opts: &Opts,
+    ) -> Result<String, Error> {
+        let res = self.render_cached(source, display, |source, _| {
+            Ok(katex::render_with_opts(source, opts)?)
+        });
+        match res {
+            Err(Error::KaTeXError(e)) if !self.strict => {
+                self.warnings.push(DocumentWarning {
+                    position: None,
+                    message: format!("Could not render math {}: {}", written, e),
+                });
+                Ok(error_element(written, &e.to_string()))
+            }
+            res => res,
+        }
+    }
+}
+
+/// Math that couldn't be rendered, shown as written with the error as its title.
+fn error_element(written: &str, error: &str) -> String {
+    let mut title = String::new();
+    escape_html(&mut title, error).expect("Invalid format");
+    format!(
+        "<span class=\"katex-error\" title=\"{}\">{}</span>",
+        title,
+        escape_punctuation(written)
+    )
+}
+
+impl Default for KaTeX {
+    fn default() -> Self {
+        KaTeX {
+            inline: default_inline(),
+            display: default_display(),
+            macros: HashMap::new(),
+            strict: default_strict(),
+            warnings: Warnings::default(),
+            cache: RefCell::default(),
+        }
+    }
 }
 
 impl MarkdownPreprocessor for KaTeX {
@@ -39,34 +159,21 @@ impl MarkdownPreprocessor for KaTeX {
         "KaTeX preprocessor".to_string()
     }
 
-    fn process(&self, input: &str, _ctx: &tera::Context) -> Result<String, anyhow::Error> {
-        let mut rest = input;
-        let mut res = String::new();
-
-        while !rest.is_empty() {
-            match find_block(rest) {
-                Some((begin, end, delim_len)) => {
-                    let pre = &rest[..begin];
-                    let post = &rest[(end + delim_len)..];
-
-                    let source = &rest[(begin + delim_len)..end];
-
-                    let opts = Opts::builder().display_mode(delim_len == 2).build()?;
-                    let ktex = katex::render_with_opts(source, opts)?;
-
-                    res.push_str(pre);
-                    res.push_str(&ktex);
-
-                    rest = post;
-                }
-                None => {
-                    res.push_str(rest);
-                    rest = ""
-                }
-            }
-        }
+    fn process(&self, input: &str, _ctx: &tera::Context) -> Result<String, Error> {
+        let (inline_opts, display_opts) = (self.opts(false), self.opts(true));
+        render_math(
+            input,
+            &self.inline,
+            &self.display,
+            |source, display, written| {
+                let opts = if display { &display_opts } else { &inline_opts };
+                self.typeset(source, display, written, opts)
+            },
+        )
+    }
 
-        Ok(res)
+    fn typeset_math(&self, source: &str, display: bool) -> Option<Result<String, Error>> {
+        Some(self.typeset(source, display, source, &self.opts(display)))
     }
 }
 
@@ -79,15 +186,27 @@ impl Display for KaTeX {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::OutputFormat;
+    use crate::document::{Element, IteratorConfig};
+    use crate::loader::{Loader, NotebookLoader};
+    use crate::processors::numbering::ListingNumbers;
 
     fn parse(input: &str) -> String {
         let context = tera::Context::new();
-        let processor = KaTeX;
+        let processor = KaTeX::default();
         processor
             .process(input, &context)
             .expect("KaTeX parse error")
     }
 
+    #[test]
+    fn invalid_input() {
+        let err = KaTeX::default()
+            .process(r#"$\frac{2}$"#, &tera::Context::new())
+            .expect_err("Invalid math should fail");
+        assert!(matches!(err, Error::KaTeXError(_)));
+    }
+
     #[test]
     fn inline_mode() {
         let input = r#"some input $\frac{2}{3}$"#;
@@ -111,4 +230,185 @@ mod tests {
             "KaTeX should always produce the same output."
         )
     }
+
+    #[test]
+    fn custom_delimiters() {
+        let config: KaTeXConfig = serde_yaml::from_str(
+            r#"{inline: [["\\(", "\\)"], ["$", "$"]], display: [["\\[", "\\]"], ["$$", "$$"]]}"#,
+        )
+        .unwrap();
+        let processor = KaTeX {
+            inline: config.inline,
+            display: config.display,
+            macros: config.macros,
+            strict: config.strict,
+            warnings: Warnings::default(),
+            cache: RefCell::default(),
+        };
+        let render = |input: &str| processor.process(input, &tera::Context::new()).unwrap();
+
+        assert_eq!(render(r"a \(x^2\) b"), render("a $x^2$ b"));
+        assert_eq!(render(r"a \[x^2\] b"), render("a $$x^2$$ b"));
+        assert!(render(r"\[x\]").starts_with("<span class=\"katex-display\">"));
+        assert!(!render(r"\(x\)").contains("katex-display"));
+
+        // Unclosed delimiters are left as text.
+        assert_eq!(render("costs $5"), "costs $5");
+        assert_eq!(parse("costs $"), "costs $");
+    }
+
+    #[test]
+    fn lenient() {
+        let processor = KaTeX {
+            strict: false,
+            ..KaTeX::default()
+        };
+        let output = processor
+            .process(
+                "Broken $\\frac{2}$ and valid $x$ math.",
+                &tera::Context::new(),
+            )
+            .unwrap();
+
+        let (broken, valid) = output.split_once(" and ").unwrap();
+        assert!(broken.starts_with("Broken <span class=\"katex-error\" title=\""));
+        assert!(broken.contains("KaTeX parse error"));
+        assert!(broken.ends_with(">&#36;&#92;frac&#123;2&#125;&#36;</span>"));
+        assert_eq!(valid, parse("valid $x$ math."));
+
+        let warnings = processor.warnings.take();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0]
+            .message
+            .starts_with("Could not render math $\\frac{2}$:"));
+        assert!(warnings[0].message.contains("KaTeX parse error"));
+    }
+
+    #[test]
+    fn cache() {
+        let processor = KaTeX::default();
+        let calls = std::cell::Cell::new(0);
+        let render = |source: &str, display: bool| {
+            processor.render_cached(source, display, |source, display| {
+                calls.set(calls.get() + 1);
+                Ok(format!("{}:{}", source, display))
+            })
+        };
+
+        assert_eq!(render("x", false).unwrap(), "x:false");
+        assert_eq!(render("x", false).unwrap(), "x:false");
+        assert_eq!(calls.get(), 1);
+        // The display mode is part of the key.
+        assert_eq!(render("x", true).unwrap(), "x:true");
+        assert_eq!(calls.get(), 2);
+
+        // Repeated expressions in a document give the same output.
+        let output = processor
+            .process("$y$ and $y$", &tera::Context::new())
+            .unwrap();
+        let (first, second) = output.split_once(" and ").unwrap();
+        assert_eq!(first, second);
+        assert_eq!(processor.cache.borrow().len(), 3);
+    }
+
+    #[test]
+    fn macros() {
+        let config: KaTeXConfig =
+            serde_yaml::from_str(r#"{macros: {'\field': '\mathbb{K}'}}"#).unwrap();
+        let ctx = PreprocessorContext {
+            tera: Default::default(),
+            output_format: OutputFormat::Html,
+            used_templates: Default::default(),
+            include_solutions: false,
+            listing_numbers: ListingNumbers::default(),
+            math_macros: HashMap::from([
+                (r"\vx".to_string(), r"\mathbf{x}".to_string()),
+                (r"\field".to_string(), r"\mathbb{F}".to_string()),
+            ]),
+            warnings: Default::default(),
+        };
+        let processor = config.build(&ctx).unwrap();
+        let render = |input: &str| processor.process(input, &tera::Context::new()).unwrap();
+        // The rendered MathML, without the source annotation.
+        let mathml = |output: String| {
+            let start = output.find("<semantics>").unwrap();
+            let end = output.find("<annotation").unwrap();
+            output[start..end].to_string()
+        };
+
+        assert_eq!(
+            mathml(render(r"$\vx \in \field$")),
+            mathml(parse(r"$\mathbf{x} \in \mathbb{K}$"))
+        );
+        assert_eq!(
+            mathml(render(r"$$\vx^2$$")),
+            mathml(parse(r"$$\mathbf{x}^2$$"))
+        );
+        // Without the macros, the commands are unknown.
+        assert!(KaTeX::default()
+            .process(r"$\vx$", &tera::Context::new())
+            .is_err());
+    }
+
+    #[test]
+    fn notebook_markdown_cells() {
+        let notebook = r#"{
+            "metadata": {}, "nbformat": 4, "nbformat_minor": 5,
+            "cells": [{"cell_type": "markdown", "metadata": {}, "source": ["Display math:\n", "\\[x^2\\]"]}]
+        }"#;
+        let doc = NotebookLoader::default().load(notebook).unwrap();
+        let processor = KaTeXConfig {
+            inline: default_inline(),
+            display: vec![("\\[".to_string(), "\\]".to_string())],
+            macros: HashMap::new(),
+            strict: true,
+        };
+        let ctx = PreprocessorContext {
+            tera: Default::default(),
+            output_format: OutputFormat::Html,
+            used_templates: Default::default(),
+            include_solutions: false,
+            listing_numbers: ListingNumbers::default(),
+            math_macros: HashMap::new(),
+            warnings: Default::default(),
+        };
+        let processor = processor.build(&ctx).unwrap();
+        let doc = doc
+            .preprocess(processor.as_ref(), &tera::Context::new())
+            .unwrap();
+
+        match &doc.content[0] {
+            Element::Markdown { content, .. } => {
+                assert!(content.starts_with("Display math:\n<span class=\"katex-display\">"));
+                assert!(!content.contains("\\["));
+            }
+            _ => panic!("Expected a markdown cell"),
+        }
+    }
+
+    #[test]
+    fn notebook_latex_outputs() {
+        let notebook = r#"{
+            "metadata": {}, "nbformat": 4, "nbformat_minor": 5,
+            "cells": [{"cell_type": "code", "metadata": {}, "source": ["x**2"], "execution_count": 1,
+                       "outputs": [{"output_type": "execute_result", "execution_count": 1, "metadata": {},
+                                    "data": {"text/latex": ["$\\displaystyle x^{2}$"]}}]}]
+        }"#;
+        let doc = NotebookLoader::default().load(notebook).unwrap();
+        let doc = doc
+            .preprocess(&KaTeX::default(), &tera::Context::new())
+            .unwrap();
+
+        let mut html = String::new();
+        pulldown_cmark::html::push_html(
+            &mut html,
+            doc.events(IteratorConfig {
+                include_output: true,
+                ..IteratorConfig::default()
+            })
+            .map(|(e, _)| e),
+        );
+        assert!(html.contains(&parse("$$\\displaystyle x^{2}$$")));
+        assert!(!html.contains("$$"));
+    }
 }
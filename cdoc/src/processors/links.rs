@@ -0,0 +1,218 @@
+use std::fmt::{Display, Formatter};
+
+use crate::ast::{AEvent, ATag};
+use crate::document::{Document, EventContent};
+use crate::processors::{Error, EventPreprocessor};
+
+/// Prepend `prefix` to a root-relative URL. Returns None if the URL is not root-relative (external,
+/// protocol-relative or relative URLs) or if it already starts with the prefix.
+pub fn prefix_url(url: &str, prefix: &str) -> Option<String> {
+    if prefix.is_empty() || !url.starts_with('/') || url.starts_with("//") {
+        return None;
+    }
+    let has_prefix = matches!(url.strip_prefix(prefix),
+        Some(rest) if rest.is_empty() || rest.starts_with(['/', '#', '?']));
+    (!has_prefix).then(|| format!("{}{}", prefix, url))
+}
+
+/// Prepend `prefix` to a relative URL (e.g. `other.html` or `../img.png`). Returns None for other
+/// URLs: root-relative, protocol-relative, with a scheme (e.g. `https:` or `mailto:`) or only a
+/// fragment or query.
+pub fn prefix_relative_url(url: &str, prefix: &str) -> Option<String> {
+    let scheme = url
+        .find(':')
+        .is_some_and(|i| !url[..i].contains(['/', '?', '#']));
+    if prefix.is_empty() || url.is_empty() || url.starts_with(['/', '#', '?']) || scheme {
+        return None;
+    }
+    Some(format!("{}{}", prefix, url))
+}
+
+/// Rewrite the values of the given attributes (e.g. `href` and `src`) in raw HTML. Values for
+/// which `rewrite` returns None are kept.
+pub fn rewrite_html(
+    html: &str,
+    attributes: &[&str],
+    mut rewrite: impl FnMut(&str) -> Option<String>,
+) -> String {
+    let mut out = html.to_string();
+    for attr in attributes {
+        let attr = format!(" {}=\"", attr);
+        let mut result = String::with_capacity(out.len());
+        let mut rest = out.as_str();
+        while let Some(start) = rest.find(&attr) {
+            let value_start = start + attr.len();
+            result.push_str(&rest[..value_start]);
+            rest = &rest[value_start..];
+
+            let end = rest.find('"').unwrap_or(rest.len());
+            match rewrite(&rest[..end]) {
+                Some(url) => result.push_str(&url),
+                None => result.push_str(&rest[..end]),
+            }
+            rest = &rest[end..];
+        }
+        result.push_str(rest);
+        out = result;
+    }
+    out
+}
+
+/// Makes root-relative links work when the site is deployed under a subdirectory by prepending the
+/// path prefix (the project's `url_prefix`) to the URLs of links and images, including the `href`
+/// and `src` attributes of raw HTML. Links that already include the prefix are not changed.
+///
+/// Relative URLs get the `relative` prefix instead, e.g. `../` for pages that are written one
+/// folder deeper than their document.
+///
+/// The processor is added by the build pipeline since the prefixes depend on the project
+/// configuration.
+#[derive(Debug)]
+pub struct LinkPrefix {
+    pub prefix: String,
+    pub relative: String,
+}
+
+impl LinkPrefix {
+    fn rewrite(&self, url: &str) -> Option<String> {
+        prefix_url(url, &self.prefix).or_else(|| prefix_relative_url(url, &self.relative))
+    }
+
+    fn prefix_tag(&self, tag: ATag) -> ATag {
+        match tag {
+            ATag::Link(typ, url, title) => {
+                let url = self.rewrite(&url).unwrap_or(url);
+                ATag::Link(typ, url, title)
+            }
+            ATag::Image(typ, url, title) => {
+                let url = self.rewrite(&url).unwrap_or(url);
+                ATag::Image(typ, url, title)
+            }
+            tag => tag,
+        }
+    }
+}
+
+impl EventPreprocessor for LinkPrefix {
+    fn name(&self) -> String {
+        "Link prefix".to_string()
+    }
+
+    fn process(&self, input: Document<EventContent>) -> Result<Document<EventContent>, Error> {
+        let content = input
+            .content
+            .into_iter()
+            .map(|(event, pos)| {
+                let event = match event {
+                    AEvent::Start(tag) => AEvent::Start(self.prefix_tag(tag)),
+                    AEvent::End(tag) => AEvent::End(self.prefix_tag(tag)),
+                    AEvent::Html(html) => {
+                        AEvent::Html(rewrite_html(&html, &["href", "src"], |url| {
+                            self.rewrite(url)
+                        }))
+                    }
+                    event => event,
+                };
+                (event, pos)
+            })
+            .collect();
+
+        Ok(Document {
+            metadata: input.metadata,
+            variables: input.variables,
+            content,
+        })
+    }
+}
+
+impl Display for LinkPrefix {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::{DocumentMetadata, IteratorConfig, RawContent};
+    use crate::renderers::html::HtmlRenderer;
+    use crate::renderers::Renderer;
+
+    #[test]
+    fn test_prefix_url() {
+        assert_eq!(
+            prefix_url("/part/doc.html", "/courses"),
+            Some("/courses/part/doc.html".to_string())
+        );
+        assert_eq!(prefix_url("/courses/part", "/courses"), None);
+        assert_eq!(prefix_url("/courses#top", "/courses"), None);
+        assert_eq!(
+            prefix_url("/coursesware", "/courses"),
+            Some("/courses/coursesware".to_string())
+        );
+        assert_eq!(prefix_url("https://example.com/a", "/courses"), None);
+        assert_eq!(prefix_url("//cdn.example.com/a.js", "/courses"), None);
+        assert_eq!(prefix_url("doc.html", "/courses"), None);
+        assert_eq!(prefix_url("/part", ""), None);
+    }
+
+    #[test]
+    fn test_render_with_prefix() {
+        let source =
+            "[Doc](/part/doc.html) [Prefixed](/courses/part) [External](https://example.com/) \
+            [Relative](other.html)\n\n![Plot](/resources/plot.png)\n\n\
+            <img src=\"/resources/logo.png\"> <a href=\"https://example.com/\">x</a>\n";
+        let doc = Document::<RawContent>::new(source.to_string(), DocumentMetadata::default())
+            .to_events(IteratorConfig::default());
+
+        let doc = LinkPrefix {
+            prefix: "/courses".to_string(),
+            relative: String::new(),
+        }
+        .process(doc)
+        .unwrap();
+        let html = HtmlRenderer.render(&doc).content;
+        let html = html.as_text().unwrap();
+
+        assert!(html.contains("<a href=\"/courses/part/doc.html\">Doc</a>"));
+        assert!(html.contains("<a href=\"/courses/part\">Prefixed</a>"));
+        assert!(html.contains("<a href=\"https://example.com/\">External</a>"));
+        assert!(html.contains("<a href=\"other.html\">Relative</a>"));
+        assert!(html.contains("<img src=\"/courses/resources/plot.png\" alt=\"Plot\""));
+        assert!(html.contains("<img src=\"/courses/resources/logo.png\">"));
+        assert!(html.contains("<a href=\"https://example.com/\">x</a>"));
+    }
+
+    #[test]
+    fn test_relative_prefix() {
+        assert_eq!(
+            prefix_relative_url("other.html#a", "../"),
+            Some("../other.html#a".to_string())
+        );
+        assert_eq!(
+            prefix_relative_url("../img.png", "../"),
+            Some("../../img.png".to_string())
+        );
+        assert_eq!(prefix_relative_url("/part/doc.html", "../"), None);
+        assert_eq!(prefix_relative_url("#top", "../"), None);
+        assert_eq!(prefix_relative_url("https://example.com/a", "../"), None);
+        assert_eq!(prefix_relative_url("mailto:a@b.c", "../"), None);
+        assert_eq!(prefix_relative_url("other.html", ""), None);
+
+        let source = "[Other](other.html) [Top](#top)\n\n<img src=\"plot.png\">\n";
+        let doc = Document::<RawContent>::new(source.to_string(), DocumentMetadata::default())
+            .to_events(IteratorConfig::default());
+        let doc = LinkPrefix {
+            prefix: String::new(),
+            relative: "../".to_string(),
+        }
+        .process(doc)
+        .unwrap();
+        let html = HtmlRenderer.render(&doc).content;
+        let html = html.as_text().unwrap();
+
+        assert!(html.contains("<a href=\"../other.html\">Other</a>"));
+        assert!(html.contains("<a href=\"#top\">Top</a>"));
+        assert!(html.contains("<img src=\"../plot.png\">"));
+    }
+}
@@ -0,0 +1,230 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+
+use pulldown_cmark::escape::escape_html;
+
+use crate::ast::{ACodeBlockKind, AEvent, ATag};
+use crate::config::OutputFormat;
+use crate::document::{Document, EventContent};
+use crate::processors::numbering::ListingNumbers;
+use crate::processors::{Error, EventPreprocessor, EventPreprocessorConfig, PreprocessorContext};
+
+/// Adds numbered captions to fenced code blocks with a `title` attribute, e.g.
+/// ```` ```python title="main.py" ````.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ListingsConfig;
+
+#[typetag::serde(name = "listings")]
+impl EventPreprocessorConfig for ListingsConfig {
+    fn build(&self, ctx: &PreprocessorContext) -> anyhow::Result<Box<dyn EventPreprocessor>> {
+        Ok(Box::new(Listings {
+            output_format: ctx.output_format,
+            numbers: ctx.listing_numbers.clone(),
+        }))
+    }
+}
+
+#[derive(Debug)]
+pub struct Listings {
+    pub output_format: OutputFormat,
+    pub numbers: ListingNumbers,
+}
+
+/// Split a fence info string into the remaining info (usually just the language) and the value of
+/// its `title` attribute. The title may be quoted to allow spaces.
+pub fn parse_fence_info(info: &str) -> (String, Option<String>) {
+    let start = match info.find("title=") {
+        Some(start) if start == 0 || info[..start].ends_with(char::is_whitespace) => start,
+        _ => return (info.to_string(), None),
+    };
+    let value_start = start + "title=".len();
+    let rest = &info[value_start..];
+
+    let (title, len) = match rest.strip_prefix('"') {
+        Some(quoted) => match quoted.find('"') {
+            Some(end) => (&quoted[..end], end + 2),
+            None => (quoted, rest.len()),
+        },
+        None => {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            (&rest[..end], end)
+        }
+    };
+
+    let remaining = format!("{} {}", &info[..start], &info[value_start + len..]);
+    let remaining = remaining.split_whitespace().collect::<Vec<_>>().join(" ");
+    (remaining, Some(title.to_string()))
+}
+
+impl Listings {
+    /// The events inserted before the code block. HTML gets a caption element, other formats a
+    /// paragraph with an anchor and the label in italics.
+    fn caption(&self, num: usize, title: &str) -> Vec<AEvent> {
+        let mut escaped = String::new();
+        escape_html(&mut escaped, title).expect("Invalid format");
+        match self.output_format {
+            OutputFormat::Html | OutputFormat::Pdf | OutputFormat::Epub => vec![AEvent::Html(format!(
+                "<div class=\"listing-caption\" id=\"listing-{num}\"><span class=\"listing-number\">Listing {num}:</span> {escaped}</div>\n"
+            ))],
+            _ => vec![
+                AEvent::Start(ATag::Paragraph),
+                AEvent::Html(format!("<a id=\"listing-{num}\"></a>")),
+                AEvent::Start(ATag::Emphasis),
+                AEvent::Text(format!("Listing {num}: {title}")),
+                AEvent::End(ATag::Emphasis),
+                AEvent::End(ATag::Paragraph),
+            ],
+        }
+    }
+}
+
+impl EventPreprocessor for Listings {
+    fn name(&self) -> String {
+        "Listings".to_string()
+    }
+
+    fn process(&self, input: Document<EventContent>) -> Result<Document<EventContent>, Error> {
+        // Stripped info string of the current block, used to rewrite its end tag.
+        let mut current_info: Option<String> = None;
+
+        let content = input
+            .content
+            .into_iter()
+            .flat_map(|(event, pos)| match event {
+                AEvent::Start(ATag::CodeBlock(ACodeBlockKind::Fenced(info))) => {
+                    let (info, title) = parse_fence_info(&info);
+                    let mut events = vec![];
+                    if let Some(title) = title {
+                        events = self.caption(self.numbers.next(), &title);
+                    }
+                    current_info = Some(info.clone());
+                    events.push(AEvent::Start(ATag::CodeBlock(ACodeBlockKind::Fenced(info))));
+                    events
+                        .into_iter()
+                        .map(|e| (e, pos.clone()))
+                        .collect::<Vec<_>>()
+                }
+                AEvent::End(ATag::CodeBlock(ACodeBlockKind::Fenced(info))) => {
+                    let info = current_info.take().unwrap_or(info);
+                    vec![(
+                        AEvent::End(ATag::CodeBlock(ACodeBlockKind::Fenced(info))),
+                        pos,
+                    )]
+                }
+                event => vec![(event, pos)],
+            })
+            .collect();
+
+        Ok(Document {
+            metadata: input.metadata,
+            variables: input.variables,
+            content,
+        })
+    }
+}
+
+impl Display for Listings {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::{DocPos, DocumentMetadata, DocumentVariables};
+
+    #[test]
+    fn test_parse_fence_info() {
+        assert_eq!(
+            parse_fence_info(r#"python title="main.py""#),
+            ("python".to_string(), Some("main.py".to_string()))
+        );
+        assert_eq!(
+            parse_fence_info(r#"python title="Hello world" linenos"#),
+            (
+                "python linenos".to_string(),
+                Some("Hello world".to_string())
+            )
+        );
+        assert_eq!(
+            parse_fence_info("rust title=lib.rs"),
+            ("rust".to_string(), Some("lib.rs".to_string()))
+        );
+        assert_eq!(parse_fence_info("python"), ("python".to_string(), None));
+        assert_eq!(
+            parse_fence_info("python subtitle=x"),
+            ("python subtitle=x".to_string(), None)
+        );
+    }
+
+    fn code_block(info: &str) -> Vec<AEvent> {
+        vec![
+            AEvent::Start(ATag::CodeBlock(ACodeBlockKind::Fenced(info.to_string()))),
+            AEvent::Text("print(1)\n".to_string()),
+            AEvent::End(ATag::CodeBlock(ACodeBlockKind::Fenced(info.to_string()))),
+        ]
+    }
+
+    #[test]
+    fn test_listings() {
+        let pos = DocPos::default();
+        let content = [
+            code_block(r#"python title="a.py""#),
+            code_block("python"),
+            code_block(r#"python title="<b>.py""#),
+        ]
+        .concat()
+        .into_iter()
+        .map(|e| (e, pos.clone()))
+        .collect();
+        let doc = Document {
+            content,
+            metadata: DocumentMetadata::default(),
+            variables: DocumentVariables::default(),
+        };
+
+        let listings = Listings {
+            output_format: OutputFormat::Html,
+            numbers: ListingNumbers::default(),
+        };
+        let res = listings.process(doc.clone()).unwrap();
+        assert_eq!(listings.numbers.count(), 2);
+
+        let html: Vec<&String> = res
+            .content
+            .iter()
+            .filter_map(|(e, _)| match e {
+                AEvent::Html(html) => Some(html),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(html.len(), 2);
+        assert!(html[0].contains("id=\"listing-1\""));
+        assert!(html[0].contains("Listing 1:</span> a.py"));
+        assert!(html[1].contains("Listing 2:</span> &lt;b&gt;.py"));
+
+        assert!(res.content.iter().all(|(e, _)| match e {
+            AEvent::Start(ATag::CodeBlock(ACodeBlockKind::Fenced(info)))
+            | AEvent::End(ATag::CodeBlock(ACodeBlockKind::Fenced(info))) => info == "python",
+            _ => true,
+        }));
+
+        // Numbering continues from the listings of previous documents.
+        let listings = Listings {
+            output_format: OutputFormat::Html,
+            numbers: ListingNumbers::new(3),
+        };
+        let res = listings.process(doc).unwrap();
+        let captions: Vec<&String> = res
+            .content
+            .iter()
+            .filter_map(|(e, _)| match e {
+                AEvent::Html(html) => Some(html),
+                _ => None,
+            })
+            .collect();
+        assert!(captions[0].contains("Listing 4:</span> a.py"));
+        assert!(captions[1].contains("Listing 5:</span>"));
+    }
+}
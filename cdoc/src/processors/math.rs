@@ -0,0 +1,96 @@
+use crate::processors::Error;
+
+/// A pair of opening and closing math delimiters, e.g. `["\\(", "\\)"]`.
+pub type Delimiter = (String, String);
+
+pub(crate) fn default_inline() -> Vec<Delimiter> {
+    vec![("$".to_string(), "$".to_string())]
+}
+
+pub(crate) fn default_display() -> Vec<Delimiter> {
+    vec![("$$".to_string(), "$$".to_string())]
+}
+
+/// A math block in the input.
+struct MathBlock {
+    /// Position of the opening delimiter.
+    begin: usize,
+    /// Position of the math source and the closing delimiter.
+    source: (usize, usize),
+    /// Position after the closing delimiter.
+    end: usize,
+    display: bool,
+}
+
+/// Find the first opening delimiter. The longest delimiter wins if several start at the same
+/// position (e.g. `$$` before `$`).
+fn find_open<'a>(
+    input: &str,
+    inline: &'a [Delimiter],
+    display: &'a [Delimiter],
+) -> Option<(usize, &'a Delimiter, bool)> {
+    let inline = inline.iter().map(|d| (d, false));
+    let display = display.iter().map(|d| (d, true));
+    inline
+        .chain(display)
+        .filter(|(d, _)| !d.0.is_empty() && !d.1.is_empty())
+        .filter_map(|(d, display)| Some((input.find(d.0.as_str())?, d, display)))
+        .min_by(|a, b| a.0.cmp(&b.0).then(b.1 .0.len().cmp(&a.1 .0.len())))
+}
+
+/// Find the next math block. Returns the block, or the position after an opening delimiter
+/// that is never closed.
+fn find_block(
+    input: &str,
+    inline: &[Delimiter],
+    display: &[Delimiter],
+) -> Option<Result<MathBlock, usize>> {
+    let (begin, (open, close), display) = find_open(input, inline, display)?;
+    let start = begin + open.len();
+    Some(match input[start..].find(close.as_str()) {
+        Some(len) => Ok(MathBlock {
+            begin,
+            source: (start, start + len),
+            end: start + len + close.len(),
+            display,
+        }),
+        None => Err(start),
+    })
+}
+
+/// Replace the math blocks of the input with the output of `render`, which gets the math source,
+/// whether it is display math and the block as written (including its delimiters). Unclosed
+/// delimiters are kept as text.
+pub(crate) fn render_math(
+    input: &str,
+    inline: &[Delimiter],
+    display: &[Delimiter],
+    render: impl Fn(&str, bool, &str) -> Result<String, Error>,
+) -> Result<String, Error> {
+    let mut rest = input;
+    let mut res = String::new();
+
+    while !rest.is_empty() {
+        match find_block(rest, inline, display) {
+            Some(Ok(block)) => {
+                let source = &rest[block.source.0..block.source.1];
+                let written = &rest[block.begin..block.end];
+
+                res.push_str(&rest[..block.begin]);
+                res.push_str(&render(source, block.display, written)?);
+
+                rest = &rest[block.end..];
+            }
+            Some(Err(end)) => {
+                res.push_str(&rest[..end]);
+                rest = &rest[end..];
+            }
+            None => {
+                res.push_str(rest);
+                rest = ""
+            }
+        }
+    }
+
+    Ok(res)
+}
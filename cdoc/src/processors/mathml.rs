@@ -0,0 +1,991 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::iter::Peekable;
+use std::vec::IntoIter;
+
+use crate::processors::math::{default_display, default_inline, render_math, Delimiter};
+use crate::processors::{Error, MarkdownPreprocessor, PreprocessorConfig, PreprocessorContext};
+
+/// Renders math to MathML without KaTeX (and its JavaScript engine). Only a common subset of
+/// LaTeX is supported (see [to_mathml]).
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MathMLConfig {
+    /// Delimiters of inline math. Defaults to `$...$`.
+    #[serde(default = "default_inline")]
+    pub inline: Vec<Delimiter>,
+    /// Delimiters of display math. Defaults to `$$...$$`.
+    #[serde(default = "default_display")]
+    pub display: Vec<Delimiter>,
+    /// LaTeX macros, e.g. `\R` for `\mathbb{R}`. They are added to the macros of the project
+    /// and take precedence over them.
+    #[serde(default)]
+    pub macros: HashMap<String, String>,
+}
+
+impl Default for MathMLConfig {
+    fn default() -> Self {
+        MathMLConfig {
+            inline: default_inline(),
+            display: default_display(),
+            macros: HashMap::new(),
+        }
+    }
+}
+
+#[typetag::serde(name = "mathml")]
+impl PreprocessorConfig for MathMLConfig {
+    fn build(&self, ctx: &PreprocessorContext) -> anyhow::Result<Box<dyn MarkdownPreprocessor>> {
+        let mut macros = ctx.math_macros.clone();
+        macros.extend(self.macros.clone());
+        Ok(Box::new(MathML {
+            inline: self.inline.clone(),
+            display: self.display.clone(),
+            macros,
+        }))
+    }
+}
+
+#[derive(Debug)]
+pub struct MathML {
+    pub inline: Vec<Delimiter>,
+    pub display: Vec<Delimiter>,
+    pub macros: HashMap<String, String>,
+}
+
+impl Default for MathML {
+    fn default() -> Self {
+        MathML {
+            inline: default_inline(),
+            display: default_display(),
+            macros: HashMap::new(),
+        }
+    }
+}
+
+impl MarkdownPreprocessor for MathML {
+    fn name(&self) -> String {
+        "MathML preprocessor".to_string()
+    }
+
+    fn process(&self, input: &str, _ctx: &tera::Context) -> Result<String, Error> {
+        render_math(input, &self.inline, &self.display, |source, display, _| {
+            to_mathml(source, display, &self.macros)
+        })
+    }
+
+    fn typeset_math(&self, source: &str, display: bool) -> Option<Result<String, Error>> {
+        Some(to_mathml(source, display, &self.macros))
+    }
+}
+
+impl Display for MathML {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Convert LaTeX math to a MathML `<math>` element.
+///
+/// Supported are numbers, letters, operators, groups, sub- and superscripts, fractions, roots,
+/// Greek letters, common symbols and functions, `\text`, font commands (e.g. `\mathbf`),
+/// spacing, `\left`/`\right` and the matrix, `cases` and `aligned` environments. Other commands
+/// are an error, unless they are one of the `macros` (see [expand_macros]).
+pub fn to_mathml(
+    source: &str,
+    display: bool,
+    macros: &HashMap<String, String>,
+) -> Result<String, Error> {
+    let mut parser = MathParser {
+        tokens: Vec::new(),
+        pos: 0,
+        display,
+    };
+    let body = expand_macros(tokenize(source), macros, 0)
+        .and_then(|tokens| {
+            parser.tokens = tokens;
+            parser.parse_row()
+        })
+        .and_then(|body| match parser.next() {
+            None => Ok(body),
+            Some(token) => Err(MathMLErrorKind::Unexpected(token.to_string())),
+        })
+        .map_err(|kind| Error::MathMLError {
+            math: source.to_string(),
+            kind,
+        })?;
+
+    let mode = if display { r#" display="block""# } else { "" };
+    Ok(format!(
+        r#"<math xmlns="http://www.w3.org/1998/Math/MathML"{}>{}</math>"#,
+        mode, body
+    ))
+}
+
+/// Why LaTeX math can't be converted to MathML. Tokens are described as written, e.g. `` `\frac` ``.
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum MathMLErrorKind {
+    #[error("unexpected {0}")]
+    Unexpected(String),
+    #[error("expected {expected} but found {found}")]
+    Expected { expected: String, found: String },
+    /// The math ends before an argument, a closing delimiter or the end of an environment.
+    #[error("missing {0}")]
+    Missing(String),
+    #[error("unsupported command `\\{0}`")]
+    UnsupportedCommand(String),
+    #[error("unsupported environment `{0}`")]
+    UnsupportedEnvironment(String),
+    /// The delimiter after `\left` or `\right` is not supported.
+    #[error("invalid delimiter {0}")]
+    InvalidDelimiter(String),
+    #[error("`\\begin{{{begin}}}` ended by `\\end{{{end}}}`")]
+    MismatchedEnvironment { begin: String, end: String },
+    #[error("missing argument of macro {0}")]
+    MissingMacroArgument(String),
+    /// A macro uses itself (or expands to too many nested macros).
+    #[error("macro {0} is nested too deeply")]
+    MacroTooDeep(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    /// A command like `\frac` or a control symbol like `\,` (without the backslash).
+    Command(String),
+    Open,
+    Close,
+    Sup,
+    Sub,
+    Align,
+    Space,
+    Char(char),
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Command(name) => write!(f, "`\\{}`", name),
+            Token::Open => write!(f, "`{{`"),
+            Token::Close => write!(f, "`}}`"),
+            Token::Sup => write!(f, "`^`"),
+            Token::Sub => write!(f, "`_`"),
+            Token::Align => write!(f, "`&`"),
+            Token::Space => write!(f, "space"),
+            Token::Char(c) => write!(f, "`{}`", c),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        let token = match c {
+            '\\' => match chars.next() {
+                Some(c) if c.is_ascii_alphabetic() => {
+                    let mut name = c.to_string();
+                    while let Some(c) = chars.next_if(|c| c.is_ascii_alphabetic()) {
+                        name.push(c);
+                    }
+                    Token::Command(name)
+                }
+                Some(c) => Token::Command(c.to_string()),
+                None => Token::Command(String::new()),
+            },
+            '{' => Token::Open,
+            '}' => Token::Close,
+            '^' => Token::Sup,
+            '_' => Token::Sub,
+            '&' => Token::Align,
+            c if c.is_whitespace() => {
+                while chars.next_if(|c| c.is_whitespace()).is_some() {}
+                Token::Space
+            }
+            c => Token::Char(c),
+        };
+        tokens.push(token);
+    }
+    tokens
+}
+
+/// Macros can use other macros up to this depth, which stops macros that use themselves.
+const MAX_MACRO_DEPTH: usize = 32;
+
+/// Replace the macros (e.g. `\R` for `\mathbb{R}`) in the tokens like KaTeX does. Macros can take
+/// arguments, which are used as `#1` to `#9` in their definition, and use other macros.
+fn expand_macros(
+    tokens: Vec<Token>,
+    macros: &HashMap<String, String>,
+    depth: usize,
+) -> Result<Vec<Token>, MathMLErrorKind> {
+    let mut expanded = Vec::new();
+    let mut tokens = tokens.into_iter().peekable();
+    while let Some(token) = tokens.next() {
+        let definition = match &token {
+            Token::Command(name) => macros.get(&format!("\\{}", name)),
+            _ => None,
+        };
+        let Some(definition) = definition else {
+            expanded.push(token);
+            continue;
+        };
+        if depth >= MAX_MACRO_DEPTH {
+            return Err(MathMLErrorKind::MacroTooDeep(token.to_string()));
+        }
+
+        let body = tokenize(definition);
+        let parameters = body
+            .windows(2)
+            .filter_map(|pair| match pair {
+                [Token::Char('#'), Token::Char(n)] => n.to_digit(10),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0);
+        let arguments = (0..parameters)
+            .map(|_| macro_argument(&mut tokens))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| MathMLErrorKind::MissingMacroArgument(token.to_string()))?;
+
+        let mut substituted = Vec::new();
+        let mut body = body.into_iter().peekable();
+        while let Some(t) = body.next() {
+            let parameter = match t {
+                Token::Char('#') => body.next_if(|t| matches!(t, Token::Char('1'..='9'))),
+                _ => None,
+            };
+            match parameter {
+                Some(Token::Char(n)) => {
+                    let index = n.to_digit(10).expect("Parameter is a digit") as usize;
+                    substituted.extend(arguments[index - 1].iter().cloned());
+                }
+                _ => substituted.push(t),
+            }
+        }
+        expanded.extend(expand_macros(substituted, macros, depth + 1)?);
+    }
+    Ok(expanded)
+}
+
+/// The next macro argument: a group (without its braces) or a single token.
+fn macro_argument(tokens: &mut Peekable<IntoIter<Token>>) -> Option<Vec<Token>> {
+    while tokens.next_if_eq(&Token::Space).is_some() {}
+    match tokens.next()? {
+        Token::Open => {
+            let mut argument = Vec::new();
+            let mut depth = 0;
+            loop {
+                match tokens.next()? {
+                    Token::Close if depth == 0 => return Some(argument),
+                    token => {
+                        match token {
+                            Token::Open => depth += 1,
+                            Token::Close => depth -= 1,
+                            _ => {}
+                        }
+                        argument.push(token);
+                    }
+                }
+            }
+        }
+        token => Some(vec![token]),
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Wrap several nodes in a row.
+fn row(nodes: Vec<String>) -> String {
+    if nodes.len() == 1 {
+        nodes.into_iter().next().unwrap()
+    } else {
+        format!("<mrow>{}</mrow>", nodes.concat())
+    }
+}
+
+/// The MathML of a command that stands for a single symbol.
+fn symbol(name: &str) -> Option<String> {
+    let identifier = match name {
+        "alpha" => "α",
+        "beta" => "β",
+        "gamma" => "γ",
+        "delta" => "δ",
+        "epsilon" => "ϵ",
+        "varepsilon" => "ε",
+        "zeta" => "ζ",
+        "eta" => "η",
+        "theta" => "θ",
+        "vartheta" => "ϑ",
+        "iota" => "ι",
+        "kappa" => "κ",
+        "lambda" => "λ",
+        "mu" => "μ",
+        "nu" => "ν",
+        "xi" => "ξ",
+        "pi" => "π",
+        "varpi" => "ϖ",
+        "rho" => "ρ",
+        "varrho" => "ϱ",
+        "sigma" => "σ",
+        "varsigma" => "ς",
+        "tau" => "τ",
+        "upsilon" => "υ",
+        "phi" => "ϕ",
+        "varphi" => "φ",
+        "chi" => "χ",
+        "psi" => "ψ",
+        "omega" => "ω",
+        "Gamma" => "Γ",
+        "Delta" => "Δ",
+        "Theta" => "Θ",
+        "Lambda" => "Λ",
+        "Xi" => "Ξ",
+        "Pi" => "Π",
+        "Sigma" => "Σ",
+        "Upsilon" => "Υ",
+        "Phi" => "Φ",
+        "Psi" => "Ψ",
+        "Omega" => "Ω",
+        "infty" => "∞",
+        "partial" => "∂",
+        "nabla" => "∇",
+        "ell" => "ℓ",
+        "hbar" => "ℏ",
+        "emptyset" => "∅",
+        "Re" => "ℜ",
+        "Im" => "ℑ",
+        "aleph" => "ℵ",
+        _ => "",
+    };
+    if !identifier.is_empty() {
+        return Some(format!("<mi>{}</mi>", identifier));
+    }
+
+    let operator = match name {
+        "cdot" => "⋅",
+        "times" => "×",
+        "div" => "÷",
+        "pm" => "±",
+        "mp" => "∓",
+        "ast" => "∗",
+        "star" => "⋆",
+        "circ" => "∘",
+        "bullet" => "∙",
+        "leq" | "le" => "≤",
+        "geq" | "ge" => "≥",
+        "neq" | "ne" => "≠",
+        "approx" => "≈",
+        "equiv" => "≡",
+        "sim" => "∼",
+        "simeq" => "≃",
+        "cong" => "≅",
+        "propto" => "∝",
+        "ll" => "≪",
+        "gg" => "≫",
+        "in" => "∈",
+        "notin" => "∉",
+        "ni" => "∋",
+        "subset" => "⊂",
+        "subseteq" => "⊆",
+        "supset" => "⊃",
+        "supseteq" => "⊇",
+        "cup" => "∪",
+        "cap" => "∩",
+        "setminus" => "∖",
+        "wedge" | "land" => "∧",
+        "vee" | "lor" => "∨",
+        "neg" | "lnot" => "¬",
+        "forall" => "∀",
+        "exists" => "∃",
+        "to" | "rightarrow" => "→",
+        "leftarrow" | "gets" => "←",
+        "leftrightarrow" => "↔",
+        "Rightarrow" => "⇒",
+        "Leftarrow" => "⇐",
+        "Leftrightarrow" | "iff" => "⇔",
+        "implies" => "⟹",
+        "mapsto" => "↦",
+        "ldots" | "dots" => "…",
+        "cdots" => "⋯",
+        "vdots" => "⋮",
+        "ddots" => "⋱",
+        "langle" => "⟨",
+        "rangle" => "⟩",
+        "lfloor" => "⌊",
+        "rfloor" => "⌋",
+        "lceil" => "⌈",
+        "rceil" => "⌉",
+        "mid" => "∣",
+        "parallel" | "|" => "‖",
+        "perp" => "⊥",
+        "oplus" => "⊕",
+        "otimes" => "⊗",
+        "{" => "{",
+        "}" => "}",
+        "%" => "%",
+        "#" => "#",
+        "$" => "$",
+        "_" => "_",
+        "&" => "&amp;",
+        _ => return None,
+    };
+    Some(format!("<mo>{}</mo>", operator))
+}
+
+/// The MathML of large operators and functions, and whether their scripts are placed above and
+/// below in display math.
+fn operator(name: &str) -> Option<(String, bool)> {
+    let large = match name {
+        "sum" => "∑",
+        "prod" => "∏",
+        "coprod" => "∐",
+        "bigcup" => "⋃",
+        "bigcap" => "⋂",
+        _ => "",
+    };
+    if !large.is_empty() {
+        return Some((format!("<mo>{}</mo>", large), true));
+    }
+    let integral = match name {
+        "int" => "∫",
+        "iint" => "∬",
+        "iiint" => "∭",
+        "oint" => "∮",
+        _ => "",
+    };
+    if !integral.is_empty() {
+        return Some((format!("<mo>{}</mo>", integral), false));
+    }
+    match name {
+        "lim" | "max" | "min" | "sup" | "inf" => Some((format!("<mi>{}</mi>", name), true)),
+        "sin" | "cos" | "tan" | "cot" | "sec" | "csc" | "arcsin" | "arccos" | "arctan" | "sinh"
+        | "cosh" | "tanh" | "log" | "ln" | "exp" | "det" | "dim" | "ker" | "deg" | "gcd"
+        | "arg" => Some((format!("<mi>{}</mi>", name), false)),
+        _ => None,
+    }
+}
+
+/// The width of spacing commands.
+fn space(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "," => "0.1667em",
+        ":" | ">" => "0.2222em",
+        ";" => "0.2778em",
+        " " => "0.25em",
+        "!" => "-0.1667em",
+        "quad" => "1em",
+        "qquad" => "2em",
+        _ => return None,
+    })
+}
+
+/// The `mathvariant` of font commands.
+fn variant(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "mathrm" => "normal",
+        "mathit" => "italic",
+        "mathbf" => "bold",
+        "boldsymbol" => "bold-italic",
+        "mathbb" => "double-struck",
+        "mathcal" => "script",
+        "mathfrak" => "fraktur",
+        "mathsf" => "sans-serif",
+        "mathtt" => "monospace",
+        _ => return None,
+    })
+}
+
+/// The fences of matrix environments.
+fn environment_fences(name: &str) -> Option<(&'static str, &'static str)> {
+    Some(match name {
+        "matrix" | "aligned" => ("", ""),
+        "pmatrix" => ("(", ")"),
+        "bmatrix" => ("[", "]"),
+        "Bmatrix" => ("{", "}"),
+        "vmatrix" => ("|", "|"),
+        "Vmatrix" => ("‖", "‖"),
+        "cases" => ("{", ""),
+        _ => return None,
+    })
+}
+
+fn fenced(open: &str, body: String, close: &str) -> String {
+    let fence = |f: &str| {
+        if f.is_empty() {
+            String::new()
+        } else {
+            format!(r#"<mo fence="true">{}</mo>"#, escape(f))
+        }
+    };
+    format!("<mrow>{}{}{}</mrow>", fence(open), body, fence(close))
+}
+
+struct MathParser {
+    tokens: Vec<Token>,
+    pos: usize,
+    display: bool,
+}
+
+impl MathParser {
+    fn skip_spaces(&mut self) {
+        while self.tokens.get(self.pos) == Some(&Token::Space) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<&Token> {
+        self.skip_spaces();
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        self.skip_spaces();
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), MathMLErrorKind> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(MathMLErrorKind::Expected {
+                expected: expected.to_string(),
+                found: token.to_string(),
+            }),
+            None => Err(MathMLErrorKind::Missing(expected.to_string())),
+        }
+    }
+
+    /// Parse nodes until the end of a group, a `\right` or the end of a table cell.
+    fn parse_nodes(&mut self) -> Result<Vec<String>, MathMLErrorKind> {
+        let mut nodes = Vec::new();
+        while let Some(token) = self.peek() {
+            match token {
+                Token::Close | Token::Align => break,
+                Token::Command(c) if c == "\\" || c == "right" || c == "end" => break,
+                _ => nodes.push(self.parse_scripted()?),
+            }
+        }
+        Ok(nodes)
+    }
+
+    fn parse_row(&mut self) -> Result<String, MathMLErrorKind> {
+        Ok(row(self.parse_nodes()?))
+    }
+
+    /// Parse an atom with its sub- and superscripts.
+    fn parse_scripted(&mut self) -> Result<String, MathMLErrorKind> {
+        let (base, limits) = self.parse_atom()?;
+        let mut sub = None;
+        let mut sup = None;
+        loop {
+            match self.peek() {
+                Some(Token::Sub) if sub.is_none() => {
+                    self.pos += 1;
+                    sub = Some(self.parse_argument()?);
+                }
+                Some(Token::Sup) if sup.is_none() => {
+                    self.pos += 1;
+                    sup = Some(self.parse_argument()?);
+                }
+                _ => break,
+            }
+        }
+
+        let (under, over, both) = if limits && self.display {
+            ("munder", "mover", "munderover")
+        } else {
+            ("msub", "msup", "msubsup")
+        };
+        Ok(match (sub, sup) {
+            (None, None) => base,
+            (Some(sub), None) => format!("<{0}>{1}{2}</{0}>", under, base, sub),
+            (None, Some(sup)) => format!("<{0}>{1}{2}</{0}>", over, base, sup),
+            (Some(sub), Some(sup)) => format!("<{0}>{1}{2}{3}</{0}>", both, base, sub, sup),
+        })
+    }
+
+    /// Parse the argument of a command or script, which is a single atom or a group.
+    fn parse_argument(&mut self) -> Result<String, MathMLErrorKind> {
+        Ok(self.parse_atom()?.0)
+    }
+
+    /// Parse a single atom. Also returns whether its scripts are limits (placed above and below
+    /// in display math).
+    fn parse_atom(&mut self) -> Result<(String, bool), MathMLErrorKind> {
+        let token = self
+            .next()
+            .ok_or_else(|| MathMLErrorKind::Missing("argument".to_string()))?;
+        let atom = match token {
+            Token::Open => {
+                let body = self.parse_row()?;
+                self.expect(Token::Close)?;
+                body
+            }
+            Token::Char(c) if c.is_ascii_digit() || c == '.' => {
+                let mut number = c.to_string();
+                while let Some(Token::Char(c)) = self.tokens.get(self.pos) {
+                    if !(c.is_ascii_digit() || *c == '.') {
+                        break;
+                    }
+                    number.push(*c);
+                    self.pos += 1;
+                }
+                format!("<mn>{}</mn>", number)
+            }
+            Token::Char(c) if c.is_alphabetic() => format!("<mi>{}</mi>", c),
+            Token::Char('\'') => "<mo>′</mo>".to_string(),
+            Token::Char(c) => format!("<mo>{}</mo>", escape(&c.to_string())),
+            Token::Command(name) => return self.parse_command(&name),
+            token => return Err(MathMLErrorKind::Unexpected(token.to_string())),
+        };
+        Ok((atom, false))
+    }
+
+    fn parse_command(&mut self, name: &str) -> Result<(String, bool), MathMLErrorKind> {
+        if let Some(symbol) = symbol(name) {
+            return Ok((symbol, false));
+        }
+        if let Some(operator) = operator(name) {
+            return Ok(operator);
+        }
+        if let Some(width) = space(name) {
+            return Ok((format!(r#"<mspace width="{}"/>"#, width), false));
+        }
+        if let Some(variant) = variant(name) {
+            let text = escape(self.parse_text()?.trim());
+            return Ok((
+                format!(r#"<mi mathvariant="{}">{}</mi>"#, variant, text),
+                false,
+            ));
+        }
+
+        let node = match name {
+            "frac" | "dfrac" | "tfrac" => {
+                let numerator = self.parse_argument()?;
+                let denominator = self.parse_argument()?;
+                format!("<mfrac>{}{}</mfrac>", numerator, denominator)
+            }
+            "sqrt" => {
+                let index = if self.peek() == Some(&Token::Char('[')) {
+                    self.pos += 1;
+                    let mut nodes = Vec::new();
+                    while self.peek() != Some(&Token::Char(']')) {
+                        if self.peek().is_none() {
+                            return Err(MathMLErrorKind::Missing("`]`".to_string()));
+                        }
+                        nodes.push(self.parse_scripted()?);
+                    }
+                    self.pos += 1;
+                    Some(row(nodes))
+                } else {
+                    None
+                };
+                let radicand = self.parse_argument()?;
+                match index {
+                    Some(index) => format!("<mroot>{}{}</mroot>", radicand, index),
+                    None => format!("<msqrt>{}</msqrt>", radicand),
+                }
+            }
+            "text" | "textrm" | "mbox" => format!("<mtext>{}</mtext>", escape(&self.parse_text()?)),
+            "operatorname" => format!("<mi>{}</mi>", escape(self.parse_text()?.trim())),
+            "left" => {
+                let open = self.parse_delimiter()?;
+                let body = self.parse_row()?;
+                self.expect(Token::Command("right".to_string()))?;
+                let close = self.parse_delimiter()?;
+                fenced(&open, body, &close)
+            }
+            "begin" => self.parse_environment()?,
+            _ => return Err(MathMLErrorKind::UnsupportedCommand(name.to_string())),
+        };
+        Ok((node, false))
+    }
+
+    /// Parse the delimiter after `\left` or `\right`. The delimiter `.` is empty.
+    fn parse_delimiter(&mut self) -> Result<String, MathMLErrorKind> {
+        Ok(match self.next() {
+            Some(Token::Char('.')) => String::new(),
+            Some(Token::Char(c)) => c.to_string(),
+            Some(Token::Command(name)) => match name.as_str() {
+                "{" | "}" => name,
+                "|" | "Vert" => "‖".to_string(),
+                "vert" | "lvert" | "rvert" => "|".to_string(),
+                "langle" => "⟨".to_string(),
+                "rangle" => "⟩".to_string(),
+                "lfloor" => "⌊".to_string(),
+                "rfloor" => "⌋".to_string(),
+                "lceil" => "⌈".to_string(),
+                "rceil" => "⌉".to_string(),
+                _ => {
+                    return Err(MathMLErrorKind::InvalidDelimiter(
+                        Token::Command(name).to_string(),
+                    ))
+                }
+            },
+            Some(token) => return Err(MathMLErrorKind::InvalidDelimiter(token.to_string())),
+            None => return Err(MathMLErrorKind::Missing("delimiter".to_string())),
+        })
+    }
+
+    /// Parse a group as text, e.g. the argument of `\text`.
+    fn parse_text(&mut self) -> Result<String, MathMLErrorKind> {
+        self.expect(Token::Open)?;
+        let mut text = String::new();
+        let mut depth = 0;
+        loop {
+            let token = self
+                .tokens
+                .get(self.pos)
+                .cloned()
+                .ok_or_else(|| MathMLErrorKind::Missing("`}`".to_string()))?;
+            self.pos += 1;
+            match token {
+                Token::Close if depth == 0 => return Ok(text),
+                Token::Close => depth -= 1,
+                Token::Open => depth += 1,
+                Token::Command(name) if name.chars().all(|c| c.is_ascii_alphabetic()) => {
+                    text.push('\\');
+                    text.push_str(&name);
+                }
+                Token::Command(name) => text.push_str(&name),
+                Token::Sup => text.push('^'),
+                Token::Sub => text.push('_'),
+                Token::Align => text.push('&'),
+                Token::Space => text.push(' '),
+                Token::Char(c) => text.push(c),
+            }
+        }
+    }
+
+    /// Parse an environment after its `\begin`.
+    fn parse_environment(&mut self) -> Result<String, MathMLErrorKind> {
+        let name = self.parse_text()?;
+        let (open, close) = environment_fences(&name)
+            .ok_or(MathMLErrorKind::UnsupportedEnvironment(name.clone()))?;
+
+        let mut rows = Vec::new();
+        loop {
+            let mut cells = vec![self.parse_nodes()?];
+            while self.peek() == Some(&Token::Align) {
+                self.pos += 1;
+                cells.push(self.parse_nodes()?);
+            }
+            // A line break before `\end` doesn't start a new row.
+            if cells.len() > 1 || !cells[0].is_empty() {
+                rows.push(cells);
+            }
+            match self.next() {
+                Some(Token::Command(c)) if c == "\\" => continue,
+                Some(Token::Command(c)) if c == "end" => break,
+                Some(token) => return Err(MathMLErrorKind::Unexpected(token.to_string())),
+                None => return Err(MathMLErrorKind::Missing(format!("`\\end{{{}}}`", name))),
+            }
+        }
+        let end = self.parse_text()?;
+        if end != name {
+            return Err(MathMLErrorKind::MismatchedEnvironment { begin: name, end });
+        }
+
+        let align = match name.as_str() {
+            "cases" => r#" columnalign="left""#,
+            "aligned" => r#" columnalign="right left""#,
+            _ => "",
+        };
+        let rows: String = rows
+            .into_iter()
+            .map(|cells| {
+                let cells: String = cells
+                    .into_iter()
+                    .map(|cell| format!("<mtd>{}</mtd>", row(cell)))
+                    .collect();
+                format!("<mtr>{}</mtr>", cells)
+            })
+            .collect();
+        let table = format!("<mtable{}>{}</mtable>", align, rows);
+        Ok(if open.is_empty() && close.is_empty() {
+            table
+        } else {
+            fenced(open, table, close)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processors::numbering::ListingNumbers;
+
+    fn inline(source: &str) -> String {
+        let math = to_mathml(source, false, &HashMap::new()).expect("MathML conversion failed");
+        math.strip_prefix(r#"<math xmlns="http://www.w3.org/1998/Math/MathML">"#)
+            .and_then(|m| m.strip_suffix("</math>"))
+            .expect("Not an inline math element")
+            .to_string()
+    }
+
+    #[test]
+    fn test_basics() {
+        assert_eq!(
+            inline("x^2 + 1.5"),
+            "<mrow><msup><mi>x</mi><mn>2</mn></msup><mo>+</mo><mn>1.5</mn></mrow>"
+        );
+        assert_eq!(
+            inline(r"\frac{a}{b_i}"),
+            "<mfrac><mi>a</mi><msub><mi>b</mi><mi>i</mi></msub></mfrac>"
+        );
+        assert_eq!(
+            inline(r"\sqrt[3]{\alpha}"),
+            "<mroot><mi>α</mi><mn>3</mn></mroot>"
+        );
+        assert_eq!(
+            inline(r"\text{if } x < 0"),
+            "<mrow><mtext>if </mtext><mi>x</mi><mo>&lt;</mo><mn>0</mn></mrow>"
+        );
+        assert_eq!(
+            inline(r"\mathbb{R}"),
+            r#"<mi mathvariant="double-struck">R</mi>"#
+        );
+        assert_eq!(
+            inline(r"\left( x \right."),
+            r#"<mrow><mo fence="true">(</mo><mi>x</mi></mrow>"#
+        );
+    }
+
+    #[test]
+    fn test_limits() {
+        let sum = r"\sum_{i=1}^n i";
+        assert!(inline(sum).starts_with("<mrow><msubsup><mo>∑</mo>"));
+        let display = to_mathml(sum, true, &HashMap::new()).unwrap();
+        assert!(display.starts_with(
+            r#"<math xmlns="http://www.w3.org/1998/Math/MathML" display="block"><mrow><munderover><mo>∑</mo>"#
+        ));
+    }
+
+    #[test]
+    fn test_environments() {
+        assert_eq!(
+            inline(r"\begin{pmatrix} a & b \\ c & d \\ \end{pmatrix}"),
+            "<mrow><mo fence=\"true\">(</mo><mtable>\
+             <mtr><mtd><mi>a</mi></mtd><mtd><mi>b</mi></mtd></mtr>\
+             <mtr><mtd><mi>c</mi></mtd><mtd><mi>d</mi></mtd></mtr>\
+             </mtable><mo fence=\"true\">)</mo></mrow>"
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let missing = |what: &str| MathMLErrorKind::Missing(what.to_string());
+        for (source, expected) in [
+            (r"\frac{2}", missing("argument")),
+            (
+                r"\unknown",
+                MathMLErrorKind::UnsupportedCommand("unknown".to_string()),
+            ),
+            (r"x}", MathMLErrorKind::Unexpected("`}`".to_string())),
+            (r"\begin{matrix} a", missing(r"`\end{matrix}`")),
+            (
+                r"\begin{matrix} a \end{pmatrix}",
+                MathMLErrorKind::MismatchedEnvironment {
+                    begin: "matrix".to_string(),
+                    end: "pmatrix".to_string(),
+                },
+            ),
+            (r"\left( x", missing(r"`\right`")),
+            (
+                r"\left\x x \right)",
+                MathMLErrorKind::InvalidDelimiter(r"`\x`".to_string()),
+            ),
+        ] {
+            match to_mathml(source, false, &HashMap::new()) {
+                Err(Error::MathMLError { math, kind }) => {
+                    assert_eq!(math, source);
+                    assert_eq!(kind, expected, "{}", source);
+                }
+                res => panic!("Expected an error for {}: {:?}", source, res),
+            }
+        }
+
+        let err = to_mathml(r"\frac{2}", false, &HashMap::new()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            r"MathML error: missing argument in `\frac{2}`"
+        );
+    }
+
+    #[test]
+    fn test_process() {
+        let output = MathML::default()
+            .process("a $x$ b $$y$$", &tera::Context::new())
+            .unwrap();
+        assert_eq!(
+            output,
+            "a <math xmlns=\"http://www.w3.org/1998/Math/MathML\"><mi>x</mi></math> b \
+             <math xmlns=\"http://www.w3.org/1998/Math/MathML\" display=\"block\"><mi>y</mi></math>"
+        );
+    }
+
+    #[test]
+    fn test_macros() {
+        let macros = HashMap::from([
+            (r"\R".to_string(), r"\mathbb{R}".to_string()),
+            (r"\norm".to_string(), r"\left| #1 \right|".to_string()),
+            (r"\pair".to_string(), r"(#1, #2)".to_string()),
+            (r"\loop".to_string(), r"\loop".to_string()),
+        ]);
+        let mathml = |source: &str| to_mathml(source, false, &macros);
+
+        assert_eq!(
+            mathml(r"x \in \R").unwrap(),
+            to_mathml(r"x \in \mathbb{R}", false, &HashMap::new()).unwrap()
+        );
+        assert_eq!(
+            mathml(r"\norm{x^2}").unwrap(),
+            to_mathml(r"\left| x^2 \right|", false, &HashMap::new()).unwrap()
+        );
+        assert_eq!(
+            mathml(r"\pair a {\R}").unwrap(),
+            to_mathml(r"(a, \mathbb{R})", false, &HashMap::new()).unwrap()
+        );
+
+        for (source, expected) in [
+            (
+                r"\norm",
+                MathMLErrorKind::MissingMacroArgument(r"`\norm`".to_string()),
+            ),
+            (
+                r"\loop",
+                MathMLErrorKind::MacroTooDeep(r"`\loop`".to_string()),
+            ),
+        ] {
+            match mathml(source) {
+                Err(Error::MathMLError { kind, .. }) => assert_eq!(kind, expected),
+                res => panic!("Expected an error for {}: {:?}", source, res),
+            }
+        }
+    }
+
+    #[test]
+    fn test_project_macros() {
+        let ctx = PreprocessorContext {
+            tera: Default::default(),
+            output_format: crate::config::OutputFormat::Html,
+            used_templates: Default::default(),
+            include_solutions: false,
+            listing_numbers: ListingNumbers::default(),
+            math_macros: HashMap::from([(r"\R".to_string(), r"\mathbb{R}".to_string())]),
+            warnings: Default::default(),
+        };
+        let processor = MathMLConfig::default().build(&ctx).unwrap();
+        assert_eq!(
+            processor.process(r"$\R$", &tera::Context::new()).unwrap(),
+            MathML::default()
+                .process(r"$\mathbb{R}$", &tera::Context::new())
+                .unwrap()
+        );
+    }
+}
@@ -1,35 +1,174 @@
 use dyn_clone::DynClone;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fmt::{Debug, Display};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
 use tera::Tera;
 use thiserror::Error;
 
 use crate::config::OutputFormat;
-use crate::document::{DocPos, Document, EventContent};
+use crate::document::{DocPos, Document, DocumentWarning, EventContent, SourcePosition};
 use crate::parsers::split::Rule;
+use crate::processors::mathml::MathMLErrorKind;
+use crate::processors::numbering::ListingNumbers;
+use crate::processors::shortcodes::ShortCodeProcessError;
 
 mod escapes;
 pub mod exercises;
+#[cfg(feature = "katex")]
 pub mod katex;
+pub mod links;
+pub mod listings;
+pub mod math;
+pub mod mathml;
+pub mod numbering;
+pub mod runnable;
 pub mod shortcodes;
 
+/// Errors returned by the preprocessors and event processors. There is a variant for each kind
+/// of failure so that embedders can handle them individually. The type converts to
+/// [anyhow::Error] like any other error. New variants can be added, so matches need a wildcard
+/// arm.
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum Error {
     #[error("code split syntax error at {}: {}", .1, .0)]
     CodeParseError(#[source] Box<pest::error::Error<Rule>>, DocPos),
     #[error("could not parse attributes: {}", .0)]
     AttrParseError(#[from] toml::de::Error),
+    #[error("shortcode error: {}", .0)]
+    ShortcodeError(#[from] ShortCodeProcessError),
+    #[cfg(feature = "katex")]
+    #[error("KaTeX error: {}", .0)]
+    KaTeXError(#[from] ::katex::Error),
+    /// Math that the MathML preprocessor can't convert.
+    #[error("MathML error: {} in `{}`", .kind, .math)]
+    MathMLError { math: String, kind: MathMLErrorKind },
+}
+
+impl Error {
+    /// Where the error is in the document, if known.
+    pub fn position(&self) -> Option<SourcePosition> {
+        match self {
+            Error::CodeParseError(_, pos) => Some(pos.into()),
+            Error::ShortcodeError(ShortCodeProcessError::Located { line, column, .. }) => {
+                Some(SourcePosition {
+                    cell: None,
+                    line: *line,
+                    column: Some(*column),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Move the position of an error down by a number of lines, e.g. to make the position of an
+    /// error in the content of a markdown document relative to the file with its front matter.
+    pub(crate) fn offset_lines(self, lines: usize) -> Self {
+        match self {
+            Error::ShortcodeError(e) => Error::ShortcodeError(e.offset_lines(lines)),
+            e => e,
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct PreprocessorContext {
-    pub tera: Tera,
+    /// Shortcode templates. The environment is shared between documents to avoid re-parsing.
+    pub tera: Arc<Tera>,
     pub output_format: OutputFormat,
+    /// The templates used by the shortcodes of the documents and the documents that use them. It
+    /// is shared between documents so that usage can be reported for a complete build.
+    pub used_templates: TemplateUsage,
+    /// Show the solutions of exercises instead of their placeholders (the `solutions` parser
+    /// setting). Documents can override it with `code_solutions`.
+    pub include_solutions: bool,
+    /// Numbers of the listings of the document. They continue after the listings before the
+    /// document in its numbering scope.
+    pub listing_numbers: ListingNumbers,
+    /// LaTeX macros of the project (e.g. `\R` for `\mathbb{R}`), used by the math
+    /// preprocessors.
+    pub math_macros: HashMap<String, String>,
+    /// Warnings of the markdown preprocessors. They are added to the document after parsing.
+    pub warnings: Warnings,
+}
+
+/// Collects the warnings of the markdown preprocessors of a document, which (unlike event
+/// processors) can't add them to the document variables. Clones share the warnings.
+#[derive(Clone, Debug, Default)]
+pub struct Warnings(Arc<Mutex<Vec<DocumentWarning>>>);
+
+impl Warnings {
+    pub fn push(&self, warning: DocumentWarning) {
+        self.0.lock().expect("Warnings lock poisoned").push(warning);
+    }
+
+    /// Remove the collected warnings.
+    pub fn take(&self) -> Vec<DocumentWarning> {
+        std::mem::take(&mut *self.0.lock().expect("Warnings lock poisoned"))
+    }
+}
+
+/// Records which templates are used and by which documents. Clones share the record. Each
+/// document gets its own clone from [TemplateUsage::for_document] so that its uses are attributed
+/// to it.
+#[derive(Clone, Debug, Default)]
+pub struct TemplateUsage {
+    uses: Arc<Mutex<BTreeMap<String, BTreeSet<PathBuf>>>>,
+    document: Option<PathBuf>,
+}
+
+impl TemplateUsage {
+    /// A clone that attributes the templates it records to the given document.
+    pub fn for_document(&self, document: &Path) -> Self {
+        TemplateUsage {
+            uses: self.uses.clone(),
+            document: Some(document.to_path_buf()),
+        }
+    }
+
+    pub fn record(&self, template: &str) {
+        let mut uses = self.uses.lock().expect("Template usage lock poisoned");
+        let documents = uses.entry(template.to_string()).or_default();
+        if let Some(document) = &self.document {
+            documents.insert(document.clone());
+        }
+    }
+
+    pub fn is_used(&self, template: &str) -> bool {
+        self.uses
+            .lock()
+            .expect("Template usage lock poisoned")
+            .contains_key(template)
+    }
+
+    /// The documents that use each used template.
+    pub fn documents(&self) -> BTreeMap<String, BTreeSet<PathBuf>> {
+        self.uses
+            .lock()
+            .expect("Template usage lock poisoned")
+            .clone()
+    }
+
+    pub fn clear(&self) {
+        self.uses
+            .lock()
+            .expect("Template usage lock poisoned")
+            .clear();
+    }
 }
 
 pub trait MarkdownPreprocessor: Display {
     fn name(&self) -> String;
-    fn process(&self, input: &str, ctx: &tera::Context) -> Result<String, anyhow::Error>;
+    fn process(&self, input: &str, ctx: &tera::Context) -> Result<String, Error>;
+
+    /// Typeset LaTeX math (without delimiters) if the preprocessor is a math backend. This is used
+    /// for math outside the markdown of a document, e.g. the `text/latex` outputs of notebook
+    /// cells. Other preprocessors return None.
+    fn typeset_math(&self, _source: &str, _display: bool) -> Option<Result<String, Error>> {
+        None
+    }
 }
 
 pub trait EventPreprocessor: Display {
@@ -40,6 +179,11 @@ pub trait EventPreprocessor: Display {
 #[typetag::serde(tag = "type")]
 pub trait PreprocessorConfig: Debug + Send + Sync + DynClone {
     fn build(&self, ctx: &PreprocessorContext) -> anyhow::Result<Box<dyn MarkdownPreprocessor>>;
+
+    /// Whether the preprocessor renders math with KaTeX, whose output needs the KaTeX stylesheet.
+    fn uses_katex(&self) -> bool {
+        false
+    }
 }
 
 #[typetag::serde(tag = "type")]
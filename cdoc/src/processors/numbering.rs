@@ -0,0 +1,264 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::sync::{Arc, Mutex};
+
+use crate::ast::{AEvent, ATag};
+use crate::config::OutputFormat;
+use crate::document::{Document, EventContent, Section};
+use crate::processors::{Error, EventPreprocessor};
+use crate::renderers::notebook::heading_num;
+
+/// The numbering of a document, derived from its position in the project.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SectionNumber {
+    /// Number of the part or chapter containing the document (e.g. `[1, 2]` for chapter 1.2).
+    pub prefix: Vec<usize>,
+    /// Number of the first section of the document or None if its headings are not numbered
+    /// (as in part indices).
+    pub first_section: Option<usize>,
+}
+
+/// The part of a project in which listings are numbered consecutively. Numbers restart at 1 in
+/// each document, chapter or only once for the whole project.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NumberingScope {
+    Document,
+    Chapter,
+    Project,
+}
+
+impl NumberingScope {
+    /// The scope used when none is configured. Formats that combine all documents into one output
+    /// (PDF) number across the project, the others number each document on its own.
+    pub fn default_for(format: OutputFormat) -> Self {
+        match format {
+            OutputFormat::Pdf => NumberingScope::Project,
+            _ => NumberingScope::Document,
+        }
+    }
+}
+
+/// Gives out the numbers of the listings of a document. Numbers continue after the listings
+/// before the document in its numbering scope. Clones share the count, so the listings of a
+/// document can be counted by processing it.
+#[derive(Debug, Clone, Default)]
+pub struct ListingNumbers {
+    offset: usize,
+    count: Arc<Mutex<usize>>,
+}
+
+impl ListingNumbers {
+    pub fn new(offset: usize) -> Self {
+        ListingNumbers {
+            offset,
+            count: Arc::default(),
+        }
+    }
+
+    /// The number of the next listing.
+    pub fn next(&self) -> usize {
+        let mut count = self.count.lock().expect("Listing numbers lock poisoned");
+        *count += 1;
+        self.offset + *count
+    }
+
+    /// The number of listings that have been numbered.
+    pub fn count(&self) -> usize {
+        *self.count.lock().expect("Listing numbers lock poisoned")
+    }
+}
+
+/// Join the components of a number with dots (e.g. `1.2.3`).
+pub fn format_number(number: &[usize]) -> String {
+    number
+        .iter()
+        .map(|n| n.to_string())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Count the headings at the given level.
+pub fn count_sections(content: &EventContent, start_level: usize) -> usize {
+    content
+        .iter()
+        .filter(|(e, _)| matches!(e, AEvent::Start(ATag::Heading(level, _, _)) if heading_num(*level) == start_level))
+        .count()
+}
+
+/// Prefixes headings with their number. Headings at `start_level` are sections numbered below the
+/// part or chapter of the document (e.g. 1.2.3), deeper headings are numbered below their section
+/// (1.2.3.1) and higher headings are not numbered.
+///
+/// The processor is added by the build pipeline when numbering is enabled in the project
+/// configuration since the numbers depend on the project structure.
+#[derive(Debug)]
+pub struct Numbering {
+    pub start_level: usize,
+    pub number: SectionNumber,
+    pub output_format: OutputFormat,
+}
+
+impl Numbering {
+    fn label(&self, number: &str, id: &str) -> AEvent {
+        match self.output_format {
+            OutputFormat::Html | OutputFormat::Pdf | OutputFormat::Epub => AEvent::Html(format!(
+                "<span class=\"section-number\" id=\"{id}\">{number}</span> "
+            )),
+            _ => AEvent::Text(format!("{number} ")),
+        }
+    }
+}
+
+impl EventPreprocessor for Numbering {
+    fn name(&self) -> String {
+        "Numbering".to_string()
+    }
+
+    fn process(&self, input: Document<EventContent>) -> Result<Document<EventContent>, Error> {
+        let mut variables = input.variables;
+        if !self.number.prefix.is_empty() {
+            variables.number = Some(format_number(&self.number.prefix));
+        }
+        let first_section = match self.number.first_section {
+            Some(first_section) => first_section,
+            None => {
+                return Ok(Document {
+                    metadata: input.metadata,
+                    variables,
+                    content: input.content,
+                })
+            }
+        };
+
+        // Counters for the numbered levels, starting at start_level.
+        let start_level = self.start_level.clamp(1, 6);
+        let mut counters = vec![0; 7 - start_level];
+        counters[0] = first_section.saturating_sub(1);
+        let mut current: Option<Section> = None;
+
+        let mut content = Vec::with_capacity(input.content.len());
+        for (event, pos) in input.content {
+            match &event {
+                AEvent::Start(ATag::Heading(level, _, _)) if heading_num(*level) >= start_level => {
+                    let depth = heading_num(*level) - start_level;
+                    counters[depth] += 1;
+                    counters[depth + 1..].iter_mut().for_each(|c| *c = 0);
+
+                    let mut number = self.number.prefix.clone();
+                    number.extend(&counters[..=depth]);
+                    let number = format_number(&number);
+                    let id = format!("sec-{}", number.replace('.', "-"));
+                    let label = self.label(&number, &id);
+
+                    current = Some(Section {
+                        number,
+                        title: String::new(),
+                        id,
+                        level: heading_num(*level),
+                    });
+                    content.push((event, pos.clone()));
+                    content.push((label, pos));
+                    continue;
+                }
+                AEvent::Text(text) | AEvent::Code(text) => {
+                    if let Some(section) = current.as_mut() {
+                        section.title.push_str(text);
+                    }
+                }
+                AEvent::End(ATag::Heading(_, _, _)) => {
+                    if let Some(section) = current.take() {
+                        variables.sections.push(section);
+                    }
+                }
+                _ => {}
+            }
+            content.push((event, pos));
+        }
+
+        Ok(Document {
+            metadata: input.metadata,
+            variables,
+            content,
+        })
+    }
+}
+
+impl Display for Numbering {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::{DocumentMetadata, DocumentVariables, IteratorConfig, RawContent};
+
+    fn events(source: &str) -> Document<EventContent> {
+        Document::<RawContent>::new(source.to_string(), DocumentMetadata::default())
+            .to_events(IteratorConfig::default())
+    }
+
+    #[test]
+    fn test_numbering() {
+        let doc = events("# Title\n\n## First\n\n### Sub `code`\n\n## Second\n\n### Sub\n");
+        assert_eq!(count_sections(&doc.content, 2), 2);
+
+        let numbering = Numbering {
+            start_level: 2,
+            number: SectionNumber {
+                prefix: vec![1, 2],
+                first_section: Some(3),
+            },
+            output_format: OutputFormat::Html,
+        };
+        let res = numbering.process(doc).unwrap();
+
+        assert_eq!(res.variables.number, Some("1.2".to_string()));
+        let numbers: Vec<&str> = res
+            .variables
+            .sections
+            .iter()
+            .map(|s| s.number.as_str())
+            .collect();
+        assert_eq!(numbers, vec!["1.2.3", "1.2.3.1", "1.2.4", "1.2.4.1"]);
+        assert_eq!(res.variables.sections[1].title, "Sub code");
+        assert_eq!(res.variables.sections[1].id, "sec-1-2-3-1");
+
+        let labels: Vec<&String> = res
+            .content
+            .iter()
+            .filter_map(|(e, _)| match e {
+                AEvent::Html(html) => Some(html),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            labels[0],
+            "<span class=\"section-number\" id=\"sec-1-2-3\">1.2.3</span> "
+        );
+    }
+
+    #[test]
+    fn test_unnumbered_headings() {
+        let doc = Document {
+            content: events("## Intro\n").content,
+            metadata: DocumentMetadata::default(),
+            variables: DocumentVariables::default(),
+        };
+        let numbering = Numbering {
+            start_level: 2,
+            number: SectionNumber {
+                prefix: vec![1],
+                first_section: None,
+            },
+            output_format: OutputFormat::Notebook,
+        };
+        let res = numbering.process(doc).unwrap();
+
+        assert_eq!(res.variables.number, Some("1".to_string()));
+        assert!(res.variables.sections.is_empty());
+        assert_eq!(res.content.len(), 3);
+    }
+}
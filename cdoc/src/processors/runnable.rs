@@ -0,0 +1,268 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+
+use pulldown_cmark::escape::escape_html;
+
+use crate::ast::{ACodeBlockKind, AEvent, ATag};
+use crate::config::OutputFormat;
+use crate::document::{Document, EventContent};
+use crate::processors::{Error, EventPreprocessor, EventPreprocessorConfig, PreprocessorContext};
+
+/// The fence attribute that marks a code block as runnable.
+pub const RUNNABLE: &str = "runnable";
+
+/// Loads Pyodide and turns each editor mount into an editable block with a button that runs it.
+const DEFAULT_LOADER: &str = r#"<script src="https://cdn.jsdelivr.net/pyodide/v0.22.1/full/pyodide.js"></script>
+<script>
+window.addEventListener("DOMContentLoaded", () => {
+  let pyodide = null;
+  document.querySelectorAll("div.runnable").forEach((mount) => {
+    const code = mount.querySelector("code");
+    code.contentEditable = "true";
+    code.spellcheck = false;
+    const button = document.createElement("button");
+    button.className = "runnable-run";
+    button.textContent = "Run";
+    const output = document.createElement("pre");
+    output.className = "runnable-output";
+    button.addEventListener("click", async () => {
+      output.textContent = "Running...";
+      pyodide = pyodide || loadPyodide();
+      const py = await pyodide;
+      const lines = [];
+      py.setStdout({ batched: (s) => lines.push(s) });
+      py.setStderr({ batched: (s) => lines.push(s) });
+      try {
+        await py.runPythonAsync(code.innerText);
+      } catch (e) {
+        lines.push(String(e));
+      }
+      output.textContent = lines.join("\n");
+    });
+    mount.append(button, output);
+  });
+});
+</script>
+"#;
+
+fn default_loader() -> String {
+    DEFAULT_LOADER.to_string()
+}
+
+/// Makes fenced code blocks with the `runnable` attribute (e.g. ```` ```python runnable ````)
+/// editable and runnable in HTML output.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RunnableConfig {
+    /// HTML that loads the editor runtime. It is inserted once per document, before the first
+    /// runnable block. Defaults to a Pyodide based Python runner.
+    #[serde(default = "default_loader")]
+    pub loader: String,
+}
+
+impl Default for RunnableConfig {
+    fn default() -> Self {
+        RunnableConfig {
+            loader: default_loader(),
+        }
+    }
+}
+
+#[typetag::serde(name = "runnable")]
+impl EventPreprocessorConfig for RunnableConfig {
+    fn build(&self, ctx: &PreprocessorContext) -> anyhow::Result<Box<dyn EventPreprocessor>> {
+        Ok(Box::new(Runnable {
+            output_format: ctx.output_format,
+            loader: self.loader.clone(),
+        }))
+    }
+}
+
+#[derive(Debug)]
+pub struct Runnable {
+    pub output_format: OutputFormat,
+    pub loader: String,
+}
+
+/// Remove the `runnable` attribute from a fence info string. Returns the remaining info and
+/// whether the attribute was present.
+pub fn strip_runnable(info: &str) -> (String, bool) {
+    let mut runnable = false;
+    let remaining = info
+        .split_whitespace()
+        .filter(|word| {
+            let is_option = *word == RUNNABLE;
+            runnable |= is_option;
+            !is_option
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    (remaining, runnable)
+}
+
+impl Runnable {
+    /// The opening tag of the element the editor is mounted on.
+    fn mount(info: &str) -> String {
+        let mut language = String::new();
+        escape_html(
+            &mut language,
+            info.split_whitespace().next().unwrap_or_default(),
+        )
+        .expect("Invalid format");
+        format!("<div class=\"runnable\" data-language=\"{}\">\n", language)
+    }
+}
+
+impl EventPreprocessor for Runnable {
+    fn name(&self) -> String {
+        "Runnable code".to_string()
+    }
+
+    fn process(&self, input: Document<EventContent>) -> Result<Document<EventContent>, Error> {
+        let html = self.output_format == OutputFormat::Html;
+        let mut loaded = false;
+        // Stripped info string of the current block and whether it is runnable, used to rewrite
+        // its end tag.
+        let mut current: Option<(String, bool)> = None;
+
+        let content = input
+            .content
+            .into_iter()
+            .flat_map(|(event, pos)| {
+                let events = match event {
+                    AEvent::Start(ATag::CodeBlock(ACodeBlockKind::Fenced(info))) => {
+                        let (info, runnable) = strip_runnable(&info);
+                        let mut events = vec![];
+                        if runnable && html {
+                            if !loaded {
+                                events.push(AEvent::Html(self.loader.clone()));
+                                loaded = true;
+                            }
+                            events.push(AEvent::Html(Self::mount(&info)));
+                        }
+                        current = Some((info.clone(), runnable));
+                        events.push(AEvent::Start(ATag::CodeBlock(ACodeBlockKind::Fenced(info))));
+                        events
+                    }
+                    AEvent::End(ATag::CodeBlock(ACodeBlockKind::Fenced(info))) => {
+                        let (info, runnable) = current.take().unwrap_or((info, false));
+                        let mut events =
+                            vec![AEvent::End(ATag::CodeBlock(ACodeBlockKind::Fenced(info)))];
+                        if runnable && html {
+                            events.push(AEvent::Html("</div>\n".to_string()));
+                        }
+                        events
+                    }
+                    event => vec![event],
+                };
+                events
+                    .into_iter()
+                    .map(|e| (e, pos.clone()))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        Ok(Document {
+            metadata: input.metadata,
+            variables: input.variables,
+            content,
+        })
+    }
+}
+
+impl Display for Runnable {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::{DocPos, DocumentMetadata, DocumentVariables};
+
+    fn document() -> Document<EventContent> {
+        let pos = DocPos::default();
+        let block = |info: &str| {
+            vec![
+                AEvent::Start(ATag::CodeBlock(ACodeBlockKind::Fenced(info.to_string()))),
+                AEvent::Text("print(1)\n".to_string()),
+                AEvent::End(ATag::CodeBlock(ACodeBlockKind::Fenced(info.to_string()))),
+            ]
+        };
+        let content = [
+            block("python runnable"),
+            block("python"),
+            block(r#"python runnable title="a.py""#),
+        ]
+        .concat()
+        .into_iter()
+        .map(|e| (e, pos.clone()))
+        .collect();
+        Document {
+            content,
+            metadata: DocumentMetadata::default(),
+            variables: DocumentVariables::default(),
+        }
+    }
+
+    fn process(output_format: OutputFormat) -> Vec<AEvent> {
+        let runnable = Runnable {
+            output_format,
+            loader: "<script>load()</script>".to_string(),
+        };
+        let res = runnable.process(document()).unwrap();
+        res.content.into_iter().map(|(e, _)| e).collect()
+    }
+
+    #[test]
+    fn test_strip_runnable() {
+        assert_eq!(
+            strip_runnable("python runnable"),
+            ("python".to_string(), true)
+        );
+        assert_eq!(strip_runnable("python"), ("python".to_string(), false));
+        assert_eq!(
+            strip_runnable("python runnables"),
+            ("python runnables".to_string(), false)
+        );
+    }
+
+    #[test]
+    fn test_html() {
+        let events = process(OutputFormat::Html);
+        let html: Vec<&String> = events
+            .iter()
+            .filter_map(|e| match e {
+                AEvent::Html(html) => Some(html),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            html,
+            vec![
+                "<script>load()</script>",
+                "<div class=\"runnable\" data-language=\"python\">\n",
+                "</div>\n",
+                "<div class=\"runnable\" data-language=\"python\">\n",
+                "</div>\n",
+            ]
+        );
+        assert!(matches!(
+            &events[2],
+            AEvent::Start(ATag::CodeBlock(ACodeBlockKind::Fenced(info))) if info == "python"
+        ));
+    }
+
+    #[test]
+    fn test_other_formats() {
+        let events = process(OutputFormat::Notebook);
+        assert_eq!(events.len(), 9);
+        assert!(events.iter().all(|e| match e {
+            AEvent::Start(ATag::CodeBlock(ACodeBlockKind::Fenced(info)))
+            | AEvent::End(ATag::CodeBlock(ACodeBlockKind::Fenced(info))) =>
+                !info.contains(RUNNABLE),
+            AEvent::Html(_) => false,
+            _ => true,
+        }));
+    }
+}
@@ -1,25 +1,90 @@
+use std::collections::HashMap;
 use std::error::Error;
-use std::fmt::{Debug, Display, Formatter};
+use std::fmt::{Debug, Display, Formatter, Write};
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
 
+use anyhow::anyhow;
 use pulldown_cmark::html::push_html;
-use pulldown_cmark::{Options, Parser};
+use pulldown_cmark::Parser;
 use serde::{Deserialize, Serialize};
 use tera::Tera;
 use thiserror::Error;
+use tracing::debug;
 
-use crate::parsers::shortcodes::{parse_shortcode, Rule};
-use crate::processors::{MarkdownPreprocessor, PreprocessorConfig, PreprocessorContext};
+use crate::config::OutputFormat;
+use crate::document::IteratorConfig;
+use crate::parsers::shortcodes::{parse_shortcode, ParameterValue, Rule};
+use crate::processors::{
+    Error as ProcessorError, MarkdownPreprocessor, PreprocessorConfig, PreprocessorContext,
+    TemplateUsage,
+};
+use crate::utils::{escape_punctuation, levenshtein};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct ShortcodesConfig;
+pub struct ShortcodesConfig {
+    /// Path of a shortcode template relative to the shortcode template directory. The `{name}`
+    /// placeholder is replaced by the shortcode name and `{ext}` by the template extension of the
+    /// output format.
+    #[serde(default = "default_template_pattern")]
+    pub template_pattern: String,
+    /// Add a newline after each rendered block shortcode. The resulting blank line ends raw HTML
+    /// blocks, but it also makes lists loose, so it can be disabled for tight layouts.
+    #[serde(default = "default_block_newline")]
+    pub block_newline: bool,
+    /// How deeply block shortcodes can be nested in each other. Deeper nesting is an error.
+    #[serde(default = "default_max_depth")]
+    pub max_depth: usize,
+}
+
+const DEFAULT_TEMPLATE_PATTERN: &str = "{ext}/{name}.tera.{ext}";
+
+fn default_template_pattern() -> String {
+    DEFAULT_TEMPLATE_PATTERN.to_string()
+}
+
+fn default_block_newline() -> bool {
+    true
+}
+
+fn default_max_depth() -> usize {
+    32
+}
+
+impl Default for ShortcodesConfig {
+    fn default() -> Self {
+        ShortcodesConfig {
+            template_pattern: default_template_pattern(),
+            block_newline: default_block_newline(),
+            max_depth: default_max_depth(),
+        }
+    }
+}
 
 #[typetag::serde(name = "shortcodes")]
 impl PreprocessorConfig for ShortcodesConfig {
     fn build(&self, ctx: &PreprocessorContext) -> anyhow::Result<Box<dyn MarkdownPreprocessor>> {
-        Ok(Box::new(Shortcodes {
+        if !self.template_pattern.contains("{name}") {
+            return Err(anyhow!(
+                "Shortcode template pattern '{}' is missing the {{name}} placeholder",
+                self.template_pattern
+            ));
+        }
+
+        let mut shortcodes = Shortcodes {
             tera: ctx.tera.clone(),
+            output_format: ctx.output_format,
             file_ext: ctx.output_format.template_extension().to_string(),
-        }))
+            template_pattern: self.template_pattern.clone(),
+            block_newline: self.block_newline,
+            max_depth: self.max_depth,
+            used_templates: ctx.used_templates.clone(),
+            defaults: HashMap::new(),
+        };
+        shortcodes.defaults = shortcodes.load_defaults()?;
+        Ok(Box::new(shortcodes))
     }
 }
 
@@ -31,14 +96,144 @@ enum ShortcodeInfo {
     },
 }
 
+const BLOCK_END: &str = "{% end %}";
+/// Offset of the closing `%}` in [BLOCK_END], which is where block end positions point.
+const BLOCK_END_CLOSE: usize = BLOCK_END.len() - "%}".len();
+
+/// Backslash-escape the characters that have a meaning in inline markdown.
+fn escape_markdown(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(
+            c,
+            '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '|' | '#' | '!'
+        ) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Register the filters available to shortcode templates. `escape_md` backslash-escapes the
+/// characters of a value that have a meaning in markdown (e.g. `{{ text | escape_md }}`), which
+/// is the markdown counterpart of Tera's `escape` filter.
+pub fn register_filters(tera: &mut Tera) {
+    tera.register_filter(
+        "escape_md",
+        |value: &tera::Value, _: &HashMap<String, tera::Value>| {
+            let value = tera::try_get_value!("escape_md", "value", String, value);
+            Ok(tera::Value::String(escape_markdown(&value)))
+        },
+    );
+}
+
+/// Built-in shortcodes. They take precedence over templates with the same name. `raw` and
+/// `markdown` are block shortcodes and `documents` is inline. Conditional blocks
+/// (`{% if <condition> %}`) are built in as well.
+const BUILTIN_SHORTCODES: [&str; 3] = ["raw", "markdown", "documents"];
+
+/// The condition of an `{% if <condition> %}` block, given the contents of its tag.
+fn condition(tag: &str) -> Option<&str> {
+    tag.strip_prefix("if")
+        .filter(|rest| rest.starts_with(char::is_whitespace))
+        .map(str::trim)
+}
+
+/// A markdown list linking to the documents of a chapter (the `chapter_documents` of the context,
+/// set for chapter index documents). Each item is the title and the summary if the document has
+/// one.
+fn documents_list(ctx: &tera::Context) -> String {
+    let documents = ctx
+        .get("chapter_documents")
+        .and_then(|d| d.as_array())
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    let mut list = String::new();
+    for doc in documents {
+        let field = |name: &str| doc.get(name).and_then(|v| v.as_str());
+        let (Some(title), Some(url)) = (field("title"), field("url")) else {
+            continue;
+        };
+        write!(list, "\n- [{}]({})", escape_markdown(title), url).expect("Invalid format");
+        if let Some(summary) = field("summary") {
+            write!(list, ": {}", escape_markdown(summary)).expect("Invalid format");
+        }
+    }
+    if !list.is_empty() {
+        list.push('\n');
+    }
+    list
+}
+
+/// Find the `%}` and `{% end %}` of a `{% raw %}` block starting at `start`. The body of a raw
+/// block is not scanned, so it ends at the first end tag.
+fn raw_block(input: &str, start: usize) -> Option<(usize, usize)> {
+    let end = start + input[start..].find("%}")?;
+    if input[(start + 2)..end].trim() != "raw" {
+        return None;
+    }
+    let end_block = end + input[end..].find(BLOCK_END)?;
+    Some((end, end_block))
+}
+
+/// Whether the delimiter at `pos` is escaped by a backslash (e.g. `\{{`). A backslash that is
+/// itself escaped (`\\{{`) doesn't escape the delimiter.
+fn is_escaped(input: &str, pos: usize) -> bool {
+    input[..pos]
+        .chars()
+        .rev()
+        .take_while(|c| *c == '\\')
+        .count()
+        % 2
+        == 1
+}
+
 fn extract_block(start: usize, input: &str) -> Option<ShortcodeInfo> {
+    if let Some((end, end_block)) = raw_block(input, start) {
+        return Some(ShortcodeInfo::Block {
+            def: (start, end),
+            end: (end_block, end_block + BLOCK_END_CLOSE),
+        });
+    }
     let end = start + input[start..].find("%}")?;
 
-    let end_block = end + input[end..].find("{% end %}")?;
+    // Skip the end tags of nested block shortcodes so that they don't close this one. Delimiters
+    // inside code are not shortcodes. Code is found as the search goes (rather than for the whole
+    // rest of the input) to keep documents with many blocks linear.
+    let next_code = |pos: usize| find_next_block(&input[pos..]).map(|(s, e)| (s + pos, e + pos));
+    let mut code = next_code(end);
+    let mut depth = 0;
+    let mut pos = end;
+    let end_block = loop {
+        let next = pos + input[pos..].find("{%")?;
+        if let Some((_, code_end)) = code.filter(|(code_start, _)| *code_start < next) {
+            pos = code_end;
+            code = next_code(pos);
+            continue;
+        }
+        if let Some((_, raw_end)) = raw_block(input, next) {
+            pos = raw_end + BLOCK_END.len();
+            continue;
+        }
+        if is_escaped(input, next) {
+            pos = next + 2;
+            continue;
+        }
+        if input[next..].starts_with(BLOCK_END) {
+            if depth == 0 {
+                break next;
+            }
+            depth -= 1;
+        } else {
+            depth += 1;
+        }
+        pos = next + 2;
+    };
 
     Some(ShortcodeInfo::Block {
         def: (start, end),
-        end: (end_block, end_block + 7),
+        end: (end_block, end_block + BLOCK_END_CLOSE),
     })
 }
 
@@ -65,6 +260,14 @@ fn find_all_blocks(input: &str) -> Vec<(usize, usize)> {
     }
 }
 
+/// Find the code block (or span) that contains the given position. A shortcode is ignored if it
+/// starts inside code, even if the closing delimiter is found after the code ends.
+fn code_block_at(blocks: &[(usize, usize)], pos: usize) -> Option<(usize, usize)> {
+    // The blocks are sorted and don't overlap.
+    let i = blocks.partition_point(|(_, end)| *end <= pos);
+    blocks.get(i).filter(|(start, _)| *start < pos).copied()
+}
+
 fn find_next_block(input: &str) -> Option<(usize, usize)> {
     let start = input.find('`')?;
     let end_delim = if input[(start + 1)..].len() > 2 && &input[(start + 1)..(start + 3)] == "``" {
@@ -77,22 +280,146 @@ fn find_next_block(input: &str) -> Option<(usize, usize)> {
     Some((start, end))
 }
 
+/// The body of a `{% raw %}` block, shown as written. HTML formats get a `<pre class="raw">`
+/// element whose punctuation is escaped so that no later processor changes it, other formats a
+/// fenced code block.
+fn raw_block_body(body: &str, output_format: OutputFormat) -> String {
+    match output_format {
+        OutputFormat::Html | OutputFormat::Pdf | OutputFormat::Epub => {
+            format!("<pre class=\"raw\">{}</pre>", escape_punctuation(body))
+        }
+        _ => {
+            // The fence must be longer than any run of backticks in the body.
+            let longest = body
+                .split(|c| c != '`')
+                .map(str::len)
+                .max()
+                .unwrap_or_default();
+            let fence = "`".repeat((longest + 1).max(3));
+            format!("{fence}\n{body}\n{fence}")
+        }
+    }
+}
+
+/// Finds the shortcode delimiters (`{{` and `{%`) of an input in order. The position of the next
+/// delimiter of each kind is remembered so the rest of the input isn't searched again for every
+/// shortcode.
+struct Delimiters<'a> {
+    input: &'a str,
+    /// The next `{{` and `{%` (`Some(None)` if there are no more).
+    found: [Option<Option<usize>>; 2],
+}
+
+impl<'a> Delimiters<'a> {
+    fn new(input: &'a str) -> Self {
+        Delimiters {
+            input,
+            found: [None, None],
+        }
+    }
+
+    /// Position of the first delimiter at or after `from`, which must not decrease between calls.
+    fn next(&mut self, from: usize) -> Option<usize> {
+        for (found, delimiter) in self.found.iter_mut().zip(["{{", "{%"]) {
+            let stale = match found {
+                None => true,
+                Some(Some(pos)) => *pos < from,
+                Some(None) => false,
+            };
+            if stale {
+                *found = Some(self.input[from..].find(delimiter).map(|pos| pos + from));
+            }
+        }
+        self.found.iter().flatten().flatten().min().copied()
+    }
+}
+
+/// Extract the shortcode that starts with the delimiter at `start`.
+fn shortcode_at(input: &str, start: usize) -> Option<ShortcodeInfo> {
+    if input[start..].starts_with("{{") {
+        extract_inline(start, input)
+    } else {
+        extract_block(start, input)
+    }
+}
+
+#[cfg(test)]
 fn find_shortcode(input: &str) -> Option<ShortcodeInfo> {
-    let start_inline = input.find("{{");
-    let start_block = input.find("{%");
-
-    match start_inline {
-        None => start_block.and_then(|start| extract_block(start, input)),
-        Some(inline_start_idx) => match start_block {
-            None => extract_inline(inline_start_idx, input),
-            Some(block_start_idx) => {
-                if inline_start_idx < block_start_idx {
-                    extract_inline(inline_start_idx, input)
-                } else {
-                    extract_block(block_start_idx, input)
-                }
+    shortcode_at(input, Delimiters::new(input).next(0)?)
+}
+
+/// A shortcode invocation found in a source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShortcodeUse {
+    pub name: String,
+    pub parameters: HashMap<String, ParameterValue>,
+    /// Whether the shortcode uses the block syntax.
+    pub block: bool,
+    /// Byte range of the shortcode in the source, including the end tag of blocks.
+    pub range: Range<usize>,
+    /// Line number (starting at 1) where the shortcode starts.
+    pub line: usize,
+}
+
+/// Find the shortcode invocations in a source without rendering them, i.e. templates don't need
+/// to exist. Shortcodes nested in blocks are included after their block. Like when rendering,
+/// shortcodes in code and in the body of `{% raw %}` blocks are ignored. Invocations with invalid
+/// syntax are skipped.
+pub fn collect_shortcodes(input: &str) -> Vec<ShortcodeUse> {
+    let mut uses = Vec::new();
+    collect_nested(input, 0, &mut uses);
+    for u in &mut uses {
+        u.line = input[..u.range.start].matches('\n').count() + 1;
+    }
+    uses
+}
+
+/// Collect the shortcodes of `input`, which starts at `base` in the full source.
+fn collect_nested(input: &str, base: usize, uses: &mut Vec<ShortcodeUse>) {
+    let blocks = find_all_blocks(input);
+    let mut delimiters = Delimiters::new(input);
+    let mut offset = 0;
+
+    while offset < input.len() {
+        let rest = &input[offset..];
+        let next = delimiters.next(offset).map(|n| n - offset);
+        if let Some((_, block_end)) = next.and_then(|n| code_block_at(&blocks, n + offset)) {
+            offset = block_end;
+            continue;
+        }
+        if let Some(n) = next.filter(|n| is_escaped(rest, *n)) {
+            offset += n + 2;
+            continue;
+        }
+
+        let (source, range, body) = match next.and_then(|n| shortcode_at(rest, n)) {
+            None => return,
+            Some(ShortcodeInfo::Inline(start, end)) => {
+                (&rest[(start + 2)..end], start..(end + 2), None)
             }
-        },
+            Some(ShortcodeInfo::Block { def, end }) => (
+                &rest[(def.0 + 2)..def.1],
+                def.0..(end.1 + 2),
+                Some((def.1 + 2)..end.0),
+            ),
+        };
+        if let (Some(body), Some(_)) = (&body, condition(source.trim())) {
+            collect_nested(&rest[body.clone()], base + offset + body.start, uses);
+        } else if let Ok(code) = parse_shortcode(source.trim()) {
+            let block = body.is_some();
+            let is_raw = block && code.name == "raw";
+            uses.push(ShortcodeUse {
+                name: code.name.clone(),
+                parameters: code.into_parameters(),
+                block,
+                range: (base + offset + range.start)..(base + offset + range.end),
+                line: 0,
+            });
+            if let Some(body) = body.filter(|_| !is_raw) {
+                collect_nested(&rest[body.clone()], base + offset + body.start, uses);
+            }
+        }
+        offset += range.end;
     }
 }
 
@@ -105,6 +432,82 @@ pub enum ShortCodeProcessError {
     },
     // #[error("shortcode syntax error: {}", .0)]
     Pest(#[from] Box<pest::error::Error<Rule>>),
+    /// No template exists for the shortcode. Contains the closest matching shortcode name, if any.
+    Unknown {
+        name: String,
+        suggestion: Option<String>,
+    },
+    /// A block shortcode is nested deeper than the limit.
+    TooDeep {
+        name: String,
+        max_depth: usize,
+    },
+    /// The companion file with parameter defaults of a shortcode couldn't be read or parsed.
+    Defaults {
+        path: String,
+        message: String,
+    },
+    /// An error of the shortcode at a line and column (both starting at 1) of the source.
+    Located {
+        line: usize,
+        column: usize,
+        source: Box<ShortCodeProcessError>,
+    },
+}
+
+impl ShortCodeProcessError {
+    /// Attach the position of the shortcode that caused the error. Errors that already have a
+    /// position come from the body of a block shortcode and are relative to it, so `position`
+    /// is where the body starts.
+    fn at(self, position: (usize, usize)) -> Self {
+        let (line, column) = position;
+        match self {
+            ShortCodeProcessError::Located {
+                line: inner_line,
+                column: inner_column,
+                source,
+            } => ShortCodeProcessError::Located {
+                line: line + inner_line - 1,
+                column: if inner_line == 1 {
+                    column + inner_column - 1
+                } else {
+                    inner_column
+                },
+                source,
+            },
+            error => ShortCodeProcessError::Located {
+                line,
+                column,
+                source: Box::new(error),
+            },
+        }
+    }
+
+    /// Move the position of a located error down by a number of lines.
+    pub(crate) fn offset_lines(self, lines: usize) -> Self {
+        match self {
+            ShortCodeProcessError::Located {
+                line,
+                column,
+                source,
+            } => ShortCodeProcessError::Located {
+                line: line + lines,
+                column,
+                source,
+            },
+            error => error,
+        }
+    }
+}
+
+/// The line and column (both starting at 1) of a byte offset in the input.
+fn line_column(input: &str, offset: usize) -> (usize, usize) {
+    let before = &input[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
 }
 
 impl Display for ShortCodeProcessError {
@@ -121,6 +524,26 @@ impl Display for ShortCodeProcessError {
                 Ok(())
             }
             ShortCodeProcessError::Pest(inner) => Display::fmt(&inner, f),
+            ShortCodeProcessError::Unknown { name, suggestion } => {
+                write!(f, "unknown shortcode `{}`", name)?;
+                if let Some(suggestion) = suggestion {
+                    write!(f, "; did you mean `{}`?", suggestion)?;
+                }
+                Ok(())
+            }
+            ShortCodeProcessError::TooDeep { name, max_depth } => write!(
+                f,
+                "block shortcode `{}` is nested more than {} levels deep",
+                name, max_depth
+            ),
+            ShortCodeProcessError::Defaults { path, message } => {
+                write!(f, "invalid shortcode defaults in {}: {}", path, message)
+            }
+            ShortCodeProcessError::Located {
+                line,
+                column,
+                source,
+            } => write!(f, "line {}, column {}: {}", line, column, source),
         }
     }
 }
@@ -179,151 +602,388 @@ impl Display for ShortCodeProcessError {
 
 #[derive(Debug)]
 pub struct Shortcodes {
-    tera: Tera,
+    tera: Arc<Tera>,
+    output_format: OutputFormat,
     file_ext: String,
+    template_pattern: String,
+    block_newline: bool,
+    max_depth: usize,
+    used_templates: TemplateUsage,
+    /// Parameter defaults of the shortcodes, keyed by template name (see [Shortcodes::load_defaults]).
+    defaults: HashMap<String, toml::value::Table>,
 }
 
 impl Shortcodes {
-    pub fn new(pattern: &str, file_ext: &str) -> Result<Self, tera::Error> {
-        Ok(Shortcodes {
-            tera: Tera::new(pattern)?,
-            file_ext: file_ext.to_string(),
+    pub fn new(pattern: &str, output_format: OutputFormat) -> Result<Self, ShortCodeProcessError> {
+        let mut tera = Tera::new(pattern)?;
+        register_filters(&mut tera);
+        let mut shortcodes = Shortcodes {
+            tera: Arc::new(tera),
+            output_format,
+            file_ext: output_format.template_extension().to_string(),
+            template_pattern: default_template_pattern(),
+            block_newline: default_block_newline(),
+            max_depth: default_max_depth(),
+            used_templates: TemplateUsage::default(),
+            defaults: HashMap::new(),
+        };
+        shortcodes.defaults = shortcodes.load_defaults()?;
+        Ok(shortcodes)
+    }
+
+    fn template_name(&self, name: &str) -> String {
+        self.template_pattern
+            .replace("{name}", name)
+            .replace("{ext}", &self.file_ext)
+    }
+
+    /// The names of the shortcodes that have a template for the current output format.
+    fn shortcode_names(&self) -> Vec<String> {
+        let pattern = self.template_pattern.replace("{ext}", &self.file_ext);
+        let (prefix, suffix) = pattern.split_once("{name}").unwrap_or((&pattern, ""));
+        self.tera
+            .get_template_names()
+            .filter_map(|t| t.strip_prefix(prefix)?.strip_suffix(suffix))
+            .map(|name| name.to_string())
+            .collect()
+    }
+
+    /// Get the template name of a shortcode. Fails with a suggestion of the closest shortcode name
+    /// if the template doesn't exist (to catch typos).
+    fn find_template(&self, name: &str) -> Result<String, ShortCodeProcessError> {
+        let template = self.template_name(name);
+        if self.tera.get_template_names().any(|t| t == template) {
+            return Ok(template);
+        }
+
+        // Allows a swapped pair of characters in short names and more edits in longer ones.
+        let max_distance = (name.chars().count() / 3).max(2);
+        let suggestion = self
+            .shortcode_names()
+            .into_iter()
+            .chain(BUILTIN_SHORTCODES.map(String::from))
+            .map(|candidate| (levenshtein(name, &candidate), candidate))
+            .filter(|(distance, candidate)| {
+                *distance <= max_distance && *distance < candidate.chars().count()
+            })
+            .min()
+            .map(|(_, candidate)| candidate);
+        Err(ShortCodeProcessError::Unknown {
+            name: name.to_string(),
+            suggestion,
         })
     }
 
+    fn render_template(&self, name: &str, ctx: &tera::Context) -> Result<String, tera::Error> {
+        debug!("rendering shortcode template {}", name);
+        self.tera.render(name, ctx)
+    }
+
+    /// Record the templates of the shortcodes in a source. Uses are found in the source rather
+    /// than when rendering so that shortcodes in excluded conditional content are included.
+    fn record_uses(&self, input: &str) {
+        collect_shortcodes(input)
+            .iter()
+            .map(|u| self.template_name(&u.name))
+            .filter(|template| self.tera.get_template_names().any(|t| t == template))
+            .for_each(|template| self.used_templates.record(&template));
+    }
+
+    /// Read the parameter defaults of the shortcodes of the output format. The defaults of a
+    /// shortcode are read from `<name>.toml` in the directory of its template file (if it exists).
+    /// Templates that weren't loaded from a file have no defaults.
+    fn load_defaults(&self) -> Result<HashMap<String, toml::value::Table>, ShortCodeProcessError> {
+        let mut all_defaults = HashMap::new();
+        for name in self.shortcode_names() {
+            let template = self.template_name(&name);
+            let path = match self
+                .tera
+                .get_template(&template)?
+                .path
+                .as_ref()
+                .and_then(|p| Path::new(p).parent())
+            {
+                Some(dir) => dir.join(format!("{}.toml", name)),
+                None => continue,
+            };
+            if !path.is_file() {
+                continue;
+            }
+
+            let defaults_error = |message: String| ShortCodeProcessError::Defaults {
+                path: path.display().to_string(),
+                message,
+            };
+            let source = fs::read_to_string(&path).map_err(|e| defaults_error(e.to_string()))?;
+            let defaults: toml::value::Table =
+                toml::from_str(&source).map_err(|e| defaults_error(e.to_string()))?;
+            all_defaults.insert(template, defaults);
+        }
+        Ok(all_defaults)
+    }
+
+    /// Insert the parameter defaults of a shortcode into the template context.
+    fn insert_defaults(&self, template: &str, ctx: &mut tera::Context) {
+        if let Some(defaults) = self.defaults.get(template) {
+            for (k, v) in defaults {
+                ctx.insert(k, v);
+            }
+        }
+    }
+
+    /// Insert shortcode arguments into the template context. Quoted values are inserted as-is.
+    /// Unquoted values are evaluated as Tera expressions if their root variable exists in the
+    /// context (e.g. `project.url_prefix` or `page.title`) and are otherwise used literally.
+    ///
+    /// Evaluated values are inserted as data (never as template source). Values are inserted
+    /// unescaped; templates escape them when they are output (html templates are autoescaped by
+    /// Tera and markdown templates use the `escape_md` filter).
+    fn insert_parameters(
+        parameters: HashMap<String, ParameterValue>,
+        ctx: &mut tera::Context,
+    ) -> Result<(), tera::Error> {
+        for (k, v) in parameters {
+            let value = match v {
+                ParameterValue::Literal(s) => s,
+                ParameterValue::Expression(s) => {
+                    let root = s
+                        .split(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                        .next()
+                        .unwrap_or_default();
+                    if ctx.contains_key(root) {
+                        Tera::one_off(&format!("{{{{ {} }}}}", s), ctx, false)?
+                    } else {
+                        s
+                    }
+                }
+            };
+            ctx.insert(k, &value);
+        }
+        Ok(())
+    }
+
     fn render_inline_template(
         &self,
         shortcode: &str,
         ctx: &tera::Context,
-    ) -> anyhow::Result<String> {
+    ) -> Result<String, ShortCodeProcessError> {
         let code = parse_shortcode(shortcode)?;
-        let name = format!("{}/{}.tera.{}", self.file_ext, code.name, self.file_ext);
+        if code.name == "documents" {
+            return Ok(documents_list(ctx));
+        }
+        let name = self.find_template(&code.name)?;
 
         let mut ctx = ctx.clone();
-        for (k, v) in code.parameters {
-            ctx.insert(k, &v);
-        }
+        self.insert_defaults(&name, &mut ctx);
+        Shortcodes::insert_parameters(code.into_parameters(), &mut ctx)?;
 
-        let res = self.tera.render(&name, &ctx)?;
+        let res = self.render_template(&name, &ctx)?;
         let res = res.replace("\n\n", "\n");
         Ok(res)
     }
 
-    fn render_block_template(
+    /// Process the shortcodes of the body of a block at the given depth.
+    fn process_body(
         &self,
-        shortcode: &str,
+        name: String,
         body: &str,
         ctx: &tera::Context,
-    ) -> Result<String, anyhow::Error> {
-        let code = parse_shortcode(shortcode)?;
-        let name = format!("{}/{}.tera.{}", self.file_ext, code.name, self.file_ext);
-
-        let mut ctx = ctx.clone();
-
-        for (k, v) in code.parameters {
-            ctx.insert(k, &v);
+        depth: usize,
+    ) -> Result<String, ProcessorError> {
+        if depth >= self.max_depth {
+            return Err(ShortCodeProcessError::TooDeep {
+                name,
+                max_depth: self.max_depth,
+            }
+            .into());
         }
+        self.process_nested(body, ctx, depth + 1)
+    }
 
-        let processed = self.process(body, &ctx)?;
+    /// Process the shortcodes of a block body and render it to html for html templates.
+    fn render_body(
+        &self,
+        name: String,
+        body: &str,
+        ctx: &tera::Context,
+        depth: usize,
+    ) -> Result<String, ProcessorError> {
+        let processed = self.process_body(name, body, ctx, depth)?;
 
-        let body_final = if self.file_ext == "html" {
-            let parser = Parser::new_ext(&processed, Options::all());
+        Ok(if self.file_ext == "html" {
+            // Documents can turn off smart punctuation in their front matter.
+            let smart_punctuation = ctx
+                .get("page")
+                .and_then(|page| page.get("smart_punctuation"))
+                .and_then(|value| value.as_bool())
+                .unwrap_or(true);
+            let options = IteratorConfig {
+                smart_punctuation,
+                ..Default::default()
+            }
+            .markdown_options();
+            let parser = Parser::new_ext(&processed, options);
             let mut html = String::new();
             push_html(&mut html, parser);
             html
         } else {
             processed
-        };
+        })
+    }
+
+    /// Process the body of an `{% if <condition> %}` block if the condition (a Tera expression,
+    /// e.g. `var.audience == "student"`) is true. Otherwise the body is left out entirely. A
+    /// condition on a variable that isn't set is an error rather than false.
+    fn render_conditional(
+        &self,
+        condition: &str,
+        body: &str,
+        ctx: &tera::Context,
+        depth: usize,
+    ) -> Result<String, ProcessorError> {
+        // Tera treats unknown variables in `if` tags as false, but not in expressions.
+        let check = format!("{{{{ {} }}}}", condition);
+        Tera::one_off(&check, ctx, false).map_err(ShortCodeProcessError::from)?;
+        let test = format!("{{% if {} %}}true{{% endif %}}", condition);
+        let included = Tera::one_off(&test, ctx, false).map_err(ShortCodeProcessError::from)?;
+        if included == "true" {
+            self.process_body("if".to_string(), body, ctx, depth)
+        } else {
+            Ok(String::new())
+        }
+    }
+
+    fn render_block_template(
+        &self,
+        shortcode: &str,
+        body: &str,
+        ctx: &tera::Context,
+        depth: usize,
+    ) -> Result<String, ProcessorError> {
+        let code = parse_shortcode(shortcode).map_err(ShortCodeProcessError::from)?;
+        let name = self.find_template(&code.name)?;
+
+        let mut ctx = ctx.clone();
+        self.insert_defaults(&name, &mut ctx);
+        let shortcode_name = code.name.clone();
+        Shortcodes::insert_parameters(code.into_parameters(), &mut ctx)
+            .map_err(ShortCodeProcessError::from)?;
+
+        let body_final = self.render_body(shortcode_name, body, &ctx, depth)?;
 
         ctx.insert("body", &body_final);
-        let res = self.tera.render(&name, &ctx)?;
+        let res = self
+            .render_template(&name, &ctx)
+            .map_err(ShortCodeProcessError::from)?;
         let res = res.replace("\n\n", "\n");
         Ok(res)
     }
-}
 
-impl MarkdownPreprocessor for Shortcodes {
-    fn name(&self) -> String {
-        "Shortcode processor".to_string()
-    }
-
-    fn process(&self, input: &str, ctx: &tera::Context) -> Result<String, anyhow::Error> {
+    /// Render the shortcodes of the input, which is the body of `depth` nested block shortcodes.
+    fn process_nested(
+        &self,
+        input: &str,
+        ctx: &tera::Context,
+        depth: usize,
+    ) -> Result<String, ProcessorError> {
         let mut rest = input;
         let mut offset = 0;
 
         let mut result = String::new();
 
         let blocks = find_all_blocks(input);
+        let mut delimiters = Delimiters::new(input);
 
         while !rest.is_empty() {
-            match find_shortcode(rest) {
+            // Delimiters inside code are not shortcodes. Copy everything up to the end of the
+            // code block unchanged.
+            let next = delimiters.next(offset).map(|n| n - offset);
+            if let Some((_, block_end)) = next.and_then(|n| code_block_at(&blocks, n + offset)) {
+                let relative = block_end - offset;
+                result.push_str(&rest[..relative]);
+                rest = &rest[relative..];
+                offset += relative;
+                continue;
+            }
+            // An escaped delimiter is written literally without the backslash.
+            if let Some(n) = next.filter(|n| is_escaped(rest, *n)) {
+                result.push_str(&rest[..(n - 1)]);
+                result.push_str(&rest[n..(n + 2)]);
+                rest = &rest[(n + 2)..];
+                offset += n + 2;
+                continue;
+            }
+
+            match next.and_then(|n| shortcode_at(rest, n)) {
                 None => {
                     result.push_str(rest);
                     rest = "";
                 }
 
-                Some(info) => {
-                    match info {
-                        ShortcodeInfo::Inline(start, end) => {
-                            match blocks
-                                .iter()
-                                .find(|(bs, be)| bs < &(start + offset) && be >= &(end + offset))
-                            {
-                                None => {
-                                    let pre = &rest[..start];
-                                    let post = &rest[(end + 2)..];
-                                    let tmp_name = rest[(start + 2)..(end - 1)].trim();
-
-                                    let res = self.render_inline_template(tmp_name, ctx)?;
-
-                                    result.push_str(pre);
-                                    result.push_str(&res);
-
-                                    rest = post; // Start next round after the current shortcode position
-                                    offset += end + 2;
-                                }
-                                Some((_, block_end)) => {
-                                    let relative = *block_end - offset;
-                                    let pre = &rest[..relative];
-                                    result.push_str(pre);
-                                    rest = &rest[relative..];
-                                    offset += relative;
-                                }
-                            }
-                        }
-                        ShortcodeInfo::Block { def, end } => {
-                            match blocks
-                                .iter()
-                                .find(|(bs, be)| bs < &(def.1 + offset) && be > &(end.0 + offset))
-                            {
-                                None => {
-                                    let pre = &rest[..def.0];
-                                    let post = &rest[(end.1 + 2)..];
-
-                                    let tmp_name = rest[(def.0 + 2)..(def.1 - 1)].trim();
-                                    let body = rest[(def.1 + 2)..end.0].trim();
-
-                                    let res = self.render_block_template(tmp_name, body, ctx)?;
-
-                                    result.push_str(pre);
-                                    result.push_str(&res);
-                                    result.push('\n');
-
-                                    rest = post; // Start next round after the current shortcode position
-                                    offset += end.1 + 2;
-                                }
-
-                                Some((_, block_end)) => {
-                                    let relative = *block_end - offset;
-                                    let pre = &rest[..relative];
-                                    result.push_str(pre);
-                                    rest = &rest[relative..];
-                                    offset += relative;
-                                }
+                Some(info) => match info {
+                    ShortcodeInfo::Inline(start, end) => {
+                        let pre = &rest[..start];
+                        let post = &rest[(end + 2)..];
+                        let tmp_name = rest[(start + 2)..end].trim();
+
+                        let res = self
+                            .render_inline_template(tmp_name, ctx)
+                            .map_err(|e| e.at(line_column(input, offset + start)))?;
+
+                        result.push_str(pre);
+                        result.push_str(&res);
+
+                        rest = post; // Start next round after the current shortcode position
+                        offset += end + 2;
+                    }
+                    ShortcodeInfo::Block { def, end } => {
+                        let pre = &rest[..def.0];
+                        let post = &rest[(end.1 + 2)..];
+
+                        let tmp_name = rest[(def.0 + 2)..def.1].trim();
+                        let raw_body = &rest[(def.1 + 2)..end.0];
+                        let body = raw_body.trim();
+                        let body_start = def.1 + 2 + raw_body.len() - raw_body.trim_start().len();
+                        // Errors of nested shortcodes have positions relative to the body.
+                        let locate = |e: ProcessorError| match e {
+                            ProcessorError::ShortcodeError(
+                                e @ ShortCodeProcessError::Located { .. },
+                            ) => e.at(line_column(input, offset + body_start)).into(),
+                            ProcessorError::ShortcodeError(e) => {
+                                e.at(line_column(input, offset + def.0)).into()
                             }
+                            e => e,
+                        };
+
+                        let res = match tmp_name {
+                            // Only surrounding line breaks are removed to keep indentation.
+                            "raw" => raw_block_body(
+                                rest[(def.1 + 2)..end.0].trim_matches(['\n', '\r']),
+                                self.output_format,
+                            ),
+                            "markdown" => self
+                                .render_body(tmp_name.to_string(), body, ctx, depth)
+                                .map_err(locate)?,
+                            _ => match condition(tmp_name) {
+                                Some(condition) => self
+                                    .render_conditional(condition, body, ctx, depth)
+                                    .map_err(locate)?,
+                                None => self
+                                    .render_block_template(tmp_name, body, ctx, depth)
+                                    .map_err(locate)?,
+                            },
+                        };
+
+                        result.push_str(pre);
+                        result.push_str(&res);
+                        if self.block_newline {
+                            result.push('\n');
                         }
+
+                        rest = post; // Start next round after the current shortcode position
+                        offset += end.1 + 2;
                     }
-                }
+                },
             }
         }
 
@@ -331,6 +991,19 @@ impl MarkdownPreprocessor for Shortcodes {
     }
 }
 
+impl MarkdownPreprocessor for Shortcodes {
+    fn name(&self) -> String {
+        "Shortcode processor".to_string()
+    }
+
+    fn process(&self, input: &str, ctx: &tera::Context) -> Result<String, ProcessorError> {
+        // Processing checks the nesting depth before the source is searched for uses.
+        let output = self.process_nested(input, ctx, 0)?;
+        self.record_uses(input);
+        Ok(output)
+    }
+}
+
 impl Display for Shortcodes {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.name())
@@ -340,6 +1013,12 @@ impl Display for Shortcodes {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::OutputFormat;
+    use crate::loader::{Loader, MarkdownLoader};
+    use crate::processors::numbering::ListingNumbers;
+    use crate::processors::Warnings;
+    use pulldown_cmark::Options;
+    use std::path::PathBuf;
 
     #[test]
     fn test_extract_inline() {
@@ -385,4 +1064,777 @@ mod tests {
         assert!(find_shortcode(err_inline_start).is_none(), "{}", msg);
         assert!(find_shortcode(err_inline_start2).is_none(), "{}", msg);
     }
+
+    #[test]
+    fn test_used_templates() {
+        let shortcodes = shortcodes_for(
+            &[
+                ("html/used.tera.html", "used"),
+                ("html/unused.tera.html", "unused"),
+            ],
+            OutputFormat::Html,
+            &default_template_pattern(),
+        );
+
+        let output = shortcodes
+            .process("Some {{ used }} text", &tera::Context::new())
+            .expect("Shortcode processing failed");
+        assert_eq!(output, "Some used text");
+
+        assert!(shortcodes.used_templates.is_used("html/used.tera.html"));
+        assert!(!shortcodes.used_templates.is_used("html/unused.tera.html"));
+
+        let err = shortcodes
+            .process("Some {{ missing }} text", &tera::Context::new())
+            .expect_err("Missing template should fail");
+        assert!(matches!(
+            err,
+            ProcessorError::ShortcodeError(ShortCodeProcessError::Located { source, .. })
+                if matches!(*source, ShortCodeProcessError::Unknown { .. })
+        ));
+    }
+
+    #[test]
+    fn test_template_usage_documents() {
+        let templates = [
+            ("html/note.tera.html", "{{ body }}"),
+            ("html/badge.tera.html", "badge"),
+        ];
+        let usage = TemplateUsage::default();
+        let process = |document: &str, input: &str| {
+            let ctx = PreprocessorContext {
+                used_templates: usage.for_document(Path::new(document)),
+                ..context_for(&templates, OutputFormat::Html)
+            };
+            ShortcodesConfig::default()
+                .build(&ctx)
+                .unwrap()
+                .process(input, &tera::Context::new())
+                .expect("Shortcode processing failed");
+        };
+        process("a.md", "{{ badge }}\n{% note %}\n{{ badge }}\n{% end %}");
+        process("b.md", "{{ badge }}");
+        // Shortcodes in excluded conditional content are uses too.
+        process("c.md", "{% if false %}\n{{ badge }}\n{% end %}");
+
+        let documents = usage.documents();
+        let paths = |template: &str| -> Vec<&Path> {
+            documents[template].iter().map(PathBuf::as_path).collect()
+        };
+        assert_eq!(documents.len(), 2);
+        assert_eq!(
+            paths("html/badge.tera.html"),
+            [Path::new("a.md"), Path::new("b.md"), Path::new("c.md")]
+        );
+        assert_eq!(paths("html/note.tera.html"), [Path::new("a.md")]);
+    }
+
+    #[test]
+    fn test_error_position() {
+        let shortcodes = shortcodes_for(
+            &[("html/note.tera.html", "<div>{{ body }}</div>")],
+            OutputFormat::Html,
+            &default_template_pattern(),
+        );
+        let error = |input: &str| {
+            shortcodes
+                .process(input, &tera::Context::new())
+                .unwrap_err()
+                .to_string()
+        };
+
+        assert_eq!(
+            error("# Title\n\nSee {{ figure(src=\"a.png\" }} here."),
+            format!(
+                "shortcode error: line 3, column 5: {}",
+                parse_shortcode("figure(src=\"a.png\"").err().unwrap()
+            )
+        );
+        // Errors in the body of a block are reported at their line in the document.
+        assert!(error("Text\n{% note %}\nok\n  {{ missing }}\n{% end %}\n")
+            .starts_with("shortcode error: line 4, column 3: unknown shortcode `missing`"));
+        assert!(error("{% other %}x{% end %}").starts_with("shortcode error: line 1, column 1:"));
+    }
+
+    #[test]
+    fn test_error_position_front_matter() {
+        let shortcodes = shortcodes_for(&[], OutputFormat::Html, &default_template_pattern());
+        let doc = MarkdownLoader
+            .load("---\ntitle: Doc\n---\n\nSee {{ missing }} here.\n")
+            .unwrap();
+        let error = doc
+            .preprocess(&shortcodes, &tera::Context::new())
+            .unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("shortcode error: line 5, column 5: unknown shortcode `missing`"));
+    }
+
+    #[test]
+    fn test_unknown_suggestion() {
+        let shortcodes = shortcodes_for(
+            &[
+                ("html/note.tera.html", "note"),
+                ("html/image.tera.html", "image"),
+                ("md/notebook.tera.md", "notebook"),
+            ],
+            OutputFormat::Html,
+            &default_template_pattern(),
+        );
+
+        let error = |input: &str| {
+            shortcodes
+                .process(input, &tera::Context::new())
+                .expect_err("Unknown shortcode should fail")
+                .to_string()
+        };
+        assert_eq!(
+            error("{{ noote }}"),
+            "shortcode error: line 1, column 1: unknown shortcode `noote`; did you mean `note`?"
+        );
+        assert_eq!(
+            error("{% imgae(src=a.png) %}body{% end %}"),
+            "shortcode error: line 1, column 1: unknown shortcode `imgae`; did you mean `image`?"
+        );
+        // Templates for other output formats are not suggested.
+        assert_eq!(
+            error("{{ notebok }}"),
+            "shortcode error: line 1, column 1: unknown shortcode `notebok`"
+        );
+        assert_eq!(
+            error("{{ figure }}"),
+            "shortcode error: line 1, column 1: unknown shortcode `figure`"
+        );
+    }
+
+    #[test]
+    fn test_expression_parameters() {
+        let shortcodes = shortcodes_for(
+            &[("html/badge.tera.html", "{{ version }}")],
+            OutputFormat::Html,
+            &default_template_pattern(),
+        );
+
+        let mut ctx = tera::Context::new();
+        ctx.insert("project", &HashMap::from([("version", "<b>1.0</b>")]));
+
+        let render = |input: &str| shortcodes.process(input, &ctx).unwrap();
+        assert_eq!(
+            render("{{ badge(version=project.version) }}"),
+            "&lt;b&gt;1.0&lt;&#x2F;b&gt;"
+        );
+        assert_eq!(
+            render("{{ badge(version=\"project.version\") }}"),
+            "project.version"
+        );
+        assert_eq!(render("{{ badge(version=other.value) }}"), "other.value");
+    }
+
+    #[test]
+    fn test_escaped_delimiters() {
+        let shortcodes = shortcodes_for(
+            &[
+                ("html/name.tera.html", "{{ arg0 }}"),
+                ("html/note.tera.html", "<div>{{ body | safe }}</div>"),
+            ],
+            OutputFormat::Html,
+            &default_template_pattern(),
+        );
+        let render = |input: &str| shortcodes.process(input, &tera::Context::new()).unwrap();
+
+        assert_eq!(
+            render(r#"Write \{{ name(x) }} to get {{ name("x") }}\{{"#),
+            r#"Write {{ name(x) }} to get x{{"#
+        );
+        assert_eq!(
+            render(r#"{{ name(a) }}\{% note %}{{ name(b) }}"#),
+            "a{% note %}b"
+        );
+        // An escaped backslash doesn't escape the delimiter.
+        assert_eq!(render(r#"\\{{ name(a) }}"#), r#"\\a"#);
+        // Escaped end tags don't close the block.
+        assert_eq!(
+            render(r#"{% note %}Use \{% end %} to close{% end %}"#),
+            "<div><p>Use {% end %} to close</p>\n</div>\n"
+        );
+        // Code is kept as is.
+        assert_eq!(render(r#"`\{{ x }}` {{ name(a) }}"#), r#"`\{{ x }}` a"#);
+
+        let uses = collect_shortcodes(r#"\{{ name(a) }} {{ name(b) }}"#);
+        assert_eq!(uses.len(), 1);
+        assert_eq!(uses[0].range, 15..28);
+    }
+
+    #[test]
+    fn test_positional_arguments() {
+        let shortcodes = shortcodes_for(
+            &[
+                (
+                    "html/youtube.tera.html",
+                    "<iframe src=\"{{ arg0 }}?start={{ start | default(value=0) }}\"></iframe>",
+                ),
+                (
+                    "html/note.tera.html",
+                    "<div title=\"{{ arg0 }}\">{{ body }}</div>",
+                ),
+            ],
+            OutputFormat::Html,
+            &default_template_pattern(),
+        );
+        let render = |input: &str| shortcodes.process(input, &tera::Context::new()).unwrap();
+
+        assert_eq!(
+            render("{{ youtube(\"abc\") }}"),
+            "<iframe src=\"abc?start=0\"></iframe>"
+        );
+        assert_eq!(
+            render("{{ youtube(abc, start=10) }}"),
+            "<iframe src=\"abc?start=10\"></iframe>"
+        );
+        assert!(render("{% note(\"Tip\") %}Text{% end %}").starts_with("<div title=\"Tip\">"));
+        assert!(shortcodes
+            .process("{{ youtube(start=10, \"abc\") }}", &tera::Context::new())
+            .is_err());
+    }
+
+    fn tera_for(templates: &[(&str, &str)]) -> Tera {
+        let mut tera = Tera::default();
+        register_filters(&mut tera);
+        tera.add_raw_templates(templates.to_vec()).unwrap();
+        tera
+    }
+
+    fn shortcodes_for(
+        templates: &[(&str, &str)],
+        output_format: OutputFormat,
+        pattern: &str,
+    ) -> Shortcodes {
+        Shortcodes {
+            tera: Arc::new(tera_for(templates)),
+            output_format,
+            file_ext: output_format.template_extension().to_string(),
+            template_pattern: pattern.to_string(),
+            block_newline: true,
+            max_depth: default_max_depth(),
+            used_templates: TemplateUsage::default(),
+            defaults: HashMap::new(),
+        }
+    }
+
+    fn context_for(templates: &[(&str, &str)], output_format: OutputFormat) -> PreprocessorContext {
+        PreprocessorContext {
+            tera: Arc::new(tera_for(templates)),
+            output_format,
+            used_templates: TemplateUsage::default(),
+            include_solutions: false,
+            listing_numbers: ListingNumbers::default(),
+            math_macros: HashMap::new(),
+            warnings: Warnings::default(),
+        }
+    }
+
+    #[test]
+    fn test_escape_parameters_html() {
+        let ctx = tera::Context::new();
+        let input = "{{ link(text=\"a <b> & 'c'\") }}";
+        let expected = "<a>a &lt;b&gt; &amp; &#x27;c&#x27;</a>";
+
+        // Autoescaped by Tera.
+        let shortcodes = shortcodes_for(
+            &[("html/link.tera.html", "<a>{{ text }}</a>")],
+            OutputFormat::Html,
+            &default_template_pattern(),
+        );
+        assert_eq!(shortcodes.process(input, &ctx).unwrap(), expected);
+
+        // Not autoescaped by Tera because of the template name.
+        let shortcodes = shortcodes_for(
+            &[("shortcodes/link.html.tera", "<a>{{ text | escape }}</a>")],
+            OutputFormat::Html,
+            "shortcodes/{name}.{ext}.tera",
+        );
+        assert_eq!(shortcodes.process(input, &ctx).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_escape_parameters_md() {
+        let shortcodes = shortcodes_for(
+            &[(
+                "md/link.tera.md",
+                "[{{ text | escape_md }}]({{ url | escape_md }})",
+            )],
+            OutputFormat::Notebook,
+            &default_template_pattern(),
+        );
+        let input = "| {{ link(text=\"a] | *b* <i>\", url=\"x_y.html\") }} | c |\n|---|---|\n";
+        let output = shortcodes.process(input, &tera::Context::new()).unwrap();
+        assert_eq!(
+            output,
+            "| [a\\] \\| \\*b\\* \\<i\\>](x\\_y.html) | c |\n|---|---|\n"
+        );
+
+        // The values are unchanged when the markdown is parsed.
+        let mut html = String::new();
+        push_html(&mut html, Parser::new_ext(&output, Options::all()));
+        assert!(html.contains("<th><a href=\"x_y.html\">a] | *b* &lt;i&gt;</a></th>"));
+        assert!(html.contains("<th>c</th>"));
+    }
+
+    #[test]
+    fn test_unescaped_parameters() {
+        let shortcodes = shortcodes_for(
+            &[(
+                "md/message.tera.md",
+                "{% if kind == \"a < b\" %}```\n{{ title }}\n```{% endif %}",
+            )],
+            OutputFormat::Notebook,
+            &default_template_pattern(),
+        );
+        let input = "{{ message(kind=\"a < b\", title=\"x_y *z*\") }}";
+        let output = shortcodes.process(input, &tera::Context::new()).unwrap();
+        assert_eq!(output, "```\nx_y *z*\n```");
+    }
+
+    #[test]
+    fn test_raw_block() {
+        let shortcodes = shortcodes_for(
+            &[("html/note.tera.html", "<div>{{ body | safe }}</div>")],
+            OutputFormat::Html,
+            &default_template_pattern(),
+        );
+        let input =
+            "{% note %}\n{% raw %}\n  {{ missing }} {% other %}\n*a* $x$\n{% end %}\n{% end %}\n";
+        let output = shortcodes.process(input, &tera::Context::new()).unwrap();
+
+        let raw = "<pre class=\"raw\">  &#123;&#123; missing &#125;&#125; &#123;&#37; other &#37;&#125;\n\
+                   &#42;a&#42; &#36;x&#36;</pre>";
+        assert_eq!(output, format!("<div>{}\n</div>\n\n", raw));
+
+        // The body is shown literally and math delimiters don't match.
+        let output = shortcodes
+            .process("{% raw %}$x$ {{ y }}{% end %}", &tera::Context::new())
+            .unwrap();
+        let math = crate::processors::mathml::MathML::default();
+        assert_eq!(
+            math.process(&output, &tera::Context::new()).unwrap(),
+            output
+        );
+        let mut html = String::new();
+        push_html(&mut html, Parser::new_ext(&output, Options::all()));
+        assert_eq!(
+            html,
+            "<pre class=\"raw\">&#36;x&#36; &#123;&#123; y &#125;&#125;</pre>\n"
+        );
+
+        // Other formats get a fenced code block, longer than the fences in the body.
+        let shortcodes = shortcodes_for(&[], OutputFormat::Notebook, &default_template_pattern());
+        let output = shortcodes
+            .process(
+                "{% raw %}\n*a* {{ y }}\n```\ncode\n```\n{% end %}",
+                &tera::Context::new(),
+            )
+            .unwrap();
+        assert_eq!(output, "````\n*a* {{ y }}\n```\ncode\n```\n````\n");
+    }
+
+    #[test]
+    fn test_markdown_block() {
+        let shortcodes = shortcodes_for(
+            &[("html/badge.tera.html", "<b>badge</b>")],
+            OutputFormat::Html,
+            &default_template_pattern(),
+        );
+        let input = "<div>\n{% markdown %}\n*emph* {{ badge }}\n{% end %}\n</div>\n";
+        let output = shortcodes.process(input, &tera::Context::new()).unwrap();
+        assert_eq!(
+            output,
+            "<div>\n<p><em>emph</em> <b>badge</b></p>\n\n\n</div>\n"
+        );
+
+        // Markdown output keeps the body as markdown.
+        let shortcodes = shortcodes_for(&[], OutputFormat::Notebook, &default_template_pattern());
+        let output = shortcodes
+            .process("{% markdown %}\n*emph*\n{% end %}", &tera::Context::new())
+            .unwrap();
+        assert_eq!(output, "*emph*\n");
+    }
+
+    #[test]
+    fn test_documents_list() {
+        let shortcodes = shortcodes_for(&[], OutputFormat::Html, &default_template_pattern());
+        let mut ctx = tera::Context::new();
+        ctx.insert(
+            "chapter_documents",
+            &serde_json::json!([
+                {"id": "intro", "title": "Intro_1", "summary": "Start *here*", "url": "/ch/intro.html"},
+                {"id": "more", "title": "More", "summary": null, "url": "/ch/more.html"}
+            ]),
+        );
+        let output = shortcodes
+            .process("Contents:\n{{ documents }}", &ctx)
+            .unwrap();
+        assert_eq!(
+            output,
+            "Contents:\n\n- [Intro\\_1](/ch/intro.html): Start \\*here\\*\n- [More](/ch/more.html)\n"
+        );
+
+        // Documents that aren't chapter indices have no list.
+        let output = shortcodes
+            .process("{{ documents }}", &tera::Context::new())
+            .unwrap();
+        assert_eq!(output, "");
+    }
+
+    #[test]
+    fn test_collect_shortcodes() {
+        let input = "A {{ badge(version=project.version) }} `{{ code }}`\n\
+                     {% note(title=\"Note\") %}\n{{ image(url=cat.jpg) }}\n{% end %}\n\
+                     {% raw %}{{ literal }}{% end %} {{ bad(x=) }}";
+        let uses = collect_shortcodes(input);
+
+        let summary: Vec<(&str, bool, &str, usize)> = uses
+            .iter()
+            .map(|u| (u.name.as_str(), u.block, &input[u.range.clone()], u.line))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("badge", false, "{{ badge(version=project.version) }}", 1),
+                (
+                    "note",
+                    true,
+                    "{% note(title=\"Note\") %}\n{{ image(url=cat.jpg) }}\n{% end %}",
+                    2
+                ),
+                ("image", false, "{{ image(url=cat.jpg) }}", 3),
+                ("raw", true, "{% raw %}{{ literal }}{% end %}", 5),
+            ]
+        );
+        assert_eq!(
+            uses[0].parameters.get("version"),
+            Some(&ParameterValue::Expression("project.version".to_string()))
+        );
+        assert_eq!(
+            uses[1].parameters.get("title"),
+            Some(&ParameterValue::Literal("Note".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_shared_tera() {
+        let ctx = context_for(
+            &[("html/block.tera.html", "<div>{{ body }}</div>")],
+            OutputFormat::Html,
+        );
+        let input = "{% block %}\ncontent\n{% end %}\n".repeat(100);
+
+        // Simulates building many documents that each contain many block shortcodes. The
+        // template environment must be parsed once and shared rather than cloned.
+        for _ in 0..10 {
+            let processor = ShortcodesConfig::default().build(&ctx).unwrap();
+            let output = processor
+                .process(&input, &tera::Context::new())
+                .expect("Shortcode processing failed");
+            assert_eq!(output.matches("<div>").count(), 100);
+            assert_eq!(Arc::strong_count(&ctx.tera), 2);
+        }
+        assert_eq!(Arc::strong_count(&ctx.tera), 1);
+    }
+
+    #[test]
+    fn test_template_pattern() {
+        let ctx = context_for(
+            &[("shortcodes/badge.html.tera", "badge")],
+            OutputFormat::Html,
+        );
+
+        let config = ShortcodesConfig {
+            template_pattern: "shortcodes/{name}.{ext}.tera".to_string(),
+            ..Default::default()
+        };
+        let output = config
+            .build(&ctx)
+            .unwrap()
+            .process("A {{ badge }}", &tera::Context::new())
+            .unwrap();
+        assert_eq!(output, "A badge");
+
+        let invalid = ShortcodesConfig {
+            template_pattern: "shortcodes/badge.tera".to_string(),
+            ..Default::default()
+        };
+        assert!(invalid.build(&ctx).is_err());
+    }
+
+    #[test]
+    fn test_block_newline_in_list() {
+        let ctx = context_for(&[("md/note.tera.md", "**note**")], OutputFormat::Notebook);
+        let input = "- first\n  {% note %}body{% end %}\n- second\n";
+        let render = |block_newline| {
+            let config = ShortcodesConfig {
+                block_newline,
+                ..Default::default()
+            };
+            config
+                .build(&ctx)
+                .unwrap()
+                .process(input, &tera::Context::new())
+                .unwrap()
+        };
+
+        assert_eq!(render(true), "- first\n  **note**\n\n- second\n");
+
+        let output = render(false);
+        assert_eq!(output, "- first\n  **note**\n- second\n");
+
+        // The list stays tight, i.e. the items are not wrapped in paragraphs.
+        let events: Vec<_> = Parser::new(&output).collect();
+        let lists = events
+            .iter()
+            .filter(|e| {
+                matches!(
+                    e,
+                    pulldown_cmark::Event::Start(pulldown_cmark::Tag::List(_))
+                )
+            })
+            .count();
+        assert_eq!(lists, 1);
+        assert!(!events.iter().any(|e| matches!(
+            e,
+            pulldown_cmark::Event::Start(pulldown_cmark::Tag::Paragraph)
+        )));
+    }
+
+    #[test]
+    fn test_inline_adjacent_to_code() {
+        let shortcodes = shortcodes_for(
+            &[("html/x.tera.html", "X")],
+            OutputFormat::Html,
+            &default_template_pattern(),
+        );
+        let render = |input: &str| shortcodes.process(input, &tera::Context::new()).unwrap();
+
+        assert_eq!(render("`code`{{ x }} after"), "`code`X after");
+        assert_eq!(render("before {{ x }}`code`"), "before X`code`");
+        assert_eq!(render("`{{ x }}`{{ x }}"), "`{{ x }}`X");
+        assert_eq!(render("a `{{ x }}` b {{ x }} c"), "a `{{ x }}` b X c");
+        assert_eq!(render("`{{` text {{ x }}"), "`{{` text X");
+        assert_eq!(render("```\n{{ x }}\n```\n{{ x }}"), "```\n{{ x }}\n```\nX");
+    }
+
+    #[test]
+    fn test_block_with_markdown_body() {
+        let shortcodes = shortcodes_for(
+            &[
+                (
+                    "html/card.tera.html",
+                    "<div class=\"card\">\n{{ body | safe }}\n</div>",
+                ),
+                ("html/note.tera.html", "<aside>{{ body | safe }}</aside>"),
+            ],
+            OutputFormat::Html,
+            &default_template_pattern(),
+        );
+
+        let input = include_str!("../../resources/test/card.md");
+        let output = shortcodes.process(input, &tera::Context::new()).unwrap();
+        let mut html = String::new();
+        push_html(&mut html, Parser::new_ext(&output, Options::all()));
+        assert!(!output.contains("{%"));
+
+        // The whole card is a single HTML block containing the rendered body.
+        let card = html
+            .split("<div class=\"card\">")
+            .nth(1)
+            .and_then(|rest| rest.split("</div>").next())
+            .expect("Card not rendered");
+        assert!(card.contains("<h2>Card heading</h2>"));
+        assert!(card.contains("<li>First item</li>\n<li>Second item</li>"));
+        assert!(card.contains("<aside><p>Nested <em>note</em></p>"));
+        assert!(card.contains("<p>Last paragraph.</p>"));
+        assert!(html.ends_with("</div>\n<p>Text after the card.</p>\n"));
+
+        // An end tag inside code neither closes a block nor stops the processing.
+        let output = shortcodes
+            .process(
+                "Use `{% end %}`.\n\n{% note %}\ntext\n{% end %}",
+                &tera::Context::new(),
+            )
+            .unwrap();
+        assert_eq!(output, "Use `{% end %}`.\n\n<aside><p>text</p>\n</aside>\n");
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let ctx = context_for(
+            &[("html/box.tera.html", "<div>{{ body | safe }}</div>")],
+            OutputFormat::Html,
+        );
+        let nested = |depth: usize| {
+            format!(
+                "{}inner{}",
+                "{% box %}".repeat(depth),
+                "{% end %}".repeat(depth)
+            )
+        };
+        let config = ShortcodesConfig {
+            max_depth: 3,
+            ..Default::default()
+        };
+        let processor = config.build(&ctx).unwrap();
+
+        let output = processor
+            .process(&nested(3), &tera::Context::new())
+            .unwrap();
+        assert_eq!(output.matches("<div>").count(), 3);
+
+        // A block that includes itself too deeply fails instead of overflowing the stack.
+        let err = processor
+            .process(&nested(4), &tera::Context::new())
+            .expect_err("Too deep nesting should fail");
+        assert_eq!(
+            err.to_string(),
+            "shortcode error: line 1, column 28: block shortcode `box` is nested more than 3 levels deep"
+        );
+
+        let deep = ShortcodesConfig::default().build(&ctx).unwrap();
+        assert!(deep
+            .process(&nested(10_000), &tera::Context::new())
+            .is_err());
+    }
+
+    #[test]
+    fn test_defaults() {
+        let dir = tempfile::tempdir().unwrap();
+        let html = dir.path().join("html");
+        std::fs::create_dir(&html).unwrap();
+        std::fs::write(
+            html.join("note.tera.html"),
+            "<div class=\"{{ color }}\">{{ title }} ({{ size }})</div>",
+        )
+        .unwrap();
+        std::fs::write(html.join("note.toml"), "color = \"info\"\nsize = 3\n").unwrap();
+
+        let pattern = format!("{}/**/*", dir.path().display());
+        let shortcodes = Shortcodes::new(&pattern, OutputFormat::Html).unwrap();
+
+        let output = shortcodes
+            .process("{{ note(title=Hi) }}", &tera::Context::new())
+            .expect("Shortcode processing failed");
+        assert_eq!(output, "<div class=\"info\">Hi (3)</div>");
+
+        let output = shortcodes
+            .process("{{ note(title=Hi, color=danger) }}", &tera::Context::new())
+            .expect("Shortcode processing failed");
+        assert_eq!(output, "<div class=\"danger\">Hi (3)</div>");
+
+        // Defaults are read once when the processor is created.
+        std::fs::write(html.join("note.toml"), "color = ").unwrap();
+        let output = shortcodes
+            .process("{{ note(title=Hi) }}", &tera::Context::new())
+            .expect("Shortcode processing failed");
+        assert_eq!(output, "<div class=\"info\">Hi (3)</div>");
+
+        let err = Shortcodes::new(&pattern, OutputFormat::Html)
+            .expect_err("Invalid defaults should fail");
+        assert!(matches!(err, ShortCodeProcessError::Defaults { .. }));
+    }
+
+    #[test]
+    fn test_many_blocks() {
+        let shortcodes = shortcodes_for(
+            &[
+                ("html/note.tera.html", "<div>{{ body | safe }}</div>"),
+                ("html/badge.tera.html", "<b>{{ text }}</b>"),
+            ],
+            OutputFormat::Html,
+            &default_template_pattern(),
+        );
+        // Code, escaped delimiters and nested blocks between the blocks must not make the
+        // document slower to process than its parts.
+        let part = |i: usize| {
+            format!(
+                "Text `{{% note %}} {i}` \\{{% here.\n\n{{% note %}}\nBlock {{{{ badge(text={i}) }}}}\n\n{{% note %}}\n*{i}*\n{{% end %}}\n{{% end %}}\n\n"
+            )
+        };
+        let ctx = tera::Context::new();
+        let input: String = (0..1000).map(part).collect();
+        let expected: String = (0..1000)
+            .map(|i| shortcodes.process(&part(i), &ctx).unwrap())
+            .collect();
+
+        let output = shortcodes
+            .process(&input, &ctx)
+            .expect("Shortcode processing failed");
+        assert_eq!(output, expected);
+        assert_eq!(output.matches("<div>").count(), 2000);
+        assert_eq!(output.matches("<b>").count(), 1000);
+        assert_eq!(output.matches("{% here").count(), 1000);
+    }
+
+    #[test]
+    fn test_conditional_blocks() {
+        let shortcodes = shortcodes_for(
+            &[("html/note.tera.html", "<div>{{ body | safe }}</div>")],
+            OutputFormat::Html,
+            &default_template_pattern(),
+        );
+        let input = "Intro\n\n{% if var.audience == \"instructor\" %}\n## Solution\n\n{% note %}\nHint\n{% end %}\n{% end %}\n\nOutro";
+
+        let render = |audience: &str| {
+            let mut ctx = tera::Context::new();
+            ctx.insert("var", &HashMap::from([("audience", audience)]));
+            shortcodes
+                .process(input, &ctx)
+                .expect("Shortcode processing failed")
+        };
+
+        assert_eq!(
+            render("instructor"),
+            "Intro\n\n## Solution\n\n<div><p>Hint</p>\n</div>\n\n\n\nOutro"
+        );
+        assert_eq!(render("student"), "Intro\n\n\n\n\nOutro");
+
+        let ctx = tera::Context::new();
+        assert_eq!(
+            shortcodes
+                .process("{% if true%}yes{% end %}", &ctx)
+                .unwrap(),
+            "yes\n"
+        );
+        // Unknown conditions fail instead of leaving out the body.
+        let err = shortcodes
+            .process("{% if tru %}yes{% end %}", &ctx)
+            .expect_err("Unknown condition should fail");
+        assert!(err.to_string().contains("Variable `tru` not found"));
+        assert!(shortcodes.process(input, &ctx).is_err());
+
+        // Nested shortcodes are still collected.
+        let uses = collect_shortcodes(input);
+        assert_eq!(uses.len(), 1);
+        assert_eq!(uses[0].name, "note");
+    }
+
+    #[test]
+    fn test_tags_without_spaces() {
+        let shortcodes = shortcodes_for(
+            &[
+                ("html/name.tera.html", "{{ arg0 }}"),
+                ("html/note.tera.html", "<div>{{ body | safe }}</div>"),
+            ],
+            OutputFormat::Html,
+            &default_template_pattern(),
+        );
+        let render = |input: &str| shortcodes.process(input, &tera::Context::new()).unwrap();
+
+        assert_eq!(
+            render("{%raw%}{{ x }}{% end %}"),
+            "<pre class=\"raw\">&#123;&#123; x &#125;&#125;</pre>\n"
+        );
+        assert_eq!(render("{{name(a)}}"), "a");
+        assert_eq!(
+            render("{%note%}text{% end %}"),
+            "<div><p>text</p>\n</div>\n"
+        );
+    }
 }
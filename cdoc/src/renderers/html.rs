@@ -1,22 +1,170 @@
+use crate::ast::{AEvent, ATag};
 use crate::document::{Document, EventContent};
+use pulldown_cmark::escape::escape_html;
 use pulldown_cmark::html;
 use serde::{Deserialize, Serialize};
 
+use crate::document::plain_text;
+use crate::renderers::notebook::heading_num;
 use crate::renderers::{RenderResult, Renderer};
+use crate::utils::HeadingIds;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Default)]
 pub struct HtmlRenderer;
 
 #[typetag::serde(name = "renderer_config")]
 impl Renderer for HtmlRenderer {
     fn render(&self, doc: &Document<EventContent>) -> Document<RenderResult> {
-        let iter = doc.to_events();
+        let events = doc.content.iter().map(|(e, _)| e.clone());
+        let iter = heading_ids(events).into_iter().map(|e| e.into());
         let mut output = String::new();
         html::push_html(&mut output, iter);
         Document {
-            content: output,
+            content: output.into(),
             metadata: doc.metadata.clone(),
             variables: doc.variables.clone(),
         }
     }
 }
+
+/// Write the tags of headings as HTML with an `id` attribute for in-page links (see [HeadingIds]).
+/// The events inside a heading are buffered until its end since the text is needed for the start
+/// tag.
+fn heading_ids(events: impl Iterator<Item = AEvent>) -> Vec<AEvent> {
+    let mut output = Vec::new();
+    let mut ids = HeadingIds::default();
+    let mut heading: Option<Vec<AEvent>> = None;
+
+    for event in events {
+        match (event, heading.as_mut()) {
+            (AEvent::Start(ATag::Heading(..)), None) => heading = Some(Vec::new()),
+            (AEvent::End(ATag::Heading(level, id, classes)), Some(inner)) => {
+                let id = ids.next(id.as_deref(), &plain_text(inner.iter()));
+
+                let mut start = format!("<h{} id=\"", heading_num(level));
+                escape_html(&mut start, &id).expect("Invalid format");
+                if !classes.is_empty() {
+                    start.push_str("\" class=\"");
+                    escape_html(&mut start, &classes.join(" ")).expect("Invalid format");
+                }
+                start.push_str("\">");
+
+                output.push(AEvent::Html(start));
+                output.append(inner);
+                output.push(AEvent::Html(format!("</h{}>\n", heading_num(level))));
+                heading = None;
+            }
+            (event, Some(inner)) => inner.push(event),
+            (event, None) => output.push(event),
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::{DocumentMetadata, IteratorConfig, RawContent};
+    use crate::loader::{Loader, NotebookLoader};
+
+    const NOTEBOOK: &str = r#"{
+        "metadata": {},
+        "nbformat": 4,
+        "nbformat_minor": 5,
+        "cells": [
+            {"cell_type": "code", "metadata": {}, "source": ["1 + 1"], "execution_count": 3,
+             "outputs": [{"output_type": "execute_result", "execution_count": 3,
+                          "data": {"text/plain": ["2"]}, "metadata": {}}]},
+            {"cell_type": "code", "metadata": {}, "source": ["x = 1"], "execution_count": null,
+             "outputs": []}
+        ]
+    }"#;
+
+    fn render(config: IteratorConfig) -> String {
+        let doc = NotebookLoader::default()
+            .load(NOTEBOOK)
+            .unwrap()
+            .to_events(config);
+        let output = HtmlRenderer.render(&doc).content;
+        output.as_text().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_cell_prompts() {
+        let config = IteratorConfig::default().include_output();
+        let plain = render(config);
+        assert!(!plain.contains("prompt"));
+        assert!(plain.contains("<pre><code class=\"language-python\">1 + 1</code></pre>"));
+
+        let html = render(config.cell_prompts());
+        assert_eq!(html.matches("<div class=\"cell code-cell\">").count(), 2);
+        assert!(html.contains(
+            "<div class=\"cell-input\"><div class=\"prompt\">In&nbsp;[3]:</div><div class=\"cell-body\">\n\
+            <pre><code class=\"language-python\">1 + 1</code></pre>\n</div>\n</div>\n"
+        ));
+        assert!(html.contains("<div class=\"prompt\">Out&nbsp;[3]:</div>"));
+        assert!(html.contains("<div class=\"prompt\">In&nbsp;[ ]:</div>"));
+        // Cells without outputs have no output gutter.
+        assert_eq!(html.matches("<div class=\"cell-output\">").count(), 1);
+        assert_eq!(html.matches("<div").count(), html.matches("</div>").count());
+    }
+
+    fn render_markdown(source: &str) -> String {
+        let doc = Document::<RawContent>::new(source.to_string(), DocumentMetadata::default())
+            .to_events(IteratorConfig::default());
+        let output = HtmlRenderer.render(&doc).content;
+        output.as_text().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_heading_ids() {
+        let html = render_markdown(
+            "# Getting *Started*\n\n## Getting started\n\nText\n\n### Getting started!\n\n\
+            ## Custom {#custom .wide}\n\n## `code()` & co.\n\n## ???\n",
+        );
+        assert_eq!(
+            html,
+            "<h1 id=\"getting-started\">Getting <em>Started</em></h1>\n\
+            <h2 id=\"getting-started-1\">Getting started</h2>\n\
+            <p>Text</p>\n\
+            <h3 id=\"getting-started-2\">Getting started!</h3>\n\
+            <h2 id=\"custom\" class=\"wide\">Custom</h2>\n\
+            <h2 id=\"code-co\"><code>code()</code> &amp; co.</h2>\n\
+            <h2 id=\"section\">???</h2>\n"
+        );
+    }
+
+    #[test]
+    fn test_headings() {
+        let doc = Document::<RawContent>::new(
+            "# Intro\n\n## Setup\n\n### Linux & *macOS*\n\n## Setup\n\n#### `pip`\n\n# Summary {#end}\n"
+                .to_string(),
+            DocumentMetadata::default(),
+        )
+        .to_events(IteratorConfig::default());
+
+        let headings = doc.headings();
+        let outline: Vec<(usize, &str, &str)> = headings
+            .iter()
+            .map(|h| (h.level, h.text.as_str(), h.slug.as_str()))
+            .collect();
+        assert_eq!(
+            outline,
+            vec![
+                (1, "Intro", "intro"),
+                (2, "Setup", "setup"),
+                (3, "Linux & macOS", "linux-macos"),
+                (2, "Setup", "setup-1"),
+                (4, "pip", "pip"),
+                (1, "Summary", "end"),
+            ]
+        );
+
+        // The slugs are the anchors of the rendered headings.
+        let html = HtmlRenderer.render(&doc).content;
+        let html = html.as_text().unwrap();
+        for heading in headings {
+            assert!(html.contains(&format!("<h{} id=\"{}\">", heading.level, heading.slug)));
+        }
+    }
+}
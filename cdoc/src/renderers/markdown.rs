@@ -1,11 +1,11 @@
 use crate::document::{DocPos, Document, EventContent};
 use crate::renderers::notebook::heading_num;
 use crate::renderers::{RenderResult, Renderer};
-use pulldown_cmark::{CodeBlockKind, Event, Tag};
+use pulldown_cmark::{Alignment, CodeBlockKind, Event, Tag};
 use serde::{Deserialize, Serialize};
 use std::fmt::Write;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Default)]
 pub struct MarkdownRenderer;
 
 #[typetag::serde(name = "renderer_config")]
@@ -13,7 +13,7 @@ impl Renderer for MarkdownRenderer {
     fn render(&self, doc: &Document<EventContent>) -> Document<RenderResult> {
         let output = render_markdown(doc.to_events_with_pos());
         Document {
-            content: output,
+            content: output.into(),
             metadata: doc.metadata.clone(),
             variables: doc.variables.clone(),
         }
@@ -23,7 +23,27 @@ impl Renderer for MarkdownRenderer {
 struct MarkdownWriter<I> {
     iter: I,
     source: String,
-    list_order_num: Option<u64>,
+    /// Open lists (one per level of nesting) with the number of the next item of ordered lists.
+    lists: Vec<Option<u64>>,
+    /// Positions in the output where the open list items start and their markers. The content of
+    /// an item is indented by the width of its marker when it ends so that nested lists and
+    /// paragraphs stay part of it.
+    items: Vec<(usize, String)>,
+    /// Column alignments of the current table.
+    table_alignments: Vec<Alignment>,
+    /// Cells of the current table row. Rows are written when they end since the separator line
+    /// follows the head row.
+    table_row: Vec<String>,
+    /// Position in the output where the current table cell starts.
+    cell_start: usize,
+    /// Footnote definitions (label and text) in the order they appear. They are written at the end
+    /// of the document.
+    footnotes: Vec<(String, String)>,
+    /// Position in the output where the current footnote definition starts.
+    footnote_start: usize,
+    /// Positions in the output where the open block quotes start (one per level of nesting). The
+    /// lines of a quote are prefixed when it ends.
+    quote_starts: Vec<usize>,
 }
 
 impl<'a, I> MarkdownWriter<I>
@@ -34,10 +54,38 @@ where
         MarkdownWriter {
             iter,
             source: String::new(),
-            list_order_num: None,
+            lists: Vec::new(),
+            items: Vec::new(),
+            table_alignments: Vec::new(),
+            table_row: Vec::new(),
+            cell_start: 0,
+            footnotes: Vec::new(),
+            footnote_start: 0,
+            quote_starts: Vec::new(),
         }
     }
 
+    /// Write the buffered cells of a table row as a line of a pipe table.
+    fn write_table_row(&mut self) {
+        let cells = std::mem::take(&mut self.table_row);
+        writeln!(self.source, "| {} |", cells.join(" | ")).expect("Invalid format");
+    }
+
+    /// Write the line separating the head row from the body with the alignment of each column.
+    fn write_table_separator(&mut self) {
+        let columns: Vec<&str> = self
+            .table_alignments
+            .iter()
+            .map(|alignment| match alignment {
+                Alignment::None => "---",
+                Alignment::Left => ":---",
+                Alignment::Center => ":---:",
+                Alignment::Right => "---:",
+            })
+            .collect();
+        writeln!(self.source, "|{}|", columns.join("|")).expect("Invalid format");
+    }
+
     fn start_tag(&mut self, tag: Tag<'a>) {
         match tag {
             Tag::Paragraph => {}
@@ -46,7 +94,7 @@ where
                 prefix.push(' ');
                 self.source.push_str(&prefix);
             }
-            Tag::BlockQuote => {}
+            Tag::BlockQuote => self.quote_starts.push(self.source.len()),
             Tag::CodeBlock(kind) => match kind {
                 CodeBlockKind::Indented => {
                     self.source.push_str("```plain\n");
@@ -56,26 +104,38 @@ where
                     writeln!(self.source, "```{}", s).expect("Invalid format");
                 }
             },
-            Tag::List(i) => {
-                self.list_order_num = i;
-            }
-            Tag::Item => match self.list_order_num {
-                None => self.source.push_str("- "),
-                Some(i) => {
-                    write!(self.source, "{}. ", i).expect("Invalid format");
-                    self.list_order_num = self.list_order_num.map(|i| i + 1);
+            Tag::List(start) => {
+                // A nested list starts on its own line (the text of tight items isn't wrapped in a
+                // paragraph).
+                if !self.source.is_empty() && !self.source.ends_with('\n') {
+                    self.source.push('\n');
                 }
-            },
-            Tag::FootnoteDefinition(_) => {}
-            Tag::Table(_) => {}
+                self.lists.push(start);
+            }
+            Tag::Item => {
+                let marker = match self.lists.last_mut() {
+                    Some(Some(num)) => {
+                        *num += 1;
+                        format!("{}. ", *num - 1)
+                    }
+                    _ => "- ".to_string(),
+                };
+                self.items.push((self.source.len(), marker));
+            }
+            Tag::FootnoteDefinition(_) => self.footnote_start = self.source.len(),
+            Tag::Table(alignments) => {
+                self.table_alignments = alignments;
+                self.source.push('\n');
+            }
             Tag::TableHead => {}
             Tag::TableRow => {}
-            Tag::TableCell => {}
+            Tag::TableCell => self.cell_start = self.source.len(),
             Tag::Emphasis => self.source.push('*'),
             Tag::Strong => self.source.push_str("__"),
-            Tag::Strikethrough => {}
+            Tag::Strikethrough => self.source.push_str("~~"),
             Tag::Link(_, _, _) => self.source.push('['),
-            Tag::Image(_, _, _) => {}
+            // The alt text is written by the text events inside the tag.
+            Tag::Image(_, _, _) => self.source.push_str("!["),
         }
     }
 
@@ -84,21 +144,73 @@ where
             Tag::CodeBlock(_) => self.source.push_str("\n```\n"),
             Tag::Paragraph => self.source.push('\n'),
             Tag::Heading(_, _, _) => self.source.push_str("\n\n"),
-            Tag::BlockQuote => {}
-            Tag::List(_) => self.source.push('\n'),
-            Tag::Item => self.source.push('\n'),
-            Tag::FootnoteDefinition(_) => {}
-            Tag::Table(_) => {}
-            Tag::TableHead => {}
-            Tag::TableRow => {}
-            Tag::TableCell => {}
+            Tag::BlockQuote => {
+                let start = self.quote_starts.pop().unwrap_or_default();
+                let quote = self.source.split_off(start);
+                for line in quote.trim_end().lines() {
+                    if line.is_empty() {
+                        self.source.push_str(">\n");
+                    } else {
+                        writeln!(self.source, "> {}", line).expect("Invalid format");
+                    }
+                }
+                // Ends the quote so that the following text isn't part of it.
+                self.source.push('\n');
+            }
+            Tag::List(_) => {
+                self.lists.pop();
+                self.source.push('\n');
+            }
+            Tag::Item => {
+                let (start, marker) = self.items.pop().unwrap_or_default();
+                let item = self.source.split_off(start);
+                let indent = " ".repeat(marker.len());
+                for (i, line) in item.trim_end().lines().enumerate() {
+                    if i == 0 {
+                        self.source.push_str(&marker);
+                    } else if !line.is_empty() {
+                        self.source.push_str(&indent);
+                    }
+                    writeln!(self.source, "{}", line).expect("Invalid format");
+                }
+            }
+            Tag::FootnoteDefinition(label) => {
+                let text = self.source.split_off(self.footnote_start);
+                self.footnotes
+                    .push((label.into_string(), text.trim().to_string()));
+            }
+            Tag::Table(_) => {
+                self.table_alignments.clear();
+                self.source.push('\n');
+            }
+            Tag::TableHead => {
+                self.write_table_row();
+                self.write_table_separator();
+            }
+            Tag::TableRow => self.write_table_row(),
+            Tag::TableCell => {
+                let cell = self.source.split_off(self.cell_start);
+                self.table_row.push(cell.trim().replace('|', "\\|"));
+            }
             Tag::Emphasis => self.source.push('*'),
             Tag::Strong => self.source.push_str("__"),
-            Tag::Strikethrough => {}
+            Tag::Strikethrough => self.source.push_str("~~"),
             Tag::Link(_type, dest, title) => {
                 write!(self.source, "]({} {})", dest, title).expect("Invalid format");
             }
-            Tag::Image(_, _, _) => {}
+            Tag::Image(_, dest, title) => {
+                if title.is_empty() {
+                    write!(self.source, "]({})", dest).expect("Invalid format");
+                } else {
+                    write!(
+                        self.source,
+                        "]({} \"{}\")",
+                        dest,
+                        title.replace('"', "\\\"")
+                    )
+                    .expect("Invalid format");
+                }
+            }
         }
     }
 
@@ -115,23 +227,256 @@ where
                         self.source.push_str(&ts)
                     }
                 }
-                Event::Code(_) => {}
+                Event::Code(code) => self.source.push_str(&inline_code(&code)),
                 Event::Html(text) => self.source.push_str(&text.into_string()),
-                Event::FootnoteReference(_) => {}
+                Event::FootnoteReference(label) => {
+                    write!(self.source, "[^{}]", label).expect("Invalid format");
+                }
                 Event::SoftBreak => self.source.push('\n'),
                 Event::HardBreak => self.source.push_str("\n\n"),
                 Event::Rule => {}
-                Event::TaskListMarker(_) => {}
+                // The marker follows the start of its item, i.e. the bullet.
+                Event::TaskListMarker(checked) => {
+                    self.source.push_str(if checked { "[x] " } else { "[ ] " })
+                }
             };
         }
 
+        for (label, text) in &self.footnotes {
+            // Following paragraphs of a definition are indented to stay part of it.
+            let text = text.replace("\n\n", "\n\n    ");
+            write!(self.source, "\n[^{}]: {}\n", label, text).expect("Invalid format");
+        }
+
         self.source
     }
 }
 
+/// Wrap inline code in a backtick fence that is longer than any run of backticks in the code.
+/// The code is padded with spaces when it starts or ends with a backtick (or with spaces on both
+/// sides) since the parser strips one space from each side.
+pub fn inline_code(code: &str) -> String {
+    let longest_run = code
+        .split(|c| c != '`')
+        .map(|run| run.len())
+        .max()
+        .unwrap_or(0);
+    let fence = "`".repeat(longest_run + 1);
+    let pad = code.starts_with('`')
+        || code.ends_with('`')
+        || (code.starts_with(' ') && code.ends_with(' ') && !code.trim().is_empty());
+    let pad = if pad { " " } else { "" };
+    format!("{fence}{pad}{code}{pad}{fence}")
+}
+
 pub fn render_markdown<'a, I>(iter: I) -> String
 where
     I: Iterator<Item = (Event<'a>, DocPos)>,
 {
     MarkdownWriter::new(iter).run()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::{Options, Parser};
+
+    /// Parses `source` and renders it back to markdown.
+    fn render_source(source: &str) -> String {
+        render_markdown(Parser::new_ext(source, Options::all()).map(|e| (e, DocPos::default())))
+    }
+
+    fn same_events(a: &str, b: &str) -> bool {
+        Parser::new_ext(a, Options::all()).eq(Parser::new_ext(b, Options::all()))
+    }
+
+    /// The text of each table cell by row.
+    fn table_cells(markdown: &str) -> Vec<Vec<String>> {
+        let mut rows = Vec::new();
+        let mut in_cell = false;
+        for event in Parser::new_ext(markdown, Options::all()) {
+            match event {
+                Event::Start(Tag::TableHead | Tag::TableRow) => rows.push(Vec::new()),
+                Event::Start(Tag::TableCell) => {
+                    in_cell = true;
+                    rows.last_mut().unwrap().push(String::new());
+                }
+                Event::End(Tag::TableCell) => in_cell = false,
+                Event::Text(text) if in_cell => {
+                    rows.last_mut().unwrap().last_mut().unwrap().push_str(&text);
+                }
+                _ => {}
+            }
+        }
+        rows
+    }
+
+    #[test]
+    fn test_table_round_trip() {
+        let source = "Text\n\n| Name | Value | Unit | Note |\n\
+            |:-----|:-----:|-----:|------|\n\
+            | a | 1 | m | x \\| y |\n\
+            | b | 2 | s | |\n\nMore text\n";
+        let output = render_source(source);
+
+        assert!(output.contains("| Name | Value | Unit | Note |\n|:---|:---:|---:|---|\n"));
+        assert!(output.contains("More text"));
+        assert_eq!(table_cells(&output), table_cells(source));
+        assert_eq!(
+            table_cells(&output),
+            vec![
+                vec!["Name", "Value", "Unit", "Note"],
+                vec!["a", "1", "m", "x | y"],
+                vec!["b", "2", "s", ""],
+            ]
+        );
+
+        let mut alignments = None;
+        for event in Parser::new_ext(&output, Options::all()) {
+            if let Event::Start(Tag::Table(a)) = event {
+                alignments = Some(a);
+            }
+        }
+        assert_eq!(
+            alignments,
+            Some(vec![
+                Alignment::Left,
+                Alignment::Center,
+                Alignment::Right,
+                Alignment::None
+            ])
+        );
+    }
+
+    #[test]
+    fn test_footnotes() {
+        let source = "Text[^b] with two[^first] footnotes.\n\n\
+            [^first]: The first definition.\n\n\
+            More text.\n\n\
+            [^b]: The second definition.\n";
+        let output = render_source(source);
+
+        assert!(output.starts_with("Text[^b] with two[^first] footnotes.\n"));
+        assert!(output.ends_with(
+            "More text.\n\n[^first]: The first definition.\n\n[^b]: The second definition.\n"
+        ));
+
+        // The output links the references to the same definitions as the source.
+        let definitions = |markdown: &str| {
+            let mut definitions = Vec::new();
+            let mut current = None;
+            for event in Parser::new_ext(markdown, Options::all()) {
+                match event {
+                    Event::FootnoteReference(label) => definitions.push((label.to_string(), None)),
+                    Event::Start(Tag::FootnoteDefinition(label)) => current = Some(label),
+                    Event::Text(text) => {
+                        if let Some(label) = &current {
+                            definitions.push((label.to_string(), Some(text.to_string())));
+                        }
+                    }
+                    Event::End(Tag::FootnoteDefinition(_)) => current = None,
+                    _ => {}
+                }
+            }
+            definitions.sort();
+            definitions
+        };
+        assert_eq!(definitions(&output), definitions(source));
+    }
+
+    #[test]
+    fn test_task_list() {
+        let source = "- [x] done\n- [ ] todo\n- plain\n\n1. [X] first\n2. [ ] second\n";
+        let output = render_source(source);
+
+        assert!(output.contains("- [x] done\n- [ ] todo\n- plain\n"));
+        assert!(output.contains("1. [x] first\n2. [ ] second\n"));
+
+        let markers = |markdown: &str| {
+            Parser::new_ext(markdown, Options::all())
+                .filter_map(|e| match e {
+                    Event::TaskListMarker(checked) => Some(checked),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(markers(&output), vec![true, false, true, false]);
+        assert_eq!(markers(&output), markers(source));
+    }
+
+    #[test]
+    fn test_images() {
+        let source =
+            "![A plot](plot.png \"The \\\"plot\\\"\")\n\n![Logo *image*](/resources/logo.svg)\n";
+        let output = render_source(source);
+
+        assert!(output.contains("![A plot](plot.png \"The \\\"plot\\\"\")"));
+        assert!(output.contains("![Logo *image*](/resources/logo.svg)"));
+
+        let images = |markdown: &str| {
+            Parser::new_ext(markdown, Options::all())
+                .filter_map(|e| match e {
+                    Event::Start(Tag::Image(_, dest, title)) => {
+                        Some((dest.to_string(), title.to_string()))
+                    }
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(images(&output), images(source));
+        assert_eq!(
+            images(&output),
+            vec![
+                ("plot.png".to_string(), "The \"plot\"".to_string()),
+                ("/resources/logo.svg".to_string(), String::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_strikethrough() {
+        let source = "Some ~~deleted~~ and ~~*both*~~ text.\n";
+        let output = render_source(source);
+
+        assert_eq!(output, "Some ~~deleted~~ and ~~*both*~~ text.\n");
+        assert!(same_events(&output, source));
+    }
+
+    #[test]
+    fn test_block_quotes() {
+        let source = "Before\n\n> Outer\n>\n> > Inner *quote*\n>\n> Back\n\nAfter\n";
+        let output = render_source(source);
+
+        assert_eq!(
+            output,
+            "Before\n> Outer\n> > Inner *quote*\n>\n> Back\n\nAfter\n"
+        );
+        assert!(same_events(&output, source));
+    }
+
+    #[test]
+    fn test_inline_code() {
+        assert_eq!(inline_code("x = 1"), "`x = 1`");
+        assert_eq!(inline_code("a ` b"), "``a ` b``");
+        assert_eq!(inline_code("`has backtick`"), "`` `has backtick` ``");
+        assert_eq!(inline_code("``"), "``` `` ```");
+
+        let source = "Use `x = 1`, ``a ` b``, `` `has backtick` `` and ``` `` ```.\n";
+        let output = render_source(source);
+
+        assert_eq!(output, source);
+        assert!(same_events(&output, source));
+    }
+
+    #[test]
+    fn test_nested_lists() {
+        let source = "1. First\n   - Bullet\n     3. Inner\n     4. Inner\n   - Bullet\n2. Second\n\nAfter\n";
+        let output = render_source(source);
+
+        assert_eq!(
+            output,
+            "1. First\n   - Bullet\n     3. Inner\n     4. Inner\n   - Bullet\n2. Second\n\nAfter\n"
+        );
+        assert!(same_events(&output, source));
+    }
+}
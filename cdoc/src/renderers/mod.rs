@@ -1,5 +1,8 @@
 use std::collections::HashMap;
-use std::ops::Deref;
+use std::ops::{Deref, Range};
+
+use pulldown_cmark::{Event, Options, Parser, Tag};
+use serde::{Deserialize, Serialize};
 
 use crate::document::{Document, EventContent};
 
@@ -7,13 +10,198 @@ pub mod html;
 pub mod markdown;
 pub mod notebook;
 
-pub type RenderResult = String;
+pub type RenderResult = RenderOutput;
+
+/// The output of a renderer. Textual formats (e.g. html and notebooks) produce text while binary
+/// formats (e.g. PDF and EPUB books) produce bytes. Text is serialized as a plain string.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum RenderOutput {
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+impl RenderOutput {
+    /// Get the output as text. Returns `None` for binary output.
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            RenderOutput::Text(text) => Some(text),
+            RenderOutput::Bytes(_) => None,
+        }
+    }
+}
+
+impl Default for RenderOutput {
+    fn default() -> Self {
+        RenderOutput::Text(String::new())
+    }
+}
+
+impl From<String> for RenderOutput {
+    fn from(text: String) -> Self {
+        RenderOutput::Text(text)
+    }
+}
+
+impl AsRef<[u8]> for RenderOutput {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            RenderOutput::Text(text) => text.as_bytes(),
+            RenderOutput::Bytes(bytes) => bytes,
+        }
+    }
+}
 
 #[typetag::serde(tag = "type")]
 pub trait Renderer {
     fn render(&self, doc: &Document<EventContent>) -> Document<RenderResult>;
 }
 
+/// Determines how raw HTML in the source of documents is treated. HTML produced by preprocessors
+/// (e.g. shortcodes and math) and notebook outputs is not affected.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HtmlPolicy {
+    /// Pass raw HTML through unchanged.
+    #[default]
+    Allow,
+    /// Clean raw HTML using the default [ammonia] allowlist of tags and attributes.
+    Sanitize,
+    /// Remove raw HTML entirely.
+    Strip,
+}
+
+impl HtmlPolicy {
+    /// Apply the policy to the raw HTML in markdown source. It runs before the preprocessors so
+    /// that only the HTML written by the author is changed. When sanitizing, the HTML of a
+    /// paragraph (or the lines of an HTML block) is cleaned as a single fragment so that tags
+    /// opened and closed in different places are kept together, while the markdown between the
+    /// tags is left as is. Removed lines of HTML blocks are replaced by empty lines so that the
+    /// line numbers of errors in the rest of the document stay the same.
+    pub fn apply(&self, source: &str) -> String {
+        if *self == HtmlPolicy::Allow {
+            return source.to_string();
+        }
+
+        let mut res = String::with_capacity(source.len());
+        let mut last = 0;
+        for run in html_runs(source) {
+            let span = run.ranges[0].start..run.ranges[run.ranges.len() - 1].end;
+            res.push_str(&source[last..span.start]);
+            last = span.end;
+
+            let html = if *self == HtmlPolicy::Sanitize {
+                // The markdown between the tags is replaced by placeholders while cleaning.
+                let mut fragment = String::new();
+                let mut gaps = Vec::new();
+                let mut prev = span.start;
+                for range in &run.ranges {
+                    if prev < range.start {
+                        fragment.push_str(&placeholder(gaps.len()));
+                        gaps.push(&source[prev..range.start]);
+                    }
+                    fragment.push_str(&source[range.clone()]);
+                    prev = range.end;
+                }
+                gaps.iter()
+                    .enumerate()
+                    .fold(sanitize(&fragment), |html, (i, gap)| {
+                        html.replace(&placeholder(i), gap)
+                    })
+            } else {
+                let mut html = String::new();
+                let mut prev = span.start;
+                for range in &run.ranges {
+                    html.push_str(&source[prev..range.start]);
+                    if run.block {
+                        html.push_str(&"\n".repeat(source[range.clone()].matches('\n').count()));
+                    }
+                    prev = range.end;
+                }
+                html
+            };
+            res.push_str(&html);
+            if run.block {
+                let lost = source[span]
+                    .matches('\n')
+                    .count()
+                    .saturating_sub(html.matches('\n').count());
+                res.push_str(&"\n".repeat(lost));
+            }
+        }
+        res.push_str(&source[last..]);
+        res
+    }
+}
+
+/// Clean HTML using the default [ammonia] allowlist. Ids and classes are kept since headings and
+/// code blocks are linked and styled through them.
+pub fn sanitize(html: &str) -> String {
+    ammonia::Builder::default()
+        .add_generic_attributes(["id", "class"])
+        .clean(html)
+        .to_string()
+}
+
+/// Raw HTML that is cleaned as one fragment: the HTML of a paragraph (or other inline content) or
+/// consecutive HTML blocks.
+struct HtmlRun {
+    /// Source ranges of the HTML.
+    ranges: Vec<Range<usize>>,
+    block: bool,
+}
+
+fn html_runs(source: &str) -> Vec<HtmlRun> {
+    let mut runs = Vec::new();
+    let mut ranges = Vec::new();
+    let mut inline = false;
+    let mut block = false;
+    for (event, range) in Parser::new_ext(source, Options::all()).into_offset_iter() {
+        match event {
+            Event::Html(_) => {
+                block |= !inline;
+                ranges.push(range);
+            }
+            Event::Start(Tag::Paragraph | Tag::Heading(..) | Tag::TableCell) => inline = true,
+            Event::End(Tag::Paragraph | Tag::Heading(..) | Tag::TableCell) => inline = false,
+            _ => {}
+        }
+        if !is_inline(&event) && !ranges.is_empty() {
+            runs.push(HtmlRun {
+                ranges: std::mem::take(&mut ranges),
+                block,
+            });
+            block = false;
+        }
+    }
+    if !ranges.is_empty() {
+        runs.push(HtmlRun { ranges, block });
+    }
+    runs
+}
+
+fn is_inline(event: &Event) -> bool {
+    match event {
+        Event::Start(tag) | Event::End(tag) => matches!(
+            tag,
+            Tag::Emphasis | Tag::Strong | Tag::Strikethrough | Tag::Link(..) | Tag::Image(..)
+        ),
+        Event::Text(_)
+        | Event::Code(_)
+        | Event::Html(_)
+        | Event::FootnoteReference(_)
+        | Event::SoftBreak
+        | Event::HardBreak => true,
+        Event::Rule | Event::TaskListMarker(_) => false,
+    }
+}
+
+/// Stands in for the markdown between tags while HTML is cleaned. The characters are from a
+/// private use area, so they don't occur in documents.
+fn placeholder(idx: usize) -> String {
+    format!("\u{E000}{}\u{E001}", idx)
+}
+
 pub struct RendererConfig {
     mapping: HashMap<String, Box<dyn Renderer>>,
 }
@@ -27,3 +215,44 @@ impl RendererConfig {
         self.mapping.get(extension).map(|b| b.deref())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::{DocumentMetadata, IteratorConfig, RawContent};
+    use crate::renderers::html::HtmlRenderer;
+
+    const SOURCE: &str = "text\n\n<div onclick=\"x()\">\n<script>alert(1)</script>\n</div>\n\n\
+        a <b onclick=\"x()\">*bold*</b> and `<i>`\n";
+
+    #[test]
+    fn html_policy_strip() {
+        let res = HtmlPolicy::Strip.apply(SOURCE);
+        assert_eq!(res, "text\n\n\n\n\n\na *bold* and `<i>`\n");
+    }
+
+    #[test]
+    fn html_policy_sanitize() {
+        let res = HtmlPolicy::Sanitize.apply(SOURCE);
+        assert!(!res.contains("script"));
+        assert!(!res.contains("onclick"));
+        assert!(res.contains("<div>"));
+        // The markdown between the tags is kept, also when it looks like HTML.
+        assert!(res.ends_with("\n\na <b>*bold*</b> and `<i>`\n"));
+        assert_eq!(res.lines().count(), SOURCE.lines().count());
+    }
+
+    #[test]
+    fn html_policy_sanitize_inline() {
+        let source = HtmlPolicy::Sanitize.apply(
+            "# Title\n\nSome <b onclick=\"x()\">bold</b> and <i>it</i><script>y()</script> text.\n",
+        );
+        let doc = Document::<RawContent>::new(source, DocumentMetadata::default())
+            .to_events(IteratorConfig::default());
+        let output = HtmlRenderer.render(&doc).content;
+        assert_eq!(
+            output.as_text().unwrap(),
+            "<h1 id=\"title\">Title</h1>\n<p>Some <b>bold</b> and <i>it</i> text.</p>\n"
+        );
+    }
+}
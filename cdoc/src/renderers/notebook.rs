@@ -8,7 +8,7 @@ use crate::document::{DocPos, Document, EventContent};
 use crate::notebook::{Cell, CellCommon, CellMeta, Notebook, NotebookMeta};
 use crate::renderers::{RenderResult, Renderer};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Default)]
 pub struct NotebookRenderer;
 
 #[typetag::serde(name = "renderer_config")]
@@ -18,7 +18,7 @@ impl Renderer for NotebookRenderer {
         let output = serde_json::to_string(&notebook).expect("Invalid notebook (this is a bug)");
 
         Document {
-            content: output,
+            content: output.into(),
             metadata: doc.metadata.clone(),
             variables: doc.variables.clone(),
         }
@@ -0,0 +1,173 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+
+/// Create a url/filename safe identifier from arbitrary text. This is the canonical way of
+/// deriving ids and anchors in the project.
+///
+/// The input is transliterated to ASCII (e.g. `é` becomes `e`), lowercased and every run of
+/// non-alphanumeric characters is replaced by a single hyphen. Leading and trailing hyphens are
+/// removed.
+pub fn slugify(input: &str) -> String {
+    let ascii = deunicode::deunicode(input);
+    let mut slug = String::with_capacity(ascii.len());
+    let mut separator = false;
+
+    for c in ascii.chars() {
+        if c.is_ascii_alphanumeric() {
+            if separator && !slug.is_empty() {
+                slug.push('-');
+            }
+            separator = false;
+            slug.push(c.to_ascii_lowercase());
+        } else {
+            separator = true;
+        }
+    }
+
+    slug
+}
+
+/// Assigns ids to the headings of a document. The id is the explicit id of a heading
+/// (`# Title {#id}`) or a slug of its text. Ids that are already used get a `-1`, `-2`, etc.
+/// suffix. Both the HTML renderer and [Document::headings](crate::document::Document::headings)
+/// use it so that links to headings match their anchors.
+#[derive(Debug, Default)]
+pub struct HeadingIds {
+    used: HashSet<String>,
+}
+
+impl HeadingIds {
+    /// The id of the next heading of the document.
+    pub fn next(&mut self, explicit: Option<&str>, text: &str) -> String {
+        let id = match explicit {
+            Some(id) => id.to_string(),
+            None => match slugify(text) {
+                slug if slug.is_empty() => "section".to_string(),
+                slug => slug,
+            },
+        };
+        if self.used.insert(id.clone()) {
+            return id;
+        }
+        (1..)
+            .map(|i| format!("{}-{}", id, i))
+            .find(|candidate| self.used.insert(candidate.clone()))
+            .expect("Ran out of ids")
+    }
+}
+
+/// The Levenshtein (edit) distance between two strings, i.e. the number of single character
+/// insertions, deletions and substitutions needed to turn one into the other.
+pub fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+/// Escape text so that it is shown literally in HTML. All ASCII punctuation is written as
+/// character references so that neither markdown, math delimiters nor shortcodes match it.
+pub fn escape_punctuation(input: &str) -> String {
+    let mut escaped = String::with_capacity(input.len());
+    for c in input.chars() {
+        if c.is_ascii_punctuation() {
+            write!(escaped, "&#{};", c as u32).expect("Invalid format");
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+/// Remove ANSI escape sequences (e.g. the colors of terminal output and Jupyter tracebacks).
+pub fn strip_ansi(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\u{1b}' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            // Control sequence: parameters and intermediate bytes up to a final byte in @..~.
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            // Operating system command: terminated by BEL or ESC \.
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\u{7}' {
+                        break;
+                    }
+                    if c == '\u{1b}' && chars.peek() == Some(&'\\') {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            // Other escapes are a single character.
+            _ => {}
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slugify_ascii() {
+        assert_eq!(slugify("Getting Started"), "getting-started");
+        assert_eq!(slugify("01_getting_started"), "01-getting-started");
+        assert_eq!(slugify("  --Hello,   World!--  "), "hello-world");
+        assert_eq!(slugify(""), "");
+        assert_eq!(slugify("---"), "");
+    }
+
+    #[test]
+    fn slugify_unicode() {
+        assert_eq!(slugify("Crème brûlée"), "creme-brulee");
+        assert_eq!(slugify("Øvelse på dansk"), "ovelse-pa-dansk");
+        assert_eq!(slugify("Straße"), "strasse");
+        assert_eq!(slugify("Ångström – Übung"), "angstrom-ubung");
+        assert_eq!(slugify("naïve café"), "naive-cafe");
+    }
+
+    #[test]
+    fn test_levenshtein() {
+        assert_eq!(levenshtein("note", "note"), 0);
+        assert_eq!(levenshtein("noote", "note"), 1);
+        assert_eq!(levenshtein("nte", "note"), 1);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+        assert_eq!(levenshtein("", "abc"), 3);
+        assert_eq!(levenshtein("é", "e"), 1);
+    }
+
+    #[test]
+    fn test_strip_ansi() {
+        assert_eq!(
+            strip_ansi("\u{1b}[0;31mValueError\u{1b}[0m: bad \u{1b}[1;32m1\u{1b}[39m"),
+            "ValueError: bad 1"
+        );
+        assert_eq!(
+            strip_ansi("\u{1b}]8;;http://a\u{7}link\u{1b}]8;;\u{1b}\\ done"),
+            "link done"
+        );
+        assert_eq!(strip_ansi("plain [text]"), "plain [text]");
+    }
+}
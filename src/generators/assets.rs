@@ -0,0 +1,158 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Maps logical asset paths (relative to the build directory, e.g. `resources/style.css`) to
+/// their fingerprinted paths (e.g. `resources/style.3f2a9c1b.css`).
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct AssetManifest(BTreeMap<String, String>);
+
+impl AssetManifest {
+    /// Name of the manifest file written to the build directory.
+    pub const FILE_NAME: &'static str = "asset-manifest.json";
+
+    /// Hash the files in `resources/` of the project that have one of the given extensions and
+    /// copy each of them to a fingerprinted name in the build directory. The original files are
+    /// left in place.
+    pub fn fingerprint(
+        root: &Path,
+        build_dir: &Path,
+        extensions: &[String],
+    ) -> anyhow::Result<Self> {
+        let mut manifest = BTreeMap::new();
+        fingerprint_dir(
+            root,
+            &root.join("resources"),
+            build_dir,
+            extensions,
+            &mut manifest,
+        )?;
+        Ok(AssetManifest(manifest))
+    }
+
+    pub fn write(&self, build_dir: &Path) -> anyhow::Result<()> {
+        fs::write(
+            build_dir.join(Self::FILE_NAME),
+            serde_json::to_string_pretty(self)?,
+        )
+        .context("Could not write asset manifest")
+    }
+
+    /// Read the manifest from the build directory. A missing manifest is empty.
+    pub fn read(build_dir: &Path) -> anyhow::Result<Self> {
+        let path = build_dir.join(Self::FILE_NAME);
+        if !path.exists() {
+            return Ok(AssetManifest::default());
+        }
+        let input = fs::read_to_string(path)?;
+        serde_json::from_str(&input).context("Could not read asset manifest")
+    }
+
+    /// Rewrite quoted references (`"{url_prefix}/{path}"` or `'{url_prefix}/{path}'`) to the
+    /// assets in the manifest. Other references, including external URLs, are left unchanged.
+    pub fn rewrite(&self, html: &str, url_prefix: &str) -> String {
+        let mut html = html.to_string();
+        for (path, fingerprinted) in &self.0 {
+            for quote in ['"', '\''] {
+                html = html.replace(
+                    &format!("{quote}{url_prefix}/{path}{quote}"),
+                    &format!("{quote}{url_prefix}/{fingerprinted}{quote}"),
+                );
+            }
+        }
+        html
+    }
+}
+
+fn fingerprint_dir(
+    root: &Path,
+    dir: &Path,
+    build_dir: &Path,
+    extensions: &[String],
+    manifest: &mut BTreeMap<String, String>,
+) -> anyhow::Result<()> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            fingerprint_dir(root, &path, build_dir, extensions, manifest)?;
+            continue;
+        }
+
+        let (Some(stem), Some(ext)) = (
+            path.file_stem().and_then(|s| s.to_str()),
+            path.extension().and_then(|s| s.to_str()),
+        ) else {
+            continue;
+        };
+        if !extensions.iter().any(|e| e == ext) {
+            continue;
+        }
+
+        let content = fs::read(&path)?;
+        let hash = format!("{:x}", Sha256::digest(&content));
+        let relative = path.strip_prefix(root)?;
+        let fingerprinted = relative.with_file_name(format!("{}.{}.{}", stem, &hash[..8], ext));
+
+        let dest = build_dir.join(&fingerprinted);
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(dest, content)?;
+
+        manifest.insert(url_path(relative), url_path(&fingerprinted));
+    }
+    Ok(())
+}
+
+/// Path with `/` separators for use in URLs.
+fn url_path(path: &Path) -> String {
+    path.iter()
+        .map(|c| c.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fingerprint_assets() {
+        let root = tempfile::tempdir().unwrap();
+        let build = tempfile::tempdir().unwrap();
+        let resources = root.path().join("resources");
+        fs::create_dir_all(resources.join("css")).unwrap();
+        fs::write(resources.join("css").join("style.css"), "body {}").unwrap();
+        fs::write(resources.join("app.js"), "let x = 1;").unwrap();
+        fs::write(resources.join("logo.png"), "png").unwrap();
+
+        let extensions = vec!["css".to_string(), "js".to_string()];
+        let manifest = AssetManifest::fingerprint(root.path(), build.path(), &extensions).unwrap();
+
+        let style = manifest.0.get("resources/css/style.css").unwrap();
+        assert!(style.starts_with("resources/css/style."));
+        assert!(style.ends_with(".css"));
+        assert!(build.path().join(style).is_file());
+        assert!(manifest.0.contains_key("resources/app.js"));
+        assert!(!manifest.0.contains_key("resources/logo.png"));
+
+        manifest.write(build.path()).unwrap();
+        assert_eq!(AssetManifest::read(build.path()).unwrap(), manifest);
+
+        let html = "<link href=\"/prefix/resources/css/style.css\">\
+            <link href=\"https://cdn.com/resources/css/style.css\">\
+            <img src='/prefix/resources/logo.png'>";
+        let rewritten = manifest.rewrite(html, "/prefix");
+        assert!(rewritten.contains(&format!("href=\"/prefix/{}\"", style)));
+        assert!(rewritten.contains("href=\"https://cdn.com/resources/css/style.css\""));
+        assert!(rewritten.contains("src='/prefix/resources/logo.png'"));
+    }
+}
@@ -0,0 +1,856 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Cursor, Write};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context};
+use markup5ever::data::NAMED_ENTITIES;
+use sha2::{Digest, Sha256};
+use tracing::warn;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use cdoc::config::OutputFormat;
+use cdoc::document::Document;
+use cdoc::processors::links::rewrite_html;
+use cdoc::renderers::{RenderOutput, RenderResult};
+
+use crate::generators::{
+    content_path, document_key, page_key, write_output, Generator, GeneratorContext,
+};
+use crate::project::{Chapter, ItemDescriptor, Project, ProjectItem};
+
+type RenderedItem = ProjectItem<Option<Document<RenderResult>>>;
+
+/// Elements without content. They must be self-closing in XHTML.
+const VOID_ELEMENTS: [&str; 13] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+/// Convert rendered HTML to XHTML: void elements are closed, attribute values are quoted,
+/// attributes without a value (e.g. `<details open>`) get their name as value and named character
+/// references that are not defined in XML (e.g. `&nbsp;`) are replaced by numeric ones. Everything
+/// else is left unchanged, so raw HTML in documents must otherwise be well-formed.
+pub fn to_xhtml(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let name_end = rest[1..]
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .map_or(rest.len(), |i| i + 1);
+        let name = rest[1..name_end].to_ascii_lowercase();
+
+        match tag_end(rest) {
+            // Closing tags, comments and declarations have no name and are kept as is.
+            Some(end) if name.starts_with(|c: char| c.is_ascii_alphabetic()) => {
+                let void = VOID_ELEMENTS.contains(&name.as_str());
+                out.push_str(&start_tag(&rest[..end], void));
+                out.push('>');
+                rest = &rest[end + 1..];
+            }
+            Some(end) => {
+                out.push_str(&rest[..=end]);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push_str(rest);
+                rest = "";
+            }
+        }
+    }
+    out.push_str(rest);
+    xml_references(&out)
+}
+
+/// Write a start tag (without its `>`) as XML. Void elements are closed.
+fn start_tag(tag: &str, void: bool) -> String {
+    let (tag, closed) = match tag.strip_suffix('/') {
+        Some(tag) => (tag, true),
+        None => (tag, false),
+    };
+    let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+    let mut out = tag[..name_end].to_string();
+    let mut rest = tag[name_end..].trim_start();
+    while !rest.is_empty() {
+        let name_len = rest
+            .find(|c: char| c.is_whitespace() || c == '=')
+            .unwrap_or(rest.len());
+        let (name, after) = rest.split_at(name_len);
+        let (value, after) = match after.trim_start().strip_prefix('=') {
+            Some(after) => {
+                let after = after.trim_start();
+                match after.chars().next() {
+                    Some(quote @ ('"' | '\'')) => {
+                        let end = after[1..].find(quote).map_or(after.len(), |i| i + 1);
+                        (&after[1..end], after.get(end + 1..).unwrap_or(""))
+                    }
+                    _ => after.split_at(after.find(char::is_whitespace).unwrap_or(after.len())),
+                }
+            }
+            None => (name, after),
+        };
+        out.push_str(&format!(
+            " {}=\"{}\"",
+            name,
+            value.replace('<', "&lt;").replace('"', "&quot;")
+        ));
+        rest = after.trim_start();
+    }
+    if closed || void {
+        out.push_str(" /");
+    }
+    out
+}
+
+/// Replace named character references by numeric ones, except for those that XML defines, and
+/// escape ampersands that don't start a reference.
+fn xml_references(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+
+        let len = rest
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '#')
+            .unwrap_or(rest.len());
+        let name = &rest[..len];
+        if len == 0 || !rest[len..].starts_with(';') {
+            out.push_str("&amp;");
+        } else if name.starts_with('#') || ["amp", "lt", "gt", "quot", "apos"].contains(&name) {
+            out.push('&');
+        } else if let Some(&(first, second)) = NAMED_ENTITIES.get(format!("{};", name).as_str()) {
+            out.push_str(&format!("&#{};", first));
+            if second != 0 {
+                out.push_str(&format!("&#{};", second));
+            }
+            rest = &rest[len + 1..];
+        } else {
+            out.push_str("&amp;");
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Position of the `>` that ends the tag at the start of `s`. Quoted attribute values are skipped.
+fn tag_end(s: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in s.char_indices().skip(1) {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            (None, '>') => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Decode the character references that templates produce in attribute values (Tera escapes `/`).
+fn unescape_attribute(value: &str) -> String {
+    value
+        .replace("&#x2F;", "/")
+        .replace("&#47;", "/")
+        .replace("&#x27;", "'")
+        .replace("&#39;", "'")
+        .replace("&quot;", "\"")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+/// Replace the values of an attribute (e.g. `href`) for which `f` returns a new value. The function
+/// receives the unescaped value.
+fn rewrite_attribute<F>(html: &str, attr: &str, mut f: F) -> String
+where
+    F: FnMut(&str) -> Option<String>,
+{
+    rewrite_html(html, &[attr], |value| {
+        f(&unescape_attribute(value)).map(|new_value| escape_xml(&new_value))
+    })
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Format seconds since the Unix epoch as a UTC timestamp (the format of `dcterms:modified`).
+fn timestamp(secs: u64) -> String {
+    // Civil date from days since the epoch, see https://howardhinnant.github.io/date_algorithms.html
+    let z = (secs / 86400) as i64 + 719468;
+    let rem = secs % 86400;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+fn media_type(path: &str) -> &'static str {
+    let ext = path.rsplit('.').next().unwrap_or_default().to_lowercase();
+    match ext.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        "css" => "text/css",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        _ => "application/octet-stream",
+    }
+}
+
+/// A document of the book.
+#[derive(Debug)]
+pub struct Page {
+    pub file: String,
+    pub title: String,
+    pub xhtml: String,
+}
+
+/// An entry of the table of contents.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TocEntry {
+    pub title: String,
+    pub href: String,
+    pub children: Vec<TocEntry>,
+}
+
+/// Files embedded in the book (images, stylesheets and fonts).
+#[derive(Debug, Default)]
+struct Resources {
+    /// Location in the book and content.
+    files: Vec<(String, Vec<u8>)>,
+    /// Location in the book of each embedded source file.
+    sources: HashMap<PathBuf, String>,
+}
+
+impl Resources {
+    /// Embed a file in the given folder of the book and return its location. Files are only
+    /// embedded once. The file name is kept unless another file with the same name exists.
+    fn add(&mut self, source: &Path, folder: &str) -> anyhow::Result<String> {
+        if let Some(href) = self.sources.get(source) {
+            return Ok(href.clone());
+        }
+        let data =
+            fs::read(source).with_context(|| format!("Could not read {}", source.display()))?;
+        let name = source
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        let mut href = format!("{}/{}", folder, name);
+        if self.files.iter().any(|(h, _)| h == &href) {
+            href = format!("{}/{}-{}", folder, self.files.len(), name);
+        }
+
+        self.files.push((href.clone(), data));
+        self.sources.insert(source.to_path_buf(), href.clone());
+        Ok(href)
+    }
+}
+
+/// A course packaged as an EPUB 3 book (with an NCX table of contents for older readers).
+#[derive(Debug)]
+pub struct EpubBook {
+    pub title: String,
+    pub author: Option<String>,
+    pub language: String,
+    pub identifier: String,
+    pub modified: String,
+    /// Documents in reading order.
+    pub pages: Vec<Page>,
+    pub toc: Vec<TocEntry>,
+    resources: Resources,
+    stylesheets: Vec<String>,
+}
+
+fn item_title(item: &RenderedItem) -> String {
+    item.content
+        .deref()
+        .as_ref()
+        .and_then(|doc| doc.metadata.title.clone())
+        .unwrap_or_else(|| item.id.clone())
+}
+
+/// Table of contents entries of an item. Items that are not part of the book are replaced by their
+/// children. The titles of part and chapter indices are prefixed with their number if numbering is
+/// enabled.
+fn toc_entries(
+    item: &RenderedItem,
+    files: &HashMap<PathBuf, String>,
+    is_index: bool,
+    children: Vec<TocEntry>,
+) -> Vec<TocEntry> {
+    let number = item
+        .content
+        .deref()
+        .as_ref()
+        .and_then(|doc| doc.variables.number.as_ref());
+    let title = match number {
+        Some(number) if is_index => format!("{} {}", number, item_title(item)),
+        _ => item_title(item),
+    };
+    match files.get(&item.path) {
+        Some(file) => vec![TocEntry {
+            title,
+            href: file.clone(),
+            children,
+        }],
+        None => children,
+    }
+}
+
+fn chapter_toc(
+    chapter: &Chapter<Option<Document<RenderResult>>>,
+    files: &HashMap<PathBuf, String>,
+) -> Vec<TocEntry> {
+    let children = chapter
+        .documents
+        .iter()
+        .flat_map(|doc| toc_entries(doc, files, false, vec![]))
+        .chain(chapter.chapters.iter().flat_map(|c| chapter_toc(c, files)))
+        .collect();
+    toc_entries(&chapter.index, files, true, children)
+}
+
+fn project_toc(
+    project: &Project<Option<Document<RenderResult>>>,
+    files: &HashMap<PathBuf, String>,
+) -> Vec<TocEntry> {
+    let mut entries = toc_entries(&project.index, files, false, vec![]);
+    for part in &project.content {
+        let children = part
+            .chapters
+            .iter()
+            .flat_map(|c| chapter_toc(c, files))
+            .collect();
+        entries.extend(toc_entries(&part.index, files, true, children));
+    }
+    entries
+}
+
+impl EpubBook {
+    /// Collect the rendered documents of the project. Links between documents are changed to
+    /// point to the pages of the book and local images are embedded.
+    pub fn new(ctx: &GeneratorContext) -> anyhow::Result<Self> {
+        let config = &ctx.config.epub;
+        let items: Vec<ItemDescriptor<Option<Document<RenderResult>>>> = ctx
+            .project
+            .clone()
+            .into_iter()
+            .filter(|item| {
+                item.doc
+                    .content
+                    .deref()
+                    .as_ref()
+                    .and_then(|doc| doc.content.as_text())
+                    .is_some()
+            })
+            .collect();
+
+        // Book file of each document, by source path and by page key.
+        let mut files = HashMap::new();
+        let mut pages_by_key = HashMap::new();
+        for (idx, item) in items.iter().enumerate() {
+            let file = format!("doc-{}.xhtml", idx + 1);
+            // Links point to the web pages of documents, which are named after their ids.
+            pages_by_key.insert(document_key(&ctx.config, &item.doc.path), file.clone());
+            files.insert(item.doc.path.clone(), file);
+        }
+
+        let mut resources = Resources::default();
+        let stylesheets = config
+            .stylesheets
+            .iter()
+            .map(|path| resources.add(&ctx.root.join(path), "styles"))
+            .collect::<anyhow::Result<Vec<String>>>()?;
+        for font in &config.fonts {
+            resources.add(&ctx.root.join(font), "fonts")?;
+        }
+
+        let title = config
+            .title
+            .clone()
+            .or_else(|| {
+                ctx.project
+                    .index
+                    .content
+                    .deref()
+                    .as_ref()
+                    .and_then(|doc| doc.metadata.title.clone())
+            })
+            .unwrap_or_else(|| "Untitled".to_string());
+
+        let mut pages = Vec::new();
+        for item in &items {
+            let doc = item.doc.content.deref().as_ref().expect("Filtered");
+            let html = doc.content.as_text().expect("Filtered");
+            let doc_dir = item
+                .doc
+                .path
+                .parent()
+                .map(|p| p.to_string_lossy().to_string())
+                .unwrap_or_default();
+
+            let html = rewrite_attribute(html, "href", |link| {
+                let (path, fragment) = link.split_once('#').unwrap_or((link, ""));
+                let path = content_path(path, &doc_dir, &ctx.config.url_prefix)?;
+                let file = pages_by_key.get(&page_key(&path))?;
+                Some(match fragment {
+                    "" => file.clone(),
+                    fragment => format!("{}#{}", file, fragment),
+                })
+            });
+            // The attribute rewrite can't fail, so the first error is kept and returned after.
+            let mut error = None;
+            let html = rewrite_attribute(&html, "src", |link| {
+                let path = content_path(link, &doc_dir, &ctx.config.url_prefix)?;
+                let source = if path.starts_with("resources/") {
+                    ctx.root.join(&path)
+                } else {
+                    ctx.root.join("content").join(&path)
+                };
+                if !source.is_file() {
+                    warn!(
+                        "{} in {} was not found and is not included in the EPUB",
+                        link,
+                        item.doc.path.display()
+                    );
+                    return None;
+                }
+                match resources.add(&source, "media") {
+                    Ok(href) => Some(href),
+                    Err(e) => {
+                        error.get_or_insert(e.context(format!(
+                            "Could not embed {} in {}",
+                            link,
+                            item.doc.path.display()
+                        )));
+                        None
+                    }
+                }
+            });
+            if let Some(e) = error {
+                return Err(e);
+            }
+
+            pages.push(Page {
+                file: files[&item.doc.path].clone(),
+                title: item_title(&item.doc),
+                xhtml: ctx
+                    .post_render
+                    .apply(&OutputFormat::Epub, to_xhtml(&html))?,
+            });
+        }
+
+        let identifier = config.identifier.clone().unwrap_or_else(|| {
+            let hash = format!("{:x}", Sha256::digest(title.as_bytes()));
+            format!("urn:courses:{}", &hash[..32])
+        });
+        let modified = timestamp(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+        );
+
+        Ok(EpubBook {
+            title,
+            author: config.author.clone(),
+            language: config.language.clone(),
+            identifier,
+            modified,
+            pages,
+            toc: project_toc(&ctx.project, &files),
+            resources,
+            stylesheets,
+        })
+    }
+
+    fn page_xhtml(&self, page: &Page) -> String {
+        let stylesheets: String = self
+            .stylesheets
+            .iter()
+            .map(|href| {
+                format!(
+                    "<link rel=\"stylesheet\" type=\"text/css\" href=\"{}\" />\n",
+                    escape_xml(href)
+                )
+            })
+            .collect();
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!DOCTYPE html>\n\
+            <html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\" xml:lang=\"{lang}\" lang=\"{lang}\">\n\
+            <head>\n<meta charset=\"UTF-8\" />\n<title>{title}</title>\n{stylesheets}</head>\n\
+            <body>\n{body}\n</body>\n</html>\n",
+            lang = escape_xml(&self.language),
+            title = escape_xml(&page.title),
+            stylesheets = stylesheets,
+            body = page.xhtml,
+        )
+    }
+
+    fn nav_xhtml(&self) -> String {
+        fn list(entries: &[TocEntry]) -> String {
+            let items: String = entries
+                .iter()
+                .map(|e| {
+                    let children = if e.children.is_empty() {
+                        String::new()
+                    } else {
+                        list(&e.children)
+                    };
+                    format!(
+                        "<li><a href=\"{}\">{}</a>{}</li>\n",
+                        escape_xml(&e.href),
+                        escape_xml(&e.title),
+                        children
+                    )
+                })
+                .collect();
+            format!("<ol>\n{}</ol>", items)
+        }
+
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!DOCTYPE html>\n\
+            <html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\" xml:lang=\"{lang}\" lang=\"{lang}\">\n\
+            <head>\n<meta charset=\"UTF-8\" />\n<title>{title}</title>\n</head>\n\
+            <body>\n<nav epub:type=\"toc\" id=\"toc\">\n<h1>{title}</h1>\n{list}\n</nav>\n</body>\n</html>\n",
+            lang = escape_xml(&self.language),
+            title = escape_xml(&self.title),
+            list = list(&self.toc),
+        )
+    }
+
+    fn toc_ncx(&self) -> String {
+        fn points(entries: &[TocEntry], order: &mut usize) -> String {
+            entries
+                .iter()
+                .map(|e| {
+                    *order += 1;
+                    let n = *order;
+                    format!(
+                        "<navPoint id=\"nav-{n}\" playOrder=\"{n}\">\n<navLabel><text>{}</text></navLabel>\n<content src=\"{}\"/>\n{}</navPoint>\n",
+                        escape_xml(&e.title),
+                        escape_xml(&e.href),
+                        points(&e.children, order),
+                    )
+                })
+                .collect()
+        }
+        fn depth(entries: &[TocEntry]) -> usize {
+            entries
+                .iter()
+                .map(|e| 1 + depth(&e.children))
+                .max()
+                .unwrap_or(0)
+        }
+
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+            <ncx xmlns=\"http://www.daisy.org/z3986/2005/ncx/\" version=\"2005-1\">\n\
+            <head>\n<meta name=\"dtb:uid\" content=\"{id}\"/>\n<meta name=\"dtb:depth\" content=\"{depth}\"/>\n</head>\n\
+            <docTitle><text>{title}</text></docTitle>\n<navMap>\n{points}</navMap>\n</ncx>\n",
+            id = escape_xml(&self.identifier),
+            depth = depth(&self.toc),
+            title = escape_xml(&self.title),
+            points = points(&self.toc, &mut 0),
+        )
+    }
+
+    fn content_opf(&self) -> String {
+        let mut manifest = String::from(
+            "<item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\"/>\n\
+            <item id=\"ncx\" href=\"toc.ncx\" media-type=\"application/x-dtbncx+xml\"/>\n",
+        );
+        let mut spine = String::new();
+        for (idx, page) in self.pages.iter().enumerate() {
+            let properties = if page.xhtml.contains("<math") {
+                " properties=\"mathml\""
+            } else {
+                ""
+            };
+            manifest.push_str(&format!(
+                "<item id=\"doc-{}\" href=\"{}\" media-type=\"application/xhtml+xml\"{}/>\n",
+                idx + 1,
+                escape_xml(&page.file),
+                properties
+            ));
+            spine.push_str(&format!("<itemref idref=\"doc-{}\"/>\n", idx + 1));
+        }
+        for (idx, (href, _)) in self.resources.files.iter().enumerate() {
+            manifest.push_str(&format!(
+                "<item id=\"res-{}\" href=\"{}\" media-type=\"{}\"/>\n",
+                idx + 1,
+                escape_xml(href),
+                media_type(href)
+            ));
+        }
+        let creator = self
+            .author
+            .as_ref()
+            .map(|a| format!("<dc:creator>{}</dc:creator>\n", escape_xml(a)))
+            .unwrap_or_default();
+
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+            <package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" unique-identifier=\"book-id\">\n\
+            <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n\
+            <dc:identifier id=\"book-id\">{id}</dc:identifier>\n<dc:title>{title}</dc:title>\n\
+            <dc:language>{lang}</dc:language>\n{creator}\
+            <meta property=\"dcterms:modified\">{modified}</meta>\n</metadata>\n\
+            <manifest>\n{manifest}</manifest>\n<spine toc=\"ncx\">\n{spine}</spine>\n</package>\n",
+            id = escape_xml(&self.identifier),
+            title = escape_xml(&self.title),
+            lang = escape_xml(&self.language),
+            creator = creator,
+            modified = self.modified,
+            manifest = manifest,
+            spine = spine,
+        )
+    }
+
+    /// Pack the book as a zip archive. The `mimetype` file must be the first (uncompressed) entry.
+    pub fn to_output(&self) -> anyhow::Result<RenderOutput> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let stored = FileOptions::default().compression_method(CompressionMethod::Stored);
+        let deflated = FileOptions::default();
+
+        zip.start_file("mimetype", stored)?;
+        zip.write_all(b"application/epub+zip")?;
+
+        zip.start_file("META-INF/container.xml", deflated)?;
+        zip.write_all(
+            b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+            <container version=\"1.0\" xmlns=\"urn:oasis:names:tc:opendocument:xmlns:container\">\n\
+            <rootfiles>\n<rootfile full-path=\"OEBPS/content.opf\" media-type=\"application/oebps-package+xml\"/>\n</rootfiles>\n\
+            </container>\n",
+        )?;
+
+        zip.start_file("OEBPS/content.opf", deflated)?;
+        zip.write_all(self.content_opf().as_bytes())?;
+        zip.start_file("OEBPS/nav.xhtml", deflated)?;
+        zip.write_all(self.nav_xhtml().as_bytes())?;
+        zip.start_file("OEBPS/toc.ncx", deflated)?;
+        zip.write_all(self.toc_ncx().as_bytes())?;
+
+        for page in &self.pages {
+            zip.start_file(format!("OEBPS/{}", page.file), deflated)?;
+            zip.write_all(self.page_xhtml(page).as_bytes())?;
+        }
+        for (href, data) in &self.resources.files {
+            zip.start_file(format!("OEBPS/{}", href), deflated)?;
+            zip.write_all(data)?;
+        }
+
+        Ok(RenderOutput::Bytes(zip.finish()?.into_inner()))
+    }
+}
+
+/// Packages all documents into a single EPUB book.
+pub struct EpubGenerator;
+
+impl Generator for EpubGenerator {
+    fn generate(&self, ctx: GeneratorContext) -> anyhow::Result<()> {
+        let book = EpubBook::new(&ctx)?;
+        write_output(
+            &ctx,
+            &OutputFormat::Epub,
+            book.to_output()?,
+            &ctx.build_dir.join(&ctx.config.epub.file_name),
+        )
+    }
+
+    fn prepare(&self, _ctx: &GeneratorContext) -> anyhow::Result<()> {
+        bail!("EPUB output requires all documents and is not supported by streaming builds")
+    }
+
+    fn generate_single(
+        &self,
+        _content: Document<RenderResult>,
+        _doc_info: ItemDescriptor<()>,
+        ctx: &GeneratorContext,
+    ) -> anyhow::Result<()> {
+        self.generate(ctx.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project::config::ProjectConfig;
+    use crate::project::Part;
+    use cdoc::config::InputFormat;
+    use std::fs::File;
+    use std::io::Read;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_to_xhtml() {
+        assert_eq!(
+            to_xhtml("<p>a<br>b&nbsp;<img src=\"x>.png\" alt=\"\"><hr /></p>"),
+            "<p>a<br />b&#160;<img src=\"x>.png\" alt=\"\" /><hr /></p>"
+        );
+        assert_eq!(to_xhtml("<pre>a < b</pre>"), "<pre>a < b</pre>");
+        assert_eq!(
+            to_xhtml("<!-- x --><details open><summary>a</summary></details>"),
+            "<!-- x --><details open=\"open\"><summary>a</summary></details>"
+        );
+        assert_eq!(
+            to_xhtml("<img src=a.png alt='say \"hi\"' data-x = 1 >"),
+            "<img src=\"a.png\" alt=\"say &quot;hi&quot;\" data-x=\"1\" />"
+        );
+    }
+
+    #[test]
+    fn test_xml_references() {
+        assert_eq!(
+            to_xhtml("a &mdash; &copy; &amp; &lt;b&gt; &#169; &#xA9;"),
+            "a &#8212; &#169; &amp; &lt;b&gt; &#169; &#xA9;"
+        );
+        assert_eq!(
+            to_xhtml("<a href=\"?a=1&b=2\" title=\"&hellip;\">R&D &unknown; &</a>"),
+            "<a href=\"?a=1&amp;b=2\" title=\"&#8230;\">R&amp;D &amp;unknown; &amp;</a>"
+        );
+    }
+
+    #[test]
+    fn test_timestamp() {
+        assert_eq!(timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(timestamp(951827415), "2000-02-29T12:30:15Z");
+    }
+
+    fn page(path: &str, title: &str, html: &str) -> RenderedItem {
+        let mut doc = Document::<RenderResult>::default();
+        doc.metadata.title = Some(title.to_string());
+        doc.content = html.to_string().into();
+        ProjectItem {
+            id: "index".to_string(),
+            format: InputFormat::Markdown,
+            path: PathBuf::from(path),
+            content: Arc::new(Some(doc)),
+        }
+    }
+
+    #[test]
+    fn test_epub() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir_all(root.path().join("content/part/chapter")).unwrap();
+        fs::write(root.path().join("content/part/chapter/plot.png"), b"png").unwrap();
+
+        let config: ProjectConfig = serde_yaml::from_str(
+            "url_prefix: /site\noutputs: []\nparsers: {}\ncustom: {}\nepub:\n  author: Me",
+        )
+        .unwrap();
+        let mut ctx = GeneratorContext {
+            root: root.path().to_path_buf(),
+            project: Project {
+                project_path: Default::default(),
+                index: page(
+                    "index.md",
+                    "Course",
+                    "<p><a href=\"/site/part/chapter/doc#sec\">Doc</a></p>\
+                    <a href=\"&#x2F;site&#x2F;part&#x2F;chapter\">Chapter</a>",
+                ),
+                content: vec![Part {
+                    id: "part".to_string(),
+                    index: ProjectItem {
+                        content: Arc::new(None),
+                        ..page("part/index.md", "Part", "")
+                    },
+                    chapters: vec![Chapter {
+                        id: "chapter".to_string(),
+                        index: page(
+                            "part/chapter/index.md",
+                            "Chapter",
+                            "<img src=\"plot.png\"><a href=\"https://example.com\">x</a>",
+                        ),
+                        documents: vec![
+                            page(
+                                "part/chapter/doc.md",
+                                "Doc & more",
+                                "<a href=\"../../index.html\">Home</a>\
+                                <a href=\"01-intro.html#setup\">Intro</a>",
+                            ),
+                            page("part/chapter/01_Intro.md", "Intro", "<p>Intro</p>"),
+                        ],
+                        files: vec![],
+                        chapters: vec![],
+                    }],
+                }],
+            },
+            config,
+            build_dir: root.path().join("build"),
+            post_render: Default::default(),
+        };
+        ctx.post_render.push(Arc::new(|format, xhtml| {
+            Ok(format!("<!-- {} -->{}", format, xhtml))
+        }));
+
+        EpubGenerator.generate(ctx).unwrap();
+
+        let file = File::open(root.path().join("build/book.epub")).unwrap();
+        let mut zip = zip::ZipArchive::new(file).unwrap();
+        assert_eq!(zip.by_index(0).unwrap().name(), "mimetype");
+
+        let mut read = |name: &str| {
+            let mut s = String::new();
+            zip.by_name(name).unwrap().read_to_string(&mut s).unwrap();
+            s
+        };
+
+        let index = read("OEBPS/doc-1.xhtml");
+        // The post-render hooks receive the XHTML of each page.
+        assert!(index.contains("<body>\n<!-- epub --><p>"));
+        assert!(index.contains("<a href=\"doc-3.xhtml#sec\">"));
+        assert!(index.contains("<a href=\"doc-2.xhtml\">"));
+        let chapter = read("OEBPS/doc-2.xhtml");
+        assert!(chapter.contains("<img src=\"media/plot.png\" />"));
+        assert!(chapter.contains("href=\"https://example.com\""));
+        let doc = read("OEBPS/doc-3.xhtml");
+        assert!(doc.contains("<a href=\"doc-1.xhtml\">"));
+        // Links use the page of a document, which is named after its id rather than its file.
+        assert!(doc.contains("<a href=\"doc-4.xhtml#setup\">"));
+        assert_eq!(read("OEBPS/media/plot.png"), "png");
+
+        let opf = read("OEBPS/content.opf");
+        assert!(opf.contains("<dc:title>Course</dc:title>"));
+        assert!(opf.contains("<dc:creator>Me</dc:creator>"));
+        assert!(opf.contains("<itemref idref=\"doc-1\"/>\n<itemref idref=\"doc-2\"/>"));
+        assert!(opf.contains("href=\"media/plot.png\" media-type=\"image/png\""));
+
+        // The part has no content, so its chapter is listed at the top level.
+        let nav = read("OEBPS/nav.xhtml");
+        assert!(nav.contains(
+            "<li><a href=\"doc-2.xhtml\">Chapter</a><ol>\n<li><a href=\"doc-3.xhtml\">Doc &amp; more</a></li>\n\
+            <li><a href=\"doc-4.xhtml\">Intro</a></li>\n</ol></li>"
+        ));
+        assert!(read("OEBPS/toc.ncx").contains("playOrder=\"3\""));
+    }
+}
@@ -0,0 +1,182 @@
+use std::fs;
+use std::ops::Deref;
+
+use serde::Serialize;
+
+use crate::generators::GeneratorContext;
+use crate::project::config::FeedConfig;
+
+const JSON_FEED_VERSION: &str = "https://jsonfeed.org/version/1.1";
+
+/// A [JSON Feed](https://www.jsonfeed.org/version/1.1/) document.
+#[derive(Serialize, Debug)]
+pub struct JsonFeed {
+    pub version: &'static str,
+    pub title: String,
+    pub home_page_url: String,
+    pub feed_url: String,
+    pub items: Vec<JsonFeedItem>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct JsonFeedItem {
+    pub id: String,
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    pub content_html: String,
+}
+
+impl JsonFeed {
+    /// Create a feed with an item for each rendered page of the project (in reading order).
+    pub fn new(ctx: &GeneratorContext, config: &FeedConfig) -> Self {
+        let site_url = format!(
+            "{}{}",
+            config.base_url.trim_end_matches('/'),
+            ctx.config.url_prefix
+        );
+
+        let items = ctx
+            .project
+            .clone()
+            .into_iter()
+            .filter_map(|item| {
+                let doc = item.doc.content.deref().as_ref()?;
+                let html = doc.content.as_text()?;
+                let url = format!(
+                    "{}{}",
+                    config.base_url.trim_end_matches('/'),
+                    ctx.config.page_url(&item.doc.path)
+                );
+
+                Some(JsonFeedItem {
+                    id: url.clone(),
+                    url,
+                    title: doc.metadata.title.clone(),
+                    summary: doc.metadata.summary.clone().or_else(|| summary(html)),
+                    content_html: html.to_string(),
+                })
+            })
+            .collect();
+
+        JsonFeed {
+            version: JSON_FEED_VERSION,
+            title: config.title.clone(),
+            home_page_url: format!("{}/", site_url),
+            feed_url: format!("{}/feed.json", site_url),
+            items,
+        }
+    }
+
+    pub fn write(&self, ctx: &GeneratorContext) -> anyhow::Result<()> {
+        fs::write(
+            ctx.build_dir.join("feed.json"),
+            serde_json::to_string_pretty(self)?,
+        )?;
+        Ok(())
+    }
+}
+
+/// Text of the first paragraph of rendered HTML with all tags removed.
+pub fn summary(html: &str) -> Option<String> {
+    let tag = html
+        .match_indices("<p")
+        .find(|(i, _)| matches!(html.as_bytes().get(i + 2), Some(b'>' | b' ')))?
+        .0;
+    let start = tag + html[tag..].find('>')? + 1;
+    let end = start + html[start..].find("</p>")?;
+
+    let mut text = String::new();
+    let mut in_tag = false;
+    for c in html[start..end].chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    (!text.is_empty()).then_some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project::config::ProjectConfig;
+    use crate::project::{Project, ProjectItem};
+    use cdoc::config::InputFormat;
+    use cdoc::document::Document;
+    use cdoc::renderers::RenderResult;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_summary() {
+        let html = "<h1>Title</h1>\n<p>First <em>important</em>\nparagraph.</p><p>Second</p>";
+        assert_eq!(
+            summary(html),
+            Some("First important paragraph.".to_string())
+        );
+        assert_eq!(summary("<h1>Title</h1>"), None);
+        assert_eq!(
+            summary("<pre>code</pre><p class=\"lead\">Lead</p>"),
+            Some("Lead".to_string())
+        );
+    }
+
+    #[test]
+    fn test_json_feed() {
+        let page = |path: &str, title: &str, content: Option<&str>| ProjectItem {
+            id: PathBuf::from(path)
+                .file_stem()
+                .unwrap()
+                .to_string_lossy()
+                .to_string(),
+            format: InputFormat::Markdown,
+            path: PathBuf::from(path),
+            content: Arc::new(content.map(|content| {
+                let mut doc = Document::<RenderResult>::default();
+                doc.metadata.title = Some(title.to_string());
+                doc.content = content.to_string().into();
+                doc
+            })),
+        };
+
+        let config: ProjectConfig = serde_yaml::from_str(
+            "url_prefix: /site\noutputs: []\nparsers: {}\ncustom: {}\nfeed:\n  title: Feed\n  base_url: https://example.com/",
+        )
+        .unwrap();
+        let ctx = GeneratorContext {
+            root: Default::default(),
+            project: Project {
+                project_path: Default::default(),
+                index: page("index.md", "Home", Some("<p>Welcome</p>")),
+                content: vec![crate::project::Part {
+                    id: "part1".to_string(),
+                    index: page("part1/index.md", "Part", None),
+                    chapters: vec![],
+                }],
+            },
+            config: config.clone(),
+            build_dir: Default::default(),
+            post_render: Default::default(),
+        };
+
+        let feed = JsonFeed::new(&ctx, config.feed.as_ref().unwrap());
+        let value = serde_json::to_value(&feed).unwrap();
+        assert_eq!(value["version"], "https://jsonfeed.org/version/1.1");
+        assert_eq!(value["home_page_url"], "https://example.com/site/");
+        assert_eq!(value["feed_url"], "https://example.com/site/feed.json");
+        // Documents without output are not included
+        assert_eq!(feed.items.len(), 1);
+        assert_eq!(
+            value["items"][0]["url"],
+            "https://example.com/site/index.html"
+        );
+        assert_eq!(value["items"][0]["title"], "Home");
+        assert_eq!(value["items"][0]["summary"], "Welcome");
+    }
+}
@@ -1,61 +1,118 @@
+use std::collections::HashMap;
 use std::fs;
 use std::ops::Deref;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use anyhow::Context;
 use indicatif::{ProgressBar, ProgressStyle};
 use tera::Tera;
 
+use cdoc::config::OutputFormat;
 use cdoc::document::Document;
 use cdoc::renderers::RenderResult;
 
-use crate::generators::{Generator, GeneratorContext};
+use crate::generators::assets::AssetManifest;
+use crate::generators::feed::JsonFeed;
+use crate::generators::{write_output, Generator, GeneratorContext};
+use crate::project::config::ProjectConfig;
 use crate::project::ItemDescriptor;
 
+/// Minify rendered HTML by collapsing whitespace and removing comments. Whitespace in `<pre>` and
+/// `<code>` elements is preserved and the content of `<script>` and `<style>` elements is not
+/// minified (only trimmed).
+pub fn minify(output: &str) -> anyhow::Result<String> {
+    let cfg = minify_html::Cfg {
+        keep_closing_tags: true,
+        keep_html_and_head_opening_tags: true,
+        ..minify_html::Cfg::spec_compliant()
+    };
+    Ok(String::from_utf8(minify_html::minify(
+        output.as_bytes(),
+        &cfg,
+    ))?)
+}
+
+/// Tera function `page_url(path=doc.path)` that gives the URL of the web page of a document
+/// according to the url style of the project.
+pub fn page_url_function(
+    config: &ProjectConfig,
+) -> impl Fn(&HashMap<String, tera::Value>) -> tera::Result<tera::Value> {
+    let config = config.clone();
+    move |args| {
+        let path = args
+            .get("path")
+            .and_then(|p| p.as_str())
+            .ok_or_else(|| tera::Error::msg("page_url requires a `path` argument"))?;
+        Ok(tera::Value::String(config.page_url(Path::new(path))))
+    }
+}
+
+/// Add a canonical link to the head of a page unless it already has one.
+pub fn add_canonical_link(html: &str, url: &str) -> String {
+    match html.find("</head>") {
+        Some(idx) if !html.contains("rel=\"canonical\"") => format!(
+            "{}<link rel=\"canonical\" href=\"{}\">\n{}",
+            &html[..idx],
+            url.replace('&', "&amp;").replace('"', "&quot;"),
+            &html[idx..]
+        ),
+        _ => html.to_string(),
+    }
+}
+
 pub struct HtmlGenerator {
-    tera: Tera,
+    tera: Arc<Tera>,
 }
 
 impl HtmlGenerator {
-    pub fn new(tera: Tera) -> Self {
+    pub fn new(tera: Arc<Tera>) -> Self {
         HtmlGenerator { tera }
     }
 }
 
 impl HtmlGenerator {
-    fn write_document(
+    /// The manifest of fingerprinted assets (empty if fingerprinting is disabled).
+    fn asset_manifest(&self, ctx: &GeneratorContext) -> anyhow::Result<AssetManifest> {
+        if ctx.config.assets.fingerprint {
+            AssetManifest::read(&ctx.build_dir)
+        } else {
+            Ok(AssetManifest::default())
+        }
+    }
+
+    /// Render a page and write it to its location for the url style of the project.
+    fn write_page(
         &self,
-        output: String,
-        doc_id: String,
+        mut context: tera::Context,
+        assets: &AssetManifest,
         doc_path: PathBuf,
-        build_dir: PathBuf,
+        ctx: &GeneratorContext,
     ) -> anyhow::Result<()> {
-        let mut html_build_dir = build_dir.join(doc_path);
-        html_build_dir.pop(); // Pop filename
+        let canonical_url = ctx.config.canonical_url(&doc_path);
+        context.insert("page_url", &ctx.config.page_url(&doc_path));
+        context.insert("canonical_url", &canonical_url);
 
-        let section_build_path = html_build_dir.join(format!("{}.html", doc_id));
-
-        fs::create_dir_all(html_build_dir).context("Could not create directory")?;
-        // let mut file = fs::OpenOptions::new().write(true).create(true).append(false).open(section_build_path)?;
-        // file.write_all(output.as_bytes())?;
-        fs::write(section_build_path, output).unwrap();
-
-        Ok(())
+        let result = self.tera.render("section.tera.html", &context)?;
+        let result = assets.rewrite(&result, &ctx.config.url_prefix);
+        let result = match canonical_url {
+            Some(url) => add_canonical_link(&result, &url),
+            None => result,
+        };
+
+        let path = ctx
+            .build_dir
+            .join(ctx.config.url_style.page_path(&doc_path));
+        write_output(ctx, &OutputFormat::Html, result.into(), &path)
     }
 }
 
 impl Generator for HtmlGenerator {
     fn generate(&self, ctx: GeneratorContext) -> anyhow::Result<()> {
-        // Copy resources
-        let resource_path_src = ctx.root.join("resources");
-        let resource_path_build_dir = ctx.build_dir.as_path().join("resources");
-
-        fs::create_dir_all(resource_path_build_dir.as_path())?;
-        let mut options = fs_extra::dir::CopyOptions::new();
-        options.overwrite = true;
+        self.prepare(&ctx)?;
 
-        fs_extra::copy_items(&[resource_path_src], ctx.build_dir.as_path(), &options)?;
-        // end of resource copy
+        if let Some(feed) = &ctx.config.feed {
+            JsonFeed::new(&ctx, feed).write(&ctx)?;
+        }
 
         let spinner = ProgressStyle::with_template("{prefix:.bold.dim} {spinner} {wide_msg}")
             .unwrap()
@@ -64,8 +121,9 @@ impl Generator for HtmlGenerator {
         pb.set_style(spinner);
 
         let proj = ctx.project.clone();
+        let assets = self.asset_manifest(&ctx)?;
 
-        for item in ctx.project {
+        for item in ctx.project.clone() {
             if let Some(c) = item.doc.content.deref() {
                 pb.set_message(format!("{}", item.doc.path.display()));
                 pb.inc(1);
@@ -74,15 +132,16 @@ impl Generator for HtmlGenerator {
                 let mut context = tera::Context::new();
                 context.insert("config", &proj); // TODO: THis is very confusing but I'm keeping it until I have a base working version of the new cdoc crate.
                 context.insert("project", &ctx.config);
+                context.insert("var", &ctx.config.vars);
                 context.insert("current_part", &item.part_id);
                 context.insert("current_chapter", &item.chapter_id);
                 context.insert("current_doc", &item.doc.id);
                 context.insert("doc", &c);
                 context.insert("html", &c.content);
                 context.insert("title", "Test");
+                context.insert("assets", &assets);
 
-                let result = self.tera.render("section.tera.html", &context)?;
-                self.write_document(result, item.doc.id, item.doc.path, ctx.build_dir.clone())?;
+                self.write_page(context, &assets, item.doc.path, &ctx)?;
             }
         }
         pb.finish_and_clear();
@@ -97,27 +156,82 @@ impl Generator for HtmlGenerator {
         Ok(())
     }
 
+    fn prepare(&self, ctx: &GeneratorContext) -> anyhow::Result<()> {
+        // Copy resources
+        let resource_path_src = ctx.root.join("resources");
+        let resource_path_build_dir = ctx.build_dir.as_path().join("resources");
+
+        fs::create_dir_all(resource_path_build_dir.as_path())?;
+        let mut options = fs_extra::dir::CopyOptions::new();
+        options.overwrite = true;
+
+        fs_extra::copy_items(&[resource_path_src], ctx.build_dir.as_path(), &options)?;
+
+        if ctx.config.assets.fingerprint {
+            AssetManifest::fingerprint(&ctx.root, &ctx.build_dir, &ctx.config.assets.extensions)?
+                .write(&ctx.build_dir)?;
+        }
+        Ok(())
+    }
+
     fn generate_single(
         &self,
         content: Document<RenderResult>,
         doc_info: ItemDescriptor<()>,
-        ctx: GeneratorContext,
+        ctx: &GeneratorContext,
     ) -> anyhow::Result<()> {
-        let proj = ctx.project.clone();
         let mut context = tera::Context::new();
-        context.insert("config", &proj); // TODO: THis is very confusing but I'm keeping it until I have a base working version of the new cdoc crate.
+        context.insert("config", &ctx.project); // TODO: THis is very confusing but I'm keeping it until I have a base working version of the new cdoc crate.
         context.insert("project", &ctx.config);
+        context.insert("var", &ctx.config.vars);
         context.insert("current_part", &doc_info.part_id);
         context.insert("current_chapter", &doc_info.chapter_id);
         context.insert("current_doc", &doc_info.doc.id);
         context.insert("doc", &content);
         context.insert("html", &content.content);
         context.insert("title", "Test");
+        let assets = self.asset_manifest(ctx)?;
+        context.insert("assets", &assets);
 
-        let result = self.tera.render("section.tera.html", &context)?;
+        self.write_page(context, &assets, doc_info.doc.path, ctx)
+    }
+}
 
-        self.write_document(result, doc_info.doc.id, doc_info.doc.path, ctx.build_dir)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_minify() {
+        let input = "<div>\n    <!-- comment -->\n    <p>Some   text</p>\n</div>\n\
+            <pre><code>fn main() {\n    println!(\"  hi  \");\n}\n</code></pre>\n\
+            <script>\n  let x = 1;\n</script>";
+        let output = minify(input).unwrap();
+
+        assert!(!output.contains("comment"));
+        assert!(output.starts_with("<div><p>Some text</p></div>"));
+        assert!(
+            output.contains("<pre><code>fn main() {\n    println!(\"  hi  \");\n}\n</code></pre>")
+        );
+        // Scripts are only trimmed
+        assert!(output.contains("<script>let x = 1;</script>"));
+    }
 
-        Ok(())
+    #[test]
+    fn test_canonical_link() {
+        let page = "<html><head><title>T</title></head><body></body></html>";
+        assert_eq!(
+            add_canonical_link(page, "https://example.com/a&b/"),
+            "<html><head><title>T</title><link rel=\"canonical\" href=\"https://example.com/a&amp;b/\">\n</head><body></body></html>"
+        );
+        let existing = add_canonical_link(page, "https://example.com/");
+        assert_eq!(
+            add_canonical_link(&existing, "https://example.com/other"),
+            existing
+        );
+        assert_eq!(
+            add_canonical_link("<p>fragment</p>", "/"),
+            "<p>fragment</p>"
+        );
     }
 }
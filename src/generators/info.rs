@@ -1,5 +1,6 @@
 use std::fs;
 
+use cdoc::config::OutputFormat;
 use cdoc::document::Document;
 use cdoc::renderers::RenderResult;
 
@@ -11,6 +12,7 @@ pub struct InfoGenerator;
 impl Generator for InfoGenerator {
     fn generate(&self, ctx: GeneratorContext) -> anyhow::Result<()> {
         let output = serde_yaml::to_string(&ctx.project)?;
+        let output = ctx.post_render.apply(&OutputFormat::Info, output)?;
         let path = ctx.build_dir.join("config.yml");
         fs::write(path, output)?;
         Ok(())
@@ -20,8 +22,8 @@ impl Generator for InfoGenerator {
         &self,
         _content: Document<RenderResult>,
         _doc_info: ItemDescriptor<()>,
-        ctx: GeneratorContext,
+        ctx: &GeneratorContext,
     ) -> anyhow::Result<()> {
-        self.generate(ctx)
+        self.generate(ctx.clone())
     }
 }
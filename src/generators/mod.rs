@@ -1,13 +1,21 @@
+use anyhow::Context;
+use cdoc::config::OutputFormat;
 use cdoc::document::Document;
-use cdoc::renderers::RenderResult;
-use std::path::PathBuf;
+use cdoc::renderers::{RenderOutput, RenderResult};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use crate::project::config::ProjectConfig;
 use crate::project::{ItemDescriptor, Project};
 
+pub mod assets;
+pub mod epub;
+pub mod feed;
 pub mod html;
 pub(crate) mod info;
 pub mod notebook;
+pub mod pdf;
 
 #[derive(Clone)]
 pub struct GeneratorContext {
@@ -15,16 +23,172 @@ pub struct GeneratorContext {
     pub project: Project<Option<Document<RenderResult>>>,
     pub config: ProjectConfig,
     pub build_dir: PathBuf,
+    pub post_render: PostRenderHooks,
+}
+
+/// A function that transforms rendered output right before it is written (e.g. a minifier). It
+/// receives the output format so it can be format-specific.
+pub type PostRenderHook = Arc<dyn Fn(&OutputFormat, &str) -> anyhow::Result<String> + Send + Sync>;
+
+/// Post-render hooks that are applied in the order they were added.
+#[derive(Clone, Default)]
+pub struct PostRenderHooks(Vec<PostRenderHook>);
+
+impl PostRenderHooks {
+    pub fn push(&mut self, hook: PostRenderHook) {
+        self.0.push(hook);
+    }
+
+    /// Run the output through all hooks, each receiving the output of the previous one.
+    pub fn apply(&self, format: &OutputFormat, output: String) -> anyhow::Result<String> {
+        self.0
+            .iter()
+            .try_fold(output, |output, hook| hook(format, &output))
+    }
+
+    /// Like [PostRenderHooks::apply] for renderer output. Binary output is returned unchanged
+    /// since the hooks work on text.
+    pub fn apply_output(
+        &self,
+        format: &OutputFormat,
+        output: RenderOutput,
+    ) -> anyhow::Result<RenderOutput> {
+        match output {
+            RenderOutput::Text(text) => Ok(RenderOutput::Text(self.apply(format, text)?)),
+            bytes => Ok(bytes),
+        }
+    }
+}
+
+/// Write rendered output to `path`, creating its folder. Text is run through the post-render hooks
+/// of the format first while binary output (e.g. a PDF) is written as is.
+pub fn write_output(
+    ctx: &GeneratorContext,
+    format: &OutputFormat,
+    output: RenderOutput,
+    path: &Path,
+) -> anyhow::Result<()> {
+    let output = ctx.post_render.apply_output(format, output)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).context("Could not create directory")?;
+    }
+    fs::write(path, output).with_context(|| format!("Could not write {}", path.display()))
+}
+
+/// Resolve `.` and `..` in a `/`-separated path.
+fn normalize(path: &str) -> String {
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            s => segments.push(s),
+        }
+    }
+    segments.join("/")
+}
+
+/// Identifies a page by its path relative to the content folder, without extension and without a
+/// trailing `index` (so that links to a folder and to its index page are the same).
+pub(crate) fn page_key(path: &str) -> String {
+    let path = normalize(path);
+    let path = path
+        .strip_suffix(".html")
+        .or_else(|| path.strip_suffix(".xhtml"))
+        .unwrap_or(&path);
+    match path.strip_suffix("index") {
+        Some(p) if p.is_empty() || p.ends_with('/') => p.trim_end_matches('/').to_string(),
+        _ => path.to_string(),
+    }
+}
+
+/// The path of a link relative to the content folder. Absolute links must start with the url
+/// prefix. Returns None for external links and links within the same page.
+pub(crate) fn content_path(link: &str, doc_dir: &str, url_prefix: &str) -> Option<String> {
+    if link.is_empty()
+        || link.starts_with('#')
+        || link.contains("://")
+        || link.starts_with("mailto:")
+        || link.starts_with("data:")
+    {
+        return None;
+    }
+    if link.starts_with('/') {
+        let path = link
+            .strip_prefix(url_prefix)
+            .filter(|p| p.is_empty() || p.starts_with('/'))
+            .unwrap_or(link);
+        Some(normalize(path))
+    } else {
+        Some(normalize(&format!("{}/{}", doc_dir, link)))
+    }
+}
+
+/// The page key of the web page of a document, which links to the document resolve to.
+pub(crate) fn document_key(config: &ProjectConfig, doc_path: &Path) -> String {
+    let url = config.page_url(doc_path);
+    page_key(&content_path(&url, "", &config.url_prefix).unwrap_or_default())
 }
 
 pub trait Generator {
     fn generate(&self, ctx: GeneratorContext) -> anyhow::Result<()>;
+    /// Set up the build directory (e.g. copy resources) before documents are generated one at a
+    /// time with [Generator::generate_single]. This is already done by [Generator::generate].
+    fn prepare(&self, _ctx: &GeneratorContext) -> anyhow::Result<()> {
+        Ok(())
+    }
     fn generate_single(
         &self,
         content: Document<RenderResult>,
         doc_info: ItemDescriptor<()>,
-        ctx: GeneratorContext,
+        ctx: &GeneratorContext,
         // config: ProjectConfig,
         // build_dir: PathBuf,
     ) -> anyhow::Result<()>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_post_render_hooks_chain() {
+        let mut hooks = PostRenderHooks::default();
+        hooks.push(Arc::new(|_, s| Ok(s.trim().to_string())));
+        hooks.push(Arc::new(|format, s| Ok(format!("{}:{}", format, s))));
+
+        let res = hooks
+            .apply(&OutputFormat::Html, "  content  ".to_string())
+            .unwrap();
+        assert_eq!(res, "html:content");
+
+        let bytes = RenderOutput::Bytes(vec![0, 1]);
+        let res = hooks.apply_output(&OutputFormat::Html, bytes.clone());
+        assert_eq!(res.unwrap(), bytes);
+
+        hooks.push(Arc::new(|_, _| Err(anyhow::anyhow!("failed"))));
+        assert!(hooks.apply(&OutputFormat::Html, "".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_link_helpers() {
+        assert_eq!(page_key("part/chapter/index"), "part/chapter");
+        assert_eq!(page_key("part/chapter/"), "part/chapter");
+        assert_eq!(page_key("part/chapter/../other/doc.html"), "part/other/doc");
+        assert_eq!(page_key("index"), "");
+        assert_eq!(page_key("part/reindex"), "part/reindex");
+
+        assert_eq!(
+            content_path("/site/part/doc", "x", "/site"),
+            Some("part/doc".to_string())
+        );
+        assert_eq!(
+            content_path("img.png", "part/chapter", "/site"),
+            Some("part/chapter/img.png".to_string())
+        );
+        assert_eq!(content_path("https://example.com", "", ""), None);
+        assert_eq!(content_path("#anchor", "", ""), None);
+    }
+}
@@ -1,12 +1,12 @@
-use std::fs;
 use std::ops::Deref;
 
 use indicatif::{ProgressBar, ProgressStyle};
 
+use cdoc::config::OutputFormat;
 use cdoc::document::Document;
 use cdoc::renderers::RenderResult;
 
-use crate::generators::{Generator, GeneratorContext};
+use crate::generators::{write_output, Generator, GeneratorContext};
 use crate::project::ItemDescriptor;
 
 pub struct CodeOutputGenerator;
@@ -19,7 +19,7 @@ impl Generator for CodeOutputGenerator {
         let pb = ProgressBar::new(0);
         pb.set_style(spinner);
 
-        for item in ctx.project {
+        for item in ctx.project.clone() {
             if let Some(c) = item.doc.content.deref() {
                 pb.set_message(format!("{}", item.doc.path.display()));
                 pb.inc(1);
@@ -28,8 +28,12 @@ impl Generator for CodeOutputGenerator {
                 notebook_build_dir.pop(); // Pop filename
                 let notebook_build_path = notebook_build_dir.join(format!("{}.ipynb", item.doc.id));
 
-                fs::create_dir_all(notebook_build_dir)?;
-                fs::write(notebook_build_path, &c.content)?;
+                write_output(
+                    &ctx,
+                    &OutputFormat::Notebook,
+                    c.content.clone(),
+                    &notebook_build_path,
+                )?;
             }
         }
 
@@ -43,14 +47,18 @@ impl Generator for CodeOutputGenerator {
         &self,
         content: Document<RenderResult>,
         doc_info: ItemDescriptor<()>,
-        ctx: GeneratorContext,
+        ctx: &GeneratorContext,
     ) -> anyhow::Result<()> {
         let mut notebook_build_dir = ctx.build_dir.as_path().join(&doc_info.doc.path);
         notebook_build_dir.pop(); // Pop filename
         let notebook_build_path = notebook_build_dir.join(format!("{}.ipynb", doc_info.doc.id));
 
-        fs::create_dir_all(notebook_build_dir)?;
-        fs::write(notebook_build_path, content.content)?;
+        write_output(
+            ctx,
+            &OutputFormat::Notebook,
+            content.content,
+            &notebook_build_path,
+        )?;
 
         Ok(())
     }
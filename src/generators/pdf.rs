@@ -0,0 +1,489 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::ops::Deref;
+use std::path::Path;
+use std::process::Command;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Context};
+use serde::Serialize;
+use tera::Tera;
+
+use cdoc::config::OutputFormat;
+use cdoc::document::Document;
+use cdoc::processors::links::rewrite_html;
+use cdoc::renderers::{RenderOutput, RenderResult};
+
+use crate::generators::{
+    content_path, document_key, page_key, write_output, Generator, GeneratorContext,
+};
+use crate::project::config::{PdfConfig, PdfEngine};
+use crate::project::ItemDescriptor;
+
+/// Name of an optional project template for the PDF layout.
+const TEMPLATE_NAME: &str = "pdf.tera.html";
+
+/// Location of the KaTeX stylesheet when it is bundled with the project (the `dist` folder of
+/// KaTeX copied to `resources/katex`, which includes its fonts).
+const KATEX_BUNDLED: &str = "resources/katex/katex.min.css";
+
+/// KaTeX stylesheet used when the project doesn't bundle it.
+const KATEX_CDN: &str = "https://cdn.jsdelivr.net/npm/katex@0.16.3/dist/katex.min.css";
+
+/// Layout used when the project has no PDF template.
+const DEFAULT_TEMPLATE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{{ title }}</title>
+{% if katex_stylesheet %}<link rel="stylesheet" href="{{ katex_stylesheet }}">
+{% endif %}{% for stylesheet in stylesheets %}<link rel="stylesheet" href="{{ stylesheet }}">
+{% endfor %}<style>
+.page-break { break-before: page; page-break-before: always; }
+</style>
+</head>
+<body>
+{% for doc in documents %}<section id="{{ doc.id }}"{% if doc.page_break %} class="page-break"{% endif %}>
+{{ doc.html | safe }}
+</section>
+{% endfor %}</body>
+</html>
+"#;
+
+/// A document in the PDF. Parts and chapters start on a new page.
+#[derive(Serialize, Debug)]
+pub struct PdfDocument {
+    /// Anchor of the document in the combined file. The ids in the document are prefixed with it.
+    pub id: String,
+    pub title: Option<String>,
+    pub html: String,
+    pub page_break: bool,
+}
+
+/// Combines all documents into a single HTML file and converts it to PDF with an external
+/// program (see [PdfEngine]).
+pub struct PdfGenerator {
+    tera: Arc<Tera>,
+}
+
+impl PdfGenerator {
+    pub fn new(tera: Arc<Tera>) -> Self {
+        PdfGenerator { tera }
+    }
+
+    /// The documents in reading order. Since they are combined in one file, links to documents
+    /// become links to their anchors and the ids of each document are prefixed with its anchor so
+    /// they stay unique. Other links and images are relative to the location of the document in
+    /// the build folder, so they are changed to absolute `file://` URLs.
+    fn documents(ctx: &GeneratorContext) -> Vec<PdfDocument> {
+        let items: Vec<_> = ctx
+            .project
+            .clone()
+            .into_iter()
+            .filter(|item| {
+                item.doc
+                    .content
+                    .deref()
+                    .as_ref()
+                    .is_some_and(|doc| doc.content.as_text().is_some())
+            })
+            .collect();
+
+        // Anchor of each document by page key.
+        let anchors: HashMap<String, String> = items
+            .iter()
+            .enumerate()
+            .map(|(idx, item)| {
+                let key = document_key(&ctx.config, &item.doc.path);
+                (key, format!("doc-{}", idx + 1))
+            })
+            .collect();
+
+        let mut documents: Vec<PdfDocument> = Vec::new();
+        for (idx, item) in items.iter().enumerate() {
+            let doc = item.doc.content.deref().as_ref().expect("Filtered");
+            let html = doc.content.as_text().expect("Filtered");
+            let id = format!("doc-{}", idx + 1);
+            let doc_dir = item
+                .doc
+                .path
+                .parent()
+                .map(|p| p.to_string_lossy().to_string())
+                .unwrap_or_default();
+
+            let html = rewrite_html(html, &["id"], |value| Some(format!("{}-{}", id, value)));
+            let html = rewrite_html(&html, &["href"], |link| {
+                if let Some(fragment) = link.strip_prefix('#') {
+                    return Some(format!("#{}-{}", id, fragment));
+                }
+                let (path, fragment) = link.split_once('#').unwrap_or((link, ""));
+                let anchor = content_path(path, &doc_dir, &ctx.config.url_prefix)
+                    .and_then(|path| anchors.get(&page_key(&path)));
+                match (anchor, fragment) {
+                    (Some(anchor), "") => Some(format!("#{}", anchor)),
+                    (Some(anchor), fragment) => Some(format!("#{}-{}", anchor, fragment)),
+                    (None, _) => {
+                        file_url(link, &item.doc.path, &ctx.build_dir, &ctx.config.url_prefix)
+                    }
+                }
+            });
+            let html = rewrite_html(&html, &["src"], |link| {
+                file_url(link, &item.doc.path, &ctx.build_dir, &ctx.config.url_prefix)
+            });
+            documents.push(PdfDocument {
+                id,
+                title: doc.metadata.title.clone(),
+                html,
+                page_break: !documents.is_empty() && item.doc_idx.is_none(),
+            });
+        }
+        documents
+    }
+
+    /// Render the combined HTML using the project's `pdf.tera.html` template if it exists.
+    pub fn render_html(&self, ctx: &GeneratorContext) -> anyhow::Result<String> {
+        let documents = Self::documents(ctx);
+        let mut context = tera::Context::new();
+        context.insert("project", &ctx.config);
+        context.insert("var", &ctx.config.vars);
+        context.insert(
+            "title",
+            &ctx.project
+                .index
+                .content
+                .deref()
+                .as_ref()
+                .and_then(|doc| doc.metadata.title.clone()),
+        );
+        context.insert("katex_stylesheet", &katex_stylesheet(ctx));
+        context.insert("stylesheets", &ctx.config.pdf.stylesheets);
+        context.insert("documents", &documents);
+
+        if self.tera.get_template_names().any(|n| n == TEMPLATE_NAME) {
+            Ok(self.tera.render(TEMPLATE_NAME, &context)?)
+        } else {
+            Ok(Tera::one_off(DEFAULT_TEMPLATE, &context, true)?)
+        }
+    }
+}
+
+/// The KaTeX stylesheet if the PDF parser renders math with KaTeX. The copy bundled in the
+/// project's resources is preferred so that the build doesn't need the network.
+fn katex_stylesheet(ctx: &GeneratorContext) -> Option<&'static str> {
+    let parser = ctx.config.parsers.get(&OutputFormat::Pdf)?;
+    if !parser.uses_katex() {
+        None
+    } else if ctx.root.join(KATEX_BUNDLED).is_file() {
+        Some(KATEX_BUNDLED)
+    } else {
+        Some(KATEX_CDN)
+    }
+}
+
+/// Absolute `file://` URL in the build folder `base` of a relative or root-relative link in the
+/// document at `doc_path`. Root-relative links may start with the url prefix. Returns None for
+/// other links (external, protocol-relative or only a fragment or query).
+fn file_url(link: &str, doc_path: &Path, base: &Path, url_prefix: &str) -> Option<String> {
+    let scheme = link
+        .find(':')
+        .is_some_and(|i| !link[..i].contains(['/', '?', '#']));
+    if link.is_empty() || link.starts_with(['#', '?']) || link.starts_with("//") || scheme {
+        return None;
+    }
+    // The link is already a URL, only the folder it is in needs to be encoded.
+    let (dir, link) = match link.strip_prefix('/') {
+        Some(root) => {
+            let root = match root.strip_prefix(url_prefix.trim_start_matches('/')) {
+                Some(rest)
+                    if !url_prefix.is_empty() && (rest.is_empty() || rest.starts_with('/')) =>
+                {
+                    rest.trim_start_matches('/')
+                }
+                _ => root,
+            };
+            (base.to_path_buf(), root)
+        }
+        None => (base.join(doc_path.parent().unwrap_or(Path::new(""))), link),
+    };
+    Some(format!("file://{}/{}", encode_path(&dir), link))
+}
+
+/// Percent-encode a file path for use in a URL. Path separators are kept.
+fn encode_path(path: &Path) -> String {
+    let path = path.to_string_lossy();
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.trim_end_matches('/').bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            byte => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Run the PDF engine on an HTML file and return the PDF.
+pub fn convert(config: &PdfConfig, html_path: &Path) -> anyhow::Result<RenderOutput> {
+    let program = config
+        .command
+        .as_deref()
+        .unwrap_or_else(|| config.engine.default_command());
+    // The engine writes to a temporary file that is read back as the output of the generator.
+    let pdf_file = tempfile::Builder::new().suffix(".pdf").tempfile()?;
+    let pdf_path = pdf_file.path();
+
+    let mut command = Command::new(program);
+    match config.engine {
+        PdfEngine::Weasyprint => command.arg(html_path).arg(pdf_path),
+        PdfEngine::Wkhtmltopdf => command
+            .arg("--enable-local-file-access")
+            .arg(html_path)
+            .arg(pdf_path),
+        PdfEngine::Chromium => command
+            .args(["--headless", "--disable-gpu", "--no-pdf-header-footer"])
+            .arg(format!("--print-to-pdf={}", pdf_path.display()))
+            .arg(format!("file://{}", html_path.canonicalize()?.display())),
+    };
+
+    let output = command.output().map_err(|e| match e.kind() {
+        ErrorKind::NotFound => anyhow!(
+            "PDF engine '{}' was not found. Install it or set the path of the executable with the pdf.command option",
+            program
+        ),
+        _ => anyhow!(e).context(format!("Could not run PDF engine '{}'", program)),
+    })?;
+
+    if !output.status.success() {
+        bail!(
+            "PDF engine '{}' failed ({}): {}",
+            program,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(RenderOutput::Bytes(fs::read(pdf_path)?))
+}
+
+impl Generator for PdfGenerator {
+    fn generate(&self, ctx: GeneratorContext) -> anyhow::Result<()> {
+        let html = self.render_html(&ctx)?;
+
+        fs::create_dir_all(&ctx.build_dir).context("Could not create directory")?;
+        // Root-relative links to resources point into the build folder like for web pages.
+        let resources = ctx.root.join("resources");
+        if resources.is_dir() {
+            let mut options = fs_extra::dir::CopyOptions::new();
+            options.overwrite = true;
+            fs_extra::copy_items(&[resources], &ctx.build_dir, &options)?;
+        }
+        let pdf_path = ctx.build_dir.join(&ctx.config.pdf.file_name);
+        let html_path = pdf_path.with_extension("html");
+        write_output(&ctx, &OutputFormat::Pdf, html.into(), &html_path)?;
+
+        let pdf = convert(&ctx.config.pdf, &html_path)?;
+        write_output(&ctx, &OutputFormat::Pdf, pdf, &pdf_path)
+    }
+
+    fn prepare(&self, _ctx: &GeneratorContext) -> anyhow::Result<()> {
+        bail!("PDF output requires all documents and is not supported by streaming builds")
+    }
+
+    fn generate_single(
+        &self,
+        _content: Document<RenderResult>,
+        _doc_info: ItemDescriptor<()>,
+        ctx: &GeneratorContext,
+    ) -> anyhow::Result<()> {
+        self.generate(ctx.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project::config::ProjectConfig;
+    use crate::project::{Chapter, Part, Project, ProjectItem};
+    use cdoc::config::InputFormat;
+    use std::path::PathBuf;
+
+    fn page(path: &str, html: &str) -> ProjectItem<Option<Document<RenderResult>>> {
+        let mut doc = Document::<RenderResult>::default();
+        doc.metadata.title = Some(html.to_string());
+        doc.content = format!("<p>{}</p>", html).into();
+        ProjectItem {
+            id: "index".to_string(),
+            format: InputFormat::Markdown,
+            path: PathBuf::from(path),
+            content: Arc::new(Some(doc)),
+        }
+    }
+
+    fn context() -> GeneratorContext {
+        let config: ProjectConfig =
+            serde_yaml::from_str("outputs: []\nparsers: {}\ncustom: {}").unwrap();
+        GeneratorContext {
+            root: Default::default(),
+            project: Project {
+                project_path: Default::default(),
+                index: page("index.md", "Home"),
+                content: vec![Part {
+                    id: "part".to_string(),
+                    index: page("part/index.md", "Part"),
+                    chapters: vec![Chapter {
+                        id: "chapter".to_string(),
+                        index: page("part/chapter/index.md", "Chapter"),
+                        documents: vec![page("part/chapter/doc.md", "Doc")],
+                        files: vec![],
+                        chapters: vec![],
+                    }],
+                }],
+            },
+            config,
+            build_dir: Default::default(),
+            post_render: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_pdf_html() {
+        let html = PdfGenerator::new(Arc::new(Tera::default()))
+            .render_html(&context())
+            .unwrap();
+
+        assert!(html.contains("<title>Home</title>"));
+        assert!(!html.contains("katex.min.css"));
+        assert_eq!(html.matches("<section").count(), 4);
+        // The part and the chapter start on a new page, the documents in a chapter don't.
+        assert_eq!(html.matches(" class=\"page-break\">").count(), 2);
+        assert!(html.contains("<section id=\"doc-3\" class=\"page-break\">\n<p>Chapter</p>"));
+        assert!(html.contains("<section id=\"doc-4\">\n<p>Doc</p>"));
+    }
+
+    #[test]
+    fn test_katex_stylesheet() {
+        let root = tempfile::tempdir().unwrap();
+        let mut ctx = context();
+        ctx.root = root.path().to_path_buf();
+        let parser = |math: &str| {
+            serde_yaml::from_str(&format!(
+                "{{preprocessors: [{{type: {}}}], event_processors: [], settings: {{}}}}",
+                math
+            ))
+            .unwrap()
+        };
+
+        ctx.config
+            .parsers
+            .insert(OutputFormat::Pdf, parser("mathml"));
+        assert_eq!(katex_stylesheet(&ctx), None);
+
+        #[cfg(feature = "katex")]
+        {
+            ctx.config
+                .parsers
+                .insert(OutputFormat::Pdf, parser("katex"));
+            assert_eq!(katex_stylesheet(&ctx), Some(KATEX_CDN));
+
+            fs::create_dir_all(root.path().join("resources/katex")).unwrap();
+            fs::write(root.path().join(KATEX_BUNDLED), "").unwrap();
+            let html = PdfGenerator::new(Arc::new(Tera::default()))
+                .render_html(&ctx)
+                .unwrap();
+            // The template escapes the slashes of the link.
+            let link = tera::escape_html(KATEX_BUNDLED);
+            assert!(html.contains(&format!("<link rel=\"stylesheet\" href=\"{}\">", link)));
+        }
+    }
+
+    #[test]
+    fn test_pdf_template_vars() {
+        let mut tera = Tera::default();
+        tera.add_raw_template(TEMPLATE_NAME, "{{ var.audience }}")
+            .unwrap();
+        let mut ctx = context();
+        ctx.config
+            .set_vars(&["audience=student".to_string()])
+            .unwrap();
+
+        let html = PdfGenerator::new(Arc::new(tera)).render_html(&ctx).unwrap();
+        assert_eq!(html, "student");
+    }
+
+    #[test]
+    fn test_links() {
+        let mut ctx = context();
+        ctx.config.url_prefix = "/site".to_string();
+        let chapter = &mut ctx.project.content[0].chapters[0];
+        chapter.documents[0].content = Arc::new(Some(Document {
+            content: "<h2 id=\"top\">Top</h2><img src=\"plot.png\">\
+                <img src=\"/site/resources/logo.png\"><a href=\"data.csv\">w</a>\
+                <a href=\"index.html#sec\">x</a><a href=\"/site/part/\">y</a>\
+                <a href=\"#top\">z</a><a href=\"https://example.com\">e</a>"
+                .to_string()
+                .into(),
+            ..Default::default()
+        }));
+        ctx.build_dir = PathBuf::from("/build/pdf");
+
+        let html = PdfGenerator::new(Arc::new(Tera::default()))
+            .render_html(&ctx)
+            .unwrap();
+        // Links to documents and within a document point to anchors in the combined file.
+        assert!(html.contains("<section id=\"doc-4\">"));
+        assert!(html.contains("<h2 id=\"doc-4-top\">"));
+        assert!(html.contains("<a href=\"#doc-3-sec\">"));
+        assert!(html.contains("<a href=\"#doc-2\">"));
+        assert!(html.contains("<a href=\"#doc-4-top\">"));
+        assert!(html.contains("<a href=\"https://example.com\">"));
+        // Images and other files are linked in the build folder.
+        assert!(html.contains("<img src=\"file:///build/pdf/part/chapter/plot.png\">"));
+        assert!(html.contains("<img src=\"file:///build/pdf/resources/logo.png\">"));
+        assert!(html.contains("<a href=\"file:///build/pdf/part/chapter/data.csv\">"));
+    }
+
+    #[test]
+    fn test_file_url_encoding() {
+        let url = file_url(
+            "plot.png",
+            Path::new("part/doc.md"),
+            Path::new("/my courses/#1 100%/pdf"),
+            "",
+        );
+        assert_eq!(
+            url.as_deref(),
+            Some("file:///my%20courses/%231%20100%25/pdf/part/plot.png")
+        );
+    }
+
+    #[test]
+    fn test_post_render_hooks() {
+        let root = tempfile::tempdir().unwrap();
+        let mut ctx = context();
+        ctx.build_dir = root.path().join("pdf");
+        // The engine is replaced by a program that does nothing.
+        ctx.config.pdf.command = Some("true".to_string());
+        ctx.post_render.push(Arc::new(|format, output| {
+            Ok(format!("<!-- {} -->{}", format, output))
+        }));
+
+        PdfGenerator::new(Arc::new(Tera::default()))
+            .generate(ctx)
+            .unwrap();
+        let html = fs::read_to_string(root.path().join("pdf/book.html")).unwrap();
+        assert!(html.starts_with("<!-- pdf -->"));
+        // The (empty) output of the engine is written unchanged.
+        assert_eq!(fs::read(root.path().join("pdf/book.pdf")).unwrap(), b"");
+    }
+
+    #[test]
+    fn test_missing_engine() {
+        let config = PdfConfig {
+            command: Some("courses-missing-pdf-engine".to_string()),
+            ..Default::default()
+        };
+        let err = convert(&config, Path::new("book.html")).unwrap_err();
+        assert!(err.to_string().contains("was not found"));
+    }
+}
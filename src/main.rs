@@ -5,7 +5,7 @@ use std::path::PathBuf;
 use std::time::Duration;
 use std::{env, fs};
 
-use anyhow::Context;
+use anyhow::{anyhow, Context};
 use clap::{Parser, Subcommand};
 use console::style;
 use inquire::{InquireError, Select};
@@ -14,6 +14,10 @@ use notify_debouncer_mini::{
     new_debouncer_opt, DebounceEventResult, DebouncedEventKind, Debouncer,
 };
 use penguin::Server;
+use tracing::Level;
+use tracing_subscriber::fmt::{self, writer::MakeWriterExt};
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::EnvFilter;
 
 use courses::pipeline::Pipeline;
 use courses::project::config::ProjectConfig;
@@ -35,12 +39,39 @@ enum Commands {
         path: Option<PathBuf>,
         #[arg(short, long, default_value = "dev")]
         mode: String,
+        /// Set a build variable (e.g. `--var audience=student`). Can be repeated.
+        #[arg(long = "var", value_name = "NAME=VALUE")]
+        vars: Vec<String>,
     },
     Build {
         #[arg(short, long)]
         path: Option<PathBuf>,
         #[arg(short, long, default_value = "release")]
         mode: String,
+        /// Write each document as soon as it is processed instead of keeping the whole project
+        /// in memory.
+        #[arg(long)]
+        streaming: bool,
+        /// Fail the build if there are any errors or warnings (e.g. in CI).
+        #[arg(long)]
+        deny_warnings: bool,
+        /// Set a build variable (e.g. `--var audience=student`). Can be repeated.
+        #[arg(long = "var", value_name = "NAME=VALUE")]
+        vars: Vec<String>,
+    },
+    /// Process every document and report the problems without writing any output (e.g. in a
+    /// pre-commit hook). Fails if there are errors.
+    Check {
+        #[arg(short, long)]
+        path: Option<PathBuf>,
+        #[arg(short, long, default_value = "release")]
+        mode: String,
+        /// Also fail if there are warnings.
+        #[arg(long)]
+        deny_warnings: bool,
+        /// Set a build variable (e.g. `--var audience=student`). Can be repeated.
+        #[arg(long = "var", value_name = "NAME=VALUE")]
+        vars: Vec<String>,
     },
     Init {
         name: Option<String>,
@@ -55,35 +86,88 @@ async fn cli_run() -> anyhow::Result<()> {
     let cli = Cli::parse();
 
     match cli.command {
-        Commands::Build { path, mode } => {
-            let path = path.unwrap_or(env::current_dir()?);
+        Commands::Build {
+            path,
+            mode,
+            streaming,
+            deny_warnings,
+            vars,
+        } => {
+            let path = std::path::absolute(path.unwrap_or(env::current_dir()?))?;
+
+            let config_path = path.join("config.yml");
+            let config_input = fs::read_to_string(config_path)?;
+            let mut config: ProjectConfig = serde_yaml::from_str(&config_input)
+                .context("Could not load project configuration")?;
+            config.set_vars(&vars)?;
 
             print!("Configuring project...");
-            let proj = Project::generate_from_directory(path.as_path())?;
+            let proj =
+                Project::generate_from_directory_filtered(path.as_path(), &config.files.build()?)?;
             println!(" {}", style("done").green());
 
+            let mut pipeline = Pipeline::new(path.as_path(), mode, config, proj)?;
+            let report = if streaming {
+                pipeline.build_all_streaming(true)?
+            } else {
+                pipeline.build_all(true)?
+            };
+            if deny_warnings && !report.diagnostics.is_empty() {
+                return Err(anyhow!(
+                    "Build failed with {} errors and {} warnings",
+                    report.errors().count(),
+                    report.warnings().count()
+                ));
+            }
+
+            println!("🌟 Done.");
+            Ok(())
+        }
+        Commands::Check {
+            path,
+            mode,
+            deny_warnings,
+            vars,
+        } => {
+            let path = std::path::absolute(path.unwrap_or(env::current_dir()?))?;
+
             let config_path = path.join("config.yml");
             let config_input = fs::read_to_string(config_path)?;
-            let config: ProjectConfig = serde_yaml::from_str(&config_input)
+            let mut config: ProjectConfig = serde_yaml::from_str(&config_input)
                 .context("Could not load project configuration")?;
+            config.set_vars(&vars)?;
+
+            let proj =
+                Project::generate_from_directory_filtered(path.as_path(), &config.files.build()?)?;
 
             let mut pipeline = Pipeline::new(path.as_path(), mode, config, proj)?;
-            pipeline.build_all(true)?;
+            let report = pipeline.check_all()?;
+            let errors = report.errors().count();
+            let warnings = report.warnings().count();
+            if errors > 0 || (deny_warnings && warnings > 0) {
+                return Err(anyhow!(
+                    "Check failed with {} errors and {} warnings",
+                    errors,
+                    warnings
+                ));
+            }
 
             println!("🌟 Done.");
             Ok(())
         }
-        Commands::Serve { path, mode } => {
-            let path = path.unwrap_or(env::current_dir()?);
-
-            print!("Configuring project...");
-            let proj = Project::generate_from_directory(path.as_path())?;
-            println!(" {}", style("done").green());
+        Commands::Serve { path, mode, vars } => {
+            let path = std::path::absolute(path.unwrap_or(env::current_dir()?))?;
 
             let config_path = path.join("config.yml");
             let config_input = fs::read_to_string(config_path)?;
-            let config: ProjectConfig = serde_yaml::from_str(&config_input)
+            let mut config: ProjectConfig = serde_yaml::from_str(&config_input)
                 .context("Could not load project configuration")?;
+            config.set_vars(&vars)?;
+
+            print!("Configuring project...");
+            let proj =
+                Project::generate_from_directory_filtered(path.as_path(), &config.files.build()?)?;
+            println!(" {}", style("done").green());
 
             let mut pipeline =
                 Pipeline::new(path.as_path(), mode.clone(), config.clone(), proj.clone())?;
@@ -93,7 +177,7 @@ async fn cli_run() -> anyhow::Result<()> {
 
             let p2 = path.as_path().join("content");
             let tp = path.as_path().join("templates");
-            let p_build = path.as_path().join("build/html");
+            let p_build = config.resolve_build_path(&path).join("html");
 
             let (server, controller) = Server::bind(([127, 0, 0, 1], 8000).into())
                 .add_mount(config.url_prefix.clone(), p_build)?
@@ -190,7 +274,7 @@ async fn cli_run() -> anyhow::Result<()> {
     }
 }
 
-fn err_print(res: anyhow::Result<()>) {
+fn err_print<T>(res: anyhow::Result<T>) {
     match res {
         Ok(_) => {}
         Err(e) => {
@@ -202,7 +286,35 @@ fn err_print(res: anyhow::Result<()>) {
     }
 }
 
+/// Log output up to `INFO` is printed to stdout without decorations since it doubles as the CLI
+/// progress output. Warnings and errors are printed to stderr with their (coloured) level. The
+/// verbosity can be changed using the `RUST_LOG` environment variable (e.g. `RUST_LOG=debug`).
+fn init_logging() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let progress = fmt::layer()
+        .without_time()
+        .with_target(false)
+        .with_level(false)
+        .with_writer(std::io::stdout.with_min_level(Level::INFO));
+    let problems = fmt::layer()
+        .without_time()
+        .with_target(false)
+        .with_ansi(console::colors_enabled_stderr())
+        .with_writer(std::io::stderr.with_max_level(Level::WARN));
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(progress)
+        .with(problems)
+        .init();
+}
+
 #[tokio::main]
 async fn main() {
-    err_print(cli_run().await)
+    init_logging();
+    let res = cli_run().await;
+    let failed = res.is_err();
+    err_print(res);
+    if failed {
+        std::process::exit(1);
+    }
 }
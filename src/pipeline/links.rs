@@ -0,0 +1,223 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use cdoc::ast::{AEvent, ATag};
+use cdoc::document::{DocPos, Document, EventContent};
+
+use crate::project::config::UrlStyle;
+
+/// Extensions of link targets that are documents. Links to other files (e.g. images) are not
+/// checked since they don't have to be part of the project.
+const PAGE_EXTENSIONS: [&str; 3] = ["html", "md", "ipynb"];
+
+/// Location of the web page of a document relative to the build folder, with `/` separators.
+fn page_key(url_style: UrlStyle, doc_path: &Path) -> String {
+    url_style
+        .page_path(doc_path)
+        .iter()
+        .map(|c| c.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// The URLs of the links in a document with their positions.
+pub(crate) fn document_links(doc: &Document<EventContent>) -> Vec<(String, DocPos)> {
+    doc.content
+        .iter()
+        .filter_map(|(event, pos)| match event {
+            AEvent::Start(ATag::Link(_, url, _)) => Some((url.clone(), pos.clone())),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The ids in a document that links can point to: its headings (including a removed title
+/// heading), section numbers and the `id` attributes of raw HTML.
+pub(crate) fn document_anchors(doc: &Document<EventContent>) -> HashSet<String> {
+    let mut anchors: HashSet<String> = doc
+        .variables
+        .headings
+        .iter()
+        .map(|h| h.slug.clone())
+        .collect();
+    anchors.extend(doc.variables.sections.iter().map(|s| s.id.clone()));
+    for (event, _) in &doc.content {
+        if let AEvent::Html(html) = event {
+            let mut rest = html.as_str();
+            while let Some(start) = rest.find(" id=\"") {
+                rest = &rest[start + 5..];
+                let end = rest.find('"').unwrap_or(rest.len());
+                anchors.insert(rest[..end].to_string());
+                rest = &rest[end..];
+            }
+        }
+    }
+    anchors
+}
+
+/// The web pages of a project and the anchors in them, used to find broken links between them.
+#[derive(Debug)]
+pub(crate) struct LinkTargets {
+    url_style: UrlStyle,
+    url_prefix: String,
+    pages: HashMap<String, HashSet<String>>,
+}
+
+impl LinkTargets {
+    pub fn new(url_style: UrlStyle, url_prefix: &str) -> Self {
+        LinkTargets {
+            url_style,
+            url_prefix: url_prefix.to_string(),
+            pages: HashMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, doc_path: &Path, anchors: HashSet<String>) {
+        self.pages
+            .insert(page_key(self.url_style, doc_path), anchors);
+    }
+
+    /// Check a link in the document at `doc_path`. Links are resolved like a browser would from
+    /// the web page of the document. Returns the problem if the link points to a page that doesn't
+    /// exist or to an anchor that isn't in the page. External links and links to files other than
+    /// pages are not checked.
+    pub fn check(&self, doc_path: &Path, url: &str) -> Option<String> {
+        let page = page_key(self.url_style, doc_path);
+        let (target, fragment) = self.resolve(&page, url)?;
+        let extension = target.rsplit('/').next()?.rsplit_once('.')?.1;
+        if !PAGE_EXTENSIONS.contains(&extension) {
+            return None;
+        }
+
+        match (self.pages.get(&target), fragment) {
+            (None, _) => Some(format!(
+                "Broken link `{}`: there is no page {}",
+                url, target
+            )),
+            (Some(anchors), Some(fragment)) if !anchors.contains(fragment) => Some(format!(
+                "Broken link `{}`: {} has no heading or element with id `{}`",
+                url, target, fragment
+            )),
+            _ => None,
+        }
+    }
+
+    /// The page (relative to the build folder) and the fragment that a link from `page` points to.
+    /// Returns None for external links.
+    fn resolve<'a>(&self, page: &str, url: &'a str) -> Option<(String, Option<&'a str>)> {
+        let (path, fragment) = match url.split_once('#') {
+            Some((path, fragment)) => (path, Some(fragment)),
+            None => (url, None),
+        };
+        let path = path.split('?').next().unwrap_or_default();
+        let scheme = path.find(':').is_some_and(|i| !path[..i].contains('/'));
+        if scheme || path.starts_with("//") {
+            return None;
+        }
+        if path.is_empty() {
+            return Some((page.to_string(), fragment));
+        }
+
+        let (base, path) = match path.strip_prefix('/') {
+            Some(root) => {
+                let prefix = self.url_prefix.trim_start_matches('/');
+                let root = match root.strip_prefix(prefix) {
+                    Some(rest)
+                        if !prefix.is_empty() && (rest.is_empty() || rest.starts_with('/')) =>
+                    {
+                        rest.trim_start_matches('/')
+                    }
+                    _ => root,
+                };
+                ("", root)
+            }
+            None => (page.rsplit_once('/').map_or("", |(dir, _)| dir), path),
+        };
+
+        let mut segments: Vec<&str> = base.split('/').filter(|s| !s.is_empty()).collect();
+        for segment in path.split('/') {
+            match segment {
+                "" | "." => {}
+                // Links above the build folder are kept so they can't match a page.
+                ".." if segments.last().is_some_and(|s| *s != "..") => {
+                    segments.pop();
+                }
+                segment => segments.push(segment),
+            }
+        }
+        // Folders are served by their index page.
+        let is_folder = path.ends_with('/')
+            || segments
+                .last()
+                .is_none_or(|last| !last.contains('.') || *last == "..");
+        if is_folder {
+            segments.push("index.html");
+        }
+        Some((segments.join("/"), fragment))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn targets(url_style: UrlStyle) -> LinkTargets {
+        let mut targets = LinkTargets::new(url_style, "/courses");
+        targets.insert(Path::new("index.md"), HashSet::new());
+        targets.insert(
+            Path::new("part/chapter/index.md"),
+            HashSet::from(["intro".to_string()]),
+        );
+        targets.insert(
+            Path::new("part/chapter/doc.md"),
+            HashSet::from(["usage".to_string()]),
+        );
+        targets
+    }
+
+    #[test]
+    fn test_check_flat() {
+        let targets = targets(UrlStyle::Flat);
+        let check = |url: &str| targets.check(Path::new("part/chapter/doc.md"), url);
+
+        assert_eq!(check("#usage"), None);
+        assert_eq!(check("index.html#intro"), None);
+        assert_eq!(check("./"), None);
+        assert_eq!(check("../../index.html"), None);
+        assert_eq!(check("/courses/part/chapter/doc.html"), None);
+        assert_eq!(check("/part/chapter/doc.html?x=1#usage"), None);
+        assert_eq!(check("https://example.com/missing.html"), None);
+        assert_eq!(check("plot.png"), None);
+
+        assert_eq!(
+            check("missing.html"),
+            Some(
+                "Broken link `missing.html`: there is no page part/chapter/missing.html"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            check("#missing"),
+            Some(
+                "Broken link `#missing`: part/chapter/doc.html has no heading or element with id `missing`"
+                    .to_string()
+            )
+        );
+        assert!(check("doc.md").is_some());
+        assert!(check("../../../index.html").is_some());
+    }
+
+    #[test]
+    fn test_check_pretty() {
+        let targets = targets(UrlStyle::Pretty);
+        let check = |url: &str| targets.check(Path::new("part/chapter/doc.md"), url);
+
+        // Relative links of pretty pages are one folder deeper (see [UrlStyle::relative_prefix]).
+        assert_eq!(check("../#intro"), None);
+        assert_eq!(check("../index.html"), None);
+        assert_eq!(check("/courses/part/chapter/doc/#usage"), None);
+        assert_eq!(check("/part/chapter/doc"), None);
+        assert!(check("../doc.html").is_some());
+        assert!(check("../missing/").is_some());
+    }
+}
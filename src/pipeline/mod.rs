@@ -1,27 +1,50 @@
 use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use anyhow::{anyhow, Context};
 use console::style;
 use indicatif::{ProgressBar, ProgressStyle};
+use serde::Serialize;
+use serde_yaml::Value;
 use tera::Tera;
-
-use cdoc::config::OutputFormat;
-use cdoc::document::Document;
-use cdoc::processors::PreprocessorContext;
+use tracing::{debug, debug_span, error, info, warn};
+
+use cdoc::config::{InputFormat, OutputFormat};
+use cdoc::document::{
+    merge_front_matter, Document, DocumentMetadata, DocumentVariables, EventContent,
+    IteratorConfig, RawContent, SourcePosition,
+};
+use cdoc::loader::Loader;
+use cdoc::parser::Parser;
+use cdoc::processors::links::LinkPrefix;
+use cdoc::processors::numbering::{
+    count_sections, format_number, ListingNumbers, Numbering, NumberingScope, SectionNumber,
+};
+use cdoc::processors::shortcodes;
+use cdoc::processors::{EventPreprocessor, PreprocessorContext, TemplateUsage, Warnings};
 use cdoc::renderers::RenderResult;
+use links::LinkTargets;
 use mover::{MoveContext, Mover};
+use report::{BuildReport, Diagnostic, DocumentFailed, Severity, ShortcodeUsage};
 
-use crate::generators::html::HtmlGenerator;
+use crate::generators::epub::EpubGenerator;
+use crate::generators::html::{self, HtmlGenerator};
 use crate::generators::info::InfoGenerator;
 use crate::generators::notebook::CodeOutputGenerator;
-use crate::generators::{Generator, GeneratorContext};
-use crate::project::config::ProjectConfig;
-use crate::project::{section_id, ItemDescriptor, Part, Project, ProjectItem};
-
+use crate::generators::pdf::PdfGenerator;
+use crate::generators::{Generator, GeneratorContext, PostRenderHooks};
+use crate::project::config::{EmptyDocumentAction, ProjectConfig};
+use crate::project::{
+    section_id, Chapter, ItemDescriptor, Part, Project, ProjectItem, Transform, TransformParents,
+};
+
+mod links;
 mod mover;
+pub mod report;
+
+type RenderedProject = Project<Option<Document<RenderResult>>>;
 
 pub struct Pipeline {
     #[allow(unused)]
@@ -29,24 +52,228 @@ pub struct Pipeline {
     project_path: PathBuf,
     project: Project<()>,
     project_config: ProjectConfig,
-    base_tera: Tera,
-    shortcode_tera: Tera,
+    base_tera: Arc<Tera>,
+    shortcode_tera: Arc<Tera>,
     cached_contexts: HashMap<OutputFormat, GeneratorContext>,
+    used_shortcodes: TemplateUsage,
+    inherited_front_matter: HashMap<PathBuf, Value>,
+    section_numbers: HashMap<PathBuf, SectionNumber>,
+    /// Number of listings before each document, for the output formats that number them across
+    /// documents.
+    listing_offsets: HashMap<OutputFormat, HashMap<PathBuf, usize>>,
+    /// The documents listed by the `documents` shortcode, keyed by the path of the chapter index.
+    chapter_documents: HashMap<PathBuf, Vec<DocumentSummary>>,
+    /// What the metadata pass learned about each document that could be loaded.
+    document_infos: HashMap<PathBuf, DocumentInfo>,
+    post_render_hooks: PostRenderHooks,
+    /// Diagnostics of the current build.
+    diagnostics: Mutex<Vec<Diagnostic>>,
+}
+
+/// What the metadata pass learns about a document (see [Pipeline::update_metadata]).
+#[derive(Debug, Clone)]
+struct DocumentInfo {
+    metadata: DocumentMetadata,
+    /// Whether the document has no content (see [Document::is_empty]).
+    empty: bool,
+    /// Number of numbered headings (see [count_sections]).
+    sections: usize,
+    /// Number of listings for each output format that numbers them across documents.
+    listings: HashMap<OutputFormat, usize>,
+}
+
+/// A document in the document list of its chapter index.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DocumentSummary {
+    pub id: String,
+    /// Title of the document or its id if it has none.
+    pub title: String,
+    pub summary: Option<String>,
+    pub url: String,
 }
 
 pub fn print_err<T>(res: anyhow::Result<T>) -> Option<T> {
     match res {
         Ok(s) => Some(s),
         Err(e) => {
-            eprintln!("{} {}", style("Error:").red().bold(), e);
+            error!("{}", e);
             e.chain()
                 .skip(1)
-                .for_each(|cause| eprintln!(" {} {}", style("caused by:").bold(), cause));
+                .for_each(|cause| error!("  caused by: {}", cause));
             None
         }
     }
 }
 
+/// Front matter fields that are never inherited from parent documents.
+const NON_INHERITED: [&str; 2] = ["title", "summary"];
+
+/// Compute the front matter each document inherits from the project defaults and the front matter
+/// of the index documents of its part and chapters. Values from the innermost parent take
+/// precedence and the defaults have the lowest precedence (see [merge_front_matter]). The project
+/// index is not inherited from.
+fn inherited_front_matter(index_front_matter: &Project<Value>, defaults: &Value) -> Project<Value> {
+    let inheritable = |item: &ProjectItem<Value>| {
+        let mut value = item.content.as_ref().clone();
+        if let Value::Mapping(map) = &mut value {
+            NON_INHERITED.iter().for_each(|key| {
+                map.remove(*key);
+            });
+        }
+        value
+    };
+
+    index_front_matter.transform_parents(&|item, part, chapter| {
+        let mut inherited = defaults.clone();
+        if let Some(part) = part {
+            if part.index.path != item.path {
+                inherited = merge_front_matter(&inherited, &inheritable(&part.index));
+            }
+
+            // Nested chapter ids are paths so the ancestors are found from their prefixes.
+            if let Some(chapter) = chapter {
+                let mut id = String::new();
+                for folder in chapter.id.split('/') {
+                    if !id.is_empty() {
+                        id.push('/');
+                    }
+                    id.push_str(folder);
+
+                    let ancestor = index_front_matter
+                        .chapter(&part.id, &id)
+                        .expect("Chapter ancestor must exist");
+                    if ancestor.index.path != item.path {
+                        inherited = merge_front_matter(&inherited, &inheritable(&ancestor.index));
+                    }
+                }
+            }
+        }
+        inherited
+    })
+}
+
+/// Number the parts, chapters and sections of a project. Each item holds whether the document is
+/// numbered and its number of sections. The sections of the documents in a chapter are numbered
+/// consecutively and nested chapters continue the numbering of the sections of their parent.
+/// Unnumbered documents are left out.
+fn section_numbers(project: &Project<(bool, usize)>) -> HashMap<PathBuf, SectionNumber> {
+    let mut numbers = HashMap::new();
+    let mut part_number = 0;
+    for part in project.content.iter().filter(|p| p.index.content.0) {
+        part_number += 1;
+        numbers.insert(
+            part.index.path.clone(),
+            SectionNumber {
+                prefix: vec![part_number],
+                first_section: None,
+            },
+        );
+
+        let chapters = part.chapters.iter().filter(|c| c.index.content.0);
+        for (idx, chapter) in chapters.enumerate() {
+            number_chapter(chapter, vec![part_number, idx + 1], &mut numbers);
+        }
+    }
+    numbers
+}
+
+fn number_chapter(
+    chapter: &Chapter<(bool, usize)>,
+    prefix: Vec<usize>,
+    numbers: &mut HashMap<PathBuf, SectionNumber>,
+) {
+    let mut sections = 0;
+    for doc in std::iter::once(&chapter.index).chain(&chapter.documents) {
+        let (numbered, count) = *doc.content;
+        if numbered {
+            numbers.insert(
+                doc.path.clone(),
+                SectionNumber {
+                    prefix: prefix.clone(),
+                    first_section: Some(sections + 1),
+                },
+            );
+            sections += count;
+        }
+    }
+
+    for sub_chapter in chapter.chapters.iter().filter(|c| c.index.content.0) {
+        sections += 1;
+        let mut sub_prefix = prefix.clone();
+        sub_prefix.push(sections);
+        number_chapter(sub_chapter, sub_prefix, numbers);
+    }
+}
+
+/// The number of listings before each document in the given scope. Each item holds the number of
+/// listings in the document. Documents are counted in reading order.
+fn listing_offsets(project: &Project<usize>, scope: NumberingScope) -> HashMap<PathBuf, usize> {
+    let mut offsets = HashMap::new();
+    let mut listings = 0;
+    let mut chapter = None;
+    for item in project.clone() {
+        let current = Some((item.part_idx, item.chapter_idx));
+        if scope == NumberingScope::Chapter && chapter != current {
+            listings = 0;
+            chapter = current;
+        }
+        offsets.insert(item.doc.path.clone(), listings);
+        if scope != NumberingScope::Document {
+            listings += *item.doc.content;
+        }
+    }
+    offsets
+}
+
+/// The document lists of the chapter indices. A chapter lists its documents followed by the index
+/// documents of its nested chapters. Documents without a summary (those that couldn't be read) are
+/// left out.
+fn chapter_documents(
+    project: &Project<Option<DocumentSummary>>,
+) -> HashMap<PathBuf, Vec<DocumentSummary>> {
+    let mut lists = HashMap::new();
+    for chapter in project.content.iter().flat_map(|p| &p.chapters) {
+        list_chapter(chapter, &mut lists);
+    }
+    lists
+}
+
+fn list_chapter(
+    chapter: &Chapter<Option<DocumentSummary>>,
+    lists: &mut HashMap<PathBuf, Vec<DocumentSummary>>,
+) {
+    let documents = chapter
+        .documents
+        .iter()
+        .chain(chapter.chapters.iter().map(|c| &c.index))
+        .filter_map(|doc| doc.content.as_ref().clone())
+        .collect();
+    lists.insert(chapter.index.path.clone(), documents);
+    for sub_chapter in &chapter.chapters {
+        list_chapter(sub_chapter, lists);
+    }
+}
+
+/// Display the result of building a single format.
+fn report_format(format: OutputFormat, errs: &[anyhow::Error]) {
+    let padding = " ".repeat(10 - format.to_string().len());
+    if errs.is_empty() {
+        info!(
+            "{}{}{}",
+            style(format).bold(),
+            padding,
+            style("success").green()
+        );
+    } else {
+        info!(
+            "{}{}{}",
+            style(format).bold(),
+            padding,
+            style(format!("({} errors)", errs.len())).red()
+        );
+    }
+}
+
 impl Pipeline {
     pub fn new<P: AsRef<Path>>(
         project_path: P,
@@ -62,18 +289,59 @@ impl Pipeline {
         let base_tera = Tera::new(&pattern).context("Error preparing project templates")?;
 
         let shortcode_pattern = path_str.to_string() + "/templates/shortcodes/**/*";
-        let shortcode_tera =
+        let mut shortcode_tera =
             Tera::new(&shortcode_pattern).context("Error preparing project templates")?;
+        shortcodes::register_filters(&mut shortcode_tera);
 
-        Ok(Pipeline {
+        Ok(Self::with_templates(
+            project_path,
             mode,
-            project_path: project_path.as_ref().to_path_buf(),
+            config,
             project,
-            project_config: config,
             base_tera,
             shortcode_tera,
+        ))
+    }
+
+    /// Create a pipeline from templates that are already loaded. Unlike [Pipeline::new], this
+    /// doesn't read the templates folder of the project, which makes it possible to render
+    /// documents entirely in memory with [Pipeline::render_source].
+    pub fn with_templates<P: AsRef<Path>>(
+        project_path: P,
+        mode: String,
+        mut config: ProjectConfig,
+        project: Project<()>,
+        mut base_tera: Tera,
+        shortcode_tera: Tera,
+    ) -> Self {
+        config.apply_parser_settings();
+        base_tera.register_function("page_url", html::page_url_function(&config));
+
+        let mut post_render_hooks = PostRenderHooks::default();
+        if config.minify_html {
+            post_render_hooks.push(Arc::new(|format, output| match format {
+                OutputFormat::Html => html::minify(output),
+                _ => Ok(output.to_string()),
+            }));
+        }
+
+        Pipeline {
+            mode,
+            project_path: project_path.as_ref().to_path_buf(),
+            project,
+            project_config: config,
+            base_tera: Arc::new(base_tera),
+            shortcode_tera: Arc::new(shortcode_tera),
             cached_contexts: HashMap::new(),
-        })
+            used_shortcodes: TemplateUsage::default(),
+            inherited_front_matter: HashMap::new(),
+            section_numbers: HashMap::new(),
+            listing_offsets: HashMap::new(),
+            chapter_documents: HashMap::new(),
+            document_infos: HashMap::new(),
+            post_render_hooks,
+            diagnostics: Mutex::new(Vec::new()),
+        }
     }
 
     fn get_generator(&self, format: OutputFormat) -> Box<dyn Generator> {
@@ -81,31 +349,51 @@ impl Pipeline {
             OutputFormat::Notebook => Box::new(CodeOutputGenerator),
             OutputFormat::Html => Box::new(HtmlGenerator::new(self.base_tera.clone())),
             OutputFormat::Info => Box::new(InfoGenerator),
+            OutputFormat::Pdf => Box::new(PdfGenerator::new(self.base_tera.clone())),
+            OutputFormat::Epub => Box::new(EpubGenerator),
         }
     }
 
     fn get_build_path(&self, format: OutputFormat) -> PathBuf {
+        let build_path = self.project_config.resolve_build_path(&self.project_path);
         match format {
-            OutputFormat::Notebook => self.project_path.join("build").join("notebooks"),
-            OutputFormat::Html => self.project_path.join("build").join("html"),
-            OutputFormat::Info => self.project_path.join("build"),
+            OutputFormat::Notebook => build_path.join("notebooks"),
+            OutputFormat::Html => build_path.join("html"),
+            OutputFormat::Info => build_path,
+            OutputFormat::Pdf => build_path.join("pdf"),
+            OutputFormat::Epub => build_path.join("epub"),
         }
     }
 
+    /// Add a hook that transforms rendered output before it is written. Hooks run in the order
+    /// they are added.
+    pub fn add_post_render_hook<F>(&mut self, hook: F)
+    where
+        F: Fn(&OutputFormat, &str) -> anyhow::Result<String> + Send + Sync + 'static,
+    {
+        self.post_render_hooks.push(Arc::new(hook));
+    }
+
     pub fn reload_shortcode_tera(&mut self) -> anyhow::Result<()> {
-        Ok(self.shortcode_tera.full_reload()?)
+        Ok(Arc::make_mut(&mut self.shortcode_tera).full_reload()?)
     }
 
     pub fn reload_base_tera(&mut self) -> anyhow::Result<()> {
-        Ok(self.base_tera.full_reload()?)
+        Ok(Arc::make_mut(&mut self.base_tera).full_reload()?)
     }
 
     pub fn build_single(&mut self, path: PathBuf) -> anyhow::Result<()> {
         let relpath = path.strip_prefix(self.project_path.join("content"))?;
-        println!("{} {}", style("Building file").bold(), relpath.display());
-        println!("{}", style("-".repeat(60)).blue());
+        info!("{} {}", style("Building file").bold(), relpath.display());
+        info!("{}", style("-".repeat(60)).blue());
+        // Problems of the previous build of the document are found again if they still exist.
+        self.diagnostics
+            .lock()
+            .expect("Diagnostics lock poisoned")
+            .retain(|d| d.document.as_deref() != Some(relpath));
         let item = self.doc_from_path(path)?;
         let item2 = item.clone();
+        self.update_metadata();
 
         let loaded = item.map_doc(|doc| {
             let path = self.project_path.join("content").join(doc.path);
@@ -117,13 +405,12 @@ impl Pipeline {
         let mut all_errors = Vec::new();
 
         for format in self.project_config.outputs.clone() {
-            print!("format: {}", style(format).bold());
             let output = self.process_document(&loaded.doc, format);
 
             match output {
                 Err(e) => {
                     all_errors.push(e);
-                    println!(" {}", style("error").red());
+                    info!("format: {} {}", style(format).bold(), style("error").red());
                 }
                 Ok(output) => {
                     if let Some(output) = output {
@@ -138,40 +425,36 @@ impl Pipeline {
                         self.get_generator(format).generate_single(
                             output,
                             item2.clone(),
-                            context,
+                            &context,
                         )?;
 
-                        println!(" {}", style("done").green());
+                        info!("format: {} {}", style(format).bold(), style("done").green());
                     } else {
-                        println!(" {}", style("no output").yellow());
+                        info!(
+                            "format: {} {}",
+                            style(format).bold(),
+                            style("no output").yellow()
+                        );
                     }
                 }
             }
             // let output = print_err(output).flatten();
         }
 
-        println!("{}", style("-".repeat(60)).blue());
+        info!("{}", style("-".repeat(60)).blue());
         if all_errors.is_empty() {
-            println!("{}", style("Success").green().bold());
+            info!("{}", style("Success").green().bold());
         } else {
             let len = all_errors.len();
             all_errors.into_iter().for_each(|e| {
-                eprintln!("{} {}", style("Error:").red().bold(), e);
+                error!("{}", e);
                 e.chain()
                     .skip(1)
-                    .for_each(|cause| eprintln!(" {} {}", style("caused by:").bold(), cause));
+                    .for_each(|cause| error!("  caused by: {}", cause));
             });
-            println!("{}", style("-".repeat(60)).blue());
-
-            println!(
-                "{}",
-                style(format!(
-                    "File built with non-critical errors ({} total)",
-                    len
-                ))
-                .yellow()
-                .bold()
-            );
+            info!("{}", style("-".repeat(60)).blue());
+
+            warn!("File built with non-critical errors ({} total)", len);
         }
 
         Ok(())
@@ -188,7 +471,10 @@ impl Pipeline {
         if let Some(part_id) = i3.part_idx {
             let part = &mut context.project.content[part_id];
             if let Some(chapter_id) = i3.chapter_idx {
-                let chapter = &mut part.chapters[chapter_id];
+                let mut chapter = &mut part.chapters[chapter_id];
+                for sub_idx in &i3.sub_chapter_idx {
+                    chapter = &mut chapter.chapters[*sub_idx];
+                }
                 if let Some(doc_id) = i3.doc_idx {
                     chapter.documents[doc_id].content = Arc::new(Some(output.clone()));
                 } else {
@@ -210,6 +496,7 @@ impl Pipeline {
         let mut chapter_id = None;
         let mut part_idx = None;
         let mut chapter_idx = None;
+        let mut sub_chapter_idx = Vec::new();
         let mut doc_idx = None;
 
         let doc_path = path
@@ -245,14 +532,27 @@ impl Pipeline {
 
             match elem {
                 None => &part.index,
-                Some(c) => {
-                    chapter_id = Some(c.id.clone());
+                Some(mut c) => {
                     let cid = part
                         .chapters
                         .iter()
                         .position(|c| c.id == first_elem)
                         .expect("Part index not found");
                     chapter_idx = Some(cid);
+
+                    // Descend into nested chapters (all remaining elements except the file name)
+                    let rest: Vec<&str> = doc_iter.filter_map(|e| e.to_str()).collect();
+                    for dir in rest.iter().take(rest.len().saturating_sub(1)) {
+                        let sub_id = format!("{}/{}", c.id, dir);
+                        let sid = c
+                            .chapters
+                            .iter()
+                            .position(|c| c.id == sub_id)
+                            .ok_or_else(|| anyhow!("Chapter {} not found", sub_id))?;
+                        sub_chapter_idx.push(sid);
+                        c = &c.chapters[sid];
+                    }
+                    chapter_id = Some(c.id.clone());
                     let doc = c.documents.iter().find(|d| d.id == file_id);
                     match doc {
                         None => &c.index,
@@ -276,6 +576,7 @@ impl Pipeline {
             chapter_id,
             part_idx,
             chapter_idx,
+            sub_chapter_idx,
             doc: doc.clone(),
             doc_idx,
             files: None,
@@ -284,44 +585,44 @@ impl Pipeline {
         Ok(item)
     }
 
-    pub fn build_all(&mut self, remove_existing: bool) -> Result<(), anyhow::Error> {
-        let build_path = self.project_path.join("build");
+    /// Build all formats. Non-critical errors (e.g. a document that fails to process) don't stop
+    /// the build and are returned in the report together with the warnings.
+    pub fn build_all(&mut self, remove_existing: bool) -> Result<BuildReport, anyhow::Error> {
+        let build_path = self.project_config.resolve_build_path(&self.project_path);
 
         if remove_existing && build_path.exists() {
             fs::remove_dir_all(build_path)?;
         }
 
         let loaded = self.load_all()?;
+        self.start_build();
 
-        println!("{}", style("=".repeat(60)).blue());
-        println!(
+        info!("{}", style("=".repeat(60)).blue());
+        info!(
             "{} ({} files)",
             style("Building project").bold(),
             loaded.len()
         );
-        println!("{}", style("-".repeat(60)).blue());
+        info!("{}", style("-".repeat(60)).blue());
 
         let mut all_errs = Vec::new();
 
         for format in &self.project_config.outputs {
-            print!(
-                "{}{}",
-                style(format).bold(),
-                " ".repeat(10 - format.to_string().len())
-            );
+            let _span = debug_span!("format", %format).entered();
+            debug!("processing documents");
             let mut format_errs = Vec::new();
-            let (output, mut errs) = self.process_all(loaded.clone(), *format);
+            let (output, mut errs) = self.process_all(loaded.clone(), *format)?;
             format_errs.append(&mut errs);
             let context = GeneratorContext {
                 root: self.project_path.to_path_buf(),
                 project: output,
                 config: self.project_config.clone(),
                 build_dir: self.get_build_path(*format),
+                post_render: self.post_render_hooks.clone(),
             };
             self.cached_contexts.insert(*format, context.clone());
 
-            // print!("[generating output");
-
+            debug!("generating output");
             let res = self
                 .get_generator(*format)
                 .generate(context.clone())
@@ -335,79 +636,640 @@ impl Pipeline {
                 }
             }
 
-            // Move extra files
-            if let Some(parser) = self.project_config.parsers.get(format) {
-                // print!(", copying additional files");
-                let move_ctx = MoveContext {
-                    project_path: self.project_path.to_path_buf(),
-                    build_dir: self.get_build_path(*format),
-                    settings: parser.settings.clone(),
-                };
+            self.move_extra_files(*format)?;
+            report_format(*format, &format_errs);
+
+            all_errs.append(&mut format_errs);
+        }
+
+        self.write_shortcode_usage()?;
+        Ok(self.report_build(all_errs))
+    }
+
+    /// Process every document for each output format without writing anything, and return the
+    /// diagnostics. This finds the same errors as a build (e.g. invalid front matter, exercise
+    /// syntax or shortcodes), but generates no outputs. Links between the web pages of the
+    /// project, and the anchors they point to, are checked as well.
+    pub fn check_all(&mut self) -> Result<BuildReport, anyhow::Error> {
+        let loaded = self.load_all()?;
+        self.start_build();
+
+        info!("{}", style("=".repeat(60)).blue());
+        info!(
+            "{} ({} files)",
+            style("Checking project").bold(),
+            loaded.len()
+        );
+        info!("{}", style("-".repeat(60)).blue());
 
-                Mover::traverse_dir(self.project_path.join("content").to_path_buf(), &move_ctx)?;
+        let mut all_errs = Vec::new();
+        for format in self.project_config.outputs.iter().filter(|f| !f.no_parse()) {
+            let mut errs = Vec::new();
+            let mut docs = Vec::new();
+            for item in loaded.clone() {
+                match self
+                    .parse_document(&item.doc, *format)
+                    .with_context(|| DocumentFailed(item.doc.path.clone()))
+                {
+                    Ok(Some(doc)) => docs.push((item.doc.path.clone(), doc)),
+                    Ok(None) => {}
+                    Err(e) => errs.push(e),
+                }
+            }
+            if *format == OutputFormat::Html {
+                self.check_links(&docs);
             }
+            report_format(*format, &errs);
+            all_errs.append(&mut errs);
+        }
 
-            // Error display
-            if format_errs.is_empty() {
-                println!("{}", style("success").green());
-            } else {
-                println!("{}", style(format!("({} errors)", format_errs.len())).red());
+        Ok(self.report_check(all_errs))
+    }
+
+    /// Record an error for each link between web pages that points to a missing page or anchor.
+    fn check_links(&self, docs: &[(PathBuf, Document<EventContent>)]) {
+        let mut targets = LinkTargets::new(
+            self.project_config.url_style,
+            &self.project_config.url_prefix,
+        );
+        for (path, doc) in docs {
+            targets.insert(path, links::document_anchors(doc));
+        }
+        for (path, doc) in docs {
+            for (url, pos) in links::document_links(doc) {
+                if let Some(message) = targets.check(path, &url) {
+                    self.error_document(path, Some(SourcePosition::from(&pos)), message);
+                }
             }
+        }
+    }
+
+    /// Build all formats without holding the full processed project in memory. A navigation tree,
+    /// containing only the metadata of each document from the metadata pass, is passed to the
+    /// generators. Each document is then loaded, processed and written before the
+    /// next one is read.
+    ///
+    /// Document variables (e.g. the first heading) are not available in the navigation tree.
+    pub fn build_all_streaming(
+        &mut self,
+        remove_existing: bool,
+    ) -> Result<BuildReport, anyhow::Error> {
+        let build_path = self.project_config.resolve_build_path(&self.project_path);
+
+        if remove_existing && build_path.exists() {
+            fs::remove_dir_all(build_path)?;
+        }
+
+        self.start_build();
+
+        info!("{}", style("=".repeat(60)).blue());
+        info!(
+            "{} ({} files, streaming)",
+            style("Building project").bold(),
+            self.project.len()
+        );
+        info!("{}", style("-".repeat(60)).blue());
+
+        let mut all_errs = Vec::new();
+
+        for format in self.project_config.outputs.clone() {
+            let _span = debug_span!("format", %format).entered();
+            debug!("computing navigation");
+            let context = GeneratorContext {
+                root: self.project_path.to_path_buf(),
+                project: self.navigation(format),
+                config: self.project_config.clone(),
+                build_dir: self.get_build_path(format),
+                post_render: self.post_render_hooks.clone(),
+            };
+
+            let mut format_errs = self.stream_format(format, &context);
+            self.cached_contexts.insert(format, context);
+
+            self.move_extra_files(format)?;
+            report_format(format, &format_errs);
 
             all_errs.append(&mut format_errs);
         }
 
-        println!("{}", style("-".repeat(60)).blue());
+        self.write_shortcode_usage()?;
+        Ok(self.report_build(all_errs))
+    }
+
+    /// Process and write each document of the project in turn.
+    fn stream_format(
+        &self,
+        format: OutputFormat,
+        context: &GeneratorContext,
+    ) -> Vec<anyhow::Error> {
+        let generator = self.get_generator(format);
+
+        // Formats that aren't parsed only consist of the metadata which is already in the
+        // navigation tree.
+        if format.no_parse() {
+            debug!("generating output");
+            return generator
+                .generate(context.clone())
+                .with_context(|| format!("Could not generate {}", format))
+                .err()
+                .into_iter()
+                .collect();
+        }
+
+        if let Err(e) = generator
+            .prepare(context)
+            .with_context(|| format!("Could not generate {}", format))
+        {
+            return vec![e];
+        }
+
+        let mut errs = Vec::new();
+        for item in self.project.clone() {
+            let res = self
+                .read_document(&item.doc)
+                .and_then(|content| {
+                    let doc = ProjectItem {
+                        id: item.doc.id.clone(),
+                        format: item.doc.format,
+                        path: item.doc.path.clone(),
+                        content: Arc::new(content),
+                    };
+                    self.process_document(&doc, format)
+                })
+                .with_context(|| DocumentFailed(item.doc.path.clone()))
+                .and_then(|output| match output {
+                    Some(output) => generator.generate_single(output, item, context),
+                    None => Ok(()),
+                });
+
+            if let Err(e) = res {
+                errs.push(e);
+            }
+        }
+        errs
+    }
+
+    /// Project tree containing only the metadata of each document from the metadata pass.
+    /// Documents that fail to load are left out (their errors are reported when they are
+    /// processed).
+    fn navigation(&self, format: OutputFormat) -> Project<Option<Document<RenderResult>>> {
+        self.project.transform(&|item| {
+            let info = self.document_infos.get(&item.path)?;
+            let doc = Document {
+                content: RenderResult::default(),
+                metadata: info.metadata.clone(),
+                variables: DocumentVariables {
+                    number: self
+                        .section_numbers
+                        .get(&item.path)
+                        .filter(|n| !n.prefix.is_empty())
+                        .map(|n| format_number(&n.prefix)),
+                    ..Default::default()
+                },
+            };
+            let doc = self.handle_empty(doc, info.empty)?;
+
+            (format.no_parse() || doc.metadata.outputs.contains(&format)).then_some(doc)
+        })
+    }
+
+    /// Copy the non-document files in the content folder to the build directory.
+    fn move_extra_files(&self, format: OutputFormat) -> anyhow::Result<()> {
+        if let Some(parser) = self.project_config.parsers.get(&format) {
+            debug!("copying additional files");
+            let move_ctx = MoveContext {
+                project_path: self.project_path.to_path_buf(),
+                build_dir: self.get_build_path(format),
+                settings: parser.settings.clone(),
+            };
+
+            Mover::traverse_dir(self.project_path.join("content").to_path_buf(), &move_ctx)?;
+        }
+        Ok(())
+    }
+
+    /// Compute the project-wide document information and reset the state of the previous build.
+    fn start_build(&mut self) {
+        self.update_metadata();
+        self.used_shortcodes.clear();
+        self.diagnostics
+            .lock()
+            .expect("Diagnostics lock poisoned")
+            .clear();
+    }
+
+    /// Record a diagnostic of the current build. Returns false if it was already recorded, which
+    /// happens when a document is processed for several output formats.
+    fn record(&self, diagnostic: Diagnostic) -> bool {
+        let mut diagnostics = self.diagnostics.lock().expect("Diagnostics lock poisoned");
+        if diagnostics.contains(&diagnostic) {
+            return false;
+        }
+        diagnostics.push(diagnostic);
+        true
+    }
+
+    /// Record and display a warning about a document.
+    fn warn_document(&self, document: &Path, position: Option<SourcePosition>, message: String) {
+        let diagnostic = Diagnostic {
+            severity: Severity::Warning,
+            document: Some(document.to_path_buf()),
+            position,
+            message,
+        };
+        if self.record(diagnostic.clone()) {
+            warn!("{}", diagnostic);
+        }
+    }
+
+    /// Record and display an error about a document that doesn't stop it from being processed.
+    fn error_document(&self, document: &Path, position: Option<SourcePosition>, message: String) {
+        let diagnostic = Diagnostic {
+            severity: Severity::Error,
+            document: Some(document.to_path_buf()),
+            position,
+            message,
+        };
+        if self.record(diagnostic.clone()) {
+            error!("{}", diagnostic);
+        }
+    }
+
+    /// Display the build summary and all errors, and return the diagnostics of the build.
+    fn report_build(&self, all_errs: Vec<anyhow::Error>) -> BuildReport {
+        info!("{}", style("-".repeat(60)).blue());
         if all_errs.is_empty() {
-            println!("{}", style("Project built successfully").green().bold());
+            info!("{}", style("Project built successfully").green().bold());
         } else {
             let len = all_errs.len();
-            all_errs.into_iter().for_each(|e| {
-                eprintln!("{} {}", style("Error:").red().bold(), e);
-                e.chain()
-                    .skip(1)
-                    .for_each(|cause| eprintln!(" {} {}", style("caused by:").bold(), cause));
-            });
-            println!("{}", style("-".repeat(60)).blue());
-
-            println!(
-                "{}",
-                style(format!(
-                    "Project built with non-critical errors ({} total)",
-                    len
-                ))
-                .yellow()
-                .bold()
+            self.report_errors(all_errs);
+            info!("{}", style("-".repeat(60)).blue());
+
+            warn!("Project built with non-critical errors ({} total)", len);
+        }
+
+        let report = self.take_report();
+        let warnings = report.warnings().count();
+        if warnings > 0 {
+            info!("{}", style("-".repeat(60)).blue());
+            warn!("Project built with warnings ({} total)", warnings);
+        }
+        info!("{}", style("=".repeat(60)).blue());
+        report
+    }
+
+    /// Display all errors and the number of problems found by a check, and return its diagnostics.
+    fn report_check(&self, all_errs: Vec<anyhow::Error>) -> BuildReport {
+        self.report_errors(all_errs);
+        let report = self.take_report();
+
+        info!("{}", style("-".repeat(60)).blue());
+        if report.diagnostics.is_empty() {
+            info!("{}", style("No problems found").green().bold());
+        } else {
+            warn!(
+                "{} problems found ({} errors, {} warnings)",
+                report.diagnostics.len(),
+                report.errors().count(),
+                report.warnings().count()
             );
         }
-        println!("{}", style("=".repeat(60)).blue());
+        info!("{}", style("=".repeat(60)).blue());
+        report
+    }
+
+    /// Record and display errors that stopped documents from being processed.
+    fn report_errors(&self, errs: Vec<anyhow::Error>) {
+        errs.into_iter().for_each(|e| {
+            self.record(Diagnostic::from_error(&e));
+            error!("{}", e);
+            e.chain()
+                .skip(1)
+                .for_each(|cause| error!("  caused by: {}", cause));
+        });
+    }
+
+    /// Report the unused shortcode templates if enabled and take the diagnostics of the build,
+    /// so that the next build starts without any.
+    fn take_report(&self) -> BuildReport {
+        if self.project_config.report_unused_shortcodes {
+            self.report_unused_shortcodes();
+        }
+        BuildReport {
+            diagnostics: std::mem::take(
+                &mut *self.diagnostics.lock().expect("Diagnostics lock poisoned"),
+            ),
+        }
+    }
+
+    /// The documents that used each shortcode template in the last build. Templates that no
+    /// document used are included without documents.
+    pub fn shortcode_usage(&self) -> ShortcodeUsage {
+        let mut usage = self.used_shortcodes.documents();
+        for name in self.shortcode_tera.get_template_names() {
+            usage.entry(name.to_string()).or_default();
+        }
+        ShortcodeUsage(usage)
+    }
 
+    /// Write the shortcode usage report to the build folder if it is enabled.
+    fn write_shortcode_usage(&self) -> anyhow::Result<()> {
+        if self.project_config.report_shortcode_usage {
+            let build_path = self.project_config.resolve_build_path(&self.project_path);
+            self.shortcode_usage().write(&build_path)?;
+        }
         Ok(())
     }
 
+    /// Report the shortcode templates that were loaded but not rendered by any document.
+    fn report_unused_shortcodes(&self) {
+        let mut unused: Vec<&str> = self
+            .shortcode_tera
+            .get_template_names()
+            .filter(|name| !self.used_shortcodes.is_used(name))
+            .collect();
+        unused.sort();
+
+        info!("{}", style("-".repeat(60)).blue());
+        if unused.is_empty() {
+            info!("{}", style("All shortcode templates are in use").green());
+        } else {
+            warn!("Unused shortcode templates ({} total)", unused.len());
+            unused.iter().for_each(|name| {
+                self.record(Diagnostic {
+                    severity: Severity::Warning,
+                    document: None,
+                    position: None,
+                    message: format!("Unused shortcode template templates/shortcodes/{}", name),
+                });
+                warn!(" templates/shortcodes/{}", name)
+            });
+        }
+    }
+
     fn load_all(&self) -> Result<Project<String>, anyhow::Error> {
         self.project
             .clone()
             .into_iter()
-            .map(|item| {
-                item.map_doc(|doc| {
-                    let path = self.project_path.join("content").join(doc.path);
-                    let val = fs::read_to_string(path.as_path())
-                        .context(format!("Error loading document {}", path.display()))?;
+            .map(|item| item.map_doc(|doc| self.read_document(&doc)))
+            .collect::<anyhow::Result<Vec<ItemDescriptor<String>>>>()?
+            .into_iter()
+            .collect()
+    }
+
+    /// Parse a document with the front matter it inherits from its parents.
+    fn load_document<C>(
+        &self,
+        item: &ProjectItem<C>,
+        content: &str,
+    ) -> anyhow::Result<Document<RawContent>> {
+        let defaults = self
+            .inherited_front_matter
+            .get(&item.path)
+            .unwrap_or(&Value::Null);
+        let loader: Box<dyn Loader> = match item.format {
+            InputFormat::Notebook => Box::new(self.project_config.notebook_loader.clone()),
+            format => format.loader(),
+        };
+        let mut doc = loader.load_with_defaults(content, defaults)?;
+        if let Some(title) = self.project_config.title_override(&item.path) {
+            doc.metadata.title = Some(title.clone());
+        }
+        Ok(doc)
+    }
 
-                    Ok(val)
+    /// Read every document once and compute what processing a document needs to know about the
+    /// others: the front matter it inherits, its section numbers, the number of listings before
+    /// it and the document lists of the chapter indices. Documents that
+    /// can't be loaded are left out (their errors are reported when they are processed).
+    fn update_metadata(&mut self) {
+        // Index documents are read first since the other documents inherit their front matter.
+        let index_sources = self.project.transform_parents(&|item, part, chapter| {
+            let is_index = match (part, chapter) {
+                (Some(_), Some(chapter)) => chapter.index.path == item.path,
+                _ => true,
+            };
+            is_index.then(|| self.read_document(item).ok()).flatten()
+        });
+        let index_front_matter = index_sources.transform(&|item| {
+            item.content
+                .as_ref()
+                .as_ref()
+                .and_then(|content| item.format.loader().front_matter(content).ok())
+                .unwrap_or(Value::Null)
+        });
+        self.inherited_front_matter =
+            inherited_front_matter(&index_front_matter, &self.project_config.front_matter)
+                .into_iter()
+                .map(|item| (item.doc.path, item.doc.content.as_ref().clone()))
+                .collect();
+
+        let scopes = self.numbering_scopes();
+        let infos = index_sources.transform(&|item| {
+            let content = match item.content.as_ref() {
+                Some(content) => content.clone(),
+                None => self.read_document(item).ok()?,
+            };
+            self.document_info(item, &content, &scopes).ok()
+        });
+
+        self.section_numbers = match &self.project_config.numbering {
+            Some(_) => section_numbers(&infos.transform(&|item| {
+                item.content.as_ref().as_ref().map_or((false, 0), |info| {
+                    (info.metadata.numbered.unwrap_or(true), info.sections)
                 })
+            })),
+            None => HashMap::new(),
+        };
+        self.listing_offsets = scopes
+            .iter()
+            .map(|(format, scope)| {
+                let counts = infos.transform(&|item| {
+                    item.content
+                        .as_ref()
+                        .as_ref()
+                        .and_then(|info| info.listings.get(format).copied())
+                        .unwrap_or_default()
+                });
+                (*format, listing_offsets(&counts, *scope))
+            })
+            .collect();
+        self.chapter_documents = chapter_documents(&infos.transform(&|item| {
+            let info = item.content.as_ref().as_ref()?;
+            Some(DocumentSummary {
+                id: item.id.clone(),
+                title: info
+                    .metadata
+                    .title
+                    .clone()
+                    .unwrap_or_else(|| item.id.clone()),
+                summary: info.metadata.summary.clone(),
+                url: self.project_config.page_url(&item.path),
+            })
+        }));
+        self.document_infos = infos
+            .into_iter()
+            .filter_map(|item| Some((item.doc.path, item.doc.content.as_ref().clone()?)))
+            .collect();
+    }
+
+    /// The output formats that number listings across documents, with their numbering scopes.
+    fn numbering_scopes(&self) -> Vec<(OutputFormat, NumberingScope)> {
+        self.project_config
+            .outputs
+            .iter()
+            .filter(|format| !format.no_parse())
+            .filter_map(|format| {
+                let parser = self.project_config.parsers.get(format)?;
+                Some((*format, parser.settings.numbering_scope(*format)))
             })
-            .collect::<Result<Project<String>, anyhow::Error>>()
+            .filter(|(_, scope)| *scope != NumberingScope::Document)
+            .collect()
     }
 
-    // fn load_single(&self, )
+    /// Load a document and find what the metadata pass needs from it. Listings are counted
+    /// by processing the document for each of the formats (numbering across documents), so that
+    /// those added by shortcodes are counted as well.
+    fn document_info<C>(
+        &self,
+        item: &ProjectItem<C>,
+        content: &str,
+        scopes: &[(OutputFormat, NumberingScope)],
+    ) -> anyhow::Result<DocumentInfo> {
+        let mut doc = self.load_document(item, content)?;
+        let mut events = doc.to_events(IteratorConfig::default());
+        let empty = events.is_empty();
+        let sections = match &self.project_config.numbering {
+            Some(config) => count_sections(&events.content, config.start_level),
+            None => 0,
+        };
+        // The summary is extracted from the document before it is processed, so that the chapter
+        // document lists have it.
+        let summary = events.extract_summary();
+        doc.metadata.summary = doc.metadata.summary.or(summary);
+
+        let mut listings = HashMap::new();
+        for (format, _) in scopes {
+            if !doc.metadata.outputs.contains(format) {
+                continue;
+            }
+            let parser = self
+                .project_config
+                .parsers
+                .get(format)
+                .ok_or_else(|| anyhow!("Invalid format"))?;
+            // Usage and numbers are recorded separately from the build.
+            let numbers = ListingNumbers::default();
+            let ctx =
+                self.processor_context(*format, parser, TemplateUsage::default(), numbers.clone());
+            parser.parse(
+                &doc,
+                &self.template_context(&item.path, &doc.metadata),
+                &ctx,
+            )?;
+            listings.insert(*format, numbers.count());
+        }
+
+        Ok(DocumentInfo {
+            metadata: doc.metadata,
+            empty,
+            sections,
+            listings,
+        })
+    }
+
+    /// The context of the processors of a document.
+    fn processor_context(
+        &self,
+        format: OutputFormat,
+        parser: &Parser,
+        used_templates: TemplateUsage,
+        listing_numbers: ListingNumbers,
+    ) -> PreprocessorContext {
+        PreprocessorContext {
+            tera: self.shortcode_tera.clone(),
+            output_format: format,
+            used_templates,
+            include_solutions: parser.settings.solutions,
+            listing_numbers,
+            math_macros: self.project_config.math_macros.clone(),
+            warnings: Warnings::default(),
+        }
+    }
+
+    /// The variables available to the shortcodes of a document.
+    fn template_context(&self, doc_path: &Path, metadata: &DocumentMetadata) -> tera::Context {
+        let mut meta = tera::Context::new();
+        meta.insert("project", &self.project_config);
+        meta.insert("page", metadata);
+        meta.insert("var", &self.project_config.vars);
+        meta.insert(
+            "chapter_documents",
+            self.chapter_documents
+                .get(doc_path)
+                .map(Vec::as_slice)
+                .unwrap_or_default(),
+        );
+        meta
+    }
+
+    fn read_document<C>(&self, doc: &ProjectItem<C>) -> anyhow::Result<String> {
+        let path = self.project_path.join("content").join(&doc.path);
+        debug!("reading {}", path.display());
+        fs::read_to_string(path.as_path())
+            .context(format!("Error loading document {}", path.display()))
+    }
+
+    /// Render the source of a document to each output format of the project without reading or
+    /// writing any files. The path is the location of the document in the content folder. It
+    /// determines the input format and is used to find the front matter the document inherits and
+    /// its numbering. Formats that the document is not rendered to are left out, as are formats
+    /// that don't render documents (info).
+    pub fn render_source(
+        &self,
+        path: &Path,
+        source: &str,
+    ) -> anyhow::Result<HashMap<OutputFormat, Document<RenderResult>>> {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default();
+        let item = ProjectItem {
+            id: section_id(path).unwrap_or_default(),
+            format: InputFormat::from_extension(extension)?,
+            path: path.to_path_buf(),
+            content: Arc::new(source.to_string()),
+        };
+
+        let mut outputs = HashMap::new();
+        for format in self.project_config.outputs.iter().filter(|f| !f.no_parse()) {
+            if let Some(doc) = self.process_document(&item, *format)? {
+                outputs.insert(*format, doc);
+            }
+        }
+        Ok(outputs)
+    }
+
+    /// Apply the empty document policy of the project. Returns None if the document is skipped.
+    fn handle_empty<C>(&self, mut doc: Document<C>, empty: bool) -> Option<Document<C>> {
+        let config = &self.project_config.empty_documents;
+        match (empty, config.action) {
+            (false, _) => Some(doc),
+            (true, EmptyDocumentAction::Skip) => None,
+            (true, EmptyDocumentAction::Render) => {
+                doc.metadata
+                    .title
+                    .get_or_insert_with(|| config.title.clone());
+                Some(doc)
+            }
+        }
+    }
 
     fn process_all(
         &self,
         project: Project<String>,
         format: OutputFormat,
-    ) -> (Project<Option<Document<RenderResult>>>, Vec<anyhow::Error>) {
+    ) -> anyhow::Result<(RenderedProject, Vec<anyhow::Error>)> {
         let spinner = ProgressStyle::with_template("{prefix:.bold.dim} {spinner} {wide_msg}")
             .unwrap()
             .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈ ");
@@ -423,12 +1285,9 @@ impl Pipeline {
                 pb.set_message(format!("{}", i.doc.path.display()));
                 pb.inc(1);
 
-                let res = self.process_document(&i.doc, format).with_context(|| {
-                    format!(
-                        "Failed to process document – {}",
-                        style(format!("content/{}", i.doc.path.display())).italic()
-                    )
-                });
+                let res = self
+                    .process_document(&i.doc, format)
+                    .with_context(|| DocumentFailed(i.doc.path.clone()));
 
                 let res = match res {
                     Ok(good) => good,
@@ -444,6 +1303,7 @@ impl Pipeline {
                     chapter_id: i.chapter_id,
                     part_idx: i.part_idx,
                     chapter_idx: i.chapter_idx,
+                    sub_chapter_idx: i.sub_chapter_idx,
                     doc_idx: i.doc_idx,
                     doc: ProjectItem {
                         id: i.doc.id,
@@ -454,13 +1314,13 @@ impl Pipeline {
                     files: i.files,
                 }
             })
-            .collect::<Project<Option<Document<RenderResult>>>>();
+            .collect::<anyhow::Result<RenderedProject>>()?;
 
         pb.finish_and_clear();
 
         // pb.finish_with_message(format!("Done"));
 
-        (res, errs)
+        Ok((res, errs))
     }
 
     fn process_document(
@@ -468,38 +1328,1052 @@ impl Pipeline {
         item: &ProjectItem<String>,
         format: OutputFormat,
     ) -> anyhow::Result<Option<Document<RenderResult>>> {
-        let doc = item.format.loader().load(&item.content)?;
-        // println!("doc {}", item.path.display());
+        let _span = debug_span!("document", path = %item.path.display(), %format).entered();
+
         if format.no_parse() {
-            Ok(Some(Document {
-                content: "".to_string(),
+            debug!("loading");
+            let doc = self.load_document(item, &item.content)?;
+            return Ok(Some(Document {
+                content: RenderResult::default(),
                 metadata: doc.metadata,
                 variables: doc.variables,
-            }))
-        } else if doc.metadata.outputs.contains(&format) {
-            let processor_ctx = PreprocessorContext {
-                tera: self.shortcode_tera.clone(),
+            }));
+        }
+
+        let res = match self.parse_document(item, format)? {
+            Some(res) => res,
+            None => return Ok(None),
+        };
+        if let Some(renderer) = format.renderer() {
+            debug!("rendering");
+            Ok(Some(renderer.render(&res)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Load a document and run the processors of an output format that parses documents, without
+    /// rendering it. Returns None if the document isn't built for the format or is skipped because
+    /// it is empty.
+    fn parse_document(
+        &self,
+        item: &ProjectItem<String>,
+        format: OutputFormat,
+    ) -> anyhow::Result<Option<Document<EventContent>>> {
+        debug!("loading");
+        let doc = self.load_document(item, &item.content)?;
+        if !doc.metadata.outputs.contains(&format) {
+            debug!("skipped (output format not enabled for document)");
+            return Ok(None);
+        }
+        let parser = self
+            .project_config
+            .parsers
+            .get(&format)
+            .ok_or_else(|| anyhow!("Invalid format"))?;
+
+        let offset = self
+            .listing_offsets
+            .get(&format)
+            .and_then(|offsets| offsets.get(&item.path))
+            .copied()
+            .unwrap_or_default();
+        let processor_ctx = self.processor_context(
+            format,
+            parser,
+            self.used_shortcodes.for_document(&item.path),
+            ListingNumbers::new(offset),
+        );
+        let meta = self.template_context(&item.path, &doc.metadata);
+        let mut res = parser.parse(&doc, &meta, &processor_ctx)?;
+
+        // Pretty web pages are written one folder deeper than their documents.
+        let relative = match format {
+            OutputFormat::Html => self.project_config.url_style.relative_prefix(&item.path),
+            _ => "",
+        };
+        if !self.project_config.url_prefix.is_empty() || !relative.is_empty() {
+            let prefix = LinkPrefix {
+                prefix: self.project_config.url_prefix.clone(),
+                relative: relative.to_string(),
+            };
+            res = prefix.process(res)?;
+        }
+
+        if let (Some(config), Some(number)) = (
+            &self.project_config.numbering,
+            self.section_numbers.get(&item.path),
+        ) {
+            let numbering = Numbering {
+                start_level: config.start_level,
+                number: number.clone(),
                 output_format: format,
             };
+            res = numbering.process(res)?;
+        }
 
-            let mut meta = tera::Context::new();
-            meta.insert("project", &self.project_config);
+        for warning in &res.variables.warnings {
+            self.warn_document(&item.path, warning.position, warning.message.clone());
+        }
+        res.variables.headings = res.headings();
+        let summary = res.extract_summary();
+        res.metadata.summary = res.metadata.summary.or(summary);
+
+        let empty = res.is_empty();
+        let mut res = match self.handle_empty(res, empty) {
+            Some(res) => res,
+            None => {
+                self.warn_document(&item.path, None, "Skipping empty document".to_string());
+                return Ok(None);
+            }
+        };
+        if res.metadata.layout.hide_title_heading && res.metadata.title.is_some() {
+            res.remove_title_heading();
+        }
+        Ok(Some(res))
+    }
+}
 
-            let res = self
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(path: &str, front_matter: &str) -> ProjectItem<Value> {
+        ProjectItem {
+            id: path.to_string(),
+            format: InputFormat::Markdown,
+            path: PathBuf::from(path),
+            content: Arc::new(serde_yaml::from_str(front_matter).unwrap()),
+        }
+    }
+
+    #[test]
+    fn test_inherited_front_matter() {
+        let project = Project {
+            project_path: Default::default(),
+            index: item("index", "code_split: true"),
+            content: vec![Part {
+                id: "part1".to_string(),
+                index: item(
+                    "part1",
+                    "title: Part\ncode_split: true\nnotebook_output: true",
+                ),
+                chapters: vec![Chapter {
+                    id: "chapter1".to_string(),
+                    index: item("chapter1", "notebook_output: false"),
+                    documents: vec![item("doc1", "{}")],
+                    files: vec![],
+                    chapters: vec![Chapter {
+                        id: "chapter1/sub1".to_string(),
+                        index: item("sub1", "layout:\n  hide_sidebar: true"),
+                        documents: vec![item("doc2", "{}")],
+                        files: vec![],
+                        chapters: vec![],
+                    }],
+                }],
+            }],
+        };
+
+        let defaults = serde_yaml::from_str("code_split: false\ncode_solutions: false").unwrap();
+        let inherited: HashMap<PathBuf, Value> = inherited_front_matter(&project, &defaults)
+            .into_iter()
+            .map(|item| (item.doc.path, item.doc.content.as_ref().clone()))
+            .collect();
+        let get = |path: &str| inherited.get(&PathBuf::from(path)).unwrap().clone();
+        let yaml = |s: &str| serde_yaml::from_str::<Value>(s).unwrap();
+
+        // The project index is never inherited and indices don't inherit from themselves
+        assert_eq!(get("index"), defaults);
+        assert_eq!(get("part1"), defaults);
+        // Titles are not inherited
+        assert_eq!(
+            get("chapter1"),
+            yaml("code_split: true\nnotebook_output: true\ncode_solutions: false")
+        );
+        // The chapter overrides the part
+        assert_eq!(
+            get("doc1"),
+            yaml("code_split: true\nnotebook_output: false\ncode_solutions: false")
+        );
+        assert_eq!(
+            get("doc2"),
+            yaml("code_split: true\nnotebook_output: false\ncode_solutions: false\nlayout:\n  hide_sidebar: true")
+        );
+    }
+
+    fn counted(path: &str, numbered: bool, sections: usize) -> ProjectItem<(bool, usize)> {
+        ProjectItem {
+            id: path.to_string(),
+            format: InputFormat::Markdown,
+            path: PathBuf::from(path),
+            content: Arc::new((numbered, sections)),
+        }
+    }
+
+    /// A chapter with the id of its index.
+    fn chapter<C>(
+        index: ProjectItem<C>,
+        documents: Vec<ProjectItem<C>>,
+        chapters: Vec<Chapter<C>>,
+    ) -> Chapter<C> {
+        Chapter {
+            id: index.id.clone(),
+            index,
+            documents,
+            files: vec![],
+            chapters,
+        }
+    }
+
+    #[test]
+    fn test_section_numbers() {
+        let project = Project {
+            project_path: Default::default(),
+            index: counted("index", true, 1),
+            content: vec![
+                Part {
+                    id: "preface".to_string(),
+                    index: counted("preface", false, 0),
+                    chapters: vec![],
+                },
+                Part {
+                    id: "part".to_string(),
+                    index: counted("part", true, 2),
+                    chapters: vec![
+                        chapter(counted("ch1", true, 0), vec![], vec![]),
+                        chapter(
+                            counted("ch2", true, 1),
+                            vec![
+                                counted("doc1", true, 2),
+                                counted("notes", false, 3),
+                                counted("doc2", true, 1),
+                            ],
+                            vec![chapter(counted("sub", true, 1), vec![], vec![])],
+                        ),
+                    ],
+                },
+            ],
+        };
+
+        let numbers = section_numbers(&project);
+        let get = |path: &str| numbers.get(&PathBuf::from(path)).cloned();
+        let number = |prefix: Vec<usize>, first_section| {
+            Some(SectionNumber {
+                prefix,
+                first_section,
+            })
+        };
+
+        assert_eq!(get("index"), None);
+        assert_eq!(get("preface"), None);
+        assert_eq!(get("notes"), None);
+        // Part indices are numbered but their headings are not
+        assert_eq!(get("part"), number(vec![1], None));
+        assert_eq!(get("ch1"), number(vec![1, 1], Some(1)));
+        // Sections continue across the documents of a chapter
+        assert_eq!(get("ch2"), number(vec![1, 2], Some(1)));
+        assert_eq!(get("doc1"), number(vec![1, 2], Some(2)));
+        assert_eq!(get("doc2"), number(vec![1, 2], Some(4)));
+        // Nested chapters come after the sections of their parent
+        assert_eq!(get("sub"), number(vec![1, 2, 5], Some(1)));
+    }
+
+    #[test]
+    fn test_listing_offsets() {
+        let project = Project {
+            project_path: Default::default(),
+            index: counted("index", true, 1),
+            content: vec![Part {
+                id: "part".to_string(),
+                index: counted("part", true, 0),
+                chapters: vec![
+                    chapter(
+                        counted("ch1", true, 1),
+                        vec![counted("doc1", true, 2)],
+                        vec![],
+                    ),
+                    chapter(
+                        counted("ch2", true, 0),
+                        vec![counted("doc2", true, 1)],
+                        vec![],
+                    ),
+                ],
+            }],
+        }
+        .transform(&|item| item.content.1);
+
+        let offsets = |scope| {
+            let offsets = listing_offsets(&project, scope);
+            ["index", "part", "ch1", "doc1", "ch2", "doc2"]
+                .map(|path| offsets[&PathBuf::from(path)])
+        };
+        assert_eq!(offsets(NumberingScope::Document), [0; 6]);
+        assert_eq!(offsets(NumberingScope::Chapter), [0, 0, 0, 1, 0, 0]);
+        assert_eq!(offsets(NumberingScope::Project), [0, 1, 1, 2, 4, 4]);
+    }
+
+    #[test]
+    fn test_chapter_documents() {
+        let item = |path: &str, readable: bool| ProjectItem {
+            id: path.to_string(),
+            format: InputFormat::Markdown,
+            path: PathBuf::from(path),
+            content: Arc::new(readable.then(|| DocumentSummary {
+                id: path.to_string(),
+                title: path.to_uppercase(),
+                summary: None,
+                url: format!("/{}.html", path),
+            })),
+        };
+        let project = Project {
+            project_path: Default::default(),
+            index: item("index", true),
+            content: vec![Part {
+                id: "part".to_string(),
+                index: item("part", true),
+                chapters: vec![chapter(
+                    item("ch1", true),
+                    vec![item("doc1", true), item("broken", false)],
+                    vec![chapter(
+                        item("ch1/sub", true),
+                        vec![item("doc2", true)],
+                        vec![],
+                    )],
+                )],
+            }],
+        };
+
+        let lists = chapter_documents(&project);
+        let ids = |path: &str| {
+            lists[&PathBuf::from(path)]
+                .iter()
+                .map(|d| d.id.as_str())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids("ch1"), ["doc1", "ch1/sub"]);
+        assert_eq!(ids("ch1/sub"), ["doc2"]);
+        assert_eq!(lists[&PathBuf::from("ch1")][0].title, "DOC1");
+        assert!(!lists.contains_key(&PathBuf::from("part")));
+    }
+
+    #[test]
+    fn test_chapter_document_summaries() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = dir.path().join("content");
+        fs::create_dir_all(content.join("part/chapter")).unwrap();
+        fs::write(content.join("index.md"), "").unwrap();
+        fs::write(content.join("part/index.md"), "").unwrap();
+        fs::write(content.join("part/chapter/index.md"), "").unwrap();
+        fs::write(
+            content.join("part/chapter/a.md"),
+            "---\ntitle: A\n---\n# A\n\nFirst *paragraph*.\n\nSecond.\n",
+        )
+        .unwrap();
+        fs::write(
+            content.join("part/chapter/b.md"),
+            "---\ntitle: B\nsummary: Set\n---\nFirst.\n",
+        )
+        .unwrap();
+
+        let config: ProjectConfig = serde_yaml::from_str(&format!(
+            "outputs: [html]\nparsers:\n  html: {PARSER}\ncustom: {{}}\n"
+        ))
+        .unwrap();
+        let project = Project::generate_from_directory(dir.path()).unwrap();
+        let mut pipeline = Pipeline::with_templates(
+            dir.path(),
+            "draft".to_string(),
+            config,
+            project,
+            Tera::default(),
+            Tera::default(),
+        );
+        pipeline.update_metadata();
+
+        let documents = &pipeline.chapter_documents[&PathBuf::from("part/chapter/index.md")];
+        let summaries: Vec<_> = documents.iter().map(|d| d.summary.as_deref()).collect();
+        assert_eq!(summaries, [Some("First paragraph."), Some("Set")]);
+    }
+
+    #[test]
+    fn test_numbering_scope() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = dir.path().join("content");
+        fs::create_dir_all(content.join("part/chapter")).unwrap();
+        fs::write(content.join("index.md"), "").unwrap();
+        fs::write(content.join("part/index.md"), "").unwrap();
+        fs::write(content.join("part/chapter/index.md"), "").unwrap();
+        // The listing of the first document comes from a shortcode.
+        fs::write(
+            content.join("part/chapter/a.md"),
+            "---\ntitle: A\n---\n{{ code }}\n",
+        )
+        .unwrap();
+        fs::write(
+            content.join("part/chapter/b.md"),
+            "---\ntitle: B\n---\n```python title=\"b.py\"\nprint(2)\n```\n",
+        )
+        .unwrap();
+
+        let config: ProjectConfig = serde_yaml::from_str(
+            "outputs: [html]\n\
+            parsers:\n\
+            \x20 html:\n\
+            \x20   preprocessors: [{type: shortcodes}]\n\
+            \x20   event_processors: [{type: listings}]\n\
+            \x20   settings: {numbering_scope: project}\n\
+            custom: {}\n",
+        )
+        .unwrap();
+        let mut shortcode_tera = Tera::default();
+        shortcode_tera
+            .add_raw_template(
+                "html/code.tera.html",
+                "```python title=\"a.py\"\nprint(1)\n```",
+            )
+            .unwrap();
+        let project = Project::generate_from_directory(dir.path()).unwrap();
+        let mut pipeline = Pipeline::with_templates(
+            dir.path(),
+            "draft".to_string(),
+            config,
+            project,
+            Tera::default(),
+            shortcode_tera,
+        );
+        pipeline.update_metadata();
+
+        let render = |path: &str| {
+            let source = fs::read_to_string(content.join(path)).unwrap();
+            pipeline.render_source(Path::new(path), &source).unwrap()[&OutputFormat::Html]
+                .content
+                .as_text()
+                .unwrap()
+                .to_string()
+        };
+        // The second document continues the numbering of the first.
+        let a = render("part/chapter/a.md");
+        assert!(a.contains("Listing 1:"));
+        let b = render("part/chapter/b.md");
+        assert!(b.contains("Listing 2:"), "{}", b);
+    }
+
+    #[test]
+    fn test_build_all_streaming() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = dir.path().join("content");
+        fs::create_dir_all(content.join("part/chapter")).unwrap();
+        fs::write(content.join("index.md"), "---\ntitle: Course\n---\nWelcome").unwrap();
+        fs::write(content.join("part/index.md"), "---\ntitle: Part\n---\nPart").unwrap();
+        fs::write(
+            content.join("part/chapter/index.md"),
+            "---\ntitle: Chapter\n---\nChapter",
+        )
+        .unwrap();
+        fs::write(
+            content.join("part/chapter/intro.md"),
+            "---\ntitle: Intro\n---\nThe first paragraph.",
+        )
+        .unwrap();
+        fs::write(
+            content.join("part/chapter/empty.md"),
+            "---\ntitle: Empty\n---\n",
+        )
+        .unwrap();
+
+        let config: ProjectConfig = serde_yaml::from_str(&format!(
+            "outputs: [notebook, info]\n\
+            parsers:\n\
+            \x20 notebook: {PARSER}\n\
+            custom: {{}}\n\
+            empty_documents: {{action: skip}}\n"
+        ))
+        .unwrap();
+        let project = Project::generate_from_directory(dir.path()).unwrap();
+        let mut pipeline = Pipeline::with_templates(
+            dir.path(),
+            "draft".to_string(),
+            config,
+            project,
+            Tera::default(),
+            Tera::default(),
+        );
+        let report = pipeline.build_all_streaming(false).unwrap();
+
+        let build = dir.path().join("build");
+        assert!(build.join("notebooks/part/chapter/intro.ipynb").exists());
+        assert!(!build.join("notebooks/part/chapter/empty.ipynb").exists());
+        // The navigation tree has the metadata from the metadata pass, without skipped documents.
+        let info = fs::read_to_string(build.join("config.yml")).unwrap();
+        assert!(info.contains("title: Intro"));
+        assert!(info.contains("summary: The first paragraph."));
+        assert!(!info.contains("title: Empty"));
+
+        let warnings: Vec<&Diagnostic> = report.warnings().collect();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(
+            warnings[0].document,
+            Some(PathBuf::from("part/chapter/empty.md"))
+        );
+    }
+
+    const PARSER: &str = "{preprocessors: [], event_processors: [], settings: {}}";
+
+    /// Pipeline without templates that renders html and notebooks with the given parser.
+    fn memory_pipeline(parser: &str, extra_config: &str) -> Pipeline {
+        let config: ProjectConfig = serde_yaml::from_str(&format!(
+            "outputs: [html, notebook, info]\n\
+            parsers:\n\
+            \x20 html: {parser}\n\
+            \x20 notebook: {parser}\n\
+            custom: {{}}\n{extra_config}"
+        ))
+        .unwrap();
+        let project = Project {
+            project_path: PathBuf::new(),
+            index: item("index.md", "null").map(|_| ()),
+            content: vec![],
+        };
+        Pipeline::with_templates(
+            "",
+            "draft".to_string(),
+            config,
+            project,
+            Tera::default(),
+            Tera::default(),
+        )
+    }
+
+    #[test]
+    fn test_notebook_shortcodes() {
+        let config: ProjectConfig = serde_yaml::from_str(
+            "outputs: [html, notebook]\n\
+            parsers:\n\
+            \x20 html: {preprocessors: [{type: shortcodes}], event_processors: [], settings: {}}\n\
+            \x20 notebook: {preprocessors: [{type: shortcodes}], event_processors: [], settings: {}}\n\
+            custom: {}\n",
+        )
+        .unwrap();
+        let project = Project {
+            project_path: PathBuf::new(),
+            index: item("index.md", "null").map(|_| ()),
+            content: vec![],
+        };
+        let mut shortcodes = Tera::default();
+        shortcodes
+            .add_raw_templates(vec![
+                ("html/badge.tera.html", "<b>{{ text }}</b>"),
+                ("md/badge.tera.md", "**{{ text }}**"),
+            ])
+            .unwrap();
+        let pipeline = Pipeline::with_templates(
+            "",
+            "draft".to_string(),
+            config,
+            project,
+            Tera::default(),
+            shortcodes,
+        );
+
+        let source = r#"{"metadata": {}, "nbformat": 4, "nbformat_minor": 5, "cells": [
+            {"cell_type": "raw", "metadata": {}, "source": ["title: Doc"]},
+            {"cell_type": "markdown", "metadata": {}, "source": ["Cell {{ badge(text=new) }}"]},
+            {"cell_type": "code", "metadata": {}, "execution_count": null, "outputs": [],
+             "source": ["'{{ badge(text=code) }}'"]}
+        ]}"#;
+        let outputs = pipeline
+            .render_source(Path::new("part/doc.ipynb"), source)
+            .unwrap();
+        let html = outputs[&OutputFormat::Html].content.as_text().unwrap();
+        assert!(html.contains("Cell <b>new</b>"));
+        // Code cells are not preprocessed.
+        assert!(html.contains("{{ badge(text=code) }}"));
+        let notebook = serde_json::to_string(&outputs[&OutputFormat::Notebook].content).unwrap();
+        assert!(notebook.contains("Cell __new__"));
+    }
+
+    #[test]
+    fn test_html_policy() {
+        let render = |policy: &str| {
+            let config: ProjectConfig = serde_yaml::from_str(&format!(
+                "outputs: [html]\n\
+                parsers:\n\
+                \x20 html: {{preprocessors: [{{type: shortcodes}}, {{type: mathml}}], event_processors: [], settings: {{html_policy: {policy}}}}}\n\
+                custom: {{}}\n"
+            ))
+            .unwrap();
+            let project = Project {
+                project_path: PathBuf::new(),
+                index: item("index.md", "null").map(|_| ()),
+                content: vec![],
+            };
+            let mut shortcodes = Tera::default();
+            shortcodes
+                .add_raw_template(
+                    "html/badge.tera.html",
+                    "<b style=\"color: red\">{{ text }}</b>",
+                )
+                .unwrap();
+            let pipeline = Pipeline::with_templates(
+                "",
+                "draft".to_string(),
+                config,
+                project,
+                Tera::default(),
+                shortcodes,
+            );
+            pipeline
+                .render_source(
+                    Path::new("part/doc.md"),
+                    "---\ntitle: Doc\n---\nSome <span onclick=\"x()\">text</span> with $x$ and {{ badge(text=new) }}.\n",
+                )
+                .unwrap()
+                .remove(&OutputFormat::Html)
+                .unwrap()
+                .content
+                .as_text()
+                .unwrap()
+                .to_string()
+        };
+
+        let sanitized = render("sanitize");
+        assert!(sanitized.contains("Some <span>text</span> with "));
+        let stripped = render("strip");
+        assert!(stripped.contains("Some text with "));
+        // Only the HTML of the source is changed, not the HTML of math and shortcodes.
+        for html in [sanitized, stripped] {
+            assert!(!html.contains("onclick"));
+            assert!(html.contains("<math xmlns=\"http://www.w3.org/1998/Math/MathML\">"));
+            assert!(html.contains("<b style=\"color: red\">new</b>"));
+        }
+    }
+
+    #[test]
+    fn test_shortcode_usage() {
+        let config: ProjectConfig = serde_yaml::from_str(
+            "outputs: [html]\n\
+            parsers:\n\
+            \x20 html: {preprocessors: [{type: shortcodes}], event_processors: [], settings: {}}\n\
+            custom: {}\n",
+        )
+        .unwrap();
+        let project = Project {
+            project_path: PathBuf::new(),
+            index: item("index.md", "null").map(|_| ()),
+            content: vec![],
+        };
+        let mut shortcodes = Tera::default();
+        shortcodes
+            .add_raw_templates(vec![
+                ("html/badge.tera.html", "<b>{{ text }}</b>"),
+                ("html/note.tera.html", "<div>{{ body }}</div>"),
+                ("html/unused.tera.html", ""),
+            ])
+            .unwrap();
+        let pipeline = Pipeline::with_templates(
+            "",
+            "draft".to_string(),
+            config,
+            project,
+            Tera::default(),
+            shortcodes,
+        );
+
+        pipeline
+            .render_source(
+                Path::new("part/a.md"),
+                "---\ntitle: Doc\n---\n{% note %}\n{{ badge(text=a) }}\n{% end %}\n",
+            )
+            .unwrap();
+        pipeline
+            .render_source(
+                Path::new("part/b.md"),
+                "---\ntitle: Doc\n---\nText {{ badge(text=b) }}\n",
+            )
+            .unwrap();
+
+        let usage = serde_json::to_value(pipeline.shortcode_usage()).unwrap();
+        assert_eq!(
+            usage,
+            serde_json::json!({
+                "html/badge.tera.html": ["part/a.md", "part/b.md"],
+                "html/note.tera.html": ["part/a.md"],
+                "html/unused.tera.html": [],
+            })
+        );
+    }
+
+    #[test]
+    fn test_check_all() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = dir.path().join("content");
+        fs::create_dir_all(content.join("part/chapter")).unwrap();
+        fs::write(content.join("index.md"), "---\ntitle: Home\n---\n").unwrap();
+        fs::write(content.join("part/index.md"), "---\ntitle: Part\n---\n").unwrap();
+        fs::write(
+            content.join("part/chapter/index.md"),
+            "---\ntitle: [\n---\n",
+        )
+        .unwrap();
+        fs::write(
+            content.join("part/chapter/doc.md"),
+            "---\ntitle: Doc\n---\n\nSee {{ missing }}.\n",
+        )
+        .unwrap();
+
+        let config: ProjectConfig = serde_yaml::from_str(
+            "outputs: [html]\n\
+            parsers:\n\
+            \x20 html: {preprocessors: [{type: shortcodes}], event_processors: [], settings: {}}\n\
+            custom: {}\n",
+        )
+        .unwrap();
+        let project = Project::generate_from_directory(dir.path()).unwrap();
+        let mut pipeline = Pipeline::with_templates(
+            dir.path(),
+            "draft".to_string(),
+            config,
+            project,
+            Tera::default(),
+            Tera::default(),
+        );
+
+        let report = pipeline.check_all().unwrap();
+        let errors: Vec<_> = report.errors().collect();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(
+            errors[0].document,
+            Some(PathBuf::from("part/chapter/index.md"))
+        );
+        assert_eq!(
+            errors[1].document,
+            Some(PathBuf::from("part/chapter/doc.md"))
+        );
+        assert_eq!(
+            errors[1].position,
+            Some(SourcePosition {
+                cell: None,
+                line: 5,
+                column: Some(5),
+            })
+        );
+        assert!(errors[1].message.contains("unknown shortcode `missing`"));
+        assert!(!dir.path().join("build").exists());
+    }
+
+    #[test]
+    fn test_check_links() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = dir.path().join("content");
+        fs::create_dir_all(content.join("part/chapter")).unwrap();
+        fs::write(content.join("index.md"), "---\ntitle: Home\n---\n").unwrap();
+        fs::write(content.join("part/index.md"), "---\ntitle: Part\n---\n").unwrap();
+        fs::write(
+            content.join("part/chapter/index.md"),
+            "---\ntitle: Chapter\n---\n# Intro\n",
+        )
+        .unwrap();
+        fs::write(
+            content.join("part/chapter/doc.md"),
+            "---\ntitle: Doc\n---\n# Usage\n\n\
+            [a](#usage) [b](index.html#intro) [c](../../index.html) [d](https://example.com)\n\n\
+            [e](missing.html) [f](index.html#outro)\n",
+        )
+        .unwrap();
+
+        let config: ProjectConfig = serde_yaml::from_str(
+            "outputs: [html]\n\
+            parsers:\n\
+            \x20 html: {preprocessors: [], event_processors: [], settings: {}}\n\
+            custom: {}\n",
+        )
+        .unwrap();
+        let project = Project::generate_from_directory(dir.path()).unwrap();
+        let mut pipeline = Pipeline::with_templates(
+            dir.path(),
+            "draft".to_string(),
+            config,
+            project,
+            Tera::default(),
+            Tera::default(),
+        );
+
+        let report = pipeline.check_all().unwrap();
+        let errors: Vec<_> = report.errors().collect();
+        assert_eq!(errors.len(), 2);
+        assert!(errors
+            .iter()
+            .all(|e| e.document == Some(PathBuf::from("part/chapter/doc.md"))));
+        assert!(errors[0].message.contains("`missing.html`"));
+        assert_eq!(errors[0].position.map(|p| p.line), Some(8));
+        assert!(errors[1].message.contains("id `outro`"));
+    }
+
+    #[test]
+    fn test_smart_punctuation() {
+        let pipeline = memory_pipeline(PARSER, "");
+        let render = |path: &str, front_matter: &str| {
+            let source = format!("---\ntitle: Doc\n{front_matter}---\n\"Quoted\" -- text\n");
+            pipeline
+                .render_source(Path::new(path), &source)
+                .unwrap()
+                .remove(&OutputFormat::Html)
+                .unwrap()
+                .content
+                .as_text()
+                .unwrap()
+                .to_string()
+        };
+
+        let smart = render("part/smart.md", "");
+        assert!(smart.contains("\u{201c}Quoted\u{201d} \u{2013} text"));
+        let plain = render("part/plain.md", "smart_punctuation: false\n");
+        assert!(plain.contains("&quot;Quoted&quot; -- text"));
+        // The option only applies to the document that sets it.
+        assert_eq!(render("part/other.md", ""), smart);
+    }
+
+    #[test]
+    fn test_render_source() {
+        let pipeline = memory_pipeline(PARSER, "");
+
+        let source = "---\ntitle: Doc\n---\n# Heading\n\nSome *text*.\n";
+        let outputs = pipeline
+            .render_source(Path::new("part/doc.md"), source)
+            .unwrap();
+
+        // Info doesn't render documents.
+        assert_eq!(outputs.len(), 2);
+        assert!(outputs.contains_key(&OutputFormat::Notebook));
+        let html = &outputs[&OutputFormat::Html];
+        assert_eq!(html.metadata.title, Some("Doc".to_string()));
+        assert!(html.content.as_text().unwrap().contains("<em>text</em>"));
+
+        assert!(pipeline
+            .render_source(Path::new("part/doc.txt"), source)
+            .is_err());
+    }
+
+    #[test]
+    fn test_summary() {
+        let pipeline = memory_pipeline(PARSER, "");
+        let render = |source: &str| {
+            pipeline
+                .render_source(Path::new("part/doc.md"), source)
+                .unwrap()
+                .remove(&OutputFormat::Html)
+                .unwrap()
+        };
+
+        let html =
+            render("---\ntitle: Doc\n---\n# Doc\n\nFirst.\n\nSecond\n\n<!--more-->\n\nRest.\n");
+        assert_eq!(html.metadata.summary.as_deref(), Some("First. Second"));
+        assert!(!html.content.as_text().unwrap().contains("<!--more-->"));
+
+        let html = render("---\ntitle: Doc\n---\n# Doc\n\nFirst `code`\nline.\n\nRest.\n");
+        assert_eq!(html.metadata.summary.as_deref(), Some("First code line."));
+
+        // A summary in the front matter takes precedence.
+        let html = render("---\ntitle: Doc\nsummary: Set\n---\nFirst.\n");
+        assert_eq!(html.metadata.summary.as_deref(), Some("Set"));
+    }
+
+    #[test]
+    fn test_title_overrides() {
+        let render = |extra_config: &str, source: &str| {
+            memory_pipeline(PARSER, extra_config)
+                .render_source(Path::new("part/doc.md"), source)
+                .unwrap()
+                .remove(&OutputFormat::Html)
+                .unwrap()
+                .metadata
+                .title
+        };
+        let source = "---\ntitle: Doc\n---\nText.\n";
+
+        assert_eq!(render("", source), Some("Doc".to_string()));
+        assert_eq!(
+            render("titles:\n  part/doc.md: New\n  other/doc.md: Other", source),
+            Some("New".to_string())
+        );
+        // Bare ids are ambiguous and don't match.
+        assert_eq!(
+            render("titles:\n  doc: New", source),
+            Some("Doc".to_string())
+        );
+        // Documents without a title get it too.
+        assert_eq!(
+            render(
+                "titles:\n  part/doc.md: New",
+                "---\nsummary: S\n---\nText.\n"
+            ),
+            Some("New".to_string())
+        );
+    }
+
+    #[test]
+    fn test_hide_title_heading() {
+        let pipeline = memory_pipeline(PARSER, "");
+        let render = |source: &str| {
+            let html = pipeline
+                .render_source(Path::new("part/doc.md"), source)
+                .unwrap()
+                .remove(&OutputFormat::Html)
+                .unwrap();
+            (html.content.as_text().unwrap().to_string(), html.variables)
+        };
+
+        let (text, _) = render("---\ntitle: Doc\n---\n# Doc\n\nText.\n");
+        assert!(text.contains("<h1"));
+
+        let (text, variables) = render(
+            "---\ntitle: Doc\nlayout:\n  hide_title_heading: true\n---\n# Doc\n\nText.\n\n## Doc\n",
+        );
+        assert!(!text.contains("<h1"));
+        assert!(text.contains(r#"<h2 id="doc-1""#));
+        assert_eq!(variables.headings.len(), 2);
+        assert_eq!(variables.headings[0].text, "Doc");
+    }
+
+    #[test]
+    fn test_empty_documents() {
+        let empty = Path::new("part/empty.md");
+
+        let outputs = memory_pipeline(PARSER, "")
+            .render_source(empty, "")
+            .unwrap();
+        assert_eq!(outputs.len(), 2);
+        let html = &outputs[&OutputFormat::Html];
+        assert_eq!(html.metadata.title, Some("Untitled".to_string()));
+
+        let pipeline = memory_pipeline(
+            PARSER,
+            "empty_documents:\n  action: render\n  title: Draft\n",
+        );
+        let outputs = pipeline.render_source(empty, " \n\n").unwrap();
+        assert_eq!(
+            outputs[&OutputFormat::Html].metadata.title,
+            Some("Draft".to_string())
+        );
+        // Documents with a title keep it.
+        let outputs = pipeline
+            .render_source(empty, "---\ntitle: Notes\n---\n")
+            .unwrap();
+        assert_eq!(
+            outputs[&OutputFormat::Html].metadata.title,
+            Some("Notes".to_string())
+        );
+
+        let pipeline = memory_pipeline(PARSER, "empty_documents:\n  action: skip\n");
+        assert!(pipeline.render_source(empty, "").unwrap().is_empty());
+        assert!(pipeline
+            .render_source(empty, "---\ntitle: Notes\n---\n\n  \n")
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            pipeline
+                .render_source(empty, "---\ntitle: Notes\n---\n`code`")
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let source = "---\ntitle: Exercise\n---\nText\n\n```python\n\
+            #| << CODE\n#| >> SOLUTION <<\nprint(\"solution\")\n#| >> END_CODE\n```\n";
+        let parser = "{preprocessors: [], event_processors: [{type: code_split}], settings: {}}";
+        let pipeline = memory_pipeline(parser, "empty_documents:\n  action: skip\n");
+        pipeline
+            .render_source(Path::new("part/exercise.md"), source)
+            .unwrap();
+        pipeline
+            .render_source(Path::new("part/empty.md"), "")
+            .unwrap();
+
+        // The warnings of a document are only recorded once, although it is rendered for two
+        // output formats.
+        let report = pipeline.report_build(vec![anyhow!("Broken")]);
+        assert_eq!(report.errors().count(), 1);
+        let warnings: Vec<&Diagnostic> = report.warnings().collect();
+        assert_eq!(warnings.len(), 2);
+        assert_eq!(
+            warnings[0].document,
+            Some(PathBuf::from("part/exercise.md"))
+        );
+        assert!(warnings[0].message.starts_with("Empty placeholder"));
+        assert_eq!(warnings[1].document, Some(PathBuf::from("part/empty.md")));
+        assert_eq!(warnings[1].message, "Skipping empty document");
+
+        // The next build starts without diagnostics.
+        assert!(pipeline.report_build(Vec::new()).diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_include_solutions() {
+        let source = "---\ntitle: Exercise\n---\n```python\n\
+            #| << CODE\n# print(\"placeholder\")\n#| >> SOLUTION <<\n\
+            print(\"solution\")\n#| >> END_CODE\n```\n";
+        let render = |solutions: bool, source: &str| {
+            let parser = format!(
+                "{{preprocessors: [], event_processors: [{{type: code_split}}], \
+                settings: {{solutions: {solutions}}}}}"
+            );
+            memory_pipeline(&parser, "")
+                .render_source(Path::new("part/exercise.md"), source)
+                .unwrap()
+                .into_iter()
+                .map(|(format, doc)| (format, doc.content.as_text().unwrap().to_string()))
+                .collect::<HashMap<_, _>>()
+        };
+
+        let outputs = render(true, source);
+        assert_eq!(outputs.len(), 2);
+        for output in outputs.values() {
+            assert!(output.contains("solution"));
+            assert!(!output.contains("placeholder"));
+        }
+        for output in render(false, source).values() {
+            assert!(output.contains("placeholder"));
+            assert!(!output.contains("solution"));
+        }
+
+        let hidden = source.replace("title: Exercise", "title: Exercise\ncode_solutions: false");
+        for output in render(true, &hidden).values() {
+            assert!(output.contains("placeholder"));
+        }
+    }
+
+    #[test]
+    fn test_build_vars() {
+        let source = "---\ntitle: Exercise\n---\nTask.\n\n\
+            {% if var.audience == \"instructor\" %}\nInstructor notes.\n{% end %}\n";
+        let render = |audience: &str| {
+            let parser =
+                "{preprocessors: [{type: shortcodes}], event_processors: [], settings: {}}";
+            let mut pipeline = memory_pipeline(parser, "vars: {audience: instructor}");
+            pipeline
                 .project_config
-                .parsers
-                .get(&format)
-                .ok_or_else(|| anyhow!("Invalid format"))?
-                .parse(&doc, &meta, &processor_ctx)?;
+                .set_vars(&[format!("audience={audience}")])
+                .unwrap();
+            pipeline
+                .render_source(Path::new("part/exercise.md"), source)
+                .unwrap()
+                .into_iter()
+                .map(|(format, doc)| (format, doc.content.as_text().unwrap().to_string()))
+                .collect::<HashMap<_, _>>()
+        };
 
-            // let res = print_err(res)?;
-            if let Some(renderer) = format.renderer() {
-                Ok(Some(renderer.render(&res)))
-            } else {
-                Ok(None)
-            }
-        } else {
-            Ok(None)
+        let outputs = render("instructor");
+        assert_eq!(outputs.len(), 2);
+        for output in outputs.values() {
+            assert!(output.contains("Task."));
+            assert!(output.contains("Instructor notes."));
+        }
+        for output in render("student").values() {
+            assert!(output.contains("Task."));
+            assert!(!output.contains("Instructor"));
         }
     }
 }
@@ -45,7 +45,7 @@ impl Mover {
                         "md" | "ipynb" => {}
                         "py" => {
                             let input = fs::read_to_string(entry_path.as_path())?;
-                            let parsed = parse_code_string(&input)?;
+                            let parsed = parse_code_string(&input, "python")?;
                             let output = parsed.write_string(ctx.settings.solutions);
 
                             // let mut file = fs::OpenOptions::new().write(true).create(true).append(false).open(section_build_path)?;
@@ -0,0 +1,163 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use cdoc::document::SourcePosition;
+use cdoc::processors::Error as ProcessorError;
+use console::style;
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// A problem found while building the project.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Path of the document (relative to the content folder) if the problem is in a document.
+    pub document: Option<PathBuf>,
+    /// Where the problem is in the document, if known.
+    pub position: Option<SourcePosition>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// The diagnostic of a build error. Errors of documents (see [DocumentFailed]) keep the path
+    /// of the document and the position of the error in it, if known.
+    pub(crate) fn from_error(error: &anyhow::Error) -> Self {
+        let document = error.downcast_ref::<DocumentFailed>().map(|d| d.0.clone());
+        let position = error
+            .chain()
+            .find_map(|e| e.downcast_ref::<ProcessorError>())
+            .and_then(ProcessorError::position);
+        let message = match document {
+            // The document is shown by the diagnostic instead of the context.
+            Some(_) => error
+                .chain()
+                .skip(1)
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(": "),
+            None => format!("{:#}", error),
+        };
+        Diagnostic {
+            severity: Severity::Error,
+            document,
+            position,
+            message,
+        }
+    }
+}
+
+/// Context of the error of a document that failed to process. The path is relative to the
+/// content folder.
+#[derive(Debug)]
+pub(crate) struct DocumentFailed(pub PathBuf);
+
+impl Display for DocumentFailed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Failed to process document – {}",
+            style(format!("content/{}", self.0.display())).italic()
+        )
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(document) = &self.document {
+            write!(f, "content/{}", document.display())?;
+            if let Some(position) = &self.position {
+                write!(f, " ({})", position)?;
+            }
+            write!(f, ": ")?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+/// The diagnostics of a build, in the order they were found.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct BuildReport {
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl BuildReport {
+    pub fn errors(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.with_severity(Severity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.with_severity(Severity::Warning)
+    }
+
+    fn with_severity(&self, severity: Severity) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics
+            .iter()
+            .filter(move |d| d.severity == severity)
+    }
+}
+
+/// The documents (relative to the content folder) that use each shortcode template, keyed by
+/// template name. It shows which documents are affected when a template is changed or removed.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct ShortcodeUsage(pub BTreeMap<String, BTreeSet<PathBuf>>);
+
+impl ShortcodeUsage {
+    pub const FILE_NAME: &'static str = "shortcode-usage.json";
+
+    pub fn write(&self, build_dir: &Path) -> anyhow::Result<()> {
+        fs::create_dir_all(build_dir)?;
+        fs::write(
+            build_dir.join(Self::FILE_NAME),
+            serde_json::to_string_pretty(self)?,
+        )
+        .context("Could not write shortcode usage report")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diagnostic(severity: Severity) -> Diagnostic {
+        Diagnostic {
+            severity,
+            document: Some(PathBuf::from("part/doc.md")),
+            position: Some(SourcePosition {
+                cell: None,
+                line: 4,
+                column: Some(2),
+            }),
+            message: "Something".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_severities() {
+        let report = BuildReport {
+            diagnostics: vec![
+                diagnostic(Severity::Warning),
+                diagnostic(Severity::Error),
+                diagnostic(Severity::Warning),
+            ],
+        };
+        assert_eq!(report.warnings().count(), 2);
+        assert_eq!(report.errors().count(), 1);
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            diagnostic(Severity::Warning).to_string(),
+            "content/part/doc.md (line 4, column 2): Something"
+        );
+    }
+}
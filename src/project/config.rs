@@ -1,23 +1,528 @@
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
-use serde::{Deserialize, Serialize};
+use anyhow::Context;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Deserializer, Serialize};
 
 use cdoc::config::OutputFormat;
-use cdoc::parser::Parser;
+use cdoc::loader::NotebookLoader;
+use cdoc::notebook::OutputFallback;
+use cdoc::parser::{Parser, ParserSettings};
+use cdoc::renderers::HtmlPolicy;
+
+use crate::project::section_id;
 
 /// Refers to a configuration.yml file in the project that specifies a variety
 /// of options for the project.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProjectConfig {
-    #[serde(default)]
+    /// Path the site is served under (e.g. `/courses` for `https://host/courses/`). It is prepended
+    /// to root-relative links in documents. Also accepted as `path_prefix`.
+    #[serde(
+        default,
+        alias = "path_prefix",
+        deserialize_with = "deserialize_url_prefix"
+    )]
     pub url_prefix: String,
+    /// Absolute URL of the site without the url prefix (e.g. `https://example.com`). Web pages link
+    /// to their canonical URL if it is set.
+    #[serde(default)]
+    pub base_url: Option<String>,
+    /// Layout of the generated web pages.
+    #[serde(default)]
+    pub url_style: UrlStyle,
+    /// Folder for the build outputs. A relative path is resolved against the project folder (the
+    /// folder that contains `config.yml`), an absolute path is used as-is.
+    #[serde(default = "default_build_path")]
+    pub build_path: PathBuf,
     #[serde(default = "default_config")]
     pub build: HashMap<String, BuildConfig>,
     #[serde(default)]
     pub repository: RepositoryConfig,
     pub outputs: Vec<OutputFormat>,
     pub parsers: HashMap<OutputFormat, Parser>,
+    /// Parser settings for every output format. The values that are set override the settings of
+    /// the individual parsers.
+    #[serde(default)]
+    pub parser_settings: ParserSettingsConfig,
     pub custom: HashMap<String, serde_yaml::Value>,
+    /// List shortcode templates that were never used by any document after a full build.
+    #[serde(default)]
+    pub report_unused_shortcodes: bool,
+    /// Write `shortcode-usage.json` to the build folder, listing the documents that use each
+    /// shortcode template.
+    #[serde(default)]
+    pub report_shortcode_usage: bool,
+    /// Selects which non-document files in chapter folders are included as chapter files.
+    #[serde(default)]
+    pub files: FilesConfig,
+    /// Emit a JSON Feed (`feed.json`) of the web pages.
+    #[serde(default)]
+    pub feed: Option<FeedConfig>,
+    /// Number parts, chapters and headings (e.g. 1.2.3). Disabled if not set.
+    #[serde(default)]
+    pub numbering: Option<NumberingConfig>,
+    /// Settings for the `pdf` output.
+    #[serde(default)]
+    pub pdf: PdfConfig,
+    /// Settings for the `epub` output.
+    #[serde(default)]
+    pub epub: EpubConfig,
+    /// Fingerprinting of static assets for cache busting.
+    #[serde(default)]
+    pub assets: AssetsConfig,
+    /// Minify the generated HTML pages.
+    #[serde(default)]
+    pub minify_html: bool,
+    /// How documents without any text are handled.
+    #[serde(default)]
+    pub empty_documents: EmptyDocumentsConfig,
+    /// Options for loading notebooks.
+    #[serde(default)]
+    pub notebook_loader: NotebookLoader,
+    /// Default front matter for every document. It is overridden by the front matter documents
+    /// inherit from their parents and by their own.
+    #[serde(default)]
+    pub front_matter: serde_yaml::Value,
+    /// Titles that replace the titles of documents, keyed by the path of the document in the
+    /// content folder (e.g. `part1/chapter1/intro.md`). Ids are not used since they are only
+    /// unique within a chapter.
+    #[serde(default)]
+    pub titles: HashMap<String, String>,
+    /// Build variables, available to shortcodes and templates as `var` (e.g. `var.audience`).
+    /// They can be overridden on the command line with `--var name=value`.
+    #[serde(default)]
+    pub vars: HashMap<String, serde_yaml::Value>,
+    /// LaTeX macros for the math preprocessors of all parsers, e.g. `\vx` for `\mathbf{x}`.
+    #[serde(default)]
+    pub math_macros: HashMap<String, String>,
+}
+
+/// How web pages are laid out in the build folder, which determines their URLs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UrlStyle {
+    /// Each document is a file named after its id (`part/doc.html`).
+    #[default]
+    Flat,
+    /// Each document is an `index.html` in a folder named after its id (`part/doc/index.html`)
+    /// and its URL ends with a slash (`part/doc/`).
+    Pretty,
+}
+
+impl UrlStyle {
+    /// Location of the web page of a document relative to the build folder. The page is named
+    /// after the id of the document (see [section_id]). Index documents are always written as
+    /// `index.html` in their folder.
+    pub fn page_path(&self, doc_path: &Path) -> PathBuf {
+        let id = section_id(doc_path).unwrap_or_default();
+        match self {
+            UrlStyle::Flat => doc_path.with_file_name(format!("{}.html", id)),
+            UrlStyle::Pretty if id == "index" => doc_path.with_file_name("index.html"),
+            UrlStyle::Pretty => doc_path.with_file_name(id).join("index.html"),
+        }
+    }
+
+    /// Prefix that makes the relative links of a document work from its web page. Pretty pages of
+    /// documents other than indexes are one folder deeper than the document.
+    pub fn relative_prefix(&self, doc_path: &Path) -> &'static str {
+        match self {
+            UrlStyle::Pretty if section_id(doc_path).as_deref() != Some("index") => "../",
+            _ => "",
+        }
+    }
+
+    /// URL of the web page of a document relative to the site root.
+    pub fn page_url(&self, doc_path: &Path) -> String {
+        let path = self.page_path(doc_path);
+        let url = path
+            .iter()
+            .map(|c| c.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        match self {
+            UrlStyle::Flat => url,
+            UrlStyle::Pretty => url.strip_suffix("index.html").unwrap_or(&url).to_string(),
+        }
+    }
+}
+
+/// Normalize the url prefix to start with a slash and not end with one (the root is an empty
+/// prefix).
+fn deserialize_url_prefix<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let prefix = String::deserialize(deserializer)?;
+    let prefix = prefix.trim().trim_matches('/');
+    Ok(if prefix.is_empty() {
+        String::new()
+    } else {
+        format!("/{}", prefix)
+    })
+}
+
+fn default_build_path() -> PathBuf {
+    PathBuf::from("build")
+}
+
+impl ProjectConfig {
+    /// URL of the web page of a document (e.g. `/courses/part/doc.html`).
+    pub fn page_url(&self, doc_path: &Path) -> String {
+        format!("{}/{}", self.url_prefix, self.url_style.page_url(doc_path))
+    }
+
+    /// The title set for a document in `titles`, if any.
+    pub fn title_override(&self, doc_path: &Path) -> Option<&String> {
+        doc_path.to_str().and_then(|path| self.titles.get(path))
+    }
+
+    /// Absolute URL of the web page of a document if the `base_url` is set.
+    pub fn canonical_url(&self, doc_path: &Path) -> Option<String> {
+        self.base_url
+            .as_ref()
+            .map(|base| format!("{}{}", base.trim_end_matches('/'), self.page_url(doc_path)))
+    }
+
+    /// Apply the project-level parser settings to the settings of each parser.
+    pub fn apply_parser_settings(&mut self) {
+        for parser in self.parsers.values_mut() {
+            self.parser_settings.apply(&mut parser.settings);
+        }
+    }
+
+    /// Set build variables from `name=value` assignments, e.g. given on the command line. They
+    /// override the variables of the configuration file. Values are parsed like scalars in the
+    /// configuration file, so `year=2024` is a number and `draft=true` a boolean. Other values
+    /// (including lists and maps) are strings.
+    pub fn set_vars(&mut self, assignments: &[String]) -> anyhow::Result<()> {
+        for assignment in assignments {
+            let (name, value) = assignment
+                .split_once('=')
+                .filter(|(name, _)| !name.trim().is_empty())
+                .with_context(|| {
+                    format!("Invalid variable '{}', expected name=value", assignment)
+                })?;
+            let value = match serde_yaml::from_str(value) {
+                Ok(
+                    scalar @ (serde_yaml::Value::Bool(_)
+                    | serde_yaml::Value::Number(_)
+                    | serde_yaml::Value::String(_)),
+                ) => scalar,
+                _ => serde_yaml::Value::String(value.trim().to_string()),
+            };
+            self.vars.insert(name.trim().to_string(), value);
+        }
+        Ok(())
+    }
+
+    /// Resolve the build folder for the project in `project_path`. The result does not depend on
+    /// the working directory unless `project_path` is relative.
+    pub fn resolve_build_path(&self, project_path: &Path) -> PathBuf {
+        if self.build_path.is_absolute() {
+            self.build_path.clone()
+        } else {
+            project_path.join(&self.build_path)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeedConfig {
+    pub title: String,
+    /// Address of the site without the url prefix, e.g. `https://example.com`.
+    pub base_url: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NumberingConfig {
+    /// Heading level of the sections in a chapter (e.g. 2 for `##`). Higher levels are not numbered.
+    #[serde(default = "default_start_level")]
+    pub start_level: usize,
+}
+
+fn default_start_level() -> usize {
+    2
+}
+
+/// Project-level [ParserSettings]. Unset values leave the settings of the parsers unchanged.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ParserSettingsConfig {
+    /// Show the solutions of exercises instead of their placeholders.
+    #[serde(default)]
+    pub solutions: Option<bool>,
+    /// Include the outputs of notebook cells.
+    #[serde(default)]
+    pub notebook_outputs: Option<bool>,
+    /// How raw HTML is handled (allow, sanitize or strip).
+    #[serde(default)]
+    pub html_policy: Option<HtmlPolicy>,
+    /// Show notebook code cells with Jupyter-like prompts.
+    #[serde(default)]
+    pub cell_prompts: Option<bool>,
+    /// Highlight notebook code cells in their own language (e.g. from a `%%bash` magic).
+    #[serde(default)]
+    pub cell_languages: Option<bool>,
+    /// What is shown for notebook outputs that can't be rendered.
+    #[serde(default)]
+    pub output_fallback: Option<OutputFallback>,
+}
+
+impl ParserSettingsConfig {
+    fn apply(&self, settings: &mut ParserSettings) {
+        if let Some(solutions) = self.solutions {
+            settings.solutions = solutions;
+        }
+        if let Some(notebook_outputs) = self.notebook_outputs {
+            settings.notebook_outputs = notebook_outputs;
+        }
+        if let Some(html_policy) = self.html_policy {
+            settings.html_policy = html_policy;
+        }
+        if let Some(cell_prompts) = self.cell_prompts {
+            settings.cell_prompts = cell_prompts;
+        }
+        if let Some(cell_languages) = self.cell_languages {
+            settings.cell_languages = cell_languages;
+        }
+        if let Some(output_fallback) = self.output_fallback {
+            settings.output_fallback = output_fallback;
+        }
+    }
+}
+
+/// What happens to documents without any text (e.g. an empty file or one with only front matter).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmptyDocumentAction {
+    /// Render the document like any other. It gets the fallback title if it has none.
+    #[default]
+    Render,
+    /// Leave the document out of the outputs and the navigation with a warning.
+    Skip,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmptyDocumentsConfig {
+    #[serde(default)]
+    pub action: EmptyDocumentAction,
+    /// Title of rendered empty documents that don't set one.
+    #[serde(default = "default_empty_title")]
+    pub title: String,
+}
+
+fn default_empty_title() -> String {
+    "Untitled".to_string()
+}
+
+impl Default for EmptyDocumentsConfig {
+    fn default() -> Self {
+        EmptyDocumentsConfig {
+            action: EmptyDocumentAction::default(),
+            title: default_empty_title(),
+        }
+    }
+}
+
+/// External program used to convert HTML to PDF.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PdfEngine {
+    #[default]
+    Weasyprint,
+    Wkhtmltopdf,
+    /// Headless Chromium (or Chrome).
+    Chromium,
+}
+
+impl PdfEngine {
+    /// The usual name of the executable.
+    pub fn default_command(&self) -> &str {
+        match self {
+            PdfEngine::Weasyprint => "weasyprint",
+            PdfEngine::Wkhtmltopdf => "wkhtmltopdf",
+            PdfEngine::Chromium => "chromium",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PdfConfig {
+    #[serde(default)]
+    pub engine: PdfEngine,
+    /// Name or path of the engine executable. Defaults to [PdfEngine::default_command].
+    #[serde(default)]
+    pub command: Option<String>,
+    /// Name of the generated file in the pdf build folder.
+    #[serde(default = "default_pdf_file_name")]
+    pub file_name: String,
+    /// Additional stylesheets included by the default PDF layout, after the KaTeX stylesheet.
+    #[serde(default)]
+    pub stylesheets: Vec<String>,
+}
+
+impl Default for PdfConfig {
+    fn default() -> Self {
+        PdfConfig {
+            engine: PdfEngine::default(),
+            command: None,
+            file_name: default_pdf_file_name(),
+            stylesheets: vec![],
+        }
+    }
+}
+
+fn default_pdf_file_name() -> String {
+    "book.pdf".to_string()
+}
+
+/// Metadata and embedded files of the `epub` output.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EpubConfig {
+    /// Book title. Defaults to the title of the project index.
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub author: Option<String>,
+    #[serde(default = "default_epub_language")]
+    pub language: String,
+    /// Unique identifier of the book (e.g. an ISBN or URL). Derived from the title by default.
+    #[serde(default)]
+    pub identifier: Option<String>,
+    /// Name of the generated file in the epub build folder.
+    #[serde(default = "default_epub_file_name")]
+    pub file_name: String,
+    /// Stylesheets to include in every page (relative to the project folder).
+    #[serde(default)]
+    pub stylesheets: Vec<PathBuf>,
+    /// Font files to embed (relative to the project folder).
+    #[serde(default)]
+    pub fonts: Vec<PathBuf>,
+}
+
+impl Default for EpubConfig {
+    fn default() -> Self {
+        EpubConfig {
+            title: None,
+            author: None,
+            language: default_epub_language(),
+            identifier: None,
+            file_name: default_epub_file_name(),
+            stylesheets: vec![],
+            fonts: vec![],
+        }
+    }
+}
+
+fn default_epub_language() -> String {
+    "en".to_string()
+}
+
+fn default_epub_file_name() -> String {
+    "book.epub".to_string()
+}
+
+/// Settings for fingerprinting assets in `resources/` (e.g. `style.css` becomes
+/// `style.3f2a9c1b.css`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AssetsConfig {
+    #[serde(default)]
+    pub fingerprint: bool,
+    /// Extensions of the files to fingerprint.
+    #[serde(default = "default_fingerprint_extensions")]
+    pub extensions: Vec<String>,
+}
+
+impl Default for AssetsConfig {
+    fn default() -> Self {
+        AssetsConfig {
+            fingerprint: false,
+            extensions: default_fingerprint_extensions(),
+        }
+    }
+}
+
+fn default_fingerprint_extensions() -> Vec<String> {
+    vec!["css".to_string(), "js".to_string()]
+}
+
+/// Glob patterns matched against the file names of non-document files in chapter folders.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FilesConfig {
+    /// Only include files matching at least one of these patterns. All files are included if
+    /// the list is empty.
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Exclude files matching any of these patterns. Defaults to dotfiles and common editor and
+    /// OS junk files.
+    #[serde(default = "default_exclude")]
+    pub exclude: Vec<String>,
+}
+
+impl Default for FilesConfig {
+    fn default() -> Self {
+        FilesConfig {
+            include: Vec::new(),
+            exclude: default_exclude(),
+        }
+    }
+}
+
+fn default_exclude() -> Vec<String> {
+    [
+        ".*",
+        "*~",
+        "#*#",
+        "*.swp",
+        "*.tmp",
+        "Thumbs.db",
+        "desktop.ini",
+    ]
+    .into_iter()
+    .map(String::from)
+    .collect()
+}
+
+impl FilesConfig {
+    pub fn build(&self) -> anyhow::Result<FileFilter> {
+        Ok(FileFilter {
+            include: glob_set(&self.include)?,
+            exclude: glob_set(&self.exclude)?,
+        })
+    }
+}
+
+fn glob_set(patterns: &[String]) -> anyhow::Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder
+            .add(Glob::new(pattern).with_context(|| format!("Invalid file pattern {}", pattern))?);
+    }
+    Ok(builder.build()?)
+}
+
+/// Compiled version of [FilesConfig].
+#[derive(Debug, Clone)]
+pub struct FileFilter {
+    include: GlobSet,
+    exclude: GlobSet,
+}
+
+impl FileFilter {
+    /// Check whether a file (by name) should be included.
+    pub fn is_match(&self, file_name: &str) -> bool {
+        (self.include.is_empty() || self.include.is_match(file_name))
+            && !self.exclude.is_match(file_name)
+    }
+}
+
+impl Default for FileFilter {
+    fn default() -> Self {
+        FilesConfig::default()
+            .build()
+            .expect("Default file patterns are valid")
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -47,3 +552,149 @@ fn default_config() -> HashMap<String, BuildConfig> {
 pub struct BuildConfig {
     pub katex_output: bool,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(build_path: &str) -> ProjectConfig {
+        let input = format!("outputs: []\nparsers: {{}}\ncustom: {{}}\nbuild_path: {build_path}");
+        serde_yaml::from_str(&input).unwrap()
+    }
+
+    #[test]
+    fn test_resolve_build_path() {
+        let project = std::env::temp_dir().join("project");
+
+        let default: ProjectConfig =
+            serde_yaml::from_str("outputs: []\nparsers: {}\ncustom: {}").unwrap();
+        assert_eq!(default.resolve_build_path(&project), project.join("build"));
+
+        // Relative paths are resolved against the project folder and not the working directory.
+        let relative = config("../out");
+        let resolved = relative.resolve_build_path(&project);
+        assert!(resolved.is_absolute());
+        assert_eq!(resolved, project.join("../out"));
+
+        let absolute = std::env::temp_dir().join("out");
+        let config = config(absolute.to_str().unwrap());
+        assert_eq!(config.resolve_build_path(&project), absolute);
+    }
+
+    #[test]
+    fn test_url_prefix() {
+        let prefix = |input: &str| {
+            serde_yaml::from_str::<ProjectConfig>(&format!(
+                "outputs: []\nparsers: {{}}\ncustom: {{}}\n{input}"
+            ))
+            .unwrap()
+            .url_prefix
+        };
+        assert_eq!(prefix(""), "");
+        assert_eq!(prefix("url_prefix: /"), "");
+        assert_eq!(prefix("url_prefix: /courses"), "/courses");
+        assert_eq!(prefix("url_prefix: courses/"), "/courses");
+        assert_eq!(prefix("path_prefix: /courses/"), "/courses");
+    }
+
+    #[test]
+    fn test_vars() {
+        let mut config: ProjectConfig = serde_yaml::from_str(
+            "outputs: []\nparsers: {}\ncustom: {}\nvars: {audience: instructor, year: 2023}",
+        )
+        .unwrap();
+        config
+            .set_vars(&["audience=student".to_string(), "term = a=b".to_string()])
+            .unwrap();
+        assert_eq!(config.vars["audience"], "student");
+        assert_eq!(config.vars["year"], 2023);
+        assert_eq!(config.vars["term"], "a=b");
+
+        // Values have the same types as in the configuration file.
+        config
+            .set_vars(&[
+                "year=2024".to_string(),
+                "draft=true".to_string(),
+                "title=Intro: basics".to_string(),
+                "empty=".to_string(),
+            ])
+            .unwrap();
+        assert_eq!(config.vars["year"], 2024);
+        assert_eq!(config.vars["draft"], true);
+        assert_eq!(config.vars["title"], "Intro: basics");
+        assert_eq!(config.vars["empty"], "");
+        assert!(config.set_vars(&["audience".to_string()]).is_err());
+        assert!(config.set_vars(&["=student".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_url_style() {
+        let doc = Path::new("part/chapter/doc.md");
+        let index = Path::new("part/chapter/index.md");
+
+        assert_eq!(
+            UrlStyle::Flat.page_path(doc),
+            PathBuf::from("part/chapter/doc.html")
+        );
+        assert_eq!(UrlStyle::Flat.page_url(index), "part/chapter/index.html");
+        assert_eq!(
+            UrlStyle::Pretty.page_path(doc),
+            PathBuf::from("part/chapter/doc/index.html")
+        );
+        assert_eq!(UrlStyle::Pretty.page_url(doc), "part/chapter/doc/");
+        assert_eq!(
+            UrlStyle::Pretty.page_path(index),
+            PathBuf::from("part/chapter/index.html")
+        );
+        assert_eq!(UrlStyle::Pretty.page_url(index), "part/chapter/");
+        assert_eq!(UrlStyle::Pretty.page_url(Path::new("index.md")), "");
+
+        // Pages are named after the document id.
+        let numbered = Path::new("part/01_Intro.md");
+        assert_eq!(UrlStyle::Flat.page_url(numbered), "part/01-intro.html");
+        assert_eq!(UrlStyle::Pretty.page_url(numbered), "part/01-intro/");
+
+        assert_eq!(UrlStyle::Pretty.relative_prefix(doc), "../");
+        assert_eq!(UrlStyle::Pretty.relative_prefix(index), "");
+        assert_eq!(UrlStyle::Flat.relative_prefix(doc), "");
+
+        let config: ProjectConfig = serde_yaml::from_str(
+            "outputs: []\nparsers: {}\ncustom: {}\nurl_prefix: /courses\nurl_style: pretty\nbase_url: https://example.com/",
+        )
+        .unwrap();
+        assert_eq!(config.page_url(doc), "/courses/part/chapter/doc/");
+        assert_eq!(
+            config.canonical_url(index),
+            Some("https://example.com/courses/part/chapter/".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parser_settings() {
+        let input = "outputs: []\n\
+            parsers:\n\
+            \x20 html: {preprocessors: [], event_processors: [], settings: {solutions: true}}\n\
+            \x20 notebook: {preprocessors: [], event_processors: [], settings: {notebook_outputs: true}}\n\
+            custom: {}\n";
+
+        // Without project-level settings the parser settings are unchanged.
+        let mut config: ProjectConfig = serde_yaml::from_str(input).unwrap();
+        config.apply_parser_settings();
+        let html = &config.parsers[&OutputFormat::Html].settings;
+        assert!(html.solutions && !html.notebook_outputs);
+        let notebook = &config.parsers[&OutputFormat::Notebook].settings;
+        assert!(!notebook.solutions && notebook.notebook_outputs);
+
+        let mut config: ProjectConfig = serde_yaml::from_str(&format!(
+            "{input}parser_settings:\n  solutions: false\n  html_policy: strip\n"
+        ))
+        .unwrap();
+        config.apply_parser_settings();
+        let html = &config.parsers[&OutputFormat::Html].settings;
+        assert!(!html.solutions && !html.notebook_outputs);
+        assert_eq!(html.html_policy, HtmlPolicy::Strip);
+        let notebook = &config.parsers[&OutputFormat::Notebook].settings;
+        assert!(!notebook.solutions && notebook.notebook_outputs);
+        assert_eq!(notebook.html_policy, HtmlPolicy::Strip);
+    }
+}
@@ -1,6 +1,5 @@
 use crate::project::{Chapter, ItemDescriptor, Part, Project, ProjectItem, ProjectIterator};
-use cdoc::config::InputFormat;
-use std::path::PathBuf;
+use anyhow::{anyhow, bail};
 use std::sync::Arc;
 
 impl<D> IntoIterator for Project<D>
@@ -11,36 +10,88 @@ where
     type IntoIter = ProjectIterator<D>;
 
     fn into_iter(self) -> Self::IntoIter {
+        let mut items = vec![ItemDescriptor {
+            part_id: None,
+            chapter_id: None,
+            part_idx: Some(0),
+            chapter_idx: None,
+            sub_chapter_idx: vec![],
+            doc_idx: None,
+            doc: self.index,
+            files: None,
+        }];
+
+        for (part_idx, part) in self.content.into_iter().enumerate() {
+            let part_idx = part_idx + 1;
+            items.push(ItemDescriptor {
+                part_id: Some(part.id.clone()),
+                chapter_id: None,
+                part_idx: Some(part_idx),
+                chapter_idx: Some(0),
+                sub_chapter_idx: vec![],
+                doc_idx: None,
+                doc: part.index,
+                files: None,
+            });
+
+            for (chapter_idx, chapter) in part.chapters.into_iter().enumerate() {
+                push_chapter(
+                    &mut items,
+                    &part.id,
+                    part_idx,
+                    chapter_idx + 1,
+                    vec![],
+                    chapter,
+                );
+            }
+        }
+
         ProjectIterator {
-            part_pos: 0,
-            chapter_pos: 0,
-            doc_pos: 0,
-            config: self,
+            items: items.into_iter(),
         }
     }
 }
 
-impl<D> ItemDescriptor<D> {
-    fn new(
-        part_id: Option<String>,
-        chapter_id: Option<String>,
-        part_idx: Option<usize>,
-        chapter_idx: Option<usize>,
-        doc: ProjectItem<D>,
-        doc_idx: Option<usize>,
-        files: Option<Vec<PathBuf>>,
-    ) -> Self {
-        ItemDescriptor {
-            part_id,
-            chapter_id,
-            part_idx,
-            chapter_idx,
-            doc,
-            doc_idx,
-            files,
-        }
+/// Add the items of a chapter and its nested chapters in iteration order.
+fn push_chapter<D>(
+    items: &mut Vec<ItemDescriptor<D>>,
+    part_id: &str,
+    part_idx: usize,
+    chapter_idx: usize,
+    sub_chapter_idx: Vec<usize>,
+    chapter: Chapter<D>,
+) {
+    let Chapter {
+        id,
+        index,
+        documents,
+        files,
+        chapters,
+    } = chapter;
+    let descriptor = |doc, doc_idx| ItemDescriptor {
+        part_id: Some(part_id.to_string()),
+        chapter_id: Some(id.clone()),
+        part_idx: Some(part_idx),
+        chapter_idx: Some(chapter_idx),
+        sub_chapter_idx: sub_chapter_idx.clone(),
+        doc_idx,
+        doc,
+        files: Some(files.clone()),
+    };
+
+    items.push(descriptor(index, None));
+    for (doc_idx, doc) in documents.into_iter().enumerate() {
+        items.push(descriptor(doc, Some(doc_idx)));
+    }
+
+    for (idx, sub_chapter) in chapters.into_iter().enumerate() {
+        let mut sub_idx = sub_chapter_idx.clone();
+        sub_idx.push(idx);
+        push_chapter(items, part_id, part_idx, chapter_idx, sub_idx, sub_chapter);
     }
+}
 
+impl<D> ItemDescriptor<D> {
     /// Perform operation on the inner document, then return the result wrapped in a ConfigItem.
     pub fn map<O, F>(self, f: F) -> anyhow::Result<ItemDescriptor<O>>
     where
@@ -52,15 +103,16 @@ impl<D> ItemDescriptor<D> {
             path: self.doc.path,
             content: Arc::new(f(self.doc.content.as_ref())?),
         };
-        Ok(ItemDescriptor::new(
-            self.part_id,
-            self.chapter_id,
-            self.part_idx,
-            self.chapter_idx,
+        Ok(ItemDescriptor {
+            part_id: self.part_id,
+            chapter_id: self.chapter_id,
+            part_idx: self.part_idx,
+            chapter_idx: self.chapter_idx,
+            sub_chapter_idx: self.sub_chapter_idx,
+            doc_idx: self.doc_idx,
             doc,
-            self.doc_idx,
-            self.files,
-        ))
+            files: self.files,
+        })
     }
 
     /// Perform operation on the whole DocumentSpec.
@@ -74,71 +126,83 @@ impl<D> ItemDescriptor<D> {
             path: self.doc.path.clone(),
             content: Arc::new(f(self.doc)?),
         };
-        Ok(ItemDescriptor::new(
-            self.part_id,
-            self.chapter_id,
-            self.part_idx,
-            self.chapter_idx,
+        Ok(ItemDescriptor {
+            part_id: self.part_id,
+            chapter_id: self.chapter_id,
+            part_idx: self.part_idx,
+            chapter_idx: self.chapter_idx,
+            sub_chapter_idx: self.sub_chapter_idx,
+            doc_idx: self.doc_idx,
             doc,
-            self.doc_idx,
-            self.files,
-        ))
+            files: self.files,
+        })
     }
+}
 
-    // pub fn get_chapter<T>(&self, config: Config<T>) -> Option<Chapter<T>> {
-    //     config.content[self.par]
-    // }
+/// Get the last chapter at the given nesting depth (0 is the top level).
+fn last_chapter_mut<D>(chapters: &mut [Chapter<D>], depth: usize) -> Option<&mut Chapter<D>> {
+    let mut chapter = chapters.last_mut()?;
+    for _ in 0..depth {
+        chapter = chapter.chapters.last_mut()?;
+    }
+    Some(chapter)
 }
 
 /// Collect iterator of ConfigItem into Config (tree structure).
-impl<D: Clone + Default> FromIterator<ItemDescriptor<D>> for Project<D> {
+///
+/// The first item must be the project index (as yielded by [ProjectIterator]). Collecting an empty
+/// iterator is an error.
+impl<D> FromIterator<ItemDescriptor<D>> for anyhow::Result<Project<D>> {
     fn from_iter<T: IntoIterator<Item = ItemDescriptor<D>>>(iter: T) -> Self {
-        // let mut index = it.next().unwrap().doc;
-        let mut index: ProjectItem<D> = ProjectItem {
-            id: "".to_string(),
-            format: InputFormat::Markdown,
-            path: Default::default(),
-            content: Arc::new(D::default()),
-        };
+        let mut iter = iter.into_iter();
+        let first = iter
+            .next()
+            .ok_or_else(|| anyhow!("Cannot collect a project from an empty iterator"))?;
+        if first.part_idx != Some(0) {
+            bail!("The first item of a project iterator must be the project index");
+        }
+        let index = first.doc;
 
         let mut parts: Vec<Part<D>> = vec![];
 
-        let mut last_chapter = 0;
-
         for item in iter {
             match item.part_idx.unwrap() {
-                0 => index = item.doc,
+                0 => bail!("A project iterator can only contain one project index"),
                 _part_idx => {
                     let part_id = item.part_id.unwrap();
                     match item.chapter_idx.unwrap() {
-                        0 => {
-                            last_chapter = 0;
-                            parts.push(Part {
-                                id: part_id,
-                                index: item.doc,
-                                chapters: vec![],
-                            })
-                        }
-                        chapter_idx => {
-                            let chapter_id = item.chapter_id.unwrap();
-
-                            if last_chapter == chapter_idx {
-                                parts
-                                    .last_mut()
-                                    .unwrap()
-                                    .chapters
-                                    .last_mut()
-                                    .unwrap()
+                        0 => parts.push(Part {
+                            id: part_id,
+                            index: item.doc,
+                            chapters: vec![],
+                        }),
+                        _chapter_idx => {
+                            let part = parts.last_mut().expect("Chapter without a part");
+
+                            let depth = item.sub_chapter_idx.len();
+
+                            // Documents have a document index, chapter indices don't.
+                            match item.doc_idx {
+                                None => {
+                                    let chapter = Chapter {
+                                        id: item.chapter_id.unwrap(),
+                                        index: item.doc,
+                                        documents: vec![],
+                                        files: item.files.expect("No files"),
+                                        chapters: vec![],
+                                    };
+                                    match depth {
+                                        0 => part.chapters.push(chapter),
+                                        _ => last_chapter_mut(&mut part.chapters, depth - 1)
+                                            .expect("Nested chapter without a parent chapter")
+                                            .chapters
+                                            .push(chapter),
+                                    }
+                                }
+                                Some(_) => last_chapter_mut(&mut part.chapters, depth)
+                                    .expect("Document without a chapter")
                                     .documents
-                                    .push(item.doc);
-                            } else {
-                                parts.last_mut().unwrap().chapters.push(Chapter {
-                                    id: chapter_id,
-                                    index: item.doc,
-                                    documents: vec![],
-                                    files: item.files.expect("No files"),
-                                });
-                                last_chapter = chapter_idx;
+                                    .push(item.doc),
                             }
                         }
                     }
@@ -146,11 +210,11 @@ impl<D: Clone + Default> FromIterator<ItemDescriptor<D>> for Project<D> {
             }
         }
 
-        Project {
+        Ok(Project {
             project_path: Default::default(),
             index,
             content: parts,
-        }
+        })
     }
 }
 
@@ -161,88 +225,6 @@ where
     type Item = ItemDescriptor<D>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.part_pos {
-            0 => {
-                // Config index
-                self.part_pos += 1;
-                Some(ItemDescriptor::new(
-                    None,
-                    None,
-                    Some(0),
-                    None,
-                    self.config.index.clone(),
-                    None,
-                    None,
-                ))
-            }
-            part_idx if part_idx <= self.config.content.len() => {
-                let part = &self.config.content[part_idx - 1];
-
-                let current_chapter_pos = self.chapter_pos;
-
-                match current_chapter_pos {
-                    0 => {
-                        // Part index
-                        if part.chapters.is_empty() {
-                            self.part_pos += 1;
-                        } else {
-                            self.chapter_pos += 1;
-                        }
-                        Some(ItemDescriptor::new(
-                            Some(part.id.clone()),
-                            None,
-                            Some(part_idx),
-                            Some(0),
-                            part.index.clone(),
-                            None,
-                            None,
-                        ))
-                    }
-
-                    chapter_idx => {
-                        let chapter = &part.chapters[chapter_idx - 1];
-
-                        let current_doc_pos = self.doc_pos;
-
-                        if current_doc_pos >= chapter.documents.len() {
-                            if current_chapter_pos >= part.chapters.len() {
-                                self.part_pos += 1;
-                                self.chapter_pos = 0;
-                            } else {
-                                self.chapter_pos += 1;
-                            }
-                            self.doc_pos = 0;
-                        } else {
-                            self.doc_pos += 1;
-                        }
-
-                        match current_doc_pos {
-                            0 => {
-                                // Chapter index
-                                Some(ItemDescriptor::new(
-                                    Some(part.id.clone()),
-                                    Some(chapter.id.clone()),
-                                    Some(part_idx),
-                                    Some(chapter_idx),
-                                    chapter.index.clone(),
-                                    None,
-                                    Some(chapter.files.clone()),
-                                ))
-                            }
-                            doc_pos => Some(ItemDescriptor::new(
-                                Some(part.id.clone()),
-                                Some(chapter.id.clone()),
-                                Some(part_idx),
-                                Some(chapter_idx),
-                                chapter.documents[doc_pos - 1].clone(),
-                                Some(doc_pos - 1),
-                                Some(chapter.files.clone()),
-                            )),
-                        }
-                    }
-                }
-            }
-            _ => None,
-        }
+        self.items.next()
     }
 }
@@ -8,6 +8,8 @@ use anyhow::anyhow;
 use serde::{Deserialize, Serialize};
 
 use cdoc::config::InputFormat;
+use cdoc::utils::slugify;
+use config::FileFilter;
 pub use iterator::*;
 pub use transform::*;
 
@@ -17,7 +19,7 @@ mod iterator;
 mod transform;
 
 /// The top-level configuration of a project's content.TTT
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Project<C> {
     pub project_path: PathBuf,
     pub(crate) index: ProjectItem<C>,
@@ -25,7 +27,7 @@ pub struct Project<C> {
 }
 
 /// A part is the highest level of content division. Each project has a series of parts.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Part<C> {
     /// Part id (folder name)
     pub id: String,
@@ -35,10 +37,12 @@ pub struct Part<C> {
     pub chapters: Vec<Chapter<C>>,
 }
 
-/// Parts contain chapters.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Parts contain chapters. Chapters can be nested by placing a folder with an index document
+/// inside a chapter folder.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Chapter<C> {
-    /// Chapter id (folder name)
+    /// Chapter id (folder name). For nested chapters, the folder names from the top-level chapter
+    /// are joined with `/`, i.e. the path of the chapter relative to its part.
     pub id: String,
     /// Index document
     pub index: ProjectItem<C>,
@@ -46,12 +50,15 @@ pub struct Chapter<C> {
     pub documents: Vec<ProjectItem<C>>,
     /// Other files
     pub files: Vec<PathBuf>,
+    /// Nested chapters (in order)
+    #[serde(default = "Vec::new")]
+    pub chapters: Vec<Chapter<C>>,
 }
 
 /// Chapters contain documents. Their configuration container is called DocumentSpec. It is a generic
 /// type over the inner "document". This is useful for using the configuration as a datastructure
 /// for content throughout the build process.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ProjectItem<C> {
     /// Document id (filename excluding extension)
     pub id: String,
@@ -64,11 +71,18 @@ pub struct ProjectItem<C> {
 }
 
 /// Iterates a Config.
+///
+/// The iteration order is guaranteed to be the reading order of the project:
+///
+/// 1. The project index.
+/// 2. For each part (in order): the part index followed by each of its chapters.
+/// 3. For each chapter (in order): the chapter index followed by its documents (in order) and then
+///    each of its nested chapters (recursively, in the same order).
+///
+/// Parts without chapters only yield their index, and chapters without documents only yield their
+/// index.
 pub struct ProjectIterator<D> {
-    part_pos: usize,
-    chapter_pos: usize,
-    doc_pos: usize,
-    config: Project<D>,
+    items: std::vec::IntoIter<ItemDescriptor<D>>,
 }
 
 /// Contains necessary information for reconstructing a Config from an iterator.
@@ -78,6 +92,9 @@ pub struct ItemDescriptor<D> {
     pub chapter_id: Option<String>,
     pub part_idx: Option<usize>,
     pub chapter_idx: Option<usize>,
+    /// Positions of the nested chapters leading from the top-level chapter to the item's chapter.
+    /// Empty for items that aren't in a nested chapter.
+    pub sub_chapter_idx: Vec<usize>,
     pub doc_idx: Option<usize>,
     pub doc: ProjectItem<D>,
     pub files: Option<Vec<PathBuf>>, // Temporary solution for carrying file info
@@ -91,6 +108,39 @@ impl<C> Project<C> {
     pub fn len(&self) -> usize {
         1 + self.content.iter().map(|e| e.len()).sum::<usize>()
     }
+
+    /// Get a part by its id.
+    pub fn part(&self, part_id: &str) -> Option<&Part<C>> {
+        self.content.iter().find(|p| p.id == part_id)
+    }
+
+    /// Get a chapter by its id and the id of its part. Nested chapters are found by their full
+    /// id.
+    pub fn chapter(&self, part_id: &str, chapter_id: &str) -> Option<&Chapter<C>> {
+        self.part(part_id)?
+            .chapters
+            .iter()
+            .find_map(|c| c.chapter_by_id(chapter_id))
+    }
+
+    /// Find the part containing the item (items are matched by path). The item may come from
+    /// a transformed version of the project. Returns `None` for the project index or if the item
+    /// is not in the project.
+    pub fn part_of<O>(&self, item: &ProjectItem<O>) -> Option<&Part<C>> {
+        self.content
+            .iter()
+            .find(|p| p.index.path == item.path || p.chapters.iter().any(|c| c.contains(item)))
+    }
+
+    /// Find the chapter containing the item (items are matched by path). For items in nested
+    /// chapters, the innermost chapter is returned. Returns `None` for the project and part
+    /// indices or if the item is not in the project.
+    pub fn chapter_of<O>(&self, item: &ProjectItem<O>) -> Option<&Chapter<C>> {
+        self.content
+            .iter()
+            .flat_map(|p| p.chapters.iter())
+            .find_map(|c| c.chapter_of(item))
+    }
 }
 
 impl<C> Part<C> {
@@ -109,7 +159,32 @@ impl<C> Chapter<C> {
     }
 
     pub fn len(&self) -> usize {
-        1 + self.documents.iter().map(|_| 1).sum::<usize>()
+        1 + self.documents.len() + self.chapters.iter().map(|c| c.len()).sum::<usize>()
+    }
+
+    /// Check whether the item is in the chapter or one of its nested chapters.
+    fn contains<O>(&self, item: &ProjectItem<O>) -> bool {
+        self.chapter_of(item).is_some()
+    }
+
+    /// Find the innermost chapter (this or a nested one) containing the item.
+    fn chapter_of<O>(&self, item: &ProjectItem<O>) -> Option<&Chapter<C>> {
+        self.chapters
+            .iter()
+            .find_map(|c| c.chapter_of(item))
+            .or_else(|| {
+                (self.index.path == item.path || self.documents.iter().any(|d| d.path == item.path))
+                    .then_some(self)
+            })
+    }
+
+    /// Find this or a nested chapter by its full id.
+    fn chapter_by_id(&self, id: &str) -> Option<&Chapter<C>> {
+        if self.id == id {
+            Some(self)
+        } else {
+            self.chapters.iter().find_map(|c| c.chapter_by_id(id))
+        }
     }
 }
 
@@ -121,8 +196,18 @@ impl<C> Chapter<C> {
 
 impl Project<()> {
     /// Construct configuration from a directory (generally the project directory). The function
-    /// finds and verifies the structure of the project.
+    /// finds and verifies the structure of the project. Chapter files are selected using the
+    /// default [FileFilter].
     pub fn generate_from_directory<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        Self::generate_from_directory_filtered(path, &FileFilter::default())
+    }
+
+    /// Like [Project::generate_from_directory] but non-document files in chapter folders are
+    /// only included if they match the filter.
+    pub fn generate_from_directory_filtered<P: AsRef<Path>>(
+        path: P,
+        filter: &FileFilter,
+    ) -> anyhow::Result<Self> {
         let content_path = path.as_ref().join("content");
 
         let parts = get_sorted_paths(&content_path)?
@@ -133,29 +218,116 @@ impl Project<()> {
             })
             .map(|entry| {
                 let file_path = entry.path();
-                Part::new(file_path, content_path.as_path())
+                Part::new(file_path, content_path.as_path(), filter)
             })
             .collect::<anyhow::Result<Vec<Part<()>>>>()?;
 
         let index_doc = index_helper(&content_path, &content_path)?;
 
-        Ok(Project {
+        let project = Project {
             project_path: path.as_ref().to_path_buf(),
             index: index_doc,
             content: parts,
-        })
+        };
+        project.check_unique_ids()?;
+        Ok(project)
     }
 }
 
+impl<C> Project<C> {
+    /// Check that ids are unique at each level: parts in the project, chapters (including nested
+    /// ones) in their part and documents in their chapter. Documents can collide because their ids
+    /// are slugified file names (e.g. `intro.md` and `intro.ipynb`). Colliding ids would mix up the
+    /// navigation and the output paths.
+    fn check_unique_ids(&self) -> anyhow::Result<()> {
+        let mut conflicts = duplicate_ids(
+            "Part",
+            self.content
+                .iter()
+                .map(|part| (&part.id, item_dir(&part.index))),
+        );
+        for part in &self.content {
+            let mut chapters = Vec::new();
+            part.chapters
+                .iter()
+                .for_each(|chapter| chapter.collect_chapters(&mut chapters));
+            conflicts.append(&mut duplicate_ids(
+                "Chapter",
+                chapters
+                    .iter()
+                    .map(|chapter| (&chapter.id, item_dir(&chapter.index))),
+            ));
+            for chapter in chapters {
+                conflicts.append(&mut duplicate_ids(
+                    "Document",
+                    chapter
+                        .documents
+                        .iter()
+                        .map(|doc| (&doc.id, doc.path.as_path())),
+                ));
+            }
+        }
+
+        if conflicts.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "Duplicate ids in the project:\n{}",
+                conflicts.join("\n")
+            ))
+        }
+    }
+}
+
+impl<C> Chapter<C> {
+    /// The chapter and its nested chapters (recursively) in reading order.
+    fn collect_chapters<'a>(&'a self, chapters: &mut Vec<&'a Chapter<C>>) {
+        chapters.push(self);
+        self.chapters
+            .iter()
+            .for_each(|chapter| chapter.collect_chapters(chapters));
+    }
+}
+
+/// The folder of a part or chapter, i.e. the folder of its index document.
+fn item_dir<C>(index: &ProjectItem<C>) -> &Path {
+    index.path.parent().unwrap_or(&index.path)
+}
+
+/// Describe the ids that are used by more than one of the given items, with their paths.
+fn duplicate_ids<'a>(
+    kind: &str,
+    items: impl Iterator<Item = (&'a String, &'a Path)>,
+) -> Vec<String> {
+    let mut ids: Vec<(&String, Vec<&Path>)> = Vec::new();
+    for (id, path) in items {
+        match ids.iter_mut().find(|(other, _)| *other == id) {
+            Some((_, paths)) => paths.push(path),
+            None => ids.push((id, vec![path])),
+        }
+    }
+    ids.into_iter()
+        .filter(|(_, paths)| paths.len() > 1)
+        .map(|(id, paths)| {
+            let paths: Vec<String> = paths.iter().map(|p| p.display().to_string()).collect();
+            format!("{} id '{}' is used by {}", kind, id, paths.join(", "))
+        })
+        .collect()
+}
+
 impl Part<()> {
-    fn new<P: AsRef<Path>, PC: AsRef<Path>>(dir: P, content_path: PC) -> anyhow::Result<Self> {
+    fn new<P: AsRef<Path>, PC: AsRef<Path>>(
+        dir: P,
+        content_path: PC,
+        filter: &FileFilter,
+    ) -> anyhow::Result<Self> {
         let part_folder = chapter_id(&dir).ok_or_else(|| anyhow!("Can't get part id"))?;
         // let part_dir = dir.as_ref().join(&part_folder);
 
         let chapters = get_sorted_paths(&dir)?
             .into_iter()
             .filter(|entry| entry.metadata().map(|meta| meta.is_dir()).unwrap())
-            .map(|entry| Chapter::new(entry.path(), content_path.as_ref()))
+            .map(|entry| Chapter::new(entry.path(), content_path.as_ref(), None, filter))
             .collect::<anyhow::Result<Vec<Chapter<()>>>>()?;
 
         Ok(Part {
@@ -170,8 +342,15 @@ impl Chapter<()> {
     fn new<P: AsRef<Path>, PC: AsRef<Path>>(
         chapter_dir: P,
         content_path: PC,
+        parent_id: Option<&str>,
+        filter: &FileFilter,
     ) -> anyhow::Result<Self> {
         let section_dir = chapter_dir.as_ref();
+        let folder = chapter_id(section_dir).ok_or_else(|| anyhow!("Can't get chapter id"))?;
+        let id = match parent_id {
+            None => folder,
+            Some(parent_id) => format!("{}/{}", parent_id, folder),
+        };
 
         let paths = get_sorted_paths(section_dir)?
             .into_iter()
@@ -182,7 +361,7 @@ impl Chapter<()> {
                     .filter(|e| extension_in(e.to_str().unwrap()))
                     .is_some()
             })
-            .filter(|entry| !entry.file_name().to_str().unwrap().contains("index"))
+            .filter(|entry| !is_index(entry.path()))
             .filter(|entry| entry.metadata().map(|meta| meta.is_file()).is_ok());
 
         let file_paths = get_sorted_paths(section_dir)?
@@ -194,11 +373,19 @@ impl Chapter<()> {
                     .filter(|e| extension_in(e.to_str().unwrap()))
                     .is_none()
             })
-            .filter(|entry| !entry.file_name().to_str().unwrap().contains("index"))
+            .filter(|entry| !is_index(entry.path()))
+            .filter(|entry| filter.is_match(entry.file_name().to_str().unwrap()))
             .filter(|entry| entry.metadata().map(|meta| meta.is_file()).is_ok())
+            .filter(|entry| !is_chapter_dir(entry.path()))
             .map(|entry| entry.path())
             .collect();
 
+        let chapters = get_sorted_paths(section_dir)?
+            .into_iter()
+            .filter(|entry| is_chapter_dir(entry.path()))
+            .map(|entry| Chapter::new(entry.path(), content_path.as_ref(), Some(&id), filter))
+            .collect::<anyhow::Result<Vec<Chapter<()>>>>()?;
+
         let documents: Vec<ProjectItem<()>> = paths
             .map(|entry| ProjectItem::new(entry.path().strip_prefix(content_path.as_ref())?))
             .collect::<anyhow::Result<Vec<ProjectItem<()>>>>()?;
@@ -206,10 +393,11 @@ impl Chapter<()> {
         let index_doc = index_helper(&chapter_dir, &content_path);
 
         Ok(Chapter {
-            id: chapter_id(chapter_dir).ok_or_else(|| anyhow!("Can't get chapter id"))?,
+            id,
             index: index_doc?,
             documents,
             files: file_paths,
+            chapters,
         })
     }
 }
@@ -233,6 +421,25 @@ impl<I> ProjectItem<I> {
     }
 }
 
+impl<I> ProjectItem<I> {
+    fn try_transform_parents_helper<F, O, E>(
+        &self,
+        part: Option<&Part<I>>,
+        chapter: Option<&Chapter<I>>,
+        f: &F,
+    ) -> Result<ProjectItem<O>, E>
+    where
+        F: Fn(&ProjectItem<I>, Option<&Part<I>>, Option<&Chapter<I>>) -> Result<O, E>,
+    {
+        Ok(ProjectItem {
+            id: self.id.clone(),
+            format: self.format,
+            path: self.path.clone(),
+            content: Arc::new(f(self, part, chapter)?),
+        })
+    }
+}
+
 impl<C> ProjectItem<C> {
     pub fn map<O, F>(self, f: F) -> ProjectItem<O>
     where
@@ -247,22 +454,14 @@ impl<C> ProjectItem<C> {
     }
 }
 
-/// Extract a section_id (folder name) from a full path.
+/// Extract a section_id (slugified file name without extension) from a full path.
 pub fn section_id<P: AsRef<Path>>(path: P) -> Option<String> {
-    Some(
-        path.as_ref()
-            .file_name()?
-            .to_str()?
-            .split('.')
-            .into_iter()
-            .next()?
-            .to_string(),
-    )
+    Some(slugify(path.as_ref().file_stem()?.to_str()?))
 }
 
-/// Extract a chapter_id (folder name) from a full path.
+/// Extract a chapter_id (slugified folder name) from a full path.
 fn chapter_id<P: AsRef<Path>>(path: P) -> Option<String> {
-    Some(path.as_ref().file_name()?.to_str().unwrap().to_string())
+    Some(slugify(path.as_ref().file_name()?.to_str()?))
 }
 
 impl ProjectItem<()> {
@@ -281,6 +480,25 @@ impl ProjectItem<()> {
 
 const EXT: [&str; 2] = ["md", "ipynb"];
 
+/// Folders inside a chapter are nested chapters if they contain an index document.
+fn is_chapter_dir<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref().is_dir()
+        && EXT
+            .iter()
+            .any(|e| path.as_ref().join(format!("index.{}", e)).is_file())
+}
+
+/// Index documents are named `index` with a document extension (e.g. `index.md`). Other files
+/// with "index" in their name, like `indexing.md`, are regular documents.
+fn is_index<P: AsRef<Path>>(path: P) -> bool {
+    let path = path.as_ref();
+    path.file_stem().and_then(|s| s.to_str()) == Some("index")
+        && path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(extension_in)
+}
+
 fn extension_in(extension: &str) -> bool {
     EXT.iter().any(|e| e == &extension)
 }
@@ -312,16 +530,25 @@ mod tests {
 
     use super::*;
 
+    fn item(id: &str) -> ProjectItem<()> {
+        ProjectItem {
+            id: id.to_string(),
+            format: InputFormat::Markdown,
+            path: PathBuf::from(id),
+            content: Arc::new(()),
+        }
+    }
+
     #[test]
     fn gen_config_from_dir() {
         let cfg =
             Project::generate_from_directory("resources/test").expect("Could not read config");
         assert_eq!(cfg.content.len(), 1); // 1 part
         assert_eq!(cfg.content[0].chapters.len(), 4); // 4 chapters in part 1
-        assert_eq!(cfg.content[0].chapters[0].id, "01_getting_started");
-        assert_eq!(cfg.content[0].chapters[1].id, "02_project_organisation");
-        assert_eq!(cfg.content[0].chapters[2].id, "03_shortcodes");
-        assert_eq!(cfg.content[0].chapters[3].id, "04_exercise_tools");
+        assert_eq!(cfg.content[0].chapters[0].id, "01-getting-started");
+        assert_eq!(cfg.content[0].chapters[1].id, "02-project-organisation");
+        assert_eq!(cfg.content[0].chapters[2].id, "03-shortcodes");
+        assert_eq!(cfg.content[0].chapters[3].id, "04-exercise-tools");
     }
 
     #[test]
@@ -346,12 +573,14 @@ mod tests {
                             index: doc.clone(),
                             documents: vec![doc.clone(), doc.clone()],
                             files: vec![PathBuf::new()],
+                            chapters: vec![],
                         },
                         Chapter {
                             id: "chapter2".to_string(),
                             index: doc.clone(),
                             documents: vec![doc.clone(), doc.clone()],
                             files: vec![PathBuf::new()],
+                            chapters: vec![],
                         },
                     ],
                 },
@@ -363,10 +592,427 @@ mod tests {
             ],
         };
 
-        let cfg_mapped: Project<()> = cfg.clone().into_iter().collect();
+        let cfg_mapped: Project<()> = cfg
+            .clone()
+            .into_iter()
+            .collect::<anyhow::Result<_>>()
+            .unwrap();
 
         for (p1, p2) in zip(cfg.content, cfg_mapped.content) {
             assert_eq!(p1.id, p2.id);
         }
     }
+
+    #[test]
+    fn test_iteration_order() {
+        let cfg = Project {
+            project_path: Default::default(),
+            index: item("index"),
+            content: vec![
+                Part {
+                    id: "part1".to_string(),
+                    index: item("part1_index"),
+                    chapters: vec![
+                        Chapter {
+                            id: "chapter1".to_string(),
+                            index: item("chapter1_index"),
+                            documents: vec![item("doc1"), item("doc2")],
+                            files: vec![],
+                            chapters: vec![],
+                        },
+                        Chapter {
+                            id: "chapter2".to_string(),
+                            index: item("chapter2_index"),
+                            documents: vec![],
+                            files: vec![],
+                            chapters: vec![],
+                        },
+                        Chapter {
+                            id: "chapter3".to_string(),
+                            index: item("chapter3_index"),
+                            documents: vec![item("doc3")],
+                            files: vec![],
+                            chapters: vec![],
+                        },
+                    ],
+                },
+                Part {
+                    id: "part2".to_string(),
+                    index: item("part2_index"),
+                    chapters: vec![],
+                },
+                Part {
+                    id: "part3".to_string(),
+                    index: item("part3_index"),
+                    chapters: vec![Chapter {
+                        id: "chapter4".to_string(),
+                        index: item("chapter4_index"),
+                        documents: vec![item("doc4")],
+                        files: vec![],
+                        chapters: vec![],
+                    }],
+                },
+            ],
+        };
+
+        type Position = (Option<String>, Option<String>, String, Option<usize>);
+        let order: Vec<Position> = cfg
+            .into_iter()
+            .map(|i| (i.part_id, i.chapter_id, i.doc.id, i.doc_idx))
+            .collect();
+
+        let s = |v: &str| Some(v.to_string());
+        let expected = vec![
+            (None, None, "index".to_string(), None),
+            (s("part1"), None, "part1_index".to_string(), None),
+            (
+                s("part1"),
+                s("chapter1"),
+                "chapter1_index".to_string(),
+                None,
+            ),
+            (s("part1"), s("chapter1"), "doc1".to_string(), Some(0)),
+            (s("part1"), s("chapter1"), "doc2".to_string(), Some(1)),
+            (
+                s("part1"),
+                s("chapter2"),
+                "chapter2_index".to_string(),
+                None,
+            ),
+            (
+                s("part1"),
+                s("chapter3"),
+                "chapter3_index".to_string(),
+                None,
+            ),
+            (s("part1"), s("chapter3"), "doc3".to_string(), Some(0)),
+            (s("part2"), None, "part2_index".to_string(), None),
+            (s("part3"), None, "part3_index".to_string(), None),
+            (
+                s("part3"),
+                s("chapter4"),
+                "chapter4_index".to_string(),
+                None,
+            ),
+            (s("part3"), s("chapter4"), "doc4".to_string(), Some(0)),
+        ];
+
+        assert_eq!(order, expected);
+    }
+
+    #[test]
+    fn test_reconstruction() {
+        let cfg = Project {
+            project_path: Default::default(),
+            index: item("index"),
+            content: vec![
+                Part {
+                    id: "part1".to_string(),
+                    index: item("part1_index"),
+                    chapters: vec![
+                        Chapter {
+                            id: "chapter1".to_string(),
+                            index: item("chapter1_index"),
+                            documents: vec![],
+                            files: vec![PathBuf::from("file1")],
+                            chapters: vec![],
+                        },
+                        Chapter {
+                            id: "chapter2".to_string(),
+                            index: item("chapter2_index"),
+                            documents: vec![item("doc1"), item("doc2")],
+                            files: vec![],
+                            chapters: vec![],
+                        },
+                    ],
+                },
+                Part {
+                    id: "part2".to_string(),
+                    index: item("part2_index"),
+                    chapters: vec![],
+                },
+                Part {
+                    id: "part3".to_string(),
+                    index: item("part3_index"),
+                    chapters: vec![Chapter {
+                        id: "chapter1".to_string(),
+                        index: item("chapter3_index"),
+                        documents: vec![],
+                        files: vec![],
+                        chapters: vec![],
+                    }],
+                },
+            ],
+        };
+
+        let reconstructed: Project<()> = cfg
+            .clone()
+            .into_iter()
+            .collect::<anyhow::Result<_>>()
+            .unwrap();
+        assert_eq!(cfg, reconstructed);
+    }
+
+    #[test]
+    fn test_try_transform() {
+        let cfg = Project {
+            project_path: Default::default(),
+            index: item("index"),
+            content: vec![Part {
+                id: "part1".to_string(),
+                index: item("part1_index"),
+                chapters: vec![Chapter {
+                    id: "chapter1".to_string(),
+                    index: item("chapter1_index"),
+                    documents: vec![item("doc1"), item("doc2")],
+                    files: vec![],
+                    chapters: vec![],
+                }],
+            }],
+        };
+
+        let ok: Result<Project<String>, String> = cfg.try_transform(&|i| Ok(i.id.clone()));
+        assert_eq!(ok.unwrap(), cfg.transform(&|i| i.id.clone()));
+
+        let err: Result<Project<String>, String> = cfg.try_transform(&|i| match i.id.as_str() {
+            "doc1" => Err("doc1 failed".to_string()),
+            id => Ok(id.to_string()),
+        });
+        assert_eq!(err.unwrap_err(), "doc1 failed");
+
+        let ok: Result<Project<Option<String>>, String> =
+            cfg.try_transform_parents(&|_, _, c| Ok(c.map(|c| c.id.clone())));
+        let expected = cfg.transform_parents(&|_, _, c| c.map(|c| c.id.clone()));
+        assert_eq!(ok.unwrap(), expected);
+
+        let err: Result<Project<()>, String> = cfg.try_transform_parents(&|i, p, _| match p {
+            Some(p) if i.id == "doc2" => Err(format!("{} failed", p.id)),
+            _ => Ok(()),
+        });
+        assert_eq!(err.unwrap_err(), "part1 failed");
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_par_transform() {
+        let cfg =
+            Project::generate_from_directory("resources/test").expect("Could not read config");
+        let f = |i: &ProjectItem<()>| format!("{}:{}", i.id, i.path.display());
+
+        assert_eq!(cfg.transform(&f), cfg.par_transform(&f));
+    }
+
+    #[test]
+    fn test_item_parents() {
+        let chapter = |id: &str| Chapter {
+            id: id.to_string(),
+            index: item(&format!("{}_index", id)),
+            documents: vec![item(&format!("{}_doc1", id))],
+            files: vec![],
+            chapters: vec![],
+        };
+
+        let cfg = Project {
+            project_path: Default::default(),
+            index: item("index"),
+            content: vec![Part {
+                id: "part1".to_string(),
+                index: item("part1_index"),
+                chapters: vec![chapter("chapter1"), chapter("chapter2")],
+            }],
+        };
+        let transformed = cfg.transform(&|i| i.id.clone());
+
+        assert!(cfg.part_of(&cfg.index).is_none());
+        assert!(cfg.chapter_of(&cfg.index).is_none());
+
+        let part = &cfg.content[0];
+        assert_eq!(cfg.part_of(&part.index), Some(part));
+        assert!(cfg.chapter_of(&part.index).is_none());
+
+        let chapter = &part.chapters[1];
+        let doc = &transformed.content[0].chapters[1].documents[0];
+        assert_eq!(cfg.part_of(doc), Some(part));
+        assert_eq!(cfg.chapter_of(doc), Some(chapter));
+        assert_eq!(cfg.chapter_of(&chapter.index), Some(chapter));
+
+        assert_eq!(cfg.part("part1"), Some(part));
+        assert_eq!(cfg.chapter("part1", "chapter2"), Some(chapter));
+        assert!(cfg.chapter("part1", "missing").is_none());
+    }
+
+    #[test]
+    fn test_collect_empty() {
+        let res: anyhow::Result<Project<()>> = std::iter::empty().collect();
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            "Cannot collect a project from an empty iterator"
+        );
+    }
+
+    #[test]
+    fn test_chapter_file_filter() {
+        let dir = tempfile::tempdir().unwrap();
+        let chapter_dir = dir.path().join("content").join("part1").join("chapter1");
+        fs::create_dir_all(&chapter_dir).unwrap();
+        fs::write(dir.path().join("content").join("index.md"), "").unwrap();
+        fs::write(
+            dir.path().join("content").join("part1").join("index.md"),
+            "",
+        )
+        .unwrap();
+        for file in [
+            "index.md",
+            "doc.md",
+            "indexing_basics.md",
+            "script.py",
+            ".DS_Store",
+            "script.py~",
+        ] {
+            fs::write(chapter_dir.join(file), "").unwrap();
+        }
+
+        let cfg = Project::generate_from_directory(dir.path()).unwrap();
+        let chapter = &cfg.content[0].chapters[0];
+        assert_eq!(chapter.files, vec![chapter_dir.join("script.py")]);
+        let ids: Vec<&str> = chapter.documents.iter().map(|d| d.id.as_str()).collect();
+        assert_eq!(ids, vec!["doc", "indexing-basics"]);
+        assert_eq!(chapter.index.id, "index");
+
+        let filter = config::FilesConfig {
+            include: vec!["*.csv".to_string()],
+            exclude: vec![],
+        }
+        .build()
+        .unwrap();
+        fs::write(chapter_dir.join("data.csv"), "").unwrap();
+        let cfg = Project::generate_from_directory_filtered(dir.path(), &filter).unwrap();
+        let chapter = &cfg.content[0].chapters[0];
+        assert_eq!(chapter.files, vec![chapter_dir.join("data.csv")]);
+    }
+
+    #[test]
+    fn test_duplicate_ids() {
+        // Ids are derived from the paths like when the project is read.
+        let page = |path: &str| ProjectItem {
+            id: section_id(path).unwrap(),
+            ..item(path)
+        };
+        let chapter = |path: &str, id: &str| Chapter {
+            id: id.to_string(),
+            index: page(&format!("{}/index.md", path)),
+            documents: vec![],
+            files: vec![],
+            chapters: vec![],
+        };
+        let mut cfg = Project {
+            project_path: Default::default(),
+            index: page("index.md"),
+            content: vec![Part {
+                id: "part1".to_string(),
+                index: page("part1/index.md"),
+                chapters: vec![chapter("part1/a", "a"), chapter("part1/b", "b")],
+            }],
+        };
+        cfg.check_unique_ids().unwrap();
+
+        cfg.content[0].chapters[1].id = "a".to_string();
+        let err = cfg.check_unique_ids().unwrap_err().to_string();
+        assert!(err.contains("Chapter id 'a' is used by part1/a, part1/b"));
+
+        // Documents with the same name in different formats.
+        let dir = tempfile::tempdir().unwrap();
+        let chapter_dir = dir.path().join("content").join("part1").join("chapter1");
+        fs::create_dir_all(&chapter_dir).unwrap();
+        fs::write(dir.path().join("content").join("index.md"), "").unwrap();
+        fs::write(
+            dir.path().join("content").join("part1").join("index.md"),
+            "",
+        )
+        .unwrap();
+        for file in ["index.md", "intro.md", "intro.ipynb", "other.md"] {
+            fs::write(chapter_dir.join(file), "").unwrap();
+        }
+        let err = Project::generate_from_directory(dir.path())
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("Document id 'intro' is used by"));
+        assert!(!err.contains("other"));
+
+        // Chapter folders whose names only differ in case or punctuation.
+        fs::remove_file(chapter_dir.join("intro.ipynb")).unwrap();
+        let other_dir = dir.path().join("content").join("part1").join("Chapter1");
+        fs::create_dir_all(&other_dir).unwrap();
+        fs::write(other_dir.join("index.md"), "").unwrap();
+        let err = Project::generate_from_directory(dir.path())
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("Chapter id 'chapter1' is used by"), "{}", err);
+    }
+
+    #[test]
+    fn test_nested_chapters() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = dir.path().join("content");
+        let chapter_dir = content.join("part1").join("chapter1");
+        let sub_dir = chapter_dir.join("sub1");
+        let data_dir = chapter_dir.join("data");
+        fs::create_dir_all(&sub_dir).unwrap();
+        fs::create_dir_all(&data_dir).unwrap();
+        for file in [
+            content.join("index.md"),
+            content.join("part1").join("index.md"),
+            chapter_dir.join("index.md"),
+            chapter_dir.join("doc.md"),
+            sub_dir.join("index.md"),
+            sub_dir.join("subdoc.md"),
+            data_dir.join("data.csv"),
+        ] {
+            fs::write(file, "").unwrap();
+        }
+
+        let cfg = Project::generate_from_directory(dir.path()).unwrap();
+        let chapter = &cfg.content[0].chapters[0];
+        assert_eq!(chapter.id, "chapter1");
+        assert_eq!(chapter.documents.len(), 1);
+        // Folders without an index are not chapters
+        assert_eq!(chapter.files, vec![data_dir]);
+        assert_eq!(chapter.chapters.len(), 1);
+
+        let sub = &chapter.chapters[0];
+        assert_eq!(sub.id, "chapter1/sub1");
+        assert_eq!(sub.documents[0].id, "subdoc");
+        assert_eq!(cfg.len(), 6);
+
+        let order: Vec<(Option<String>, Vec<usize>, String)> = cfg
+            .clone()
+            .into_iter()
+            .map(|i| (i.chapter_id, i.sub_chapter_idx, i.doc.id))
+            .collect();
+        let chapter1 = Some("chapter1".to_string());
+        let sub1 = Some("chapter1/sub1".to_string());
+        assert_eq!(
+            order,
+            vec![
+                (None, vec![], "index".to_string()),
+                (None, vec![], "index".to_string()),
+                (chapter1.clone(), vec![], "index".to_string()),
+                (chapter1, vec![], "doc".to_string()),
+                (sub1.clone(), vec![0], "index".to_string()),
+                (sub1, vec![0], "subdoc".to_string()),
+            ]
+        );
+
+        let mut reconstructed: Project<()> = cfg
+            .clone()
+            .into_iter()
+            .collect::<anyhow::Result<_>>()
+            .unwrap();
+        reconstructed.project_path = cfg.project_path.clone();
+        assert_eq!(cfg, reconstructed);
+
+        assert_eq!(cfg.chapter_of(&sub.documents[0]), Some(sub));
+        assert_eq!(cfg.chapter_of(&chapter.documents[0]), Some(chapter));
+        assert_eq!(cfg.chapter("part1", "chapter1/sub1"), Some(sub));
+    }
 }
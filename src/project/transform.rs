@@ -8,6 +8,11 @@ pub trait Transform<T, I, O> {
     fn transform<F>(&self, f: &F) -> T
     where
         F: Fn(&ProjectItem<I>) -> O;
+
+    /// Fallible version of [Transform::transform]. Returns the first error encountered.
+    fn try_transform<F, E>(&self, f: &F) -> Result<T, E>
+    where
+        F: Fn(&ProjectItem<I>) -> Result<O, E>;
 }
 
 /// Convenience trait for a map-function that also has access to the possible parents of a document.
@@ -16,6 +21,12 @@ pub trait TransformParents<T, I, O> {
     fn transform_parents<F>(&self, f: &F) -> T
     where
         F: Fn(&ProjectItem<I>, Option<&Part<I>>, Option<&Chapter<I>>) -> O;
+
+    /// Fallible version of [TransformParents::transform_parents]. Returns the first error
+    /// encountered.
+    fn try_transform_parents<F, E>(&self, f: &F) -> Result<T, E>
+    where
+        F: Fn(&ProjectItem<I>, Option<&Part<I>>, Option<&Chapter<I>>) -> Result<O, E>;
 }
 
 impl<I, O> Transform<Chapter<O>, I, O> for Chapter<I> {
@@ -28,8 +39,30 @@ impl<I, O> Transform<Chapter<O>, I, O> for Chapter<I> {
             index: self.index.transform(f),
             documents: self.documents.iter().map(|d| d.transform(f)).collect(),
             files: self.files.clone(),
+            chapters: self.chapters.iter().map(|c| c.transform(f)).collect(),
         }
     }
+
+    fn try_transform<F, E>(&self, f: &F) -> Result<Chapter<O>, E>
+    where
+        F: Fn(&ProjectItem<I>) -> Result<O, E>,
+    {
+        Ok(Chapter {
+            id: self.id.clone(),
+            index: self.index.try_transform(f)?,
+            documents: self
+                .documents
+                .iter()
+                .map(|d| d.try_transform(f))
+                .collect::<Result<Vec<ProjectItem<O>>, E>>()?,
+            files: self.files.clone(),
+            chapters: self
+                .chapters
+                .iter()
+                .map(|c| c.try_transform(f))
+                .collect::<Result<Vec<Chapter<O>>, E>>()?,
+        })
+    }
 }
 
 impl<I> Chapter<I> {
@@ -48,8 +81,36 @@ impl<I> Chapter<I> {
                 .map(|d| d.transform_parents_helper(Some(part), Some(self), f))
                 .collect(),
             files: self.files.clone(),
+            chapters: self
+                .chapters
+                .iter()
+                .map(|c| c.transform_parents_helper(part, f))
+                .collect(),
         }
     }
+
+    fn try_transform_parents_helper<F, O, E>(&self, part: &Part<I>, f: &F) -> Result<Chapter<O>, E>
+    where
+        F: Fn(&ProjectItem<I>, Option<&Part<I>>, Option<&Chapter<I>>) -> Result<O, E>,
+    {
+        Ok(Chapter {
+            id: self.id.clone(),
+            index: self
+                .index
+                .try_transform_parents_helper(Some(part), Some(self), f)?,
+            documents: self
+                .documents
+                .iter()
+                .map(|d| d.try_transform_parents_helper(Some(part), Some(self), f))
+                .collect::<Result<Vec<ProjectItem<O>>, E>>()?,
+            files: self.files.clone(),
+            chapters: self
+                .chapters
+                .iter()
+                .map(|c| c.try_transform_parents_helper(part, f))
+                .collect::<Result<Vec<Chapter<O>>, E>>()?,
+        })
+    }
 }
 
 impl<I, O> Transform<Part<O>, I, O> for Part<I> {
@@ -63,6 +124,21 @@ impl<I, O> Transform<Part<O>, I, O> for Part<I> {
             chapters: self.chapters.iter().map(|c| c.transform(f)).collect(),
         }
     }
+
+    fn try_transform<F, E>(&self, f: &F) -> Result<Part<O>, E>
+    where
+        F: Fn(&ProjectItem<I>) -> Result<O, E>,
+    {
+        Ok(Part {
+            id: self.id.clone(),
+            index: self.index.try_transform(f)?,
+            chapters: self
+                .chapters
+                .iter()
+                .map(|c| c.try_transform(f))
+                .collect::<Result<Vec<Chapter<O>>, E>>()?,
+        })
+    }
 }
 
 impl<I, O> TransformParents<Part<O>, I, O> for Part<I> {
@@ -80,6 +156,23 @@ impl<I, O> TransformParents<Part<O>, I, O> for Part<I> {
                 .collect(),
         }
     }
+
+    fn try_transform_parents<F, E>(&self, f: &F) -> Result<Part<O>, E>
+    where
+        F: Fn(&ProjectItem<I>, Option<&Part<I>>, Option<&Chapter<I>>) -> Result<O, E>,
+    {
+        Ok(Part {
+            id: self.id.clone(),
+            index: self
+                .index
+                .try_transform_parents_helper(Some(self), None, f)?,
+            chapters: self
+                .chapters
+                .iter()
+                .map(|c| c.try_transform_parents_helper(self, f))
+                .collect::<Result<Vec<Chapter<O>>, E>>()?,
+        })
+    }
 }
 
 impl<I, O> Transform<Project<O>, I, O> for Project<I> {
@@ -93,6 +186,21 @@ impl<I, O> Transform<Project<O>, I, O> for Project<I> {
             content: self.content.iter().map(|p| p.transform(f)).collect(),
         }
     }
+
+    fn try_transform<F, E>(&self, f: &F) -> Result<Project<O>, E>
+    where
+        F: Fn(&ProjectItem<I>) -> Result<O, E>,
+    {
+        Ok(Project {
+            project_path: self.project_path.clone(),
+            index: self.index.try_transform(f)?,
+            content: self
+                .content
+                .iter()
+                .map(|p| p.try_transform(f))
+                .collect::<Result<Vec<Part<O>>, E>>()?,
+        })
+    }
 }
 
 impl<I, O> TransformParents<Project<O>, I, O> for Project<I> {
@@ -110,6 +218,21 @@ impl<I, O> TransformParents<Project<O>, I, O> for Project<I> {
                 .collect(),
         }
     }
+
+    fn try_transform_parents<F, E>(&self, f: &F) -> Result<Project<O>, E>
+    where
+        F: Fn(&ProjectItem<I>, Option<&Part<I>>, Option<&Chapter<I>>) -> Result<O, E>,
+    {
+        Ok(Project {
+            project_path: self.project_path.clone(),
+            index: self.index.try_transform_parents_helper(None, None, f)?,
+            content: self
+                .content
+                .iter()
+                .map(|p| p.try_transform_parents(f))
+                .collect::<Result<Vec<Part<O>>, E>>()?,
+        })
+    }
 }
 
 impl<I, O> Transform<ProjectItem<O>, I, O> for ProjectItem<I> {
@@ -124,4 +247,86 @@ impl<I, O> Transform<ProjectItem<O>, I, O> for ProjectItem<I> {
             content: Arc::new(f(self)),
         }
     }
+
+    fn try_transform<F, E>(&self, f: &F) -> Result<ProjectItem<O>, E>
+    where
+        F: Fn(&ProjectItem<I>) -> Result<O, E>,
+    {
+        Ok(ProjectItem {
+            id: self.id.clone(),
+            format: self.format,
+            path: self.path.clone(),
+            content: Arc::new(f(self)?),
+        })
+    }
+}
+
+/// Parallel version of [Transform]. Items are mapped concurrently using rayon while the project
+/// structure is preserved.
+#[cfg(feature = "parallel")]
+pub trait ParTransform<T, I, O> {
+    /// Like [Transform::transform] but the function is applied to items in parallel.
+    fn par_transform<F>(&self, f: &F) -> T
+    where
+        F: Fn(&ProjectItem<I>) -> O + Sync;
+}
+
+#[cfg(feature = "parallel")]
+mod parallel {
+    use super::{ParTransform, Transform};
+    use crate::project::{Chapter, Part, Project, ProjectItem};
+    use rayon::prelude::*;
+
+    impl<I: Send + Sync, O: Send + Sync> ParTransform<Chapter<O>, I, O> for Chapter<I> {
+        fn par_transform<F>(&self, f: &F) -> Chapter<O>
+        where
+            F: Fn(&ProjectItem<I>) -> O + Sync,
+        {
+            Chapter {
+                id: self.id.clone(),
+                index: self.index.transform(f),
+                documents: self.documents.par_iter().map(|d| d.transform(f)).collect(),
+                files: self.files.clone(),
+                chapters: self
+                    .chapters
+                    .par_iter()
+                    .map(|c| c.par_transform(f))
+                    .collect(),
+            }
+        }
+    }
+
+    impl<I: Send + Sync, O: Send + Sync> ParTransform<Part<O>, I, O> for Part<I> {
+        fn par_transform<F>(&self, f: &F) -> Part<O>
+        where
+            F: Fn(&ProjectItem<I>) -> O + Sync,
+        {
+            Part {
+                id: self.id.clone(),
+                index: self.index.transform(f),
+                chapters: self
+                    .chapters
+                    .par_iter()
+                    .map(|c| c.par_transform(f))
+                    .collect(),
+            }
+        }
+    }
+
+    impl<I: Send + Sync, O: Send + Sync> ParTransform<Project<O>, I, O> for Project<I> {
+        fn par_transform<F>(&self, f: &F) -> Project<O>
+        where
+            F: Fn(&ProjectItem<I>) -> O + Sync,
+        {
+            Project {
+                project_path: self.project_path.clone(),
+                index: self.index.transform(f),
+                content: self
+                    .content
+                    .par_iter()
+                    .map(|p| p.par_transform(f))
+                    .collect(),
+            }
+        }
+    }
 }